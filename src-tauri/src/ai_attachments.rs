@@ -0,0 +1,397 @@
+use crate::ai_chat::AiChatMessage;
+use base64::{Engine as _, engine::general_purpose};
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+// 消息附件：图片、PDF 和文本文件。支持的提供商原样发送，其他情况转成文字放进消息
+
+// 转成文字时单个附件最多保留的字符数
+const MAX_ATTACHMENT_CHARS: usize = 60_000;
+
+// PDF 单个流解压后的上限和全部流解压后的总上限，防止压缩炸弹耗尽内存
+const MAX_PDF_STREAM_BYTES: u64 = 8 * 1024 * 1024;
+const MAX_PDF_DECODED_BYTES: usize = 64 * 1024 * 1024;
+// 提取的文字超过该字节数后停止，之后也会截断到 MAX_ATTACHMENT_CHARS
+const MAX_PDF_TEXT_BYTES: usize = MAX_ATTACHMENT_CHARS * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Image,
+    Pdf,
+    Text,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mime_type: String,  // 为空时按 data URL 或类型推断
+    pub data: String,       // 图片和 PDF 为 base64（可带 data URL 前缀），文本文件为原文
+}
+
+impl Attachment {
+    // 旧版 images 字段中的 data URL 或 base64
+    pub fn from_image(image: &str) -> Self {
+        Self { kind: AttachmentKind::Image, name: String::new(), mime_type: String::new(), data: image.to_string() }
+    }
+
+    pub fn media_type(&self) -> String {
+        if !self.mime_type.trim().is_empty() {
+            return self.mime_type.trim().to_string();
+        }
+        if let Some(mime) = self.data.strip_prefix("data:").and_then(|rest| rest.split(';').next()).filter(|mime| !mime.is_empty()) {
+            return mime.to_string();
+        }
+        match self.kind {
+            AttachmentKind::Image => "image/jpeg",
+            AttachmentKind::Pdf => "application/pdf",
+            AttachmentKind::Text => "text/plain",
+        }.to_string()
+    }
+
+    // 去掉 data URL 前缀后的 base64
+    pub fn base64_data(&self) -> &str {
+        match self.data.find(";base64,") {
+            Some(start) if self.data.starts_with("data:") => &self.data[start + 8..],
+            _ => &self.data,
+        }
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type(), self.base64_data())
+    }
+
+    fn display_name(&self) -> &str {
+        if !self.name.trim().is_empty() {
+            return self.name.trim();
+        }
+        match self.kind {
+            AttachmentKind::Image => "图片",
+            AttachmentKind::Pdf => "PDF 文件",
+            AttachmentKind::Text => "文本文件",
+        }
+    }
+
+    // 附件的文字内容：文本文件取原文，PDF 尽量提取文字，图片没有文字内容
+    pub fn text(&self) -> Option<String> {
+        let text = match self.kind {
+            AttachmentKind::Image => return None,
+            AttachmentKind::Text if self.data.starts_with("data:") => {
+                let bytes = general_purpose::STANDARD.decode(self.base64_data()).ok()?;
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+            AttachmentKind::Text => self.data.clone(),
+            AttachmentKind::Pdf => {
+                let bytes = general_purpose::STANDARD.decode(self.base64_data().trim()).ok()?;
+                extract_pdf_text(&bytes)
+            }
+        };
+        Some(text).filter(|text| !text.trim().is_empty())
+    }
+
+    // 提供商不能直接读取该附件时放进消息正文的内容
+    pub fn as_text_block(&self) -> String {
+        let name = self.display_name();
+        match self.text() {
+            Some(text) => {
+                let mut excerpt: String = text.trim().chars().take(MAX_ATTACHMENT_CHARS).collect();
+                if excerpt.len() < text.trim().len() {
+                    excerpt.push_str("\n……（内容过长，已截断）");
+                }
+                format!("[附件：{}]\n{}", name, excerpt)
+            }
+            None if self.kind == AttachmentKind::Image => format!("[附件：{}（当前模型不支持图片，已省略）]", name),
+            None => format!("[附件：{}（未能提取文字内容）]", name),
+        }
+    }
+}
+
+// 消息的全部附件，旧版 images 字段中的图片排在前面
+pub fn message_attachments(message: &AiChatMessage) -> Vec<Attachment> {
+    let images = message.images.iter().flatten().map(|image| Attachment::from_image(image));
+    images.chain(message.attachments.iter().flatten().cloned()).collect()
+}
+
+// 把不能直接发送的附件转成文字，追加在消息正文后面
+pub fn content_with_text_blocks<'a>(content: &str, attachments: impl IntoIterator<Item = &'a Attachment>) -> String {
+    let mut text = content.to_string();
+    for attachment in attachments {
+        if !text.trim().is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(&attachment.as_text_block());
+    }
+    text
+}
+
+// ===== PDF 文字提取 =====
+// 只处理内容流中的文本操作符（Tj、TJ、'、"），支持 FlateDecode 压缩；
+// 使用 CID 字体的 PDF（常见于中文文档）无法直接还原文字，此时返回空字符串
+
+pub fn extract_pdf_text(pdf: &[u8]) -> String {
+    let mut text = String::new();
+    let mut decoded_total = 0;
+    let mut position = 0;
+    while let Some(start) = find(pdf, b"stream", position) {
+        // 字典只在上一个流结束之后查找，整个文件只向前扫描一遍
+        let segment_start = position;
+        position = start + b"stream".len();
+        // 跳过 endstream 中的 stream
+        if start >= 3 && &pdf[start - 3..start] == b"end" {
+            continue;
+        }
+        let mut data_start = position;
+        if pdf.get(data_start) == Some(&b'\r') {
+            data_start += 1;
+        }
+        if pdf.get(data_start) == Some(&b'\n') {
+            data_start += 1;
+        }
+        let Some(end) = find(pdf, b"endstream", data_start) else {
+            break;
+        };
+        // 流的字典位于 "N 0 obj" 和 stream 之间
+        let dictionary_start = pdf[segment_start..start].windows(3)
+            .rposition(|window| window == b"obj")
+            .map_or(segment_start, |offset| segment_start + offset);
+        let dictionary = &pdf[dictionary_start..start];
+        let raw = &pdf[data_start..end];
+        position = end + b"endstream".len();
+
+        let content = if find(dictionary, b"/FlateDecode", 0).is_some() {
+            let mut decoded = Vec::new();
+            if ZlibDecoder::new(raw).take(MAX_PDF_STREAM_BYTES).read_to_end(&mut decoded).is_err() && decoded.is_empty() {
+                continue;
+            }
+            decoded_total += decoded.len();
+            decoded
+        } else {
+            raw.to_vec()
+        };
+        if find(&content, b"BT", 0).is_some() {
+            extract_content_text(&content, &mut text);
+        }
+        if text.len() >= MAX_PDF_TEXT_BYTES || decoded_total >= MAX_PDF_DECODED_BYTES {
+            break;
+        }
+    }
+
+    // 合并多余的空行
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n")
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|index| index + from)
+}
+
+// 扫描内容流，收集文本操作符之前的字符串
+fn extract_content_text(content: &[u8], out: &mut String) {
+    let mut pending: Vec<String> = Vec::new();
+    let mut numbers: Vec<f64> = Vec::new();
+    let mut in_array = false;
+    let mut i = 0;
+    while i < content.len() {
+        match content[i] {
+            b'(' => {
+                let (bytes, next) = read_literal_string(content, i + 1);
+                pending.push(decode_pdf_string(&bytes));
+                i = next;
+            }
+            b'<' if content.get(i + 1) == Some(&b'<') => i += 2,
+            b'<' => {
+                let end = content[i..].iter().position(|&b| b == b'>').map_or(content.len(), |offset| i + offset);
+                let hex: Vec<u8> = content[i + 1..end].iter().copied().filter(u8::is_ascii_hexdigit).collect();
+                let bytes: Vec<u8> = hex.chunks(2)
+                    .filter_map(|pair| u8::from_str_radix(&String::from_utf8_lossy(pair), 16).ok())
+                    .collect();
+                // 十六进制字符串多为 CID 编码，只保留能读懂的部分
+                let decoded = decode_pdf_string(&bytes);
+                if decoded.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+                    pending.push(decoded);
+                }
+                i = end + 1;
+            }
+            b'[' => {
+                in_array = true;
+                i += 1;
+            }
+            b']' => {
+                in_array = false;
+                i += 1;
+            }
+            b'%' => {
+                while i < content.len() && content[i] != b'\n' && content[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'-' | b'+' | b'.' | b'0'..=b'9' => {
+                let start = i;
+                i += 1;
+                while i < content.len() && (content[i].is_ascii_digit() || content[i] == b'.') {
+                    i += 1;
+                }
+                let number: f64 = String::from_utf8_lossy(&content[start..i]).parse().unwrap_or(0.0);
+                // TJ 数组中较大的负间距通常是单词之间的空格
+                if in_array && number <= -200.0 {
+                    pending.push(" ".to_string());
+                }
+                numbers.push(number);
+            }
+            b if b.is_ascii_alphabetic() || b == b'\'' || b == b'"' || b == b'*' => {
+                let start = i;
+                i += 1;
+                while i < content.len() && (content[i].is_ascii_alphabetic() || content[i] == b'*') {
+                    i += 1;
+                }
+                match &content[start..i] {
+                    b"Tj" | b"TJ" => out.push_str(&pending.concat()),
+                    b"'" | b"\"" => {
+                        out.push('\n');
+                        out.push_str(&pending.concat());
+                    }
+                    b"T*" | b"ET" => out.push('\n'),
+                    b"Td" | b"TD" if numbers.len() >= 2 && numbers[numbers.len() - 1] != 0.0 => out.push('\n'),
+                    b"Td" | b"TD" => out.push(' '),
+                    _ => {}
+                }
+                pending.clear();
+                numbers.clear();
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+// 读取括号字符串，处理嵌套括号和转义，返回内容和结束后的位置
+fn read_literal_string(content: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut bytes = Vec::new();
+    let mut depth = 1;
+    while i < content.len() {
+        let b = content[i];
+        i += 1;
+        match b {
+            b'\\' => {
+                let Some(&escaped) = content.get(i) else {
+                    break;
+                };
+                i += 1;
+                match escaped {
+                    b'n' => bytes.push(b'\n'),
+                    b'r' => bytes.push(b'\r'),
+                    b't' => bytes.push(b'\t'),
+                    b'b' => bytes.push(0x08),
+                    b'f' => bytes.push(0x0c),
+                    b'0'..=b'7' => {
+                        let mut value = (escaped - b'0') as u32;
+                        for _ in 0..2 {
+                            match content.get(i) {
+                                Some(&digit @ b'0'..=b'7') => {
+                                    value = value * 8 + (digit - b'0') as u32;
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        bytes.push(value as u8);
+                    }
+                    // 行尾的反斜杠表示续行
+                    b'\r' | b'\n' => {}
+                    other => bytes.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                bytes.push(b);
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+                bytes.push(b);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    (bytes, i)
+}
+
+// 以 FE FF 开头的是 UTF-16BE，其余按单字节编码处理
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect();
+        return String::from_utf16_lossy(&units);
+    }
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn pdf_with_streams(streams: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        for (index, (filter, data)) in streams.iter().enumerate() {
+            pdf.extend_from_slice(format!("{} 0 obj\n<< /Length {}{} >>\nstream\n", index + 1, data.len(), filter).as_bytes());
+            pdf.extend_from_slice(data);
+            pdf.extend_from_slice(b"\nendstream\nendobj\n");
+        }
+        pdf.extend_from_slice(b"%%EOF");
+        pdf
+    }
+
+    #[test]
+    fn test_extract_pdf_text() {
+        let plain = b"BT /F1 12 Tf 72 700 Td (Hello \\(PDF\\)) Tj 0 -14 Td [(Wor) 20 (ld) -300 (again)] TJ ET".to_vec();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"BT (Second page) Tj T* <FEFF4E2D6587> Tj ET").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let pdf = pdf_with_streams(&[("", plain), (" /Filter /FlateDecode", compressed)]);
+        assert_eq!(extract_pdf_text(&pdf), "Hello (PDF)\nWorld again\nSecond page\n中文");
+        assert_eq!(extract_pdf_text(b"not a pdf"), "");
+    }
+
+    #[test]
+    fn test_extract_pdf_text_limits() {
+        // 解压后远超上限的流只读取前 MAX_PDF_STREAM_BYTES 字节
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(b"BT (bomb) Tj ET ").unwrap();
+        let padding = vec![b' '; 1024 * 1024];
+        for _ in 0..(2 * MAX_PDF_STREAM_BYTES as usize / padding.len()) {
+            encoder.write_all(&padding).unwrap();
+        }
+        let bomb = pdf_with_streams(&[(" /Filter /FlateDecode", encoder.finish().unwrap())]);
+        assert_eq!(extract_pdf_text(&bomb), "bomb");
+
+        // 文字超过预算后不再处理后面的流
+        let line = format!("BT ({}) Tj ET", "a".repeat(1000));
+        let streams: Vec<(&str, Vec<u8>)> = (0..MAX_PDF_TEXT_BYTES / 1000 + 10).map(|_| ("", line.clone().into_bytes())).collect();
+        let text = extract_pdf_text(&pdf_with_streams(&streams));
+        assert!(text.len() <= MAX_PDF_TEXT_BYTES + 1001);
+        assert!(text.len() >= MAX_PDF_TEXT_BYTES);
+    }
+
+    #[test]
+    fn test_attachment_text_blocks() {
+        let pdf = pdf_with_streams(&[("", b"BT (Quarterly report) Tj ET".to_vec())]);
+        let attachments = vec![
+            Attachment { kind: AttachmentKind::Pdf, name: "report.pdf".to_string(), mime_type: String::new(), data: general_purpose::STANDARD.encode(&pdf) },
+            Attachment { kind: AttachmentKind::Text, name: "notes.txt".to_string(), mime_type: String::new(), data: "data:text/plain;base64,5L2g5aW9".to_string() },
+            Attachment::from_image("data:image/png;base64,AAAA"),
+        ];
+        assert_eq!(attachments[2].media_type(), "image/png");
+        assert_eq!(attachments[2].base64_data(), "AAAA");
+        assert_eq!(attachments[0].data_url().split(',').next(), Some("data:application/pdf;base64"));
+
+        let content = content_with_text_blocks("总结一下", &attachments);
+        assert_eq!(content, "总结一下\n\n[附件：report.pdf]\nQuarterly report\n\n[附件：notes.txt]\n你好\n\n[附件：图片（当前模型不支持图片，已省略）]");
+    }
+}
@@ -1,19 +1,51 @@
+use crate::ai_attachments::{self, Attachment};
+use crate::ai_keys;
+use crate::ai_policy::{self, PolicyRun, RequestMetadata, RetryPolicy};
+use crate::ai_providers::{self, ChatRequest, ToolCall};
+use crate::ai_summary;
+use crate::ai_tools::{self, AgentReply, EventToolHost, ToolCallRecord, ToolHost};
+use crate::ai_usage::TokenUsage;
+use crate::database::{AiAgent, AiConversation, AiMessage, Database};
+use futures_util::future::{AbortHandle, AbortRegistration, Abortable, Aborted};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use reqwest;
-use futures_util::StreamExt;
-use tauri::{AppHandle, Emitter};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, State};
+
+lazy_static! {
+    // 进行中的流式请求，按 request_id 保存登记序号和中止句柄
+    static ref ACTIVE_STREAMS: Mutex<HashMap<String, (u64, AbortHandle)>> = Mutex::new(HashMap::new());
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// 区分同一 request_id 的先后两次登记
+static NEXT_STREAM_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AiChatMessage {
-    pub role: String,
+    pub role: String,  // 'system' | 'user' | 'assistant' | 'tool'
     pub content: String,
-    pub images: Option<Vec<String>>,
+    pub images: Option<Vec<String>>,  // 旧版图片字段，等同于 image 类型的附件
+    // assistant 消息中模型发起的工具调用
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // tool 消息对应的工具调用 ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    // 图片、PDF 和文本文件附件，按提供商的能力转换
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AiChatRequest {
     pub provider: String,
     pub base_url: String,
+    // 留空时使用该提供商已保存的密钥
+    #[serde(default)]
     pub api_key: String,
     pub model: String,
     pub messages: Vec<AiChatMessage>,
@@ -26,6 +58,14 @@ pub struct AiChatResponse {
     pub success: bool,
     pub content: Option<String>,
     pub message: Option<String>,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    // 实际使用的提供商，以及重试和回退情况
+    #[serde(default)]
+    pub metadata: Option<RequestMetadata>,
+    // 智能体对话中执行的工具调用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallRecord>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,6 +74,15 @@ pub struct AiStreamChunk {
     pub content: String,
     pub finished: bool,
     pub error: Option<String>,
+    // 用户主动停止时的结束事件
+    #[serde(default)]
+    pub cancelled: bool,
+    // 结束事件附带本次请求的 token 用量
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    // 重试或换用其他提供商时发送不带内容的分块，结束事件附带完整记录
+    #[serde(default)]
+    pub metadata: Option<RequestMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,105 +90,355 @@ pub struct AiStreamRequest {
     pub request_id: String,
     pub provider: String,
     pub base_url: String,
+    // 留空时使用该提供商已保存的密钥
+    #[serde(default)]
     pub api_key: String,
     pub model: String,
     pub messages: Vec<AiChatMessage>,
     pub temperature: f32,
     pub max_tokens: u32,
+    // 指定后由后端保存本轮的用户消息和助手回复，对话不存在时自动创建
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    // 与前端消息 ID 保持一致，留空时自动生成
+    #[serde(default)]
+    pub user_message_id: Option<String>,
+    #[serde(default)]
+    pub assistant_message_id: Option<String>,
+    // 用于按智能体统计用量
+    #[serde(default)]
+    pub agent_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ClaudeResponse {
-    content: Option<Vec<ClaudeContent>>,
-    #[allow(dead_code)]
-    id: Option<String>,
-}
+// 流式回复的部分内容至少每隔这么久写入一次数据库
+const TRANSCRIPT_SAVE_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Debug, Deserialize)]
-struct ClaudeContent {
-    #[serde(rename = "type")]
-    content_type: String,
-    text: String,
+// 对话标题的最大字符数
+const CONVERSATION_TITLE_CHARS: usize = 30;
+
+// 保存一轮对话所需的信息
+struct TurnInfo<'r> {
+    provider: &'r str,
+    model: &'r str,
+    messages: &'r [AiChatMessage],
+    user_message_id: Option<&'r str>,
+    assistant_message_id: Option<&'r str>,
+    agent_id: Option<&'r str>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ClaudeStreamResponse {
-    #[serde(rename = "type")]
-    type_: String,
-    delta: Option<ClaudeStreamDelta>,
+impl AiStreamRequest {
+    fn turn_info(&self) -> TurnInfo<'_> {
+        TurnInfo {
+            provider: &self.provider,
+            model: &self.model,
+            messages: &self.messages,
+            user_message_id: self.user_message_id.as_deref(),
+            assistant_message_id: self.assistant_message_id.as_deref(),
+            agent_id: self.agent_id.as_deref(),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct ClaudeStreamDelta {
-    text: Option<String>,
+// 请求带 conversation_id 时，在后端保存本轮对话，窗口中途关闭也不会丢失已收到的内容
+struct StreamTranscript<'a> {
+    database: &'a Database,
+    message: AiMessage,  // 助手消息
+    last_saved: Instant,
 }
 
-// 从 base64 数据URL中提取MIME类型
-fn get_image_mime_type(data_url: &str) -> String {
-    if data_url.starts_with("data:") {
-        if let Some(end) = data_url.find(";base64,") {
-            let mime_part = &data_url[5..end]; // 跳过 "data:" 部分
-            return mime_part.to_string();
+impl<'a> StreamTranscript<'a> {
+    fn begin(database: &'a Database, turn: &TurnInfo, conversation_id: &str) -> Result<Self, String> {
+        let now = chrono::Utc::now();
+        let created_at = now.to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let timestamp = now.timestamp_millis();
+        let user_message = turn.messages.iter().rev().find(|message| message.role == "user");
+
+        let title = user_message
+            .map(|message| message.content.lines().next().unwrap_or("").trim().chars().take(CONVERSATION_TITLE_CHARS).collect::<String>())
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| "新对话".to_string());
+        database.ensure_ai_conversation(&AiConversation {
+            id: conversation_id.to_string(),
+            title,
+            provider: turn.provider.to_string(),
+            model: turn.model.to_string(),
+            created_at: created_at.clone(),
+            updated_at: created_at.clone(),
+        }).map_err(|e| format!("保存对话失败: {}", e))?;
+
+        let new_message = |id: Option<&str>, role: &str, content: String, timestamp: i64| AiMessage {
+            id: id.map(str::to_string).unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            conversation_id: conversation_id.to_string(),
+            role: role.to_string(),
+            content,
+            provider: Some(turn.provider.to_string()),
+            model: Some(turn.model.to_string()),
+            error: false,
+            timestamp,
+            created_at: created_at.clone(),
+            usage: None,
+            agent_id: turn.agent_id.map(str::to_string),
+            attachments: Vec::new(),
+        };
+
+        if let Some(user_message) = user_message {
+            // 附件随用户消息保存，重新加载对话后可以再次发送
+            let message = AiMessage {
+                attachments: ai_attachments::message_attachments(user_message),
+                ..new_message(turn.user_message_id, "user", user_message.content.clone(), timestamp)
+            };
+            database.save_ai_message(&message).map_err(|e| format!("保存消息失败: {}", e))?;
+        }
+
+        // 助手消息排在用户消息之后
+        let message = new_message(turn.assistant_message_id, "assistant", String::new(), timestamp + 1);
+        database.save_ai_message(&message).map_err(|e| format!("保存消息失败: {}", e))?;
+
+        Ok(Self { database, message, last_saved: Instant::now() })
+    }
+
+    // 回退到其他提供商时按实际使用的提供商和模型记录
+    fn served_by(&mut self, metadata: &RequestMetadata) {
+        self.message.provider = Some(metadata.provider.clone());
+        self.message.model = Some(metadata.model.clone());
+    }
+
+    fn push(&mut self, delta: &str) {
+        self.message.content.push_str(delta);
+        if self.last_saved.elapsed() >= TRANSCRIPT_SAVE_INTERVAL {
+            self.save();
+        }
+    }
+
+    // 出错时标记 error，没有收到任何内容时保存错误信息
+    fn finish(mut self, error: Option<&str>, usage: Option<TokenUsage>) {
+        self.message.usage = usage;
+        if let Some(error) = error {
+            self.message.error = true;
+            if self.message.content.is_empty() {
+                self.message.content = error.to_string();
+            }
+        }
+        self.save();
+    }
+
+    fn save(&mut self) {
+        if let Err(e) = self.database.save_ai_message(&self.message) {
+            eprintln!("保存流式回复失败: {}", e);
         }
+        self.last_saved = Instant::now();
+    }
+}
+
+// 本地服务等不需要密钥的后端允许密钥为空
+pub fn resolve_request_key(database: &Database, provider: &str, request_key: &str, required: bool) -> Result<String, String> {
+    if required {
+        ai_keys::resolve_api_key(database, provider, request_key)
+    } else {
+        Ok(ai_keys::resolve_api_key(database, provider, request_key).unwrap_or_default())
     }
-    "image/jpeg".to_string() // 默认类型
 }
 
-// 从 base64 数据URL中提取纯base64数据
-fn extract_base64_data(data_url: &str) -> String {
-    if let Some(start) = data_url.find(";base64,") {
-        return data_url[start + 8..].to_string(); // 跳过 ";base64," 部分
+#[tauri::command]
+pub async fn send_ai_chat(
+    database: State<'_, Arc<Database>>,
+    request: AiChatRequest
+) -> Result<AiChatResponse, String> {
+    run_chat(&database, request).await
+}
+
+// 一次性对话，供其他命令复用；接口错误放在响应的 message 中
+pub async fn run_chat(database: &Database, request: AiChatRequest) -> Result<AiChatResponse, String> {
+    let start_time = std::time::Instant::now();
+    let backend = ai_providers::resolve(&request.provider);
+    let api_key = resolve_request_key(database, &request.provider, &request.api_key, backend.requires_api_key())?;
+
+    let chat_request = ChatRequest {
+        base_url: request.base_url,
+        api_key,
+        model: request.model,
+        messages: request.messages,
+        temperature: request.temperature,
+        max_tokens: request.max_tokens,
+        tools: Vec::new(),
+    };
+
+    let (result, metadata) = ai_policy::chat(database, &request.provider, chat_request, RetryPolicy::default()).await;
+    match result {
+        Ok(reply) => Ok(AiChatResponse {
+            success: true,
+            content: Some(reply.content),
+            message: Some(format!("响应成功，延迟: {}ms", start_time.elapsed().as_millis())),
+            usage: reply.usage,
+            metadata: Some(metadata),
+            tool_calls: Vec::new(),
+        }),
+        Err(e) => Ok(AiChatResponse {
+            success: false,
+            content: None,
+            message: Some(e),
+            usage: None,
+            metadata: Some(metadata),
+            tool_calls: Vec::new(),
+        }),
     }
-    data_url.to_string() // 如果不是标准格式，返回原始数据
 }
 
-#[derive(Debug, Deserialize)]
-struct DeepSeekResponse {
-    choices: Option<Vec<DeepSeekChoice>>,
-    #[allow(dead_code)]
-    id: Option<String>,
+// 智能体对话实际使用的提供商、模型和采样参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgentChatConfig {
+    pub provider: String,
+    pub base_url: String,
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub system_prompt: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct DeepSeekChoice {
-    message: Option<DeepSeekMessage>,
-    delta: Option<DeepSeekDelta>,
-    #[allow(dead_code)]
-    finish_reason: Option<String>,
+// 智能体未绑定提供商时使用当前提供商，未绑定模型时使用该提供商保存的模型
+pub fn resolve_agent_config(database: &Database, agent: &AiAgent) -> Result<AgentChatConfig, String> {
+    let bound_provider = agent.provider.as_deref().map(str::trim).filter(|provider| !provider.is_empty());
+    let provider = match bound_provider {
+        Some(provider) => provider.to_string(),
+        None => database.get_ai_providers().map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|provider| provider.is_current == 1)
+            .map(|provider| provider.provider)
+            .ok_or_else(|| "尚未选择 AI 提供商".to_string())?,
+    };
+    let saved = database.get_ai_provider(&provider).map_err(|e| e.to_string())?;
+
+    let model = agent.model.as_deref().map(str::trim).filter(|model| !model.is_empty()).map(str::to_string)
+        .or_else(|| saved.as_ref().map(|saved| saved.model.clone()).filter(|model| !model.is_empty()))
+        .ok_or_else(|| format!("智能体「{}」未指定模型，且 {} 尚未配置模型", agent.name, provider))?;
+    let system_prompt = Some(agent.system_prompt.trim().to_string())
+        .filter(|prompt| !prompt.is_empty())
+        .or_else(|| saved.as_ref().and_then(|saved| saved.system_prompt.clone()).filter(|prompt| !prompt.trim().is_empty()));
+
+    Ok(AgentChatConfig {
+        base_url: saved.and_then(|saved| saved.base_url).unwrap_or_default(),
+        provider,
+        model,
+        temperature: agent.temperature as f32,
+        max_tokens: agent.max_tokens.max(1) as u32,
+        system_prompt,
+    })
 }
 
-#[derive(Debug, Deserialize)]
-struct DeepSeekMessage {
-    #[allow(dead_code)]
-    role: String,
-    content: String,
+// 把系统提示放在最前面；前端已带上相同的系统提示时不重复添加。
+// Anthropic 后端会把 system 消息移到请求的 system 字段
+fn with_system_prompt(messages: Vec<AiChatMessage>, system_prompt: Option<&str>) -> Vec<AiChatMessage> {
+    let Some(prompt) = system_prompt else {
+        return messages;
+    };
+    let mut messages: Vec<AiChatMessage> = messages.into_iter()
+        .filter(|message| !(message.role == "system" && message.content.trim() == prompt.trim()))
+        .collect();
+    messages.insert(0, AiChatMessage { role: "system".to_string(), content: prompt.to_string(), ..Default::default() });
+    messages
 }
 
-#[derive(Debug, Deserialize)]
-struct DeepSeekDelta {
-    #[allow(dead_code)]
-    role: Option<String>,
-    content: Option<String>,
+// 按智能体的配置对话，指定 conversation_id 时保存本轮的用户消息和回复。
+// 提供商、模型、采样参数和系统提示以智能体为准；智能体启用了工具时进入工具调用循环
+pub async fn run_agent_chat(
+    database: &Database,
+    agent_id: &str,
+    conversation_id: Option<&str>,
+    messages: Vec<AiChatMessage>,
+    host: &dyn ToolHost
+) -> Result<AiChatResponse, String> {
+    let start_time = Instant::now();
+    let agent = database.get_ai_agent(agent_id).map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("智能体不存在: {}", agent_id))?;
+    let config = resolve_agent_config(database, &agent)?;
+    let backend = ai_providers::resolve(&config.provider);
+    let api_key = resolve_request_key(database, &config.provider, "", backend.requires_api_key())?;
+
+    let transcript = match conversation_id {
+        Some(conversation_id) => Some(StreamTranscript::begin(database, &TurnInfo {
+            provider: &config.provider,
+            model: &config.model,
+            messages: &messages,
+            user_message_id: None,
+            assistant_message_id: None,
+            agent_id: Some(agent_id),
+        }, conversation_id)?),
+        None => None,
+    };
+
+    // 对话已有摘要时只发送摘要和之后的消息
+    let messages = match conversation_id {
+        Some(conversation_id) => ai_summary::compact_history(database, conversation_id, messages),
+        None => messages,
+    };
+    let chat_request = ChatRequest {
+        base_url: config.base_url,
+        api_key,
+        model: config.model,
+        messages: with_system_prompt(messages, config.system_prompt.as_deref()),
+        temperature: config.temperature,
+        max_tokens: config.max_tokens,
+        tools: Vec::new(),
+    };
+
+    let (result, metadata) = if agent.tools.is_empty() {
+        let (result, metadata) = ai_policy::chat(database, &config.provider, chat_request, RetryPolicy::default()).await;
+        (result.map(|reply| AgentReply { content: reply.content, usage: reply.usage, tool_calls: Vec::new() }), metadata)
+    } else {
+        ai_tools::run_tool_loop(database, &config.provider, chat_request, &agent.tools, host).await
+    };
+    if let Some(mut transcript) = transcript {
+        if metadata.fallback {
+            transcript.served_by(&metadata);
+        }
+        match &result {
+            Ok(reply) => {
+                transcript.push(&reply.content);
+                transcript.finish(None, reply.usage);
+            }
+            Err(e) => transcript.finish(Some(e.as_str()), None),
+        }
+    }
+
+    Ok(match result {
+        Ok(reply) => AiChatResponse {
+            success: true,
+            content: Some(reply.content),
+            message: Some(format!("响应成功，延迟: {}ms", start_time.elapsed().as_millis())),
+            usage: reply.usage,
+            metadata: Some(metadata),
+            tool_calls: reply.tool_calls,
+        },
+        Err(e) => AiChatResponse { success: false, content: None, message: Some(e), usage: None, metadata: Some(metadata), tool_calls: Vec::new() },
+    })
 }
 
 #[tauri::command]
-pub async fn send_ai_chat(request: AiChatRequest) -> Result<AiChatResponse, String> {
-    let start_time = std::time::Instant::now();
-    
-    match request.provider.as_str() {
-        "claude" => send_claude_chat(request, start_time).await,
-        "deepseek" => send_deepseek_chat(request, start_time).await,
-        _ => Ok(AiChatResponse {
-            success: false,
-            content: None,
-            message: Some("不支持的 AI 提供商".to_string()),
-        })
+pub async fn send_agent_chat(
+    app_handle: AppHandle,
+    database: State<'_, Arc<Database>>,
+    agent_id: String,
+    conversation_id: Option<String>,
+    messages: Vec<AiChatMessage>
+) -> Result<AiChatResponse, String> {
+    // 写操作的确认和工具结果通过事件发给前端
+    let host = EventToolHost::new(app_handle.clone(), uuid::Uuid::new_v4().to_string());
+    let response = run_agent_chat(&database, &agent_id, conversation_id.as_deref(), messages, &host).await?;
+    if let Some(conversation_id) = conversation_id.filter(|_| response.success) {
+        ai_summary::spawn_maintenance(app_handle, database.inner().clone(), conversation_id);
     }
+    Ok(response)
 }
 
 #[tauri::command]
-pub async fn send_ai_chat_stream(app_handle: AppHandle, request: AiStreamRequest) -> Result<(), String> {
+pub async fn send_ai_chat_stream(
+    app_handle: AppHandle,
+    database: State<'_, Arc<Database>>,
+    request: AiStreamRequest
+) -> Result<(), String> {
     let request_id = request.request_id.clone();
+    // 进入命令即登记，解析密钥等准备阶段收到的停止请求同样生效
+    let registration = register_stream(&request_id)?;
 
     println!("🚀 [Tauri命令] send_ai_chat_stream 开始执行");
     println!("   request_id: {}", request_id);
@@ -150,610 +449,453 @@ pub async fn send_ai_chat_stream(app_handle: AppHandle, request: AiStreamRequest
     println!("   temperature: {}", request.temperature);
     println!("   max_tokens: {}", request.max_tokens);
 
-    match request.provider.as_str() {
-        "deepseek" => {
-            if let Err(e) = send_deepseek_chat_stream(app_handle.clone(), request).await {
-                // 发送错误事件
-                let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                    request_id,
-                    content: String::new(),
-                    finished: true,
-                    error: Some(e),
-                });
-            }
-        },
-        "claude" => {
-            if let Err(e) = send_claude_chat_stream(app_handle.clone(), request).await {
-                // 发送错误事件
-                let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                    request_id,
-                    content: String::new(),
-                    finished: true,
-                    error: Some(e),
-                });
+    let emit_delta = |content: String, metadata: Option<RequestMetadata>| {
+        let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
+            request_id: request_id.clone(),
+            content,
+            finished: false,
+            error: None,
+            cancelled: false,
+            usage: None,
+            metadata,
+        });
+    };
+    let emit_end = |error: Option<String>, cancelled: bool, usage: Option<TokenUsage>, metadata: Option<RequestMetadata>| {
+        let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
+            request_id: request_id.clone(),
+            content: String::new(),
+            finished: true,
+            error,
+            cancelled,
+            usage,
+            metadata,
+        });
+    };
+
+    let backend = ai_providers::resolve(&request.provider);
+    let api_key = match resolve_request_key(&database, &request.provider, &request.api_key, backend.requires_api_key()) {
+        Ok(api_key) => api_key,
+        Err(e) => {
+            emit_end(Some(e), false, None, None);
+            return Ok(());
+        }
+    };
+
+    let mut transcript = match request.conversation_id.as_deref() {
+        Some(conversation_id) => match StreamTranscript::begin(&database, &request.turn_info(), conversation_id) {
+            Ok(transcript) => Some(transcript),
+            Err(e) => {
+                emit_end(Some(e), false, None, None);
+                return Ok(());
             }
         },
-        _ => {
-            let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                request_id,
-                content: String::new(),
-                finished: true,
-                error: Some("不支持的 AI 提供商".to_string()),
-            });
+        None => None,
+    };
+
+    // 对话已有摘要时只发送摘要和之后的消息
+    let messages = match request.conversation_id.as_deref() {
+        Some(conversation_id) => ai_summary::compact_history(&database, conversation_id, request.messages),
+        None => request.messages,
+    };
+    let chat_request = ChatRequest {
+        base_url: request.base_url,
+        api_key,
+        model: request.model,
+        messages,
+        temperature: request.temperature,
+        max_tokens: request.max_tokens,
+        tools: Vec::new(),
+    };
+
+    // 已经输出内容后不再重试，避免回复重复
+    let streamed = AtomicBool::new(false);
+    let mut on_delta = |content: String| {
+        streamed.store(true, Ordering::Relaxed);
+        if let Some(transcript) = transcript.as_mut() {
+            transcript.push(&content);
         }
-    }
-    
-    Ok(())
-}
-
-async fn send_claude_chat(request: AiChatRequest, start_time: std::time::Instant) -> Result<AiChatResponse, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
-    let url = format!("{}/v1/messages", request.base_url);
-    
-    // 构建 Claude API 请求
-    let claude_messages: Vec<serde_json::Value> = request.messages
-        .iter()
-        .map(|msg| {
-            if let Some(images) = &msg.images {
-                if !images.is_empty() {
-                    // 构建多模态内容
-                    let mut content_array: Vec<serde_json::Value> = images
-                        .iter()
-                        .map(|image| serde_json::json!({
-                            "type": "image",
-                            "source": {
-                                "type": "base64",
-                                "media_type": get_image_mime_type(image),
-                                "data": extract_base64_data(image)
-                            }
-                        }))
-                        .collect();
-                    
-                    // 如果有文字内容，添加到数组中
-                    if !msg.content.trim().is_empty() {
-                        content_array.push(serde_json::json!({
-                            "type": "text",
-                            "text": msg.content
-                        }));
-                    }
-                    
-                    serde_json::json!({
-                        "role": msg.role,
-                        "content": content_array
-                    })
-                } else {
-                    serde_json::json!({
-                        "role": msg.role,
-                        "content": msg.content
-                    })
+        emit_delta(content, None);
+    };
+    let attempts = async {
+        let mut run = PolicyRun::new(&database, &request.provider, chat_request, RetryPolicy::default());
+        while let Some(candidate) = run.next_attempt().await {
+            match candidate.backend.stream(&candidate.request, &mut on_delta).await {
+                Ok(usage) => {
+                    run.succeeded();
+                    return (Ok(usage), run.metadata);
                 }
-            } else {
-                serde_json::json!({
-                    "role": msg.role,
-                    "content": msg.content
-                })
-            }
-        })
-        .collect();
-    
-    let claude_request = serde_json::json!({
-        "model": request.model,
-        "max_tokens": request.max_tokens,
-        "messages": claude_messages,
-        "temperature": request.temperature
-    });
-    
-    match client
-        .post(&url)
-        .json(&claude_request)
-        .header("Content-Type", "application/json")
-        .header("x-api-key", &request.api_key)
-        .header("anthropic-version", "2023-06-01")
-        .send()
-        .await
-    {
-        Ok(response) => {
-            let latency = start_time.elapsed().as_millis() as u64;
-            let status = response.status();
-            
-            if status.is_success() {
-                match response.json::<ClaudeResponse>().await {
-                    Ok(claude_response) => {
-                        if let Some(content_array) = claude_response.content {
-                            if let Some(first_content) = content_array.first() {
-                                if first_content.content_type == "text" {
-                                    return Ok(AiChatResponse {
-                                        success: true,
-                                        content: Some(first_content.text.clone()),
-                                        message: Some(format!("响应成功，延迟: {}ms", latency)),
-                                    });
-                                }
-                            }
-                        }
-                        
-                        Ok(AiChatResponse {
-                            success: false,
-                            content: None,
-                            message: Some("响应格式异常".to_string()),
-                        })
+                Err(e) => {
+                    run.failed(e, !streamed.load(Ordering::Relaxed));
+                    // 通知前端正在重试或换用其他提供商
+                    if run.has_next() {
+                        emit_delta(String::new(), Some(run.metadata.clone()));
                     }
-                    Err(e) => Ok(AiChatResponse {
-                        success: false,
-                        content: None,
-                        message: Some(format!("响应解析失败: {}", e)),
-                    })
                 }
-            } else {
-                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                Ok(AiChatResponse {
-                    success: false,
-                    content: None,
-                    message: Some(format!("HTTP {}: {}", status, error_text)),
-                })
             }
         }
-        Err(e) => {
-            let error_message = if e.is_timeout() {
-                "请求超时，请检查网络连接".to_string()
-            } else if e.is_connect() {
-                "连接失败，请检查 API 地址".to_string()
-            } else {
-                format!("网络错误: {}", e)
-            };
-            
-            Ok(AiChatResponse {
-                success: false,
-                content: None,
-                message: Some(error_message),
-            })
+        let metadata = run.metadata.clone();
+        (Err(run.into_error()), metadata)
+    };
+    // 中止时上游响应随 future 一起释放，连接随之关闭；已收到的内容照常保存
+    let (error, cancelled, usage, metadata) = match run_cancellable(registration, attempts).await {
+        Ok((Ok(usage), metadata)) => (None, false, usage, Some(metadata)),
+        Ok((Err(e), metadata)) => (Some(e), false, None, Some(metadata)),
+        Err(Aborted) => (None, true, None, None),
+    };
+
+    // 先保存再发送结束事件，前端收到结束事件后即可读到完整回复
+    if let Some(mut transcript) = transcript {
+        if let Some(metadata) = metadata.as_ref().filter(|metadata| metadata.fallback) {
+            transcript.served_by(metadata);
         }
+        transcript.finish(error.as_deref(), usage);
     }
+    let completed = error.is_none() && !cancelled;
+    // 无论成功与否都发送结束事件，失败时附带错误信息
+    emit_end(error, cancelled, usage, metadata);
+
+    // 完整收到回复后在后台生成标题、更新摘要
+    if let Some(conversation_id) = request.conversation_id.filter(|_| completed) {
+        ai_summary::spawn_maintenance(app_handle.clone(), database.inner().clone(), conversation_id);
+    }
+
+    Ok(())
 }
 
-async fn send_claude_chat_stream(app_handle: AppHandle, request: AiStreamRequest) -> Result<(), String> {
-    println!("🔵 [Claude] send_claude_chat_stream 开始执行");
-    println!("   request_id: {}", request.request_id);
-    println!("   base_url: {}", request.base_url);
-    println!("   model: {}", request.model);
-    println!("   messages_count: {}", request.messages.len());
-    println!("   max_tokens: {}", request.max_tokens);
-    println!("   temperature: {}", request.temperature);
+// 停止流式请求，请求不存在或已结束时返回 false
+#[tauri::command]
+pub async fn cancel_ai_stream(request_id: String) -> Result<bool, String> {
+    Ok(cancel_stream(&request_id))
+}
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(90))
-        .build()
-        .map_err(|e| {
-            let error = format!("创建HTTP客户端失败: {}", e);
-            println!("❌ [Claude] {}", error);
-            error
-        })?;
-
-    let url = format!("{}/v1/messages", request.base_url);
-    println!("🌐 [Claude] 请求URL: {}", url);
-    let request_id = request.request_id.clone();
-    
-    // 构建 Claude API 请求 - 分离 system 消息
-    let mut system_message: Option<String> = None;
-    let mut claude_messages: Vec<serde_json::Value> = Vec::new();
-
-    for msg in &request.messages {
-        if msg.role == "system" {
-            // 提取 system 消息作为顶级参数
-            system_message = Some(msg.content.clone());
-            println!("🔧 [Claude] 提取system消息: {}", msg.content);
-        } else {
-            // 处理非 system 消息
-            if let Some(images) = &msg.images {
-                if !images.is_empty() {
-                    // 构建多模态内容
-                    let mut content_array: Vec<serde_json::Value> = images
-                        .iter()
-                        .map(|image| serde_json::json!({
-                            "type": "image",
-                            "source": {
-                                "type": "base64",
-                                "media_type": get_image_mime_type(image),
-                                "data": extract_base64_data(image)
-                            }
-                        }))
-                        .collect();
-
-                    // 如果有文字内容，添加到数组中
-                    if !msg.content.trim().is_empty() {
-                        content_array.push(serde_json::json!({
-                            "type": "text",
-                            "text": msg.content
-                        }));
-                    }
+fn cancel_stream(request_id: &str) -> bool {
+    let entry = ACTIVE_STREAMS.lock().ok().and_then(|mut streams| streams.remove(request_id));
+    match entry {
+        Some((_, handle)) => {
+            handle.abort();
+            true
+        }
+        None => false,
+    }
+}
 
-                    claude_messages.push(serde_json::json!({
-                        "role": msg.role,
-                        "content": content_array
-                    }));
-                } else {
-                    claude_messages.push(serde_json::json!({
-                        "role": msg.role,
-                        "content": msg.content
-                    }));
-                }
-            } else {
-                claude_messages.push(serde_json::json!({
-                    "role": msg.role,
-                    "content": msg.content
-                }));
+// 已登记的请求，结束（包括被中止）时从登记表中移除
+pub struct StreamRegistration {
+    request_id: String,
+    token: u64,
+    abort: Option<AbortRegistration>,
+}
+
+impl Drop for StreamRegistration {
+    fn drop(&mut self) {
+        if let Ok(mut streams) = ACTIVE_STREAMS.lock() {
+            // 已被停止的请求可能换了新的登记，只移除自己的
+            if streams.get(&self.request_id).is_some_and(|(token, _)| *token == self.token) {
+                streams.remove(&self.request_id);
             }
         }
     }
+}
 
-    // 构建请求体，system 消息作为顶级参数
-    let mut claude_request = serde_json::json!({
-        "model": request.model,
-        "max_tokens": request.max_tokens,
-        "messages": claude_messages,
-        "temperature": request.temperature,
-        "stream": true
-    });
-
-    // 如果有 system 消息，添加为顶级参数
-    if let Some(system_content) = system_message {
-        claude_request["system"] = serde_json::Value::String(system_content);
-        println!("✅ [Claude] 已将system消息设置为顶级参数");
+// 登记中止句柄，同一 request_id 正在进行时拒绝
+pub fn register_stream(request_id: &str) -> Result<StreamRegistration, String> {
+    let mut streams = ACTIVE_STREAMS.lock().map_err(|_| "请求状态不可用".to_string())?;
+    if streams.contains_key(request_id) {
+        return Err(format!("请求 {} 正在进行中", request_id));
     }
+    let (handle, abort) = AbortHandle::new_pair();
+    let token = NEXT_STREAM_TOKEN.fetch_add(1, Ordering::Relaxed);
+    streams.insert(request_id.to_string(), (token, handle));
+    Ok(StreamRegistration { request_id: request_id.to_string(), token, abort: Some(abort) })
+}
 
-    println!("📝 [Claude] 构建请求体完成");
-    println!("   请求体大小: {} bytes", serde_json::to_string(&claude_request).unwrap_or_default().len());
-    println!("   API密钥前缀: {}...", &request.api_key.chars().take(8).collect::<String>());
-
-    println!("🚀 [Claude] 开始发送HTTP请求");
-    let response = client
-        .post(&url)
-        .json(&claude_request)
-        .header("Content-Type", "application/json")
-        .header("x-api-key", &request.api_key)
-        .header("anthropic-version", "2023-06-01")
-        .send()
-        .await
-        .map_err(|e| {
-            let error = format!("请求发送失败: {}", e);
-            println!("❌ [Claude] {}", error);
-            error
-        })?;
-
-    println!("📡 [Claude] 收到HTTP响应");
-    println!("   状态码: {}", response.status());
-    println!("   响应头: {:?}", response.headers());
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        let error = format!("HTTP {}: {}", status, error_text);
-        println!("❌ [Claude] 请求失败: {}", error);
-        return Err(error);
-    }
+// 执行 future，被 cancel_stream 中止（包括执行前已中止）时返回 Err(Aborted)
+pub async fn run_cancellable<F: Future>(mut registration: StreamRegistration, future: F) -> Result<F::Output, Aborted> {
+    let abort = registration.abort.take().ok_or(Aborted)?;
+    Abortable::new(future, abort).await
+}
 
-    // 处理流式响应
-    println!("📡 [Claude] 开始处理流式响应");
-    let mut stream = response.bytes_stream();
-    let mut buffer = String::new();
-    let mut chunk_count = 0;
-    let mut total_content = String::new();
-
-    while let Some(chunk) = stream.next().await {
-        chunk_count += 1;
-        match chunk {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                println!("📦 [Claude] 收到第{}个数据块, 大小: {} bytes", chunk_count, bytes.len());
-                println!("📦 [Claude] 原始数据内容: {:?}", text);
-                buffer.push_str(&text);
-
-                // 处理完整的行
-                while let Some(line_end) = buffer.find('\n') {
-                    let line = buffer[..line_end].trim().to_string();
-                    buffer.drain(0..=line_end);
-
-                    println!("🔍 [Claude] 处理行: {:?}", line);
-
-                    if line.starts_with("event: error") {
-                        println!("❌ [Claude] 检测到错误事件");
-                        continue;
-                    } else if line.starts_with("data: ") {
-                        let data = &line[6..]; // 移除"data: "前缀
-
-                        // 检查是否是错误数据
-                        if data.contains("\"error\":") {
-                            println!("❌ [Claude] 检测到错误数据: {}", data);
-                            // 解析错误信息并发送给前端
-                            if let Ok(error_data) = serde_json::from_str::<serde_json::Value>(data) {
-                                let error_msg = error_data.get("error")
-                                    .and_then(|e| e.as_str())
-                                    .unwrap_or("Claude API错误");
-                                let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                                    request_id: request_id.clone(),
-                                    content: String::new(),
-                                    finished: true,
-                                    error: Some(error_msg.to_string()),
-                                });
-                                return Ok(());
-                            }
-                        }
-
-                        if data == "[DONE]" {
-                            // 发送完成事件
-                            let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                                request_id: request_id.clone(),
-                                content: String::new(),
-                                finished: true,
-                                error: None,
-                            });
-                            return Ok(());
-                        }
-                        
-                        // 解析JSON数据并发送chunk
-                        if let Ok(chunk_response) = serde_json::from_str::<ClaudeStreamResponse>(data) {
-                            println!("🔄 [Claude] 解析JSON成功, 类型: {}", chunk_response.type_);
-                            if chunk_response.type_ == "content_block_delta" {
-                                if let Some(delta) = chunk_response.delta {
-                                    if let Some(text) = delta.text {
-                                        println!("💬 [Claude] 发送内容块: {:?}", text);
-                                        total_content.push_str(&text);
-                                        // 发送内容chunk
-                                        let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                                            request_id: request_id.clone(),
-                                            content: text,
-                                            finished: false,
-                                            error: None,
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                return Err(format!("流式读取错误: {}", e));
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_providers::ScriptedProvider;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    fn stream_request(conversation_id: &str) -> AiStreamRequest {
+        AiStreamRequest {
+            request_id: "req".to_string(),
+            provider: "ollama".to_string(),
+            base_url: String::new(),
+            api_key: String::new(),
+            model: "qwen".to_string(),
+            messages: vec![
+                AiChatMessage { role: "user".to_string(), content: "第一个问题".to_string(), ..Default::default() },
+                AiChatMessage { role: "assistant".to_string(), content: "回答".to_string(), ..Default::default() },
+                AiChatMessage { role: "user".to_string(), content: "第二个问题\n补充说明".to_string(), ..Default::default() },
+            ],
+            temperature: 0.7,
+            max_tokens: 100,
+            conversation_id: Some(conversation_id.to_string()),
+            user_message_id: Some("user-1".to_string()),
+            assistant_message_id: None,
+            agent_id: Some("writer".to_string()),
         }
     }
-    
-    // 如果没有收到[DONE]信号，发送完成事件
-    let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-        request_id,
-        content: String::new(),
-        finished: true,
-        error: None,
-    });
-    
-    Ok(())
-}
 
-async fn send_deepseek_chat_stream(app_handle: AppHandle, request: AiStreamRequest) -> Result<(), String> {
-    println!("🤖 [DeepSeek] send_deepseek_chat_stream 开始执行");
-    println!("   request_id: {}", request.request_id);
+    fn agent_reply() -> Result<ai_providers::ChatReply, ai_providers::ProviderError> {
+        Ok(ai_providers::ChatReply {
+            content: "好的".to_string(),
+            usage: Some(TokenUsage { input_tokens: 3, output_tokens: 1, ..TokenUsage::default() }),
+            tool_calls: Vec::new(),
+        })
+    }
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(90))
-        .build()
-        .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+    // 拒绝所有写操作确认
+    struct RejectAll;
 
-    let url = format!("{}/chat/completions", request.base_url);
-    let request_id = request.request_id.clone();
+    impl ToolHost for RejectAll {
+        fn confirm<'a>(&'a self, _tool: &'a ai_tools::BuiltinTool, _call: &'a ai_providers::ToolCall) -> futures_util::future::BoxFuture<'a, bool> {
+            Box::pin(async move { false })
+        }
+    }
 
-    println!("🌐 [DeepSeek] API URL: {}", url);
-    
-    // 检查是否有图片 - DeepSeek不支持图片
-    for msg in &request.messages {
-        if let Some(images) = &msg.images {
-            if !images.is_empty() {
-                let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                    request_id: request_id.clone(),
-                    content: String::new(),
-                    finished: true,
-                    error: Some("DeepSeek 模型暂不支持图片输入，请使用 Claude 模型".to_string())
-                });
-                return Ok(());
-            }
+    fn agent(agent_id: &str, provider: Option<&str>, model: Option<&str>) -> AiAgent {
+        AiAgent {
+            id: None,
+            agent_id: agent_id.to_string(),
+            name: agent_id.to_string(),
+            description: String::new(),
+            icon: "🤖".to_string(),
+            system_prompt: "你是写作助手".to_string(),
+            temperature: 0.2,
+            max_tokens: 512,
+            provider: provider.map(str::to_string),
+            model: model.map(str::to_string),
+            is_builtin: 0,
+            is_current: 0,
+            tools: Vec::new(),
+            created_at: String::new(),
+            updated_at: String::new(),
         }
     }
-    
-    // 构建 DeepSeek API 请求
-    let deepseek_messages: Vec<serde_json::Value> = request.messages
-        .iter()
-        .map(|msg| serde_json::json!({
-            "role": msg.role,
-            "content": msg.content
-        }))
-        .collect();
-    
-    let deepseek_request = serde_json::json!({
-        "model": request.model,
-        "messages": deepseek_messages,
-        "temperature": request.temperature,
-        "max_tokens": request.max_tokens,
-        "stream": true
-    });
-    
-    let response = client
-        .post(&url)
-        .json(&deepseek_request)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", request.api_key))
-        .send()
-        .await
-        .map_err(|e| format!("请求发送失败: {}", e))?;
-        
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("HTTP {}: {}", status, error_text));
+
+    #[test]
+    fn test_resolve_agent_config() {
+        let database = Database::open_in_memory().unwrap();
+        database.with_connection(|conn| {
+            conn.execute_batch(
+                "INSERT INTO ai_providers (provider, api_key, base_url, model, system_prompt, is_current)
+                     VALUES ('deepseek', '', 'https://proxy.example.com', 'deepseek-chat', '提供商提示', 1);
+                 INSERT INTO ai_providers (provider, api_key, model, is_current) VALUES ('claude', '', 'claude-sonnet', 0);"
+            )
+        }).unwrap();
+
+        // 未绑定时使用当前提供商及其模型
+        let config = resolve_agent_config(&database, &agent("a", None, None)).unwrap();
+        assert_eq!(config, AgentChatConfig {
+            provider: "deepseek".to_string(),
+            base_url: "https://proxy.example.com".to_string(),
+            model: "deepseek-chat".to_string(),
+            temperature: 0.2,
+            max_tokens: 512,
+            system_prompt: Some("你是写作助手".to_string()),
+        });
+
+        let config = resolve_agent_config(&database, &agent("b", Some("claude"), Some("claude-haiku"))).unwrap();
+        assert_eq!((config.provider.as_str(), config.model.as_str()), ("claude", "claude-haiku"));
+        assert!(resolve_agent_config(&database, &agent("c", Some("openai"), None)).is_err());
+
+        // 前端已带上相同的系统提示时不重复添加
+        let messages = with_system_prompt(vec![
+            AiChatMessage { role: "system".to_string(), content: "你是写作助手".to_string(), ..Default::default() },
+            AiChatMessage { role: "user".to_string(), content: "你好".to_string(), ..Default::default() },
+        ], Some("你是写作助手"));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, "system");
     }
-    
-    // 处理流式响应
-    let mut stream = response.bytes_stream();
-    let mut buffer = String::new();
-    
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                buffer.push_str(&text);
-                
-                // 处理完整的行
-                while let Some(line_end) = buffer.find('\n') {
-                    let line = buffer[..line_end].trim().to_string();
-                    buffer.drain(0..=line_end);
-                    
-                    if line.starts_with("data: ") {
-                        let data = &line[6..]; // 移除"data: "前缀
-                        
-                        if data == "[DONE]" {
-                            // 发送完成事件
-                            let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                                request_id: request_id.clone(),
-                                content: String::new(),
-                                finished: true,
-                                error: None,
-                            });
-                            return Ok(());
-                        }
-                        
-                        // 解析JSON数据并发送chunk
-                        if let Ok(chunk_response) = serde_json::from_str::<DeepSeekResponse>(data) {
-                            if let Some(choices) = chunk_response.choices {
-                                if let Some(choice) = choices.first() {
-                                    if let Some(delta) = &choice.delta {
-                                        if let Some(content) = &delta.content {
-                                            // 发送内容chunk
-                                            let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-                                                request_id: request_id.clone(),
-                                                content: content.clone(),
-                                                finished: false,
-                                                error: None,
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+
+    #[tokio::test]
+    async fn test_agent_chat_applies_agent_settings() {
+        let provider = ScriptedProvider::new(vec![agent_reply(), agent_reply()]);
+        ai_providers::register("agent-test", provider.clone());
+        let database = Database::open_in_memory().unwrap();
+        database.save_ai_agent(&agent("writer", Some("agent-test"), Some("mock-model"))).unwrap();
+
+        let messages = vec![AiChatMessage { role: "user".to_string(), content: "写一句诗".to_string(), ..Default::default() }];
+        let response = run_agent_chat(&database, "writer", Some("conv-agent"), messages.clone(), &RejectAll).await.unwrap();
+        assert!(response.success);
+
+        let request = provider.requests().pop().unwrap();
+        assert_eq!((request.model.as_str(), request.temperature, request.max_tokens), ("mock-model", 0.2, 512));
+        assert_eq!(request.messages[0].content, "你是写作助手");
+        assert!(request.tools.is_empty());
+
+        let saved = database.get_ai_messages("conv-agent").unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[1].content, "好的");
+        assert_eq!(saved[1].agent_id.as_deref(), Some("writer"));
+        assert_eq!(saved[1].usage.map(|usage| usage.input_tokens), Some(3));
+        assert!(run_agent_chat(&database, "missing", None, Vec::new(), &RejectAll).await.is_err());
+
+        // 启用了工具的智能体只向模型提供允许的工具
+        let mut researcher = agent("researcher", Some("agent-test"), Some("mock-model"));
+        researcher.tools = vec!["search_pages".to_string()];
+        database.save_ai_agent(&researcher).unwrap();
+        let response = run_agent_chat(&database, "researcher", None, messages, &RejectAll).await.unwrap();
+        assert_eq!(response.content.as_deref(), Some("好的"));
+        let request = provider.requests().pop().unwrap();
+        let tools: Vec<&str> = request.tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(tools, vec!["search_pages"]);
+        assert_eq!(request.messages[0].content, "你是写作助手");
+    }
+
+    #[test]
+    fn test_stream_transcript_saves_turn() {
+        let database = Database::open_in_memory().unwrap();
+        let request = stream_request("conv-1");
+
+        let mut transcript = StreamTranscript::begin(&database, &request.turn_info(), "conv-1").unwrap();
+        // 开始时已写入用户消息和空的助手消息
+        let messages = database.get_ai_messages("conv-1").unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, "user-1");
+        assert_eq!(messages[0].content, "第二个问题\n补充说明");
+        assert_eq!(messages[1].content, "");
+
+        transcript.push("部分");
+        transcript.save();
+        assert_eq!(database.get_ai_messages("conv-1").unwrap()[1].content, "部分");
+
+        transcript.push("回复");
+        let usage = TokenUsage { input_tokens: 12, output_tokens: 4, ..TokenUsage::default() };
+        transcript.finish(Some("HTTP 500"), Some(usage));
+        let messages = database.get_ai_messages("conv-1").unwrap();
+        assert_eq!(messages[1].content, "部分回复");
+        assert!(messages[1].error);
+        assert_eq!(messages[1].usage, Some(usage));
+        assert_eq!(messages[1].agent_id.as_deref(), Some("writer"));
+
+        // 对话按最后一条用户消息的首行命名，再次开始不会覆盖已有对话和消息
+        StreamTranscript::begin(&database, &stream_request("conv-1").turn_info(), "conv-1").unwrap().finish(Some("连接失败"), None);
+        let conversation = database.get_ai_conversations(None).unwrap().remove(0);
+        assert_eq!(conversation.title, "第二个问题");
+        let messages = database.get_ai_messages("conv-1").unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2].content, "连接失败");
+
+        // 附件随用户消息保存，旧版 images 字段也转成附件
+        let mut request = stream_request("conv-2");
+        let attachment = Attachment {
+            kind: ai_attachments::AttachmentKind::Text,
+            name: "notes.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            data: "要点".to_string(),
+        };
+        request.messages[2].images = Some(vec!["data:image/png;base64,AAAA".to_string()]);
+        request.messages[2].attachments = Some(vec![attachment.clone()]);
+        StreamTranscript::begin(&database, &request.turn_info(), "conv-2").unwrap().finish(None, None);
+        let saved = database.get_ai_messages("conv-2").unwrap();
+        assert_eq!(saved[0].attachments, vec![Attachment::from_image("data:image/png;base64,AAAA"), attachment]);
+        assert!(saved[1].attachments.is_empty());
+    }
+
+    #[test]
+    fn test_resave_keeps_usage_and_agent() {
+        let database = Database::open_in_memory().unwrap();
+        let transcript = StreamTranscript::begin(&database, &stream_request("conv-1").turn_info(), "conv-1").unwrap();
+        let usage = TokenUsage { input_tokens: 12, output_tokens: 4, ..TokenUsage::default() };
+        transcript.finish(None, Some(usage));
+
+        // 前端同步同一条消息时没有带用量和智能体
+        let mut message = database.get_ai_messages("conv-1").unwrap().remove(1);
+        message.content = "过滤后的回复".to_string();
+        message.usage = None;
+        message.agent_id = None;
+        database.save_ai_message(&message).unwrap();
+
+        let saved = database.get_ai_messages("conv-1").unwrap().remove(1);
+        assert_eq!(saved.content, "过滤后的回复");
+        assert_eq!(saved.usage, Some(usage));
+        assert_eq!(saved.agent_id.as_deref(), Some("writer"));
+    }
+
+    // 持续输出 SSE 数据的模拟服务，客户端断开后通过 closed 通知
+    async fn mock_sse_server() -> (String, oneshot::Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (closed_tx, closed_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 8192];
+            let _ = socket.read(&mut request).await;
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n").await.unwrap();
+
+            for _ in 0..500 {
+                let line = b"data: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n";
+                if socket.write_all(line).await.is_err() || socket.flush().await.is_err() {
+                    let _ = closed_tx.send(());
+                    return;
                 }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
             }
-            Err(e) => {
-                return Err(format!("流式读取错误: {}", e));
-            }
-        }
+        });
+
+        (address, closed_rx)
     }
-    
-    // 如果没有收到[DONE]信号，发送完成事件
-    let _ = app_handle.emit("ai-stream-chunk", AiStreamChunk {
-        request_id,
-        content: String::new(),
-        finished: true,
-        error: None,
-    });
-    
-    Ok(())
-}
 
-async fn send_deepseek_chat(request: AiChatRequest, start_time: std::time::Instant) -> Result<AiChatResponse, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(90))
-        .build()
-        .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
-    let url = format!("{}/chat/completions", request.base_url);
-    
-    // 检查是否有图片 - DeepSeek不支持图片
-    for msg in &request.messages {
-        if let Some(images) = &msg.images {
-            if !images.is_empty() {
-                return Err("DeepSeek 模型暂不支持图片输入，请使用 Claude 模型".to_string());
+    #[tokio::test]
+    async fn test_cancel_closes_upstream_stream() {
+        let (base_url, closed) = mock_sse_server().await;
+        let request = ChatRequest {
+            base_url,
+            api_key: String::new(),
+            model: "mock".to_string(),
+            messages: Vec::new(),
+            temperature: 0.7,
+            max_tokens: 100,
+            tools: Vec::new(),
+        };
+
+        let received = AtomicUsize::new(0);
+        let mut on_delta = |_content: String| {
+            // 收到第一段内容后停止
+            if received.fetch_add(1, Ordering::SeqCst) == 0 {
+                assert!(cancel_stream("cancel-test"));
             }
-        }
+        };
+        let backend = ai_providers::resolve("mock-sse");
+        let registration = register_stream("cancel-test").unwrap();
+        let result = run_cancellable(registration, backend.stream(&request, &mut on_delta)).await;
+
+        assert!(result.is_err());
+        assert!(received.load(Ordering::SeqCst) < 500);
+        assert!(!cancel_stream("cancel-test"));
+        tokio::time::timeout(std::time::Duration::from_secs(5), closed).await
+            .expect("上游连接未关闭")
+            .unwrap();
     }
-    
-    // 构建 DeepSeek API 请求
-    let deepseek_messages: Vec<serde_json::Value> = request.messages
-        .iter()
-        .map(|msg| serde_json::json!({
-            "role": msg.role,
-            "content": msg.content
-        }))
-        .collect();
-    
-    let deepseek_request = serde_json::json!({
-        "model": request.model,
-        "messages": deepseek_messages,
-        "temperature": request.temperature,
-        "max_tokens": request.max_tokens,
-        "stream": false
-    });
-    
-    match client
-        .post(&url)
-        .json(&deepseek_request)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", request.api_key))
-        .send()
-        .await
-    {
-        Ok(response) => {
-            let latency = start_time.elapsed().as_millis() as u64;
-            let status = response.status();
-            
-            if status.is_success() {
-                match response.json::<DeepSeekResponse>().await {
-                    Ok(deepseek_response) => {
-                        if let Some(choices) = deepseek_response.choices {
-                            if let Some(choice) = choices.first() {
-                                if let Some(message) = &choice.message {
-                                    return Ok(AiChatResponse {
-                                        success: true,
-                                        content: Some(message.content.clone()),
-                                        message: Some(format!("响应成功，延迟: {}ms", latency)),
-                                    });
-                                }
-                            }
-                        }
-                        
-                        Ok(AiChatResponse {
-                            success: false,
-                            content: None,
-                            message: Some("响应格式异常".to_string()),
-                        })
-                    }
-                    Err(e) => Ok(AiChatResponse {
-                        success: false,
-                        content: None,
-                        message: Some(format!("响应解析失败: {}", e)),
-                    })
-                }
-            } else {
-                let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                Ok(AiChatResponse {
-                    success: false,
-                    content: None,
-                    message: Some(format!("HTTP {}: {}", status, error_text)),
-                })
-            }
-        }
-        Err(e) => {
-            let error_message = if e.is_timeout() {
-                "请求超时，请检查网络连接".to_string()
-            } else if e.is_connect() {
-                "连接失败，请检查 API 地址".to_string()
-            } else {
-                format!("网络错误: {}", e)
-            };
-            
-            Ok(AiChatResponse {
-                success: false,
-                content: None,
-                message: Some(error_message),
-            })
-        }
+
+    #[tokio::test]
+    async fn test_stream_registration() {
+        // 同一 request_id 进行中时拒绝重复登记
+        let first = register_stream("registration-test").unwrap();
+        assert!(register_stream("registration-test").is_err());
+
+        // 开始执行前收到的停止请求不会丢失
+        assert!(cancel_stream("registration-test"));
+        assert!(run_cancellable(first, async { 1 }).await.is_err());
+
+        // 旧登记释放时不影响之后同名的新登记
+        let stale = register_stream("registration-test").unwrap();
+        assert!(cancel_stream("registration-test"));
+        let second = register_stream("registration-test").unwrap();
+        drop(stale);
+        assert!(cancel_stream("registration-test"));
+        assert!(run_cancellable(second, async { 1 }).await.is_err());
+        assert!(!cancel_stream("registration-test"));
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,5 @@
+use crate::ai_attachments::Attachment;
+use crate::ai_usage::TokenUsage;
 use crate::database::{Database, AiConversation, AiMessage};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -24,6 +26,13 @@ pub struct SaveMessageRequest {
     pub error: bool,
     pub timestamp: i64,
     pub created_at: String,
+    // 流式结束事件或非流式响应中的 usage
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    #[serde(default)]
+    pub agent_id: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -71,6 +80,9 @@ pub async fn save_ai_message(
         error: request.error,
         timestamp: request.timestamp,
         created_at: request.created_at,
+        usage: request.usage,
+        agent_id: request.agent_id,
+        attachments: request.attachments,
     };
     
     db.save_ai_message(&message)
@@ -0,0 +1,473 @@
+use crate::ai_chat;
+use crate::ai_providers::{self, AiProvider, EmbeddingRequest};
+use crate::ai_retrieval::{self, RetrievedChunk};
+use crate::database::Database;
+use futures_util::future::BoxFuture;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tauri::State;
+
+// 每次请求嵌入接口的分块数
+const EMBED_BATCH: usize = 32;
+
+// 默认返回的检索结果数
+const DEFAULT_LIMIT: usize = 10;
+
+// 后台更新索引时置位，避免同时发起多次更新
+static INDEXING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingSettings {
+    pub provider: String,         // 与 ai_providers.provider 一致，如 'openai' | 'ollama'
+    pub base_url: Option<String>, // 为空时使用该提供商保存的地址
+    pub model: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmbeddingIndexStats {
+    pub embedded: usize,  // 新计算的分块数
+    pub removed: usize,   // 分块已不存在而删除的向量数
+    pub total: usize,     // 当前模型的向量总数
+}
+
+// 把一批文本转换为向量，测试中使用确定性的假实现
+pub trait Embedder: Send + Sync {
+    fn model(&self) -> &str;
+
+    fn embed<'a>(&'a self, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>>;
+}
+
+// 通过 ai_providers 中的后端调用嵌入接口
+pub struct ProviderEmbedder {
+    backend: Arc<dyn AiProvider>,
+    base_url: String,
+    api_key: String,
+    model: String,
+}
+
+impl Embedder for ProviderEmbedder {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn embed<'a>(&'a self, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        Box::pin(async move {
+            let request = EmbeddingRequest {
+                base_url: self.base_url.clone(),
+                api_key: self.api_key.clone(),
+                model: self.model.clone(),
+                inputs: inputs.to_vec(),
+            };
+            self.backend.embed(&request).await
+        })
+    }
+}
+
+pub fn load_settings(database: &Database) -> Result<Option<EmbeddingSettings>, String> {
+    database.with_connection(|conn| {
+        conn.query_row(
+            "SELECT provider, base_url, model, enabled FROM ai_embedding_settings WHERE id = 1",
+            [],
+            |row| Ok(EmbeddingSettings {
+                provider: row.get(0)?,
+                base_url: row.get(1)?,
+                model: row.get(2)?,
+                enabled: row.get::<_, i32>(3)? == 1,
+            }),
+        ).optional()
+    })
+}
+
+// 按配置创建嵌入器，未配置或已停用时返回 None
+pub fn configured_embedder(database: &Database) -> Result<Option<ProviderEmbedder>, String> {
+    let Some(settings) = load_settings(database)?.filter(|settings| settings.enabled) else {
+        return Ok(None);
+    };
+
+    let backend = ai_providers::resolve(&settings.provider);
+    let base_url = match settings.base_url.filter(|url| !url.trim().is_empty()) {
+        Some(url) => url,
+        None => database.get_ai_provider(&settings.provider).map_err(|e| e.to_string())?
+            .and_then(|saved| saved.base_url)
+            .unwrap_or_default(),
+    };
+    let api_key = ai_chat::resolve_request_key(database, &settings.provider, "", backend.requires_api_key())?;
+    Ok(Some(ProviderEmbedder { backend, base_url, api_key, model: settings.model }))
+}
+
+// 归一化后余弦相似度即为点积
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// 待嵌入的分块
+struct PendingChunk {
+    source_type: String,
+    source_id: String,
+    chunk_no: i64,
+    input: String,
+    hash: String,
+}
+
+// 新增、内容变化或由其他模型计算的分块：比较 sync_index 写入的分块哈希和向量的哈希
+const PENDING_CHUNKS_SQL: &str =
+    "FROM rag_chunks c
+     LEFT JOIN rag_embeddings e
+       ON e.source_type = c.source_type AND e.source_id = c.source_id AND e.chunk_no = c.chunk_index
+     WHERE e.content_hash IS NULL OR e.content_hash != c.content_hash OR e.model != ?1";
+
+fn pending_chunks(conn: &rusqlite::Connection, model: &str) -> rusqlite::Result<Vec<PendingChunk>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT c.source_type, c.source_id, c.chunk_index, c.title, c.content, c.content_hash {} ORDER BY c.id",
+        PENDING_CHUNKS_SQL
+    ))?;
+    let rows = stmt.query_map(params![model], |row| {
+        Ok(PendingChunk {
+            source_type: row.get(0)?,
+            source_id: row.get(1)?,
+            chunk_no: row.get(2)?,
+            input: format!("{}\n{}", row.get::<_, String>(3)?, row.get::<_, String>(4)?),
+            hash: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// 向量索引是否覆盖全部分块，调用前应已同步文本分块
+pub fn index_is_current(database: &Database, model: &str) -> Result<bool, String> {
+    database.with_connection(|conn| {
+        conn.query_row(
+            &format!("SELECT NOT EXISTS (SELECT 1 {})", PENDING_CHUNKS_SQL),
+            params![model],
+            |row| row.get(0),
+        )
+    })
+}
+
+// 增量更新向量索引：先同步文本分块，再为新增或内容变化的分块计算向量
+pub async fn update_index(database: &Database, embedder: &dyn Embedder) -> Result<EmbeddingIndexStats, String> {
+    let model = embedder.model().to_string();
+    let (pending, removed) = database.with_connection(|conn| {
+        ai_retrieval::sync_index(conn)?;
+        let removed = conn.execute(
+            "DELETE FROM rag_embeddings WHERE NOT EXISTS (
+                SELECT 1 FROM rag_chunks c
+                WHERE c.source_type = rag_embeddings.source_type
+                  AND c.source_id = rag_embeddings.source_id
+                  AND c.chunk_index = rag_embeddings.chunk_no
+            )",
+            [],
+        )?;
+        Ok((pending_chunks(conn, &model)?, removed))
+    })?;
+
+    let mut embedded = 0;
+    for batch in pending.chunks(EMBED_BATCH) {
+        let inputs: Vec<String> = batch.iter().map(|chunk| chunk.input.clone()).collect();
+        let vectors = embedder.embed(&inputs).await?;
+        if vectors.len() != batch.len() {
+            return Err(format!("嵌入接口返回了 {} 个向量，应为 {} 个", vectors.len(), batch.len()));
+        }
+
+        // 每批单独提交，中途失败时已完成的部分不必重算
+        let now = chrono::Utc::now().timestamp_millis();
+        database.with_connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+            for (chunk, vector) in batch.iter().zip(vectors) {
+                let vector = normalize(vector);
+                tx.execute(
+                    "INSERT OR REPLACE INTO rag_embeddings (source_type, source_id, chunk_no, model, dim, vector, content_hash, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![chunk.source_type, chunk.source_id, chunk.chunk_no, model, vector.len() as i64, to_blob(&vector), chunk.hash, now],
+                )?;
+            }
+            tx.commit()
+        })?;
+        embedded += batch.len();
+    }
+
+    let total = database.with_connection(|conn| {
+        conn.query_row("SELECT COUNT(*) FROM rag_embeddings WHERE model = ?1", params![model], |row| row.get::<_, i64>(0))
+    })?;
+    Ok(EmbeddingIndexStats { embedded, removed, total: total as usize })
+}
+
+// 暴力计算余弦相似度，按相似度从高到低返回
+pub async fn search(
+    database: &Database,
+    embedder: &dyn Embedder,
+    query: &str,
+    sources: &[String],
+    limit: usize
+) -> Result<Vec<RetrievedChunk>, String> {
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let query_vector = embedder.embed(&[query.to_string()]).await?
+        .into_iter().next()
+        .map(normalize)
+        .ok_or_else(|| "嵌入接口未返回向量".to_string())?;
+
+    let mut results = database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.source_type, c.source_id, c.title, c.content, c.token_count, e.vector
+             FROM rag_embeddings e
+             JOIN rag_chunks c
+               ON c.source_type = e.source_type AND c.source_id = e.source_id AND c.chunk_index = e.chunk_no
+             WHERE e.model = ?1 AND e.dim = ?2"
+        )?;
+        let rows = stmt.query_map(params![embedder.model(), query_vector.len() as i64], |row| {
+            Ok(RetrievedChunk {
+                chunk_id: row.get(0)?,
+                source_type: row.get(1)?,
+                source_id: row.get(2)?,
+                title: row.get(3)?,
+                content: row.get(4)?,
+                token_count: row.get::<_, i64>(5)? as usize,
+                score: dot(&query_vector, &from_blob(&row.get::<_, Vec<u8>>(6)?)) as f64,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+    })?;
+
+    results.retain(|chunk| sources.is_empty() || sources.contains(&chunk.source_type));
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+    results.truncate(limit);
+    Ok(results)
+}
+
+// 在后台增量更新向量索引，已有更新在进行时直接返回
+pub fn spawn_index_update(database: Arc<Database>) {
+    if INDEXING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let result = match configured_embedder(&database) {
+            Ok(Some(embedder)) => update_index(&database, &embedder).await.map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        INDEXING.store(false, Ordering::SeqCst);
+        match result {
+            Ok(Some(stats)) if stats.embedded > 0 || stats.removed > 0 => {
+                println!("✅ 向量索引已更新：新增 {}，删除 {}，共 {}", stats.embedded, stats.removed, stats.total);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("更新向量索引失败: {}", e),
+        }
+    });
+}
+
+// 已配置嵌入模型且索引是最新的时做语义检索，否则返回 None。
+// 检索时不更新索引：来源变化后由 sync_index 的调用方在后台更新，更新完成前只用关键词检索
+pub async fn search_if_configured(
+    database: &Database,
+    query: &str,
+    sources: &[String],
+    limit: usize
+) -> Result<Option<Vec<RetrievedChunk>>, String> {
+    let Some(embedder) = configured_embedder(database)? else {
+        return Ok(None);
+    };
+    if !index_is_current(database, embedder.model())? {
+        return Ok(None);
+    }
+    search(database, &embedder, query, sources, limit).await.map(Some)
+}
+
+fn require_embedder(database: &Database) -> Result<ProviderEmbedder, String> {
+    configured_embedder(database)?.ok_or_else(|| "尚未配置或已停用向量嵌入模型".to_string())
+}
+
+#[tauri::command]
+pub async fn get_embedding_settings(database: State<'_, Arc<Database>>) -> Result<Option<EmbeddingSettings>, String> {
+    load_settings(&database)
+}
+
+#[tauri::command]
+pub async fn save_embedding_settings(
+    database: State<'_, Arc<Database>>,
+    settings: EmbeddingSettings
+) -> Result<(), String> {
+    if settings.provider.trim().is_empty() || settings.model.trim().is_empty() {
+        return Err("提供商和模型不能为空".to_string());
+    }
+    database.with_connection(|conn| {
+        conn.execute(
+            "INSERT INTO ai_embedding_settings (id, provider, base_url, model, enabled, updated_at)
+             VALUES (1, ?1, ?2, ?3, ?4, DATETIME('now'))
+             ON CONFLICT(id) DO UPDATE SET
+                provider = excluded.provider,
+                base_url = excluded.base_url,
+                model = excluded.model,
+                enabled = excluded.enabled,
+                updated_at = excluded.updated_at",
+            params![settings.provider.trim(), settings.base_url, settings.model.trim(), settings.enabled as i32],
+        )?;
+        Ok(())
+    })
+}
+
+// 手动更新向量索引（例如保存配置后首次建立索引）
+#[tauri::command]
+pub async fn update_embedding_index(database: State<'_, Arc<Database>>) -> Result<EmbeddingIndexStats, String> {
+    let embedder = require_embedder(&database)?;
+    update_index(&database, &embedder).await
+}
+
+#[tauri::command]
+pub async fn semantic_search(
+    database: State<'_, Arc<Database>>,
+    query: String,
+    sources: Option<Vec<String>>,
+    limit: Option<usize>
+) -> Result<Vec<RetrievedChunk>, String> {
+    let sources = ai_retrieval::validate_sources(sources)?;
+    let embedder = require_embedder(&database)?;
+    // 只检索已有向量，来源有变化时在后台更新索引
+    if database.with_connection(ai_retrieval::sync_index)? > 0 {
+        spawn_index_update(database.inner().clone());
+    }
+    search(&database, &embedder, &query, &sources, limit.unwrap_or(DEFAULT_LIMIT)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 按字符散列到固定维度的词袋向量，相同文本总是得到相同向量
+    struct FakeEmbedder {
+        model: String,
+        calls: AtomicUsize,
+    }
+
+    impl FakeEmbedder {
+        fn new(model: &str) -> Self {
+            Self { model: model.to_string(), calls: AtomicUsize::new(0) }
+        }
+    }
+
+    impl Embedder for FakeEmbedder {
+        fn model(&self) -> &str {
+            &self.model
+        }
+
+        fn embed<'a>(&'a self, inputs: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+            self.calls.fetch_add(inputs.len(), Ordering::SeqCst);
+            Box::pin(async move {
+                Ok(inputs.iter().map(|input| {
+                    let mut vector = vec![0.0; 64];
+                    for c in input.chars().filter(|c| !c.is_whitespace()) {
+                        vector[c as usize % 64] += 1.0;
+                    }
+                    vector
+                }).collect())
+            })
+        }
+    }
+
+    fn seed(database: &Database) {
+        database.with_connection(|conn| {
+            conn.execute_batch(
+                "INSERT INTO knowledge_bases (id, name, created_at, updated_at) VALUES ('kb', '知识库', 0, 0);
+                 INSERT INTO pages (id, kb_id, title, content, created_at, updated_at) VALUES ('p1', 'kb', 'cats', 'cats purr and sleep', 0, 0);
+                 INSERT INTO pages (id, kb_id, title, content, created_at, updated_at) VALUES ('p2', 'kb', 'rust', 'borrow checker lifetimes', 0, 0);
+                 INSERT INTO card_boxes (id, name) VALUES ('box', '卡片盒');
+                 INSERT INTO cards (id, box_id, title, content) VALUES ('c1', 'box', 'tea', 'green tea oolong');"
+            )
+        }).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_incremental_update() {
+        let database = Database::open_in_memory().unwrap();
+        seed(&database);
+        let embedder = FakeEmbedder::new("fake-a");
+
+        let stats = update_index(&database, &embedder).await.unwrap();
+        assert_eq!((stats.embedded, stats.removed, stats.total), (3, 0, 3));
+        assert_eq!(update_index(&database, &embedder).await.unwrap().embedded, 0);
+        assert!(index_is_current(&database, "fake-a").unwrap());
+
+        // 分块哈希在分块时写入，与向量的哈希不一致即视为落后
+        database.with_connection(|conn| conn.execute("UPDATE rag_chunks SET content_hash = 'stale' WHERE source_id = 'c1'", [])).unwrap();
+        assert!(!index_is_current(&database, "fake-a").unwrap());
+        database.with_connection(|conn| {
+            conn.execute(
+                "UPDATE rag_chunks SET content_hash = ?1 WHERE source_id = 'c1'",
+                params![ai_retrieval::chunk_hash("tea", "green tea oolong")],
+            )
+        }).unwrap();
+        assert!(index_is_current(&database, "fake-a").unwrap());
+
+        // 修改卡片、给页面添加块、删除页面
+        database.with_connection(|conn| {
+            conn.execute_batch(
+                "UPDATE cards SET content = 'black tea' WHERE id = 'c1';
+                 INSERT INTO blocks (id, page_id, type, content, created_at, updated_at) VALUES ('b1', 'p2', 'paragraph', 'traits', 0, 0);
+                 DELETE FROM pages WHERE id = 'p1';"
+            )
+        }).unwrap();
+        // 内容变化后索引落后，检索时据此改用关键词检索
+        database.with_connection(ai_retrieval::sync_index).unwrap();
+        assert!(!index_is_current(&database, "fake-a").unwrap());
+        let stats = update_index(&database, &embedder).await.unwrap();
+        assert_eq!((stats.embedded, stats.removed, stats.total), (2, 1, 2));
+
+        // 更换模型后全部重新计算
+        let other = FakeEmbedder::new("fake-b");
+        assert!(!index_is_current(&database, "fake-b").unwrap());
+        assert_eq!(update_index(&database, &other).await.unwrap().embedded, 2);
+        assert_eq!(other.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cosine_search() {
+        let database = Database::open_in_memory().unwrap();
+        seed(&database);
+        let embedder = FakeEmbedder::new("fake-a");
+        update_index(&database, &embedder).await.unwrap();
+
+        let results = search(&database, &embedder, "oolong tea", &[], 10).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].source_id, "c1");
+        assert!(results[0].score > results[1].score && results[0].score <= 1.0001);
+
+        let pages = search(&database, &embedder, "oolong tea", &["page".to_string()], 1).await.unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].source_type, "page");
+
+        // 其他模型的向量不参与检索
+        assert!(search(&database, &FakeEmbedder::new("fake-b"), "tea", &[], 10).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_vector_blob_roundtrip() {
+        let vector = normalize(vec![3.0, 4.0]);
+        assert_eq!(vector, vec![0.6, 0.8]);
+        assert_eq!(from_blob(&to_blob(&vector)), vector);
+        assert!((dot(&vector, &vector) - 1.0).abs() < 1e-6);
+    }
+}
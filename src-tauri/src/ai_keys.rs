@@ -0,0 +1,231 @@
+use crate::crypto::CryptoService;
+use crate::database::{AiProvider, Database};
+use crate::password_commands::VAULT_SESSION_ID;
+use base64::{Engine as _, engine::general_purpose};
+use lazy_static::lazy_static;
+use rand::RngCore;
+use rusqlite::params;
+use std::path::Path;
+use std::sync::Mutex;
+
+// ai_providers.api_key_scheme 的取值
+pub const SCHEME_APP: &str = "app";
+// 用户选择由密码库保护时使用主密钥加密，密码库锁定期间无法使用
+pub const SCHEME_VAULT: &str = "vault";
+pub const SCHEME_PLAIN: &str = "plain";
+
+// 应用密钥单独保存在数据目录下，只复制数据库文件无法解密 AI 密钥
+const APP_KEY_FILE: &str = "app.key";
+
+lazy_static! {
+    static ref APP_KEY: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+}
+
+// 读取应用密钥，不存在时生成一个新的随机密钥
+pub fn init_app_key(app_dir: &Path) -> Result<(), String> {
+    let path = app_dir.join(APP_KEY_FILE);
+    let key = if path.exists() {
+        let encoded = std::fs::read_to_string(&path).map_err(|e| format!("读取应用密钥失败: {}", e))?;
+        let key = general_purpose::STANDARD.decode(encoded.trim()).map_err(|e| format!("应用密钥无效: {}", e))?;
+        if key.len() != 32 {
+            return Err("应用密钥长度无效".to_string());
+        }
+        key
+    } else {
+        let mut key = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        std::fs::write(&path, general_purpose::STANDARD.encode(&key)).map_err(|e| format!("保存应用密钥失败: {}", e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600));
+        }
+        key
+    };
+
+    *APP_KEY.lock().map_err(|e| e.to_string())? = Some(key);
+    Ok(())
+}
+
+fn app_key() -> Result<Vec<u8>, String> {
+    APP_KEY.lock().map_err(|e| e.to_string())?
+        .clone()
+        .ok_or_else(|| "应用密钥尚未初始化".to_string())
+}
+
+// 加密 API 密钥：默认使用应用密钥，密码库锁定后仍可使用 AI 功能；用户选择由密码库保护时使用主密钥
+fn seal_api_key(api_key: &str, vault_protected: bool) -> Result<(String, &'static str), String> {
+    if vault_protected {
+        let vault_key = CryptoService::get_cached_key(VAULT_SESSION_ID)
+            .ok_or_else(|| "由密码库保护 AI 密钥需要先解锁密码库".to_string())?;
+        return Ok((CryptoService::encrypt_with_key(&vault_key, api_key)?, SCHEME_VAULT));
+    }
+    Ok((CryptoService::encrypt_with_key(&app_key()?, api_key)?, SCHEME_APP))
+}
+
+fn open_api_key(ciphertext: &str, scheme: &str) -> Result<String, String> {
+    match scheme {
+        SCHEME_PLAIN => Ok(ciphertext.to_string()),
+        SCHEME_APP => CryptoService::decrypt_with_key(&app_key()?, ciphertext),
+        SCHEME_VAULT => {
+            let vault_key = CryptoService::get_cached_key(VAULT_SESSION_ID)
+                .ok_or_else(|| "该 AI 提供商的密钥由密码库保护，请先解锁密码库".to_string())?;
+            CryptoService::decrypt_with_key(&vault_key, ciphertext)
+        }
+        _ => Err(format!("未知的密钥加密方式: {}", scheme)),
+    }
+}
+
+// 保存前加密新密钥；密钥留空且 has_api_key 为 true 表示沿用已保存的密钥，否则清除密钥
+// 沿用已保存的密钥但切换了是否由密码库保护时，解密后按新方式重新加密
+pub fn prepare_for_save(database: &Database, provider: &mut AiProvider) -> Result<(), String> {
+    if provider.api_key.is_empty() {
+        let existing = if provider.has_api_key {
+            database.get_ai_provider(&provider.provider).map_err(|e| e.to_string())?
+                .filter(|existing| !existing.api_key.is_empty())
+        } else {
+            None
+        };
+        match existing {
+            Some(existing) if (existing.api_key_scheme == SCHEME_VAULT) != provider.vault_protected => {
+                let api_key = open_api_key(&existing.api_key, &existing.api_key_scheme)?;
+                let (ciphertext, scheme) = seal_api_key(&api_key, provider.vault_protected)?;
+                provider.api_key = ciphertext;
+                provider.api_key_scheme = scheme.to_string();
+            }
+            Some(existing) => {
+                provider.api_key = existing.api_key;
+                provider.api_key_scheme = existing.api_key_scheme;
+            }
+            None => provider.api_key_scheme = SCHEME_PLAIN.to_string(),
+        }
+        return Ok(());
+    }
+
+    let (ciphertext, scheme) = seal_api_key(&provider.api_key, provider.vault_protected)?;
+    provider.api_key = ciphertext;
+    provider.api_key_scheme = scheme.to_string();
+    Ok(())
+}
+
+// 返回给前端前去掉密钥，只保留是否已设置
+pub fn redact(mut provider: AiProvider) -> AiProvider {
+    provider.has_api_key = !provider.api_key.is_empty();
+    provider.vault_protected = provider.api_key_scheme == SCHEME_VAULT;
+    provider.api_key = String::new();
+    provider
+}
+
+// 聊天和连接测试使用：请求中带有密钥（例如测试尚未保存的新密钥）时直接使用，否则解密已保存的密钥
+pub fn resolve_api_key(database: &Database, provider: &str, request_key: &str) -> Result<String, String> {
+    if !request_key.is_empty() {
+        return Ok(request_key.to_string());
+    }
+
+    let saved = database.get_ai_provider(provider).map_err(|e| e.to_string())?
+        .filter(|saved| !saved.api_key.is_empty())
+        .ok_or_else(|| format!("尚未配置 {} 的 API 密钥", provider))?;
+    open_api_key(&saved.api_key, &saved.api_key_scheme)
+}
+
+// 启动时把旧版明文密钥改用应用密钥加密
+pub fn migrate_plaintext_keys(database: &Database) -> Result<(), String> {
+    let rows: Vec<(i64, String)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, api_key FROM ai_providers WHERE api_key_scheme = ?1 AND api_key != ''")?;
+        let rows = stmt.query_map(params![SCHEME_PLAIN], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    })?;
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let key = app_key()?;
+    let mut encrypted = Vec::with_capacity(rows.len());
+    for (id, api_key) in rows {
+        encrypted.push((id, CryptoService::encrypt_with_key(&key, &api_key)?));
+    }
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        for (id, ciphertext) in &encrypted {
+            tx.execute(
+                "UPDATE ai_providers SET api_key = ?1, api_key_scheme = ?2 WHERE id = ?3",
+                params![ciphertext, SCHEME_APP, id],
+            )?;
+        }
+        tx.commit()
+    })?;
+    println!("✅ 已加密 {} 个 AI 提供商密钥", encrypted.len());
+    Ok(())
+}
+
+// 修改主密码时：用新主密钥重新加密由密码库保护的 AI 密钥，返回 (id, 新密文)
+pub fn reencrypt_vault_keys(database: &Database, old_key: &[u8], new_key: &[u8]) -> Result<Vec<(i64, String)>, String> {
+    let rows: Vec<(i64, String)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, api_key FROM ai_providers WHERE api_key_scheme = ?1")?;
+        let rows = stmt.query_map(params![SCHEME_VAULT], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    })?;
+
+    let mut reencrypted = Vec::with_capacity(rows.len());
+    for (id, ciphertext) in rows {
+        let api_key = CryptoService::decrypt_with_key(old_key, &ciphertext)?;
+        reencrypted.push((id, CryptoService::encrypt_with_key(new_key, &api_key)?));
+    }
+    Ok(reencrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 应用密钥是全局状态，重新加载密钥的测试需要依次执行
+    lazy_static! {
+        static ref APP_KEY_TEST: Mutex<()> = Mutex::new(());
+    }
+
+    #[test]
+    fn test_app_key_round_trip() {
+        let _guard = APP_KEY_TEST.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("ai-keys-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        init_app_key(&dir).unwrap();
+        let ciphertext = CryptoService::encrypt_with_key(&app_key().unwrap(), "sk-test-123456").unwrap();
+        assert!(!ciphertext.contains("sk-test"));
+
+        // 重新加载同一个密钥文件后仍能解密
+        init_app_key(&dir).unwrap();
+        assert_eq!(open_api_key(&ciphertext, SCHEME_APP).unwrap(), "sk-test-123456");
+        assert_eq!(open_api_key("sk-plain", SCHEME_PLAIN).unwrap(), "sk-plain");
+        assert!(open_api_key(&ciphertext, "unknown").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_vault_protection_is_opt_in() {
+        let _guard = APP_KEY_TEST.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("ai-keys-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        init_app_key(&dir).unwrap();
+
+        // 默认使用应用密钥：密码库解锁时保存的密钥，锁定后也能解密
+        CryptoService::cache_key(VAULT_SESSION_ID, vec![9u8; 32]).unwrap();
+        let sealed = seal_api_key("sk-test-123456", false);
+        let protected = seal_api_key("sk-test-654321", true);
+        CryptoService::clear_cached_key(VAULT_SESSION_ID).unwrap();
+
+        let (ciphertext, scheme) = sealed.unwrap();
+        assert_eq!(scheme, SCHEME_APP);
+        assert_eq!(open_api_key(&ciphertext, scheme).unwrap(), "sk-test-123456");
+
+        // 选择由密码库保护时使用主密钥，锁定后无法解密，也无法再保存
+        let (ciphertext, scheme) = protected.unwrap();
+        assert_eq!(scheme, SCHEME_VAULT);
+        assert!(open_api_key(&ciphertext, scheme).unwrap_err().contains("解锁密码库"));
+        assert!(seal_api_key("sk-test-654321", true).unwrap_err().contains("解锁密码库"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
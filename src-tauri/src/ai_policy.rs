@@ -0,0 +1,427 @@
+use crate::ai_chat::resolve_request_key;
+use crate::ai_providers::{self, AiProvider, ChatRequest, ProviderError};
+use crate::database::Database;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 请求策略：同一提供商按指数退避重试（遵循 Retry-After），仍失败时依次换用其他已启用的提供商；
+// 连续失败的提供商熔断一段时间，期间直接跳过
+
+lazy_static! {
+    // 按提供商名称记录熔断状态
+    static ref BREAKERS: Mutex<HashMap<String, Breaker>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,             // 同一提供商的最多重试次数
+    pub base_delay: Duration,         // 第 n 次重试前等待 base_delay * 2^n
+    pub max_delay: Duration,          // Retry-After 超过该值时不再等待，直接换提供商
+    pub failure_threshold: u32,       // 连续失败多少次后熔断
+    pub cooldown: Duration,           // 熔断持续时间，之后放行一次试探请求
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(800),
+            max_delay: Duration::from_secs(20),
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+// 一次失败的尝试；retry_in_ms 为下次重试前的等待时间，换提供商或放弃时为 None
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedAttempt {
+    pub provider: String,
+    pub model: String,
+    pub error: String,
+    pub retry_in_ms: Option<u64>,
+}
+
+// 随流式分块和一次性响应返回，说明实际使用的提供商以及重试、回退情况
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestMetadata {
+    pub provider: String,
+    pub model: String,
+    pub retries: u32,
+    pub fallback: bool,  // 是否换用了请求指定之外的提供商
+    pub failures: Vec<FailedAttempt>,
+}
+
+pub struct Candidate {
+    pub provider: String,
+    pub backend: Arc<dyn AiProvider>,
+    pub request: ChatRequest,
+}
+
+fn breaker_open(provider: &str) -> Option<Duration> {
+    let breakers = BREAKERS.lock().ok()?;
+    let open_until = breakers.get(provider)?.open_until?;
+    open_until.checked_duration_since(Instant::now())
+}
+
+fn record_success(provider: &str) {
+    if let Ok(mut breakers) = BREAKERS.lock() {
+        breakers.remove(provider);
+    }
+}
+
+// 达到阈值后熔断；冷却结束后的试探请求再次失败会立即重新熔断
+fn record_failure(provider: &str, policy: &RetryPolicy) {
+    if let Ok(mut breakers) = BREAKERS.lock() {
+        let breaker = breakers.entry(provider.to_string()).or_default();
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= policy.failure_threshold {
+            breaker.open_until = Some(Instant::now() + policy.cooldown);
+        }
+    }
+}
+
+// 一次请求的重试状态，调用方循环调用 next_attempt 并报告每次的结果
+pub struct PolicyRun<'a> {
+    database: &'a Database,
+    policy: RetryPolicy,
+    primary: String,
+    current: Option<Candidate>,
+    fallbacks: Option<VecDeque<Candidate>>,  // 第一次需要换提供商时再加载
+    attempt: u32,                            // 当前提供商已重试的次数
+    delay: Option<Duration>,
+    round_start: usize,                      // 本轮第一次失败在 failures 中的位置
+    pub metadata: RequestMetadata,
+}
+
+impl<'a> PolicyRun<'a> {
+    pub fn new(database: &'a Database, provider: &str, request: ChatRequest, policy: RetryPolicy) -> Self {
+        let metadata = RequestMetadata { provider: provider.to_string(), model: request.model.clone(), ..Default::default() };
+        Self {
+            database,
+            policy,
+            primary: provider.to_string(),
+            current: Some(Candidate { provider: provider.to_string(), backend: ai_providers::resolve(provider), request }),
+            fallbacks: None,
+            attempt: 0,
+            delay: None,
+            round_start: 0,
+            metadata,
+        }
+    }
+
+    // 等待退避时间后返回下一次尝试使用的后端和请求，没有可尝试的提供商时返回 None
+    pub async fn next_attempt(&mut self) -> Option<&Candidate> {
+        if let Some(delay) = self.delay.take() {
+            tokio::time::sleep(delay).await;
+        }
+        // 熔断中的提供商直接跳过
+        while let Some(remaining) = self.current.as_ref().and_then(|candidate| breaker_open(&candidate.provider)) {
+            let error = format!("连续请求失败，已暂停使用 {} 秒", remaining.as_secs().max(1));
+            self.push_failure(error, None);
+            self.advance();
+        }
+        let candidate = self.current.as_ref()?;
+        self.metadata.provider = candidate.provider.clone();
+        self.metadata.model = candidate.request.model.clone();
+        self.metadata.fallback = candidate.provider != self.primary;
+        Some(candidate)
+    }
+
+    // 是否还会继续尝试
+    pub fn has_next(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn succeeded(&mut self) {
+        if let Some(candidate) = &self.current {
+            record_success(&candidate.provider);
+        }
+    }
+
+    // resumable 为 false 表示已经输出了部分内容，不能再重试或换提供商，否则回复会重复
+    pub fn failed(&mut self, error: ProviderError, resumable: bool) {
+        let Some(candidate) = &self.current else {
+            return;
+        };
+        let provider = candidate.provider.clone();
+        let transient = ai_providers::is_transient_error(&error.message);
+        if transient {
+            record_failure(&provider, &self.policy);
+        }
+
+        let retry_delay = error.retry_after.unwrap_or_else(|| self.policy.base_delay * 2u32.saturating_pow(self.attempt));
+        let can_retry = resumable
+            && transient
+            && self.attempt < self.policy.max_retries
+            && retry_delay <= self.policy.max_delay
+            && breaker_open(&provider).is_none();
+
+        if can_retry {
+            self.push_failure(error.message, Some(retry_delay));
+            self.attempt += 1;
+            self.metadata.retries += 1;
+            self.delay = Some(retry_delay);
+        } else if resumable && (transient || provider != self.primary) {
+            // 请求指定的提供商出现非临时错误（如密钥无效）时直接报告；备用提供商出错则继续尝试下一个
+            self.push_failure(error.message, None);
+            self.advance();
+        } else {
+            self.push_failure(error.message, None);
+            self.current = None;
+        }
+    }
+
+    // 依次尝试直到有一次成功；全部失败时返回 None，错误信息由 into_error 给出
+    pub async fn chat(&mut self) -> Option<ai_providers::ChatReply> {
+        while let Some(candidate) = self.next_attempt().await {
+            match candidate.backend.chat(&candidate.request).await {
+                Ok(reply) => {
+                    self.succeeded();
+                    return Some(reply);
+                }
+                Err(e) => self.failed(e, true),
+            }
+        }
+        None
+    }
+
+    // 固定使用上一次成功的提供商和模型开始新一轮请求：只在该提供商上重试，不再换用其他提供商。
+    // 工具调用的后续轮次依赖前一轮的工具调用 ID，必须由同一模型处理；返回其请求供调用方追加消息
+    pub fn pin(&mut self) -> Option<&mut ChatRequest> {
+        self.fallbacks = Some(VecDeque::new());
+        self.attempt = 0;
+        self.delay = None;
+        self.round_start = self.metadata.failures.len();
+        self.current.as_mut().map(|candidate| &mut candidate.request)
+    }
+
+    // 全部失败时的错误信息：以本轮请求指定的提供商的错误为主，附上尝试次数
+    pub fn into_error(self) -> String {
+        let failures = &self.metadata.failures[self.round_start..];
+        let Some(first) = failures.first() else {
+            return "没有可用的 AI 提供商".to_string();
+        };
+        if failures.len() == 1 {
+            return first.error.clone();
+        }
+        let providers = failures.iter()
+            .fold(Vec::<&str>::new(), |mut providers, failure| {
+                if !providers.contains(&failure.provider.as_str()) {
+                    providers.push(&failure.provider);
+                }
+                providers
+            });
+        format!("{}（共尝试 {} 次，涉及 {}，均失败）", first.error, failures.len(), providers.join("、"))
+    }
+
+    fn push_failure(&mut self, error: String, retry_in: Option<Duration>) {
+        if let Some(candidate) = &self.current {
+            self.metadata.failures.push(FailedAttempt {
+                provider: candidate.provider.clone(),
+                model: candidate.request.model.clone(),
+                error,
+                retry_in_ms: retry_in.map(|delay| delay.as_millis() as u64),
+            });
+        }
+    }
+
+    fn advance(&mut self) {
+        if self.fallbacks.is_none() {
+            let template = self.current.as_ref().map(|candidate| &candidate.request);
+            self.fallbacks = Some(template.map(|request| self.load_fallbacks(request)).unwrap_or_default());
+        }
+        self.current = self.fallbacks.as_mut().and_then(VecDeque::pop_front);
+        self.attempt = 0;
+        self.delay = None;
+    }
+
+    // 已启用、配置了模型和密钥的其他提供商，当前提供商优先
+    fn load_fallbacks(&self, template: &ChatRequest) -> VecDeque<Candidate> {
+        let mut providers = match self.database.get_ai_providers() {
+            Ok(providers) => providers,
+            Err(e) => {
+                eprintln!("读取备用提供商失败: {}", e);
+                return VecDeque::new();
+            }
+        };
+        providers.sort_by_key(|provider| std::cmp::Reverse(provider.is_current));
+        providers.into_iter()
+            .filter(|provider| provider.enabled == 1 && provider.provider != self.primary && !provider.model.trim().is_empty())
+            .filter_map(|provider| {
+                let backend = ai_providers::resolve(&provider.provider);
+                let api_key = resolve_request_key(self.database, &provider.provider, "", backend.requires_api_key()).ok()?;
+                Some(Candidate {
+                    request: ChatRequest {
+                        base_url: provider.base_url.unwrap_or_default(),
+                        api_key,
+                        model: provider.model,
+                        ..template.clone()
+                    },
+                    provider: provider.provider,
+                    backend,
+                })
+            })
+            .collect()
+    }
+}
+
+// 一次性对话的策略封装
+pub async fn chat(
+    database: &Database,
+    provider: &str,
+    request: ChatRequest,
+    policy: RetryPolicy
+) -> (Result<ai_providers::ChatReply, String>, RequestMetadata) {
+    let mut run = PolicyRun::new(database, provider, request, policy);
+    if let Some(reply) = run.chat().await {
+        return (Ok(reply), run.metadata);
+    }
+    let metadata = run.metadata.clone();
+    (Err(run.into_error()), metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_providers::ScriptedProvider;
+
+    // 各次请求使用的模型
+    fn models(provider: &ScriptedProvider) -> Vec<String> {
+        provider.requests().into_iter().map(|request| request.model).collect()
+    }
+
+    fn request(model: &str) -> ChatRequest {
+        ChatRequest {
+            base_url: String::new(),
+            api_key: String::new(),
+            model: model.to_string(),
+            messages: Vec::new(),
+            temperature: 0.7,
+            max_tokens: 100,
+            tools: Vec::new(),
+        }
+    }
+
+    // 服务端通过 Retry-After 要求等待的时间超过了 max_delay
+    fn rate_limited() -> ProviderError {
+        ProviderError {
+            message: "HTTP 429 Too Many Requests: slow down".to_string(),
+            retry_after: Some(Duration::from_secs(120)),
+        }
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy { base_delay: Duration::ZERO, ..RetryPolicy::default() }
+    }
+
+    fn add_provider(database: &Database, provider: &str, model: &str, enabled: i32) {
+        database.with_connection(|conn| {
+            conn.execute(
+                "INSERT INTO ai_providers (provider, api_key, model, enabled, is_current) VALUES (?1, '', ?2, ?3, 0)",
+                rusqlite::params![provider, model, enabled],
+            )
+        }).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors_then_succeeds() {
+        let primary = ScriptedProvider::new(vec![
+            ScriptedProvider::error("HTTP 503 Service Unavailable: busy"),
+            ScriptedProvider::error("请求超时，请检查网络连接"),
+            ScriptedProvider::text("好的"),
+        ]);
+        ai_providers::register("policy-retry", primary.clone());
+        let database = Database::open_in_memory().unwrap();
+
+        let (reply, metadata) = chat(&database, "policy-retry", request("m1"), fast_policy()).await;
+        assert_eq!(reply.unwrap().content, "好的");
+        assert_eq!((metadata.retries, metadata.fallback, metadata.failures.len()), (2, false, 2));
+        assert_eq!(metadata.failures[0].retry_in_ms, Some(0));
+        assert!(breaker_open("policy-retry").is_none());
+
+        // 非临时错误不重试，也不换提供商
+        let fatal = ScriptedProvider::new(vec![ScriptedProvider::error("HTTP 401 Unauthorized: bad key")]);
+        ai_providers::register("policy-fatal", fatal.clone());
+        add_provider(&database, "policy-retry", "m1", 1);
+        let (reply, metadata) = chat(&database, "policy-fatal", request("m0"), fast_policy()).await;
+        assert_eq!(reply.unwrap_err(), "HTTP 401 Unauthorized: bad key");
+        assert_eq!(metadata.failures.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_when_retry_after_too_long() {
+        let primary = ScriptedProvider::new(vec![Err(rate_limited())]);
+        let disabled = ScriptedProvider::new(vec![ScriptedProvider::text("不应使用")]);
+        let backup = ScriptedProvider::new(vec![ScriptedProvider::text("来自备用")]);
+        ai_providers::register("policy-primary", primary.clone());
+        ai_providers::register("policy-disabled", disabled.clone());
+        ai_providers::register("policy-backup", backup.clone());
+        let database = Database::open_in_memory().unwrap();
+        add_provider(&database, "policy-disabled", "d1", 0);
+        add_provider(&database, "policy-backup", "b1", 1);
+
+        let (reply, metadata) = chat(&database, "policy-primary", request("p1"), fast_policy()).await;
+        assert_eq!(reply.unwrap().content, "来自备用");
+        assert_eq!((metadata.provider.as_str(), metadata.model.as_str(), metadata.fallback), ("policy-backup", "b1", true));
+        assert_eq!((metadata.retries, metadata.failures.len()), (0, 1));
+        assert!(models(&disabled).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pin_keeps_serving_provider() {
+        let primary = ScriptedProvider::new(vec![Err(rate_limited()), ScriptedProvider::text("不应使用")]);
+        let backup = ScriptedProvider::new(vec![
+            ScriptedProvider::text("第一轮"),
+            ScriptedProvider::text("第二轮"),
+            ScriptedProvider::error("HTTP 400 Bad Request: invalid tool_call_id"),
+        ]);
+        ai_providers::register("pin-primary", primary.clone());
+        ai_providers::register("pin-backup", backup.clone());
+        let database = Database::open_in_memory().unwrap();
+        add_provider(&database, "pin-backup", "b1", 1);
+
+        let mut run = PolicyRun::new(&database, "pin-primary", request("p1"), fast_policy());
+        assert_eq!(run.chat().await.unwrap().content, "第一轮");
+
+        // 后续轮次由第一轮实际响应的提供商和模型处理
+        run.pin().unwrap().messages.push(Default::default());
+        assert_eq!(run.chat().await.unwrap().content, "第二轮");
+        assert_eq!((run.metadata.provider.as_str(), run.metadata.model.as_str(), run.metadata.fallback), ("pin-backup", "b1", true));
+
+        // 固定后出错不再换回其他提供商，错误信息只包含本轮
+        run.pin();
+        assert!(run.chat().await.is_none());
+        assert_eq!(run.into_error(), "HTTP 400 Bad Request: invalid tool_call_id");
+        assert_eq!(models(&primary).len(), 1);
+        assert_eq!(models(&backup).as_slice(), ["b1", "b1", "b1"]);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_skips_failing_provider() {
+        let flaky = ScriptedProvider::new(Vec::new());
+        ai_providers::register("policy-flaky", flaky.clone());
+        let database = Database::open_in_memory().unwrap();
+        let policy = RetryPolicy { failure_threshold: 3, ..fast_policy() };
+
+        // 重试两次后共失败三次，达到阈值熔断
+        let (reply, metadata) = chat(&database, "policy-flaky", request("f1"), policy).await;
+        assert!(reply.unwrap_err().contains("共尝试 3 次"));
+        assert_eq!(metadata.retries, 2);
+        assert!(breaker_open("policy-flaky").is_some());
+
+        // 熔断期间不再发出请求
+        let (reply, _) = chat(&database, "policy-flaky", request("f1"), policy).await;
+        assert!(reply.unwrap_err().starts_with("连续请求失败"));
+        assert_eq!(models(&flaky).len(), 3);
+    }
+}
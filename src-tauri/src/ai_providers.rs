@@ -0,0 +1,1107 @@
+use crate::ai_attachments::{self, AttachmentKind};
+use crate::ai_chat::AiChatMessage;
+use crate::ai_usage::TokenUsage;
+use crate::sse::{SseDecoder, SseEvent};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// 发给各后端的统一请求
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub base_url: String,  // 为空时使用后端默认地址
+    pub api_key: String,
+    pub model: String,
+    pub messages: Vec<AiChatMessage>,
+    pub temperature: f32,
+    pub max_tokens: u32,
+    pub tools: Vec<ToolSpec>,  // 为空时不启用工具调用，只在一次性对话中使用
+}
+
+// 一次性对话的结果
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    pub content: String,
+    pub usage: Option<TokenUsage>,  // 接口未返回用量时为 None
+    pub tool_calls: Vec<ToolCall>,  // 模型请求调用的工具，执行后把结果作为 tool 消息发回
+}
+
+// 提供给模型的工具定义，parameters 为 JSON Schema
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+// 模型发起的一次工具调用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,  // JSON 对象
+}
+
+// 对话请求失败；retry_after 为服务端通过 Retry-After 要求的等待时间，供重试策略使用
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderError {
+    pub message: String,
+    pub retry_after: Option<std::time::Duration>,
+}
+
+impl From<String> for ProviderError {
+    fn from(message: String) -> Self {
+        Self { message, retry_after: None }
+    }
+}
+
+impl From<ProviderError> for String {
+    fn from(error: ProviderError) -> Self {
+        error.message
+    }
+}
+
+// 向量嵌入请求，一次可以提交多段文本
+#[derive(Debug, Clone)]
+pub struct EmbeddingRequest {
+    pub base_url: String,  // 为空时使用后端默认地址
+    pub api_key: String,
+    pub model: String,
+    pub inputs: Vec<String>,
+}
+
+// AI 后端：一次性对话、流式对话、连接测试和向量嵌入
+pub trait AiProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn default_base_url(&self) -> &'static str;
+
+    fn requires_api_key(&self) -> bool {
+        true
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<ChatReply, ProviderError>>;
+
+    // 每收到一段文本调用一次 on_delta，流结束后返回 token 用量
+    fn stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: &'a mut (dyn FnMut(String) + Send),
+    ) -> BoxFuture<'a, Result<Option<TokenUsage>, ProviderError>>;
+
+    fn test_connection<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<(), String>>;
+
+    // 返回的向量与 inputs 一一对应
+    fn embed<'a>(&'a self, _request: &'a EmbeddingRequest) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        Box::pin(async move { Err(format!("{} 不提供向量嵌入接口", self.name())) })
+    }
+}
+
+lazy_static! {
+    // 以 ai_providers.provider 的取值为键
+    static ref REGISTRY: RwLock<HashMap<String, Arc<dyn AiProvider>>> = RwLock::new(builtin_providers());
+}
+
+fn builtin_providers() -> HashMap<String, Arc<dyn AiProvider>> {
+    let anthropic: Arc<dyn AiProvider> = Arc::new(Anthropic);
+    let mut providers: HashMap<String, Arc<dyn AiProvider>> = HashMap::new();
+    providers.insert("deepseek".to_string(), Arc::new(OpenAiCompatible {
+        name: "DeepSeek",
+        default_base_url: "https://api.deepseek.com",
+        supports_images: false,
+    }));
+    providers.insert("openai".to_string(), Arc::new(OpenAiCompatible {
+        name: "OpenAI",
+        default_base_url: "https://api.openai.com/v1",
+        supports_images: true,
+    }));
+    providers.insert("claude".to_string(), anthropic.clone());
+    providers.insert("anthropic".to_string(), anthropic);
+    providers.insert("ollama".to_string(), Arc::new(Ollama));
+    providers
+}
+
+// 注册或替换一个后端
+#[allow(dead_code)]
+pub fn register(provider: &str, backend: Arc<dyn AiProvider>) {
+    if let Ok(mut registry) = REGISTRY.write() {
+        registry.insert(provider.to_string(), backend);
+    }
+}
+
+// 按提供商名称查找后端，未注册的名称按 OpenAI 兼容接口处理（需要填写 API 地址）
+pub fn resolve(provider: &str) -> Arc<dyn AiProvider> {
+    REGISTRY.read().ok()
+        .and_then(|registry| registry.get(provider).cloned())
+        .unwrap_or_else(|| Arc::new(OpenAiCompatible {
+            name: "OpenAI 兼容接口",
+            default_base_url: "",
+            supports_images: true,
+        }))
+}
+
+// ===== 公共工具 =====
+
+fn http_client(timeout_secs: u64) -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(timeout_secs))
+        .build()
+        .map_err(|e| format!("创建HTTP客户端失败: {}", e))
+}
+
+fn describe_send_error(e: reqwest::Error) -> String {
+    if e.is_timeout() {
+        "请求超时，请检查网络连接".to_string()
+    } else if e.is_connect() {
+        "连接失败，请检查 API 地址".to_string()
+    } else {
+        format!("网络错误: {}", e)
+    }
+}
+
+async fn send(builder: reqwest::RequestBuilder) -> Result<reqwest::Response, ProviderError> {
+    let response = builder.send().await.map_err(describe_send_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response.headers().get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        let mut message = format!("HTTP {}: {}", status, error_text);
+        if let Some(retry_after) = retry_after {
+            message.push_str(&format!("（服务端要求 {} 秒后重试）", retry_after.as_secs()));
+        }
+        return Err(ProviderError { message, retry_after });
+    }
+    Ok(response)
+}
+
+// Retry-After 可以是秒数或 HTTP 日期
+fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(std::time::Duration::from_secs(seconds as u64))
+}
+
+// 限流、服务端错误和网络问题可以重试；其他 4xx 和参数错误重试也不会成功
+pub fn is_transient_error(error: &str) -> bool {
+    if let Some(rest) = error.strip_prefix("HTTP ") {
+        let status: u16 = rest.get(..3).and_then(|code| code.parse().ok()).unwrap_or(0);
+        return status == 408 || status == 429 || status >= 500;
+    }
+    ["请求超时", "连接失败", "网络错误", "流式读取错误"].iter().any(|prefix| error.starts_with(prefix))
+}
+
+fn endpoint(base_url: &str, default_base_url: &str, path: &str) -> Result<String, String> {
+    let base_url = if base_url.trim().is_empty() { default_base_url } else { base_url.trim() };
+    if base_url.is_empty() {
+        return Err("请填写 API 地址".to_string());
+    }
+    Ok(format!("{}{}", base_url.trim_end_matches('/'), path))
+}
+
+fn parse_vector(value: &Value) -> Option<Vec<f32>> {
+    value.as_array()?.iter().map(|x| x.as_f64().map(|x| x as f32)).collect()
+}
+
+// 检查向量数量与输入一致
+fn check_embeddings(vectors: Vec<Vec<f32>>, expected: usize) -> Result<Vec<Vec<f32>>, String> {
+    if vectors.len() != expected || vectors.iter().any(Vec::is_empty) {
+        return Err(format!("嵌入接口返回了 {} 个向量，应为 {} 个", vectors.len(), expected));
+    }
+    Ok(vectors)
+}
+
+// 按行读取响应体（NDJSON），handle_line 返回 true 时提前结束
+async fn for_each_line(
+    response: reqwest::Response,
+    mut handle_line: impl FnMut(&str) -> Result<bool, String>,
+) -> Result<(), String> {
+    let mut stream = response.bytes_stream();
+    // 按字节缓冲，拿到完整一行后再解码，避免拆开多字节字符
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let bytes = chunk.map_err(|e| format!("流式读取错误: {}", e))?;
+        buffer.extend_from_slice(&bytes);
+
+        while let Some(line_end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=line_end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() && handle_line(line)? {
+                return Ok(());
+            }
+        }
+    }
+
+    let rest = String::from_utf8_lossy(&buffer);
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        handle_line(rest)?;
+    }
+    Ok(())
+}
+
+// 按 SSE 事件读取响应体，handle_event 返回 true 时提前结束
+async fn for_each_event(
+    response: reqwest::Response,
+    mut handle_event: impl FnMut(&SseEvent) -> Result<bool, String>,
+) -> Result<(), String> {
+    let mut stream = response.bytes_stream();
+    let mut decoder = SseDecoder::new();
+    while let Some(chunk) = stream.next().await {
+        let bytes = chunk.map_err(|e| format!("流式读取错误: {}", e))?;
+        for event in decoder.feed(&bytes) {
+            if handle_event(&event)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+// OpenAI 格式的工具定义，Ollama 使用相同格式
+fn openai_tools(tools: &[ToolSpec]) -> Value {
+    json!(tools.iter()
+        .map(|tool| json!({
+            "type": "function",
+            "function": { "name": tool.name, "description": tool.description, "parameters": tool.parameters }
+        }))
+        .collect::<Vec<_>>())
+}
+
+// 解析 OpenAI 格式的 tool_calls；arguments 可能是 JSON 字符串（OpenAI）或对象（Ollama），没有 id 时按序号生成
+fn parse_openai_tool_calls(message: &Value) -> Vec<ToolCall> {
+    message["tool_calls"].as_array().into_iter().flatten()
+        .enumerate()
+        .filter_map(|(index, call)| {
+            let function = &call["function"];
+            let arguments = match &function["arguments"] {
+                Value::String(text) => serde_json::from_str(text).unwrap_or_else(|_| json!({})),
+                Value::Null => json!({}),
+                value => value.clone(),
+            };
+            Some(ToolCall {
+                id: call["id"].as_str().map(str::to_string).unwrap_or_else(|| format!("call_{}", index)),
+                name: function["name"].as_str()?.to_string(),
+                arguments,
+            })
+        })
+        .collect()
+}
+
+fn message_tool_calls(message: &AiChatMessage) -> &[ToolCall] {
+    message.tool_calls.as_deref().unwrap_or_default()
+}
+
+// ===== OpenAI 兼容接口（DeepSeek、OpenAI 及其他兼容服务） =====
+
+pub struct OpenAiCompatible {
+    name: &'static str,
+    default_base_url: &'static str,
+    supports_images: bool,
+}
+
+impl OpenAiCompatible {
+    fn build_body(&self, request: &ChatRequest, stream: bool) -> Value {
+        let mut messages = Vec::with_capacity(request.messages.len());
+        for message in &request.messages {
+            if message.role == "tool" {
+                messages.push(json!({ "role": "tool", "tool_call_id": message.tool_call_id, "content": message.content }));
+                continue;
+            }
+            let tool_calls = message_tool_calls(message);
+            if !tool_calls.is_empty() {
+                let calls: Vec<Value> = tool_calls.iter()
+                    .map(|call| json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": { "name": call.name, "arguments": call.arguments.to_string() }
+                    }))
+                    .collect();
+                messages.push(json!({ "role": "assistant", "content": message.content, "tool_calls": calls }));
+                continue;
+            }
+
+            // 图片用 image_url 发送；PDF、文本文件以及不支持图片时的图片转成文字
+            let attachments = ai_attachments::message_attachments(message);
+            let (images, others): (Vec<_>, Vec<_>) = attachments.iter()
+                .partition(|attachment| self.supports_images && attachment.kind == AttachmentKind::Image);
+            let text = ai_attachments::content_with_text_blocks(&message.content, others);
+            if images.is_empty() {
+                messages.push(json!({ "role": message.role, "content": text }));
+                continue;
+            }
+
+            let mut content: Vec<Value> = Vec::new();
+            if !text.trim().is_empty() {
+                content.push(json!({ "type": "text", "text": text }));
+            }
+            for image in images {
+                content.push(json!({ "type": "image_url", "image_url": { "url": image.data_url() } }));
+            }
+            messages.push(json!({ "role": message.role, "content": content }));
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "temperature": request.temperature,
+            "max_tokens": request.max_tokens,
+            "stream": stream
+        });
+        if stream {
+            // 最后一个分块附带 usage
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = openai_tools(&request.tools);
+        }
+        body
+    }
+
+    fn post(&self, client: &reqwest::Client, request: &ChatRequest, body: &Value) -> Result<reqwest::RequestBuilder, String> {
+        let mut builder = client
+            .post(endpoint(&request.base_url, self.default_base_url(), "/chat/completions")?)
+            .header("Content-Type", "application/json")
+            .json(body);
+        if !request.api_key.is_empty() {
+            builder = builder.header("Authorization", format!("Bearer {}", request.api_key));
+        }
+        Ok(builder)
+    }
+}
+
+impl AiProvider for OpenAiCompatible {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        self.default_base_url
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<ChatReply, ProviderError>> {
+        Box::pin(async move {
+            let body = self.build_body(request, false);
+            let client = http_client(90)?;
+            let response = send(self.post(&client, request, &body)?).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+            let message = &json["choices"][0]["message"];
+            let tool_calls = parse_openai_tool_calls(message);
+            // 只调用工具时 content 为 null
+            let content = match message["content"].as_str() {
+                Some(content) => content.to_string(),
+                None if !tool_calls.is_empty() => String::new(),
+                None => return Err("响应格式异常".to_string().into()),
+            };
+            Ok(ChatReply { content, usage: TokenUsage::from_openai(&json["usage"]), tool_calls })
+        })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: &'a mut (dyn FnMut(String) + Send),
+    ) -> BoxFuture<'a, Result<Option<TokenUsage>, ProviderError>> {
+        Box::pin(async move {
+            let body = self.build_body(request, true);
+            let client = http_client(90)?;
+            let response = send(self.post(&client, request, &body)?).await?;
+            let mut usage = None;
+            for_each_event(response, |event| {
+                if event.is_done() {
+                    return Ok(true);
+                }
+                if let Ok(chunk) = serde_json::from_str::<Value>(&event.data) {
+                    if let Some(error) = chunk.get("error") {
+                        return Err(error["message"].as_str().unwrap_or("接口返回错误").to_string());
+                    }
+                    if let Some(content) = chunk["choices"][0]["delta"]["content"].as_str() {
+                        if !content.is_empty() {
+                            on_delta(content.to_string());
+                        }
+                    }
+                    if let Some(chunk_usage) = TokenUsage::from_openai(&chunk["usage"]) {
+                        usage = Some(chunk_usage);
+                    }
+                }
+                Ok(false)
+            }).await?;
+            Ok(usage)
+        })
+    }
+
+    fn test_connection<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let body = json!({
+                "model": request.model,
+                "max_tokens": 10,
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "temperature": 0.1
+            });
+            let client = http_client(30)?;
+            let response = send(self.post(&client, request, &body)?).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+            if json.get("choices").is_some() || json.get("id").is_some() {
+                Ok(())
+            } else {
+                Err("连接成功但响应格式异常".to_string())
+            }
+        })
+    }
+
+    fn embed<'a>(&'a self, request: &'a EmbeddingRequest) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        Box::pin(async move {
+            let client = http_client(60)?;
+            let mut builder = client
+                .post(endpoint(&request.base_url, self.default_base_url(), "/embeddings")?)
+                .json(&json!({ "model": request.model, "input": request.inputs }));
+            if !request.api_key.is_empty() {
+                builder = builder.header("Authorization", format!("Bearer {}", request.api_key));
+            }
+            let json: Value = send(builder).await?.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+
+            // 按 index 排序，接口不保证返回顺序
+            let mut data: Vec<(u64, Vec<f32>)> = json["data"].as_array()
+                .ok_or_else(|| "响应格式异常".to_string())?
+                .iter()
+                .map(|item| parse_vector(&item["embedding"])
+                    .map(|vector| (item["index"].as_u64().unwrap_or(0), vector))
+                    .ok_or_else(|| "响应格式异常".to_string()))
+                .collect::<Result<_, _>>()?;
+            data.sort_by_key(|(index, _)| *index);
+            check_embeddings(data.into_iter().map(|(_, vector)| vector).collect(), request.inputs.len())
+        })
+    }
+}
+
+// ===== Anthropic Messages API =====
+
+pub struct Anthropic;
+
+impl Anthropic {
+    fn build_body(request: &ChatRequest, max_tokens: u32, stream: bool) -> Value {
+        // system 消息作为顶级参数
+        let mut system_parts: Vec<&str> = Vec::new();
+        let mut messages: Vec<Value> = Vec::new();
+
+        for message in &request.messages {
+            if message.role == "system" {
+                system_parts.push(&message.content);
+                continue;
+            }
+            if message.role == "tool" {
+                // 工具结果放在 user 消息中，连续的多个结果合并为一条
+                let result = json!({ "type": "tool_result", "tool_use_id": message.tool_call_id, "content": message.content });
+                match messages.last_mut() {
+                    Some(last) if last["role"] == "user" && last["content"][0]["type"] == "tool_result" => {
+                        if let Some(content) = last["content"].as_array_mut() {
+                            content.push(result);
+                        }
+                    }
+                    _ => messages.push(json!({ "role": "user", "content": [result] })),
+                }
+                continue;
+            }
+            let tool_calls = message_tool_calls(message);
+            if !tool_calls.is_empty() {
+                let mut content: Vec<Value> = Vec::new();
+                if !message.content.trim().is_empty() {
+                    content.push(json!({ "type": "text", "text": message.content }));
+                }
+                content.extend(tool_calls.iter().map(|call| json!({
+                    "type": "tool_use",
+                    "id": call.id,
+                    "name": call.name,
+                    "input": call.arguments
+                })));
+                messages.push(json!({ "role": "assistant", "content": content }));
+                continue;
+            }
+
+            let attachments = ai_attachments::message_attachments(message);
+            if attachments.is_empty() {
+                messages.push(json!({ "role": message.role, "content": message.content }));
+                continue;
+            }
+
+            // 图片用 image 块，PDF 和文本文件用 document 块，由模型直接读取原文件
+            let mut content: Vec<Value> = attachments.iter()
+                .map(|attachment| {
+                    let source = match attachment.kind {
+                        AttachmentKind::Image => {
+                            return json!({
+                                "type": "image",
+                                "source": { "type": "base64", "media_type": attachment.media_type(), "data": attachment.base64_data() }
+                            });
+                        }
+                        AttachmentKind::Pdf => json!({ "type": "base64", "media_type": "application/pdf", "data": attachment.base64_data() }),
+                        AttachmentKind::Text => match attachment.text() {
+                            Some(text) => json!({ "type": "text", "media_type": "text/plain", "data": text }),
+                            None => return json!({ "type": "text", "text": attachment.as_text_block() }),
+                        },
+                    };
+                    let mut block = json!({ "type": "document", "source": source });
+                    if !attachment.name.trim().is_empty() {
+                        block["title"] = json!(attachment.name.trim());
+                    }
+                    block
+                })
+                .collect();
+            if !message.content.trim().is_empty() {
+                content.push(json!({ "type": "text", "text": message.content }));
+            }
+            messages.push(json!({ "role": message.role, "content": content }));
+        }
+
+        let mut body = json!({
+            "model": request.model,
+            "max_tokens": max_tokens,
+            "messages": messages,
+            "temperature": request.temperature
+        });
+        if stream {
+            body["stream"] = json!(true);
+        }
+        if !system_parts.is_empty() {
+            body["system"] = json!(system_parts.join("\n\n"));
+        }
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools.iter()
+                .map(|tool| json!({ "name": tool.name, "description": tool.description, "input_schema": tool.parameters }))
+                .collect::<Vec<_>>());
+        }
+        body
+    }
+
+    fn post(client: &reqwest::Client, request: &ChatRequest, body: &Value) -> Result<reqwest::RequestBuilder, String> {
+        Ok(client
+            .post(endpoint(&request.base_url, Anthropic.default_base_url(), "/v1/messages")?)
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &request.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(body))
+    }
+}
+
+impl AiProvider for Anthropic {
+    fn name(&self) -> &'static str {
+        "Anthropic Claude"
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        "https://api.anthropic.com"
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<ChatReply, ProviderError>> {
+        Box::pin(async move {
+            let body = Self::build_body(request, request.max_tokens, false);
+            let client = http_client(90)?;
+            let response = send(Self::post(&client, request, &body)?).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+
+            let blocks = json["content"].as_array()
+                .ok_or_else(|| "响应格式异常".to_string())?;
+            let text: String = blocks.iter()
+                .filter(|block| block["type"] == "text")
+                .filter_map(|block| block["text"].as_str())
+                .collect();
+            let tool_calls = blocks.iter()
+                .filter(|block| block["type"] == "tool_use")
+                .filter_map(|block| Some(ToolCall {
+                    id: block["id"].as_str()?.to_string(),
+                    name: block["name"].as_str()?.to_string(),
+                    arguments: block["input"].clone(),
+                }))
+                .collect();
+
+            let mut usage = TokenUsage::default();
+            usage.merge_anthropic(&json["usage"]);
+            Ok(ChatReply { content: text, usage: json.get("usage").map(|_| usage), tool_calls })
+        })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: &'a mut (dyn FnMut(String) + Send),
+    ) -> BoxFuture<'a, Result<Option<TokenUsage>, ProviderError>> {
+        Box::pin(async move {
+            let body = Self::build_body(request, request.max_tokens, true);
+            let client = http_client(90)?;
+            let response = send(Self::post(&client, request, &body)?).await?;
+            // message_start 带输入用量，message_delta 带累计输出用量
+            let mut usage: Option<TokenUsage> = None;
+            for_each_event(response, |sse_event| {
+                let Ok(event) = serde_json::from_str::<Value>(&sse_event.data) else {
+                    return Ok(false);
+                };
+                match event["type"].as_str() {
+                    Some("content_block_delta") => {
+                        if let Some(text) = event["delta"]["text"].as_str() {
+                            on_delta(text.to_string());
+                        }
+                        Ok(false)
+                    }
+                    Some("message_start") => {
+                        usage.get_or_insert_with(TokenUsage::default).merge_anthropic(&event["message"]["usage"]);
+                        Ok(false)
+                    }
+                    Some("message_delta") => {
+                        usage.get_or_insert_with(TokenUsage::default).merge_anthropic(&event["usage"]);
+                        Ok(false)
+                    }
+                    Some("message_stop") => Ok(true),
+                    Some("error") => Err(event["error"]["message"].as_str().unwrap_or("Claude API错误").to_string()),
+                    _ => Ok(false),
+                }
+            }).await?;
+            Ok(usage)
+        })
+    }
+
+    fn test_connection<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let test_request = ChatRequest {
+                messages: vec![AiChatMessage { role: "user".to_string(), content: "Hi".to_string(), ..Default::default() }],
+                ..request.clone()
+            };
+            let body = Self::build_body(&test_request, 10, false);
+            let client = http_client(30)?;
+            let response = send(Self::post(&client, request, &body)?).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+            if json.get("id").is_some() || json.get("content").is_some() {
+                Ok(())
+            } else {
+                Err("连接成功但响应格式异常".to_string())
+            }
+        })
+    }
+}
+
+// ===== 本地 Ollama 服务 =====
+
+pub struct Ollama;
+
+const OLLAMA_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Debug, PartialEq)]
+struct OllamaChunk {
+    content: Option<String>,
+    done: bool,
+    usage: Option<TokenUsage>,  // 只在最后一个分块中出现
+}
+
+impl Ollama {
+    fn build_body(request: &ChatRequest, stream: bool) -> Value {
+        let messages: Vec<Value> = request.messages.iter()
+            .map(|message| {
+                // 图片放在 images 字段，PDF 和文本文件转成文字
+                let attachments = ai_attachments::message_attachments(message);
+                let (images, others): (Vec<_>, Vec<_>) = attachments.iter()
+                    .partition(|attachment| attachment.kind == AttachmentKind::Image);
+                let content = ai_attachments::content_with_text_blocks(&message.content, others);
+                let mut value = json!({ "role": message.role, "content": content });
+                if !images.is_empty() {
+                    value["images"] = json!(images.iter().map(|image| image.base64_data()).collect::<Vec<_>>());
+                }
+                let tool_calls = message_tool_calls(message);
+                if !tool_calls.is_empty() {
+                    value["tool_calls"] = json!(tool_calls.iter()
+                        .map(|call| json!({ "function": { "name": call.name, "arguments": call.arguments } }))
+                        .collect::<Vec<_>>());
+                }
+                value
+            })
+            .collect();
+
+        let mut body = json!({
+            "model": request.model,
+            "messages": messages,
+            "stream": stream,
+            "options": {
+                "temperature": request.temperature,
+                "num_predict": request.max_tokens
+            }
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = openai_tools(&request.tools);
+        }
+        body
+    }
+
+    fn with_auth(api_key: &str, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        // 本地服务通常不需要密钥，经反向代理访问时可以配置
+        if api_key.is_empty() {
+            builder
+        } else {
+            builder.header("Authorization", format!("Bearer {}", api_key))
+        }
+    }
+
+    // 解析一行 NDJSON
+    fn parse_line(line: &str) -> Result<OllamaChunk, String> {
+        let chunk: Value = serde_json::from_str(line).map_err(|e| format!("响应解析失败: {}", e))?;
+        if let Some(error) = chunk["error"].as_str() {
+            return Err(error.to_string());
+        }
+        let content = chunk["message"]["content"].as_str()
+            .filter(|content| !content.is_empty())
+            .map(str::to_string);
+        Ok(OllamaChunk {
+            content,
+            done: chunk["done"].as_bool().unwrap_or(false),
+            usage: TokenUsage::from_ollama(&chunk),
+        })
+    }
+}
+
+impl AiProvider for Ollama {
+    fn name(&self) -> &'static str {
+        "Ollama"
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        OLLAMA_BASE_URL
+    }
+
+    fn requires_api_key(&self) -> bool {
+        false
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<ChatReply, ProviderError>> {
+        Box::pin(async move {
+            // 本地模型首次加载较慢
+            let client = http_client(300)?;
+            let builder = client.post(endpoint(&request.base_url, self.default_base_url(), "/api/chat")?)
+                .json(&Self::build_body(request, false));
+            let response = send(Self::with_auth(&request.api_key, builder)).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+            let content = json["message"]["content"].as_str()
+                .ok_or_else(|| "响应格式异常".to_string())?;
+            Ok(ChatReply {
+                content: content.to_string(),
+                usage: TokenUsage::from_ollama(&json),
+                tool_calls: parse_openai_tool_calls(&json["message"]),
+            })
+        })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        request: &'a ChatRequest,
+        on_delta: &'a mut (dyn FnMut(String) + Send),
+    ) -> BoxFuture<'a, Result<Option<TokenUsage>, ProviderError>> {
+        Box::pin(async move {
+            let client = http_client(300)?;
+            let builder = client.post(endpoint(&request.base_url, self.default_base_url(), "/api/chat")?)
+                .json(&Self::build_body(request, true));
+            let response = send(Self::with_auth(&request.api_key, builder)).await?;
+            let mut usage = None;
+            for_each_line(response, |line| {
+                let chunk = Self::parse_line(line)?;
+                if let Some(content) = chunk.content {
+                    on_delta(content);
+                }
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+                Ok(chunk.done)
+            }).await?;
+            Ok(usage)
+        })
+    }
+
+    fn test_connection<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let client = http_client(30)?;
+            let builder = client.get(endpoint(&request.base_url, self.default_base_url(), "/api/tags")?);
+            let response = send(Self::with_auth(&request.api_key, builder)).await?;
+            let json: Value = response.json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+
+            // 模型名可能省略 :latest 标签
+            let installed = json["models"].as_array().into_iter().flatten()
+                .filter_map(|model| model["name"].as_str())
+                .any(|name| name == request.model || name.strip_suffix(":latest") == Some(request.model.as_str()));
+            if installed {
+                Ok(())
+            } else {
+                Err(format!("Ollama 中未找到模型 {}，请先执行 ollama pull {}", request.model, request.model))
+            }
+        })
+    }
+
+    fn embed<'a>(&'a self, request: &'a EmbeddingRequest) -> BoxFuture<'a, Result<Vec<Vec<f32>>, String>> {
+        Box::pin(async move {
+            let client = http_client(300)?;
+            let builder = client.post(endpoint(&request.base_url, self.default_base_url(), "/api/embed")?)
+                .json(&json!({ "model": request.model, "input": request.inputs }));
+            let json: Value = send(Self::with_auth(&request.api_key, builder)).await?
+                .json().await.map_err(|e| format!("响应解析失败: {}", e))?;
+            let vectors = json["embeddings"].as_array()
+                .ok_or_else(|| "响应格式异常".to_string())?
+                .iter()
+                .map(|vector| parse_vector(vector).ok_or_else(|| "响应格式异常".to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            check_embeddings(vectors, request.inputs.len())
+        })
+    }
+}
+
+// ===== 测试用后端 =====
+
+// 按顺序返回预设结果并记录收到的请求，预设结果用完后返回服务端错误
+#[cfg(test)]
+pub struct ScriptedProvider {
+    replies: std::sync::Mutex<std::collections::VecDeque<Result<ChatReply, ProviderError>>>,
+    requests: std::sync::Mutex<Vec<ChatRequest>>,
+}
+
+#[cfg(test)]
+impl ScriptedProvider {
+    pub fn new(replies: Vec<Result<ChatReply, ProviderError>>) -> Arc<Self> {
+        Arc::new(Self {
+            replies: std::sync::Mutex::new(replies.into()),
+            requests: std::sync::Mutex::new(Vec::new()),
+        })
+    }
+
+    // 不含用量和工具调用的文本回复
+    pub fn text(content: &str) -> Result<ChatReply, ProviderError> {
+        Ok(ChatReply { content: content.to_string(), usage: None, tool_calls: Vec::new() })
+    }
+
+    pub fn error(message: &str) -> Result<ChatReply, ProviderError> {
+        Err(message.to_string().into())
+    }
+
+    pub fn requests(&self) -> Vec<ChatRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl AiProvider for ScriptedProvider {
+    fn name(&self) -> &'static str {
+        "Scripted"
+    }
+
+    fn default_base_url(&self) -> &'static str {
+        "http://localhost"
+    }
+
+    fn requires_api_key(&self) -> bool {
+        false
+    }
+
+    fn chat<'a>(&'a self, request: &'a ChatRequest) -> BoxFuture<'a, Result<ChatReply, ProviderError>> {
+        self.requests.lock().unwrap().push(request.clone());
+        let reply = self.replies.lock().unwrap().pop_front()
+            .unwrap_or_else(|| Self::error("HTTP 500 Internal Server Error: "));
+        Box::pin(async move { reply })
+    }
+
+    fn stream<'a>(
+        &'a self,
+        _request: &'a ChatRequest,
+        _on_delta: &'a mut (dyn FnMut(String) + Send),
+    ) -> BoxFuture<'a, Result<Option<TokenUsage>, ProviderError>> {
+        Box::pin(async move { Ok(None) })
+    }
+
+    fn test_connection<'a>(&'a self, _request: &'a ChatRequest) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_attachments::Attachment;
+
+    fn request(messages: Vec<AiChatMessage>) -> ChatRequest {
+        ChatRequest {
+            base_url: String::new(),
+            api_key: String::new(),
+            model: "test-model".to_string(),
+            messages,
+            temperature: 0.7,
+            max_tokens: 100,
+            tools: Vec::new(),
+        }
+    }
+
+    fn message(role: &str, content: &str, images: Option<Vec<String>>) -> AiChatMessage {
+        AiChatMessage { role: role.to_string(), content: content.to_string(), images, ..Default::default() }
+    }
+
+    #[test]
+    fn test_resolve_registry() {
+        assert_eq!(resolve("deepseek").name(), "DeepSeek");
+        assert_eq!(resolve("claude").name(), "Anthropic Claude");
+        assert!(!resolve("ollama").requires_api_key());
+        // 未注册的名称按 OpenAI 兼容接口处理，必须填写地址
+        let custom = resolve("my-gateway");
+        assert_eq!(custom.default_base_url(), "");
+        assert!(endpoint(&request(vec![]).base_url, custom.default_base_url(), "/chat/completions").is_err());
+    }
+
+    #[test]
+    fn test_anthropic_body_moves_system_prompt() {
+        let body = Anthropic::build_body(&request(vec![
+            message("system", "你是助手", None),
+            message("user", "看图", Some(vec!["data:image/png;base64,AAAA".to_string()])),
+        ]), 100, true);
+
+        assert_eq!(body["system"], "你是助手");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["messages"][0]["content"][0]["source"]["media_type"], "image/png");
+        assert_eq!(body["messages"][0]["content"][0]["source"]["data"], "AAAA");
+        assert_eq!(body["messages"][0]["content"][1]["text"], "看图");
+    }
+
+    #[test]
+    fn test_openai_compatible_images() {
+        let images_request = request(vec![message("user", "看图", Some(vec!["data:image/png;base64,AAAA".to_string()]))]);
+        // 不支持图片的模型改为在正文中说明，不再拒绝整条消息
+        let deepseek = OpenAiCompatible { name: "DeepSeek", default_base_url: "", supports_images: false };
+        let body = deepseek.build_body(&images_request, false);
+        assert_eq!(body["messages"][0]["content"], "看图\n\n[附件：图片（当前模型不支持图片，已省略）]");
+
+        let openai = OpenAiCompatible { name: "OpenAI", default_base_url: "", supports_images: true };
+        let body = openai.build_body(&images_request, true);
+        assert_eq!(body["messages"][0]["content"][1]["image_url"]["url"], "data:image/png;base64,AAAA");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn test_attachments_per_provider() {
+        let attachments = vec![
+            Attachment { kind: AttachmentKind::Pdf, name: "report.pdf".to_string(), mime_type: String::new(), data: "JVBERi0=".to_string() },
+            Attachment { kind: AttachmentKind::Text, name: "notes.md".to_string(), mime_type: String::new(), data: "# 笔记".to_string() },
+            Attachment::from_image("data:image/png;base64,AAAA"),
+        ];
+        let with_files = request(vec![AiChatMessage {
+            role: "user".to_string(),
+            content: "总结".to_string(),
+            attachments: Some(attachments),
+            ..Default::default()
+        }]);
+
+        let body = Anthropic::build_body(&with_files, 100, false);
+        let content = &body["messages"][0]["content"];
+        assert_eq!(content[0]["type"], "document");
+        assert_eq!(content[0]["source"]["media_type"], "application/pdf");
+        assert_eq!(content[0]["title"], "report.pdf");
+        assert_eq!(content[1]["source"]["data"], "# 笔记");
+        assert_eq!(content[2]["type"], "image");
+        assert_eq!(content[3]["text"], "总结");
+
+        // OpenAI 兼容接口只发送图片，其余附件转成文字
+        let openai = OpenAiCompatible { name: "OpenAI", default_base_url: "", supports_images: true };
+        let body = openai.build_body(&with_files, false);
+        let content = &body["messages"][0]["content"];
+        assert_eq!(content[0]["text"], "总结\n\n[附件：report.pdf（未能提取文字内容）]\n\n[附件：notes.md]\n# 笔记");
+        assert_eq!(content[1]["image_url"]["url"], "data:image/png;base64,AAAA");
+
+        let body = Ollama::build_body(&with_files, false);
+        assert_eq!(body["messages"][0]["images"][0], "AAAA");
+        assert!(body["messages"][0]["content"].as_str().unwrap().ends_with("# 笔记"));
+    }
+
+    #[test]
+    fn test_tool_messages_per_provider() {
+        let call = ToolCall { id: "call_1".to_string(), name: "create_task".to_string(), arguments: json!({ "title": "写周报" }) };
+        let mut with_tools = request(vec![
+            message("user", "记一下", None),
+            AiChatMessage { role: "assistant".to_string(), tool_calls: Some(vec![call.clone()]), ..Default::default() },
+            AiChatMessage { role: "tool".to_string(), content: "{\"id\":1}".to_string(), tool_call_id: Some("call_1".to_string()), ..Default::default() },
+        ]);
+        with_tools.tools = vec![ToolSpec { name: "create_task".to_string(), description: "创建任务".to_string(), parameters: json!({ "type": "object" }) }];
+
+        let openai = OpenAiCompatible { name: "OpenAI", default_base_url: "", supports_images: true };
+        let body = openai.build_body(&with_tools, false);
+        assert_eq!(body["tools"][0]["function"]["name"], "create_task");
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["arguments"], "{\"title\":\"写周报\"}");
+        assert_eq!(body["messages"][2]["tool_call_id"], "call_1");
+
+        let body = Anthropic::build_body(&with_tools, 100, false);
+        assert_eq!(body["tools"][0]["input_schema"]["type"], "object");
+        assert_eq!(body["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(body["messages"][1]["content"][0]["input"]["title"], "写周报");
+        assert_eq!(body["messages"][2]["role"], "user");
+        assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "call_1");
+
+        let body = Ollama::build_body(&with_tools, false);
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["arguments"]["title"], "写周报");
+
+        // OpenAI 的 arguments 是字符串，Ollama 是对象且没有 id
+        let parsed = parse_openai_tool_calls(&json!({ "tool_calls": [
+            { "id": "a", "function": { "name": "search_pages", "arguments": "{\"query\":\"习惯\"}" } },
+            { "function": { "name": "get_pending_tasks", "arguments": {} } }
+        ] }));
+        assert_eq!(parsed[0].arguments["query"], "习惯");
+        assert_eq!(parsed[1].id, "call_1");
+    }
+
+    #[test]
+    fn test_ollama_body_and_stream_lines() {
+        let body = Ollama::build_body(&request(vec![
+            message("user", "hi", Some(vec!["data:image/png;base64,AAAA".to_string()])),
+        ]), false);
+        assert_eq!(body["messages"][0]["images"][0], "AAAA");
+        assert_eq!(body["options"]["num_predict"], 100);
+
+        assert_eq!(
+            Ollama::parse_line(r#"{"message":{"role":"assistant","content":"你好"},"done":false}"#).unwrap(),
+            OllamaChunk { content: Some("你好".to_string()), done: false, usage: None }
+        );
+        let last = Ollama::parse_line(r#"{"message":{"content":""},"done":true,"prompt_eval_count":8,"eval_count":21}"#).unwrap();
+        assert_eq!((last.content, last.done), (None, true));
+        assert_eq!(last.usage.map(|usage| usage.output_tokens), Some(21));
+        assert!(Ollama::parse_line(r#"{"error":"model not found"}"#).is_err());
+    }
+
+    #[test]
+    fn test_transient_errors_and_retry_after() {
+        assert!(is_transient_error("HTTP 429 Too Many Requests: rate limited"));
+        assert!(is_transient_error("HTTP 503 Service Unavailable: overloaded"));
+        assert!(is_transient_error("请求超时，请检查网络连接"));
+        assert!(!is_transient_error("HTTP 401 Unauthorized: invalid key"));
+        assert!(!is_transient_error("请填写 API 地址"));
+
+        assert_eq!(parse_retry_after(" 7 "), Some(std::time::Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(std::time::Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_reports_retry_after() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/chat", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 8192];
+            let _ = socket.read(&mut request).await;
+            let response = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 7\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}";
+            socket.write_all(response).await.unwrap();
+        });
+
+        let error = send(http_client(5).unwrap().post(url)).await.unwrap_err();
+        assert!(error.message.starts_with("HTTP 429 Too Many Requests: {}"));
+        assert_eq!(error.retry_after, Some(std::time::Duration::from_secs(7)));
+    }
+}
@@ -0,0 +1,686 @@
+use crate::ai_chat::{self, AiChatMessage, AiChatRequest, AiChatResponse};
+use crate::ai_embeddings;
+use crate::database::Database;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tauri::State;
+
+// 每个分块的最大字符数
+const CHUNK_CHARS: usize = 600;
+
+// 默认的上下文 token 预算和候选分块数
+const DEFAULT_TOKEN_BUDGET: usize = 2000;
+const DEFAULT_CANDIDATES: usize = 20;
+
+// 引用片段的长度（字符）
+const SNIPPET_CHARS: usize = 120;
+
+// 可检索的来源类型，与 rag_chunks.source_type 一致
+pub const SOURCE_TYPES: [&str; 4] = ["page", "card", "reading_note", "timeline"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievedChunk {
+    pub chunk_id: i64,
+    pub source_type: String,
+    pub source_id: String,
+    pub title: String,
+    pub content: String,
+    pub token_count: usize,
+    pub score: f64,  // 越大越相关
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Citation {
+    pub index: usize,  // 上下文中的编号 [n]
+    pub source_type: String,
+    pub source_id: String,
+    pub title: String,
+    pub snippet: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetrievedContext {
+    pub context: String,
+    pub citations: Vec<Citation>,
+    pub token_count: usize,
+}
+
+// 粗略估算 token 数：中日韩文字每字约 1 个 token，其他文字约 4 个字符 1 个 token
+pub fn estimate_tokens(text: &str) -> usize {
+    let (cjk, other) = text.chars().fold((0usize, 0usize), |(cjk, other), c| {
+        if is_cjk(c) {
+            (cjk + 1, other)
+        } else if c.is_whitespace() {
+            (cjk, other)
+        } else {
+            (cjk, other + 1)
+        }
+    });
+    cjk + other.div_ceil(4)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF       // 日文假名
+        | 0x3400..=0x4DBF     // 扩展 A
+        | 0x4E00..=0x9FFF     // 基本汉字
+        | 0xAC00..=0xD7AF     // 韩文
+        | 0xF900..=0xFAFF     // 兼容汉字
+        | 0x20000..=0x2A6DF)  // 扩展 B
+}
+
+// unicode61 分词器会把连续的汉字当成一个词，建索引前在每个汉字两侧加空格，按单字索引
+fn segment(text: &str) -> String {
+    let mut segmented = String::with_capacity(text.len() * 2);
+    for c in text.chars() {
+        if is_cjk(c) {
+            segmented.push(' ');
+            segmented.push(c);
+            segmented.push(' ');
+        } else {
+            segmented.push(c);
+        }
+    }
+    segmented
+}
+
+// 查询词：英文等按单词，汉字按相邻两字（单字查询保留单字）
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for run in query.split(|c: char| !c.is_alphanumeric()).filter(|run| !run.is_empty()) {
+        let mut word = String::new();
+        let mut cjk: Vec<char> = Vec::new();
+        let flush_word = |word: &mut String, terms: &mut Vec<String>| {
+            if !word.is_empty() {
+                terms.push(std::mem::take(word).to_lowercase());
+            }
+        };
+        let flush_cjk = |cjk: &mut Vec<char>, terms: &mut Vec<String>| {
+            match cjk.len() {
+                0 => {}
+                1 => terms.push(cjk[0].to_string()),
+                _ => terms.extend(cjk.windows(2).map(|pair| pair.iter().collect::<String>())),
+            }
+            cjk.clear();
+        };
+        for c in run.chars() {
+            if is_cjk(c) {
+                flush_word(&mut word, &mut terms);
+                cjk.push(c);
+            } else {
+                flush_cjk(&mut cjk, &mut terms);
+                word.push(c);
+            }
+        }
+        flush_word(&mut word, &mut terms);
+        flush_cjk(&mut cjk, &mut terms);
+    }
+
+    let mut unique = Vec::with_capacity(terms.len());
+    for term in terms {
+        if !unique.contains(&term) {
+            unique.push(term);
+        }
+    }
+    unique
+}
+
+// 生成 FTS5 查询：每个词作为短语（汉字词拆成单字短语），任意一个命中即可，由 BM25 排序
+fn fts_query(terms: &[String]) -> Option<String> {
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.iter()
+        .map(|term| format!("\"{}\"", segment(term).split_whitespace().collect::<Vec<_>>().join(" ").replace('"', "")))
+        .collect::<Vec<_>>()
+        .join(" OR "))
+}
+
+// 提取纯文本：支持 Editor.js 的 JSON、HTML 和普通文本
+pub fn plain_text(content: &str) -> String {
+    let trimmed = content.trim();
+    if trimmed.starts_with('{') {
+        if let Ok(json) = serde_json::from_str::<Value>(trimmed) {
+            if let Some(blocks) = json["blocks"].as_array() {
+                let mut parts = Vec::new();
+                for block in blocks {
+                    collect_block_text(&block["data"], &mut parts);
+                }
+                return parts.join("\n\n");
+            }
+        }
+    }
+    if trimmed.contains('<') && trimmed.contains('>') {
+        return strip_html(trimmed);
+    }
+    trimmed.to_string()
+}
+
+fn collect_block_text(data: &Value, parts: &mut Vec<String>) {
+    for key in ["title", "text", "code", "caption", "message"] {
+        if let Some(text) = data[key].as_str() {
+            let text = strip_html(text);
+            if !text.trim().is_empty() {
+                parts.push(text);
+            }
+        }
+    }
+    if let Some(items) = data["items"].as_array() {
+        for item in items {
+            match item {
+                Value::String(text) => parts.push(strip_html(text)),
+                // 嵌套列表 { content, items }、清单 { text, checked }
+                _ => collect_block_text(&serde_json::json!({
+                    "text": item.get("content").or_else(|| item.get("text")).cloned().unwrap_or(Value::Null),
+                    "items": item.get("items").cloned().unwrap_or(Value::Null),
+                }), parts),
+            }
+        }
+    }
+    if let Some(rows) = data["content"].as_array() {
+        // 表格
+        for row in rows {
+            if let Some(cells) = row.as_array() {
+                let cells: Vec<String> = cells.iter().filter_map(Value::as_str).map(strip_html).collect();
+                parts.push(cells.join(" | "));
+            }
+        }
+    }
+}
+
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// 按段落分块，段落过长时在句末标点处拆分，仍然过长时按字符数硬拆
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces: Vec<String> = Vec::new();
+    for paragraph in text.split('\n').map(str::trim).filter(|line| !line.is_empty()) {
+        if paragraph.chars().count() <= max_chars {
+            pieces.push(paragraph.to_string());
+            continue;
+        }
+        let mut sentence = String::new();
+        for c in paragraph.chars() {
+            sentence.push(c);
+            if matches!(c, '。' | '！' | '？' | '；' | '.' | '!' | '?' | ';') || sentence.chars().count() >= max_chars {
+                pieces.push(std::mem::take(&mut sentence));
+            }
+        }
+        if !sentence.trim().is_empty() {
+            pieces.push(sentence);
+        }
+    }
+
+    let mut chunks: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        if !current.is_empty() && current.chars().count() + piece.chars().count() + 1 > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(piece.trim());
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// 读取来源的标题和纯文本，来源已删除时返回 None
+fn load_source(conn: &Connection, source_type: &str, source_id: &str) -> rusqlite::Result<Option<(String, String)>> {
+    match source_type {
+        "page" => {
+            let page = conn.query_row(
+                "SELECT COALESCE(title, ''), COALESCE(content, '') FROM pages WHERE id = ?1 AND COALESCE(is_deleted, 0) = 0",
+                params![source_id],
+                |row| Ok((row.get::<_, String>(0)?, plain_text(&row.get::<_, String>(1)?))),
+            ).optional()?;
+            let Some((title, mut text)) = page else {
+                return Ok(None);
+            };
+
+            // 以块编辑的页面，正文保存在 blocks 中
+            let mut stmt = conn.prepare(
+                "SELECT content FROM blocks WHERE page_id = ?1 AND COALESCE(is_deleted, 0) = 0 AND content IS NOT NULL ORDER BY sort_order"
+            )?;
+            let blocks = stmt.query_map(params![source_id], |row| row.get::<_, String>(0))?;
+            for block in blocks {
+                let block = plain_text(&block?);
+                if !block.is_empty() {
+                    if !text.is_empty() {
+                        text.push('\n');
+                    }
+                    text.push_str(&block);
+                }
+            }
+            Ok(Some((title, text)))
+        }
+        "card" => conn.query_row(
+            "SELECT title, COALESCE(content, '') FROM cards WHERE id = ?1",
+            params![source_id],
+            |row| Ok((row.get::<_, String>(0)?, plain_text(&row.get::<_, String>(1)?))),
+        ).optional(),
+        "reading_note" => conn.query_row(
+            "SELECT b.title, n.chapter, n.content FROM reading_notes n JOIN books b ON b.id = n.book_id WHERE n.id = ?1",
+            params![source_id],
+            |row| {
+                let book: String = row.get(0)?;
+                let chapter: Option<String> = row.get(1)?;
+                let title = match chapter.filter(|chapter| !chapter.trim().is_empty()) {
+                    Some(chapter) => format!("《{}》{}", book, chapter),
+                    None => format!("《{}》", book),
+                };
+                Ok((title, plain_text(&row.get::<_, String>(2)?)))
+            },
+        ).optional(),
+        "timeline" => conn.query_row(
+            "SELECT date, time, content FROM timeline_entries WHERE CAST(id AS TEXT) = ?1",
+            params![source_id],
+            |row| Ok((format!("时光记 {} {}", row.get::<_, String>(0)?, row.get::<_, String>(1)?), row.get::<_, String>(2)?)),
+        ).optional(),
+        _ => Ok(None),
+    }
+}
+
+// 分块内容的哈希，与向量嵌入的输入（标题 + 正文）一致，向量索引据此判断是否需要重新计算
+pub fn chunk_hash(title: &str, content: &str) -> String {
+    format!("{:x}", Sha256::digest(format!("{}\n{}", title, content).as_bytes()))
+}
+
+// 处理待更新队列：删除旧分块，重新分块写入。返回处理的来源数
+pub fn sync_index(conn: &Connection) -> rusqlite::Result<usize> {
+    let pending: Vec<(String, String)> = {
+        let mut stmt = conn.prepare("SELECT source_type, source_id FROM rag_pending")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    for (source_type, source_id) in &pending {
+        tx.execute(
+            "DELETE FROM rag_chunks_fts WHERE rowid IN (SELECT id FROM rag_chunks WHERE source_type = ?1 AND source_id = ?2)",
+            params![source_type, source_id],
+        )?;
+        tx.execute("DELETE FROM rag_chunks WHERE source_type = ?1 AND source_id = ?2", params![source_type, source_id])?;
+
+        if let Some((title, text)) = load_source(&tx, source_type, source_id)? {
+            for (index, chunk) in chunk_text(&text, CHUNK_CHARS).iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO rag_chunks (source_type, source_id, chunk_index, title, content, token_count, content_hash)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![source_type, source_id, index as i64, title, chunk, estimate_tokens(chunk) as i64, chunk_hash(&title, chunk)],
+                )?;
+                tx.execute(
+                    "INSERT INTO rag_chunks_fts (rowid, title, body) VALUES (?1, ?2, ?3)",
+                    params![tx.last_insert_rowid(), segment(&title), segment(chunk)],
+                )?;
+            }
+        }
+        tx.execute("DELETE FROM rag_pending WHERE source_type = ?1 AND source_id = ?2", params![source_type, source_id])?;
+    }
+    tx.commit()?;
+    Ok(pending.len())
+}
+
+// BM25 检索，标题命中的权重是正文的两倍
+pub fn search_chunks(conn: &Connection, query: &str, sources: &[String], limit: usize) -> rusqlite::Result<Vec<RetrievedChunk>> {
+    let Some(match_query) = fts_query(&query_terms(query)) else {
+        return Ok(Vec::new());
+    };
+
+    let mut sql = String::from(
+        "SELECT c.id, c.source_type, c.source_id, c.title, c.content, c.token_count, bm25(rag_chunks_fts, 2.0, 1.0)
+         FROM rag_chunks_fts
+         JOIN rag_chunks c ON c.id = rag_chunks_fts.rowid
+         WHERE rag_chunks_fts MATCH ?1"
+    );
+    let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(match_query)];
+    if !sources.is_empty() {
+        let placeholders: Vec<String> = (0..sources.len()).map(|i| format!("?{}", i + 2)).collect();
+        sql.push_str(&format!(" AND c.source_type IN ({})", placeholders.join(", ")));
+        values.extend(sources.iter().map(|source| Box::new(source.clone()) as Box<dyn rusqlite::ToSql>));
+    }
+    sql.push_str(&format!(" ORDER BY 7 LIMIT {}", limit));
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values.iter().map(|value| value.as_ref())), |row| {
+        Ok(RetrievedChunk {
+            chunk_id: row.get(0)?,
+            source_type: row.get(1)?,
+            source_id: row.get(2)?,
+            title: row.get(3)?,
+            content: row.get(4)?,
+            token_count: row.get::<_, i64>(5)? as usize,
+            // bm25() 越小越相关
+            score: -row.get::<_, f64>(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// 截取第一个命中词附近的片段
+fn make_snippet(content: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let lower = content.to_lowercase();
+    let hit = terms.iter()
+        .filter_map(|term| lower.find(term.as_str()))
+        .min()
+        .map(|byte_index| lower[..byte_index].chars().count())
+        .unwrap_or(0);
+
+    let start = hit.saturating_sub(SNIPPET_CHARS / 3).min(chars.len());
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+    let mut snippet: String = chars[start..end].iter().collect::<String>().replace('\n', " ");
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+// 按相关度依次放入分块直到用完 token 预算，同一来源的分块合并为一条引用
+pub fn assemble_context(chunks: &[RetrievedChunk], query: &str, token_budget: usize) -> RetrievedContext {
+    let terms = query_terms(query);
+    let mut sections: Vec<(Citation, Vec<&str>)> = Vec::new();
+    let mut used = 0;
+
+    for chunk in chunks {
+        let existing = sections.iter().position(|(citation, _)| {
+            citation.source_type == chunk.source_type && citation.source_id == chunk.source_id
+        });
+        // 新来源还需要计入标题行
+        let header_tokens = if existing.is_some() { 0 } else { estimate_tokens(&chunk.title) + 4 };
+        let cost = chunk.token_count + header_tokens;
+        if used + cost > token_budget {
+            continue;
+        }
+        used += cost;
+
+        match existing {
+            Some(index) => sections[index].1.push(&chunk.content),
+            None => sections.push((
+                Citation {
+                    index: sections.len() + 1,
+                    source_type: chunk.source_type.clone(),
+                    source_id: chunk.source_id.clone(),
+                    title: chunk.title.clone(),
+                    snippet: make_snippet(&chunk.content, &terms),
+                },
+                vec![&chunk.content],
+            )),
+        }
+    }
+
+    let context = sections.iter()
+        .map(|(citation, contents)| format!("[{}] {}（{}）\n{}", citation.index, citation.title, source_label(&citation.source_type), contents.join("\n…\n")))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    RetrievedContext {
+        context,
+        citations: sections.into_iter().map(|(citation, _)| citation).collect(),
+        token_count: used,
+    }
+}
+
+fn source_label(source_type: &str) -> &'static str {
+    match source_type {
+        "page" => "知识库页面",
+        "card" => "卡片",
+        "reading_note" => "读书笔记",
+        "timeline" => "时光记",
+        _ => "资料",
+    }
+}
+
+pub fn validate_sources(sources: Option<Vec<String>>) -> Result<Vec<String>, String> {
+    let sources = sources.unwrap_or_default();
+    if let Some(unknown) = sources.iter().find(|source| !SOURCE_TYPES.contains(&source.as_str())) {
+        return Err(format!("不支持的检索来源: {}", unknown));
+    }
+    Ok(sources)
+}
+
+// 倒数排名融合（RRF）：按各列表中的名次合并关键词检索和语义检索的结果
+const RRF_K: f64 = 60.0;
+
+fn fuse_rankings(lists: &[Vec<RetrievedChunk>], limit: usize) -> Vec<RetrievedChunk> {
+    let mut fused: Vec<RetrievedChunk> = Vec::new();
+    for list in lists {
+        for (rank, chunk) in list.iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f64 + 1.0);
+            match fused.iter_mut().find(|existing| existing.chunk_id == chunk.chunk_id) {
+                Some(existing) => existing.score += score,
+                None => fused.push(RetrievedChunk { score, ..chunk.clone() }),
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused.truncate(limit);
+    fused
+}
+
+pub async fn retrieve_context(
+    database: &Arc<Database>,
+    query: &str,
+    sources: &[String],
+    token_budget: usize,
+    limit: usize
+) -> Result<RetrievedContext, String> {
+    let (synced, mut chunks) = database.with_connection(|conn| {
+        let synced = sync_index(conn)?;
+        Ok((synced, search_chunks(conn, query, sources, limit)?))
+    })?;
+    // 有来源内容变化时在后台更新向量索引
+    if synced > 0 {
+        ai_embeddings::spawn_index_update(database.clone());
+    }
+
+    // 配置了向量嵌入且索引是最新的时合并语义检索结果，否则只用关键词检索
+    match ai_embeddings::search_if_configured(database, query, sources, limit).await {
+        Ok(Some(semantic)) => chunks = fuse_rankings(&[chunks, semantic], limit),
+        Ok(None) => {}
+        Err(e) => eprintln!("语义检索失败，仅使用关键词检索: {}", e),
+    }
+    Ok(assemble_context(&chunks, query, token_budget))
+}
+
+// 检索相关资料，供前端拼入提示词（例如流式对话）
+#[tauri::command]
+pub async fn search_rag_context(
+    database: State<'_, Arc<Database>>,
+    query: String,
+    sources: Option<Vec<String>>,
+    token_budget: Option<usize>,
+    limit: Option<usize>
+) -> Result<RetrievedContext, String> {
+    let sources = validate_sources(sources)?;
+    retrieve_context(&database, &query, &sources, token_budget.unwrap_or(DEFAULT_TOKEN_BUDGET), limit.unwrap_or(DEFAULT_CANDIDATES)).await
+}
+
+// 重建全部检索索引
+#[tauri::command]
+pub async fn rebuild_rag_index(database: State<'_, Arc<Database>>) -> Result<usize, String> {
+    let rebuilt = database.with_connection(|conn| {
+        conn.execute_batch("DELETE FROM rag_chunks_fts; DELETE FROM rag_chunks;")?;
+        conn.execute_batch(
+            "INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT 'page', id FROM pages;
+             INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT 'card', id FROM cards;
+             INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT 'reading_note', id FROM reading_notes;
+             INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT 'timeline', CAST(id AS TEXT) FROM timeline_entries;"
+        )?;
+        sync_index(conn)
+    })?;
+    ai_embeddings::spawn_index_update(database.inner().clone());
+    Ok(rebuilt)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RagChatRequest {
+    #[serde(flatten)]
+    pub chat: AiChatRequest,
+    // 检索用的问题，默认取最后一条用户消息
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub sources: Option<Vec<String>>,
+    #[serde(default)]
+    pub token_budget: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RagChatResponse {
+    #[serde(flatten)]
+    pub response: AiChatResponse,
+    pub citations: Vec<Citation>,
+}
+
+// 把检索到的资料作为系统消息放在已有系统消息之后
+fn with_context(mut messages: Vec<AiChatMessage>, context: &str) -> Vec<AiChatMessage> {
+    let position = messages.iter().take_while(|message| message.role == "system").count();
+    messages.insert(position, AiChatMessage {
+        role: "system".to_string(),
+        content: format!(
+            "以下是从用户的知识库中检索到的资料。回答时优先依据这些资料，并用 [编号] 标注引用的来源；资料中没有相关信息时请直接说明。\n\n{}",
+            context
+        ),
+        ..Default::default()
+    });
+    messages
+}
+
+// 检索增强对话：先检索资料再回答，返回回答和引用来源
+#[tauri::command]
+pub async fn send_rag_chat(
+    database: State<'_, Arc<Database>>,
+    request: RagChatRequest
+) -> Result<RagChatResponse, String> {
+    let sources = validate_sources(request.sources)?;
+    let query = request.query
+        .or_else(|| request.chat.messages.iter().rev().find(|message| message.role == "user").map(|message| message.content.clone()))
+        .unwrap_or_default();
+
+    let retrieved = retrieve_context(&database, &query, &sources, request.token_budget.unwrap_or(DEFAULT_TOKEN_BUDGET), DEFAULT_CANDIDATES).await?;
+    let mut chat = request.chat;
+    if !retrieved.citations.is_empty() {
+        chat.messages = with_context(chat.messages, &retrieved.context);
+    }
+
+    let response = ai_chat::run_chat(&database, chat).await?;
+    Ok(RagChatResponse { response, citations: retrieved.citations })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text_from_editorjs() {
+        let content = r#"{"time":1,"blocks":[
+            {"type":"header","data":{"text":"习惯养成","level":2}},
+            {"type":"paragraph","data":{"text":"每天<b>坚持</b>阅读&nbsp;30 分钟"}},
+            {"type":"list","data":{"style":"unordered","items":["早起",{"content":"运动","items":[]}]}}
+        ]}"#;
+        assert_eq!(plain_text(content), "习惯养成\n\n每天坚持阅读 30 分钟\n\n早起\n\n运动");
+        assert_eq!(plain_text("<p>a &amp; b</p>"), "a & b");
+        assert_eq!(plain_text("  普通文本 "), "普通文本");
+    }
+
+    #[test]
+    fn test_chunk_text_and_tokens() {
+        let text = format!("{}\n\n{}", "短段落", "句子一。".repeat(40));
+        let chunks = chunk_text(&text, 50);
+        assert!(chunks.len() > 2);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 50));
+        assert!(chunks[0].starts_with("短段落"));
+
+        assert_eq!(estimate_tokens("你好 world"), 4);
+    }
+
+    #[test]
+    fn test_query_terms() {
+        assert_eq!(query_terms("关于习惯的 Rust 笔记"), vec!["关于", "于习", "习惯", "惯的", "rust", "笔记"]);
+        assert_eq!(fts_query(&query_terms("习惯 \"x\"")).unwrap(), "\"习 惯\" OR \"x\"");
+        assert!(fts_query(&query_terms("？！")).is_none());
+    }
+
+    #[test]
+    fn test_fuse_rankings() {
+        let chunk = |id: i64| RetrievedChunk {
+            chunk_id: id,
+            source_type: "page".to_string(),
+            source_id: id.to_string(),
+            title: String::new(),
+            content: String::new(),
+            token_count: 1,
+            score: 0.0,
+        };
+        // 两个列表中都排在前面的分块胜出
+        let fused = fuse_rankings(&[vec![chunk(1), chunk(2), chunk(3)], vec![chunk(2), chunk(4)]], 3);
+        let ids: Vec<i64> = fused.iter().map(|chunk| chunk.chunk_id).collect();
+        assert_eq!(ids, vec![2, 1, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_index_search_and_assemble() {
+        let database = Arc::new(Database::open_in_memory().unwrap());
+        database.with_connection(|conn| {
+            conn.execute_batch(
+                "INSERT INTO knowledge_bases (id, name, created_at, updated_at) VALUES ('kb', '知识库', 0, 0);
+                 INSERT INTO pages (id, kb_id, title, content, created_at, updated_at)
+                     VALUES ('p1', 'kb', '习惯养成', '每天坚持阅读三十分钟，习惯会慢慢形成。', 0, 0);
+                 INSERT INTO pages (id, kb_id, title, content, created_at, updated_at)
+                     VALUES ('p2', 'kb', '旅行计划', '下个月去杭州。', 0, 0);
+                 INSERT INTO card_boxes (id, name) VALUES ('box', '卡片盒');
+                 INSERT INTO cards (id, box_id, title, content) VALUES ('c1', 'box', 'Atomic Habits', 'Small habits compound over time.');
+                 INSERT INTO timeline_entries (date, time, content) VALUES ('2026-01-01', '08:00', '今天开始记录阅读习惯');"
+            )
+        }).unwrap();
+
+        let context = retrieve_context(&database, "阅读习惯", &[], 2000, 10).await.unwrap();
+        let ids: Vec<&str> = context.citations.iter().map(|citation| citation.source_id.as_str()).collect();
+        assert!(ids.contains(&"p1") && ids.contains(&"1"));
+        assert!(!ids.contains(&"p2"));
+        assert!(context.context.starts_with("[1] "));
+        assert!(context.citations[0].snippet.contains("习惯"));
+
+        // 来源过滤、英文检索
+        let cards = retrieve_context(&database, "habits", &["card".to_string()], 2000, 10).await.unwrap();
+        assert_eq!(cards.citations.len(), 1);
+        assert_eq!(cards.citations[0].title, "Atomic Habits");
+
+        // 更新和删除后索引随之变化
+        database.with_connection(|conn| {
+            conn.execute_batch("UPDATE pages SET is_deleted = 1 WHERE id = 'p1'; DELETE FROM timeline_entries;")
+        }).unwrap();
+        assert!(retrieve_context(&database, "阅读习惯", &[], 2000, 10).await.unwrap().citations.is_empty());
+
+        // 预算不足时不放入任何分块
+        assert!(retrieve_context(&database, "habits", &[], 1, 10).await.unwrap().citations.is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::ai_chat::{resolve_request_key, AiChatMessage};
+use crate::ai_policy::{self, RetryPolicy};
+use crate::ai_providers::{self, ChatRequest};
+use crate::ai_retrieval::estimate_tokens;
+use crate::database::{AiConversationSummary, AiMessage, Database};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Emitter};
+
+// 对话整理：首轮对话后用低价模型生成标题；较早的消息超过阈值后压缩成滚动摘要，
+// 之后的请求只发送摘要和最近几条消息
+
+lazy_static! {
+    // 正在后台整理的对话，避免同一对话重复生成摘要
+    static ref MAINTAINING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// 未摘要的较早消息估算超过该 token 数时生成摘要
+const SUMMARY_TRIGGER_TOKENS: usize = 6000;
+
+// 最近的消息始终原样发送，不参与摘要
+const KEEP_RECENT_MESSAGES: usize = 8;
+
+// 生成标题和摘要时每条消息最多截取的字符数
+const EXCERPT_CHARS: usize = 2000;
+
+const TITLE_MAX_CHARS: usize = 20;
+
+const TITLE_PROMPT: &str = "根据下面的对话生成一个简短的标题，概括用户想做的事，不超过 15 个字。只输出标题本身，不要引号和句末标点。";
+
+const SUMMARY_PROMPT: &str = "把下面的对话压缩成一段摘要，供之后继续对话时参考。保留关键事实、结论、用户的偏好和要求，以及尚未完成的事项；省略寒暄和重复内容。不超过 400 字，只输出摘要。";
+
+// 后台整理后通知前端刷新对话列表
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConversationUpdate {
+    pub conversation_id: String,
+    pub title: Option<String>,
+    pub summarized: bool,
+}
+
+// 各提供商用于生成标题和摘要的低价模型，未知提供商沿用对话的模型
+pub fn cheap_model(provider: &str, model: &str) -> String {
+    match provider {
+        "deepseek" => "deepseek-chat",
+        "openai" => "gpt-4o-mini",
+        "claude" | "anthropic" => "claude-3-5-haiku-latest",
+        _ => model,
+    }.to_string()
+}
+
+// 对话中参与标题和摘要的消息：跳过出错的回复和空消息
+fn dialog_messages(messages: Vec<AiMessage>) -> Vec<AiMessage> {
+    messages.into_iter()
+        .filter(|message| !message.error && (message.role == "user" || message.role == "assistant"))
+        .filter(|message| !message.content.trim().is_empty())
+        .collect()
+}
+
+fn transcript(messages: &[AiMessage]) -> String {
+    messages.iter()
+        .map(|message| {
+            let speaker = if message.role == "user" { "用户" } else { "助手" };
+            let content: String = message.content.trim().chars().take(EXCERPT_CHARS).collect();
+            format!("{}：{}", speaker, content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// 只取第一行，去掉模型常带的引号、书名号和句末标点
+fn clean_title(reply: &str) -> Option<String> {
+    let line = reply.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = line.strip_prefix("标题：").or_else(|| line.strip_prefix("标题:")).unwrap_or(line);
+    let quote = |c: char| "\"'“”‘’《》「」【】#*".contains(c) || c.is_whitespace();
+    let title: String = line
+        .trim_start_matches(quote)
+        .trim_end_matches(|c: char| quote(c) || "。.！!？?，,；;：:".contains(c))
+        .chars()
+        .take(TITLE_MAX_CHARS)
+        .collect();
+    Some(title).filter(|title| !title.is_empty())
+}
+
+// 用对话所属提供商的低价模型完成一次性请求
+async fn ask_cheap_model(
+    database: &Database,
+    provider: &str,
+    model: &str,
+    instruction: &str,
+    content: String,
+    max_tokens: u32
+) -> Result<String, String> {
+    let backend = ai_providers::resolve(provider);
+    let api_key = resolve_request_key(database, provider, "", backend.requires_api_key())?;
+    let base_url = database.get_ai_provider(provider).map_err(|e| e.to_string())?
+        .and_then(|saved| saved.base_url)
+        .unwrap_or_default();
+
+    let request = ChatRequest {
+        base_url,
+        api_key,
+        model: cheap_model(provider, model),
+        messages: vec![
+            AiChatMessage { role: "system".to_string(), content: instruction.to_string(), ..Default::default() },
+            AiChatMessage { role: "user".to_string(), content, ..Default::default() },
+        ],
+        temperature: 0.3,
+        max_tokens,
+        tools: Vec::new(),
+    };
+    // 与对话请求相同，按 ai_policy 重试和回退
+    let (reply, _) = ai_policy::chat(database, provider, request, RetryPolicy::default()).await;
+    Ok(reply?.content)
+}
+
+// 首轮对话完成后生成标题；较早的消息超过阈值时更新摘要
+pub async fn maintain_conversation(database: &Database, conversation_id: &str) -> Result<ConversationUpdate, String> {
+    let conversation = database.get_ai_conversation(conversation_id).map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("对话不存在: {}", conversation_id))?;
+    let messages = dialog_messages(database.get_ai_messages(conversation_id).map_err(|e| e.to_string())?);
+    let mut update = ConversationUpdate { conversation_id: conversation_id.to_string(), ..Default::default() };
+
+    let user_turns = messages.iter().filter(|message| message.role == "user").count();
+    let answered = messages.last().is_some_and(|message| message.role == "assistant");
+    if user_turns == 1 && answered {
+        let reply = ask_cheap_model(database, &conversation.provider, &conversation.model, TITLE_PROMPT, transcript(&messages), 40).await?;
+        if let Some(title) = clean_title(&reply) {
+            database.save_generated_conversation_title(conversation_id, &title).map_err(|e| format!("更新对话标题失败: {}", e))?;
+            update.title = Some(title);
+        }
+    }
+
+    let previous = database.get_ai_conversation_summary(conversation_id).map_err(|e| e.to_string())?;
+    let pending: Vec<AiMessage> = messages.into_iter()
+        .filter(|message| previous.as_ref().is_none_or(|summary| message.timestamp > summary.until))
+        .collect();
+    let older = &pending[..pending.len().saturating_sub(KEEP_RECENT_MESSAGES)];
+    let older_tokens: usize = older.iter().map(|message| estimate_tokens(&message.content)).sum();
+    if let Some(last) = older.last().filter(|_| older_tokens > SUMMARY_TRIGGER_TOKENS) {
+        let content = match &previous {
+            Some(summary) => format!("此前的摘要：\n{}\n\n后续对话：\n{}", summary.content, transcript(older)),
+            None => transcript(older),
+        };
+        let reply = ask_cheap_model(database, &conversation.provider, &conversation.model, SUMMARY_PROMPT, content, 1024).await?;
+        let summary = reply.trim();
+        if !summary.is_empty() {
+            database.save_ai_conversation_summary(conversation_id, &AiConversationSummary {
+                content: summary.to_string(),
+                until: last.timestamp,
+            }).map_err(|e| format!("保存对话摘要失败: {}", e))?;
+            update.summarized = true;
+        }
+    }
+
+    Ok(update)
+}
+
+// 回复保存后在后台整理对话，有变化时发送 ai-conversation-updated 事件
+pub fn spawn_maintenance(app_handle: AppHandle, database: Arc<Database>, conversation_id: String) {
+    let started = MAINTAINING.lock().map(|mut running| running.insert(conversation_id.clone())).unwrap_or(false);
+    if !started {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let result = maintain_conversation(&database, &conversation_id).await;
+        if let Ok(mut running) = MAINTAINING.lock() {
+            running.remove(&conversation_id);
+        }
+        match result {
+            Ok(update) if update.title.is_some() || update.summarized => {
+                let _ = app_handle.emit("ai-conversation-updated", update);
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("整理对话 {} 失败: {}", conversation_id, e),
+        }
+    });
+}
+
+// 用摘要替换请求中已被摘要覆盖的消息。前端发送的历史与数据库对不上时原样返回
+pub fn compact_history(database: &Database, conversation_id: &str, messages: Vec<AiChatMessage>) -> Vec<AiChatMessage> {
+    let summary = match database.get_ai_conversation_summary(conversation_id) {
+        Ok(Some(summary)) => summary,
+        Ok(None) => return messages,
+        Err(e) => {
+            eprintln!("读取对话摘要失败: {}", e);
+            return messages;
+        }
+    };
+    let covered: Vec<AiMessage> = match database.get_ai_messages(conversation_id) {
+        Ok(saved) => dialog_messages(saved).into_iter().filter(|message| message.timestamp <= summary.until).collect(),
+        Err(e) => {
+            eprintln!("读取对话消息失败: {}", e);
+            return messages;
+        }
+    };
+    let Some(last_covered) = covered.last() else {
+        return messages;
+    };
+
+    // 在请求中找到摘要覆盖的最后一条消息，内容重复时取位置最接近的一条
+    let dialog: Vec<usize> = messages.iter().enumerate()
+        .filter(|(_, message)| message.role == "user" || message.role == "assistant")
+        .map(|(index, _)| index)
+        .collect();
+    let expected = covered.len() - 1;
+    let cut = dialog.iter().enumerate()
+        .filter(|(_, &index)| messages[index].role == last_covered.role && messages[index].content.trim() == last_covered.content.trim())
+        .min_by_key(|(position, _)| position.abs_diff(expected))
+        .map(|(_, &index)| index);
+    // 之后必须还有消息，否则本轮没有可回复的内容
+    let Some(cut) = cut.filter(|&cut| dialog.last().is_some_and(|&last| last > cut)) else {
+        return messages;
+    };
+
+    let summary_message = AiChatMessage {
+        role: "system".to_string(),
+        content: format!("以下是本次对话较早内容的摘要：\n{}", summary.content),
+        ..Default::default()
+    };
+    let leading_system = messages.iter().take_while(|message| message.role == "system").count();
+    let mut compacted = Vec::with_capacity(messages.len());
+    for (index, message) in messages.into_iter().enumerate() {
+        if index == leading_system {
+            compacted.push(summary_message.clone());
+        }
+        // 保留系统消息，去掉已被摘要覆盖的对话和工具消息
+        if index > cut || message.role == "system" {
+            compacted.push(message);
+        }
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_providers::ScriptedProvider;
+
+    fn save_message(database: &Database, conversation_id: &str, index: i64, role: &str, content: &str) {
+        database.save_ai_message(&AiMessage {
+            id: format!("{}-{}", conversation_id, index),
+            conversation_id: conversation_id.to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            provider: None,
+            model: None,
+            error: false,
+            timestamp: index,
+            created_at: String::new(),
+            usage: None,
+            agent_id: None,
+            attachments: Vec::new(),
+        }).unwrap();
+    }
+
+    fn save_conversation(database: &Database, conversation_id: &str, provider: &str) {
+        database.save_ai_conversation(&crate::database::AiConversation {
+            id: conversation_id.to_string(),
+            title: "新对话".to_string(),
+            provider: provider.to_string(),
+            model: "mock-model".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+        }).unwrap();
+    }
+
+    fn message(role: &str, content: &str) -> AiChatMessage {
+        AiChatMessage { role: role.to_string(), content: content.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_clean_title_and_cheap_model() {
+        assert_eq!(clean_title("\n标题：《整理读书笔记》。\n多余的说明").as_deref(), Some("整理读书笔记"));
+        assert_eq!(clean_title("  \"  \" "), None);
+        assert_eq!(cheap_model("claude", "claude-opus-4"), "claude-3-5-haiku-latest");
+        assert_eq!(cheap_model("ollama", "qwen2.5"), "qwen2.5");
+    }
+
+    #[tokio::test]
+    async fn test_title_after_first_exchange_and_rolling_summary() {
+        // 先生成标题，第二次整理时生成摘要
+        let provider = ScriptedProvider::new(vec![
+            ScriptedProvider::text("“整理读书笔记”。"),
+            ScriptedProvider::text("用户在整理读书笔记"),
+        ]);
+        ai_providers::register("summary-test", provider.clone());
+        let database = Database::open_in_memory().unwrap();
+        save_conversation(&database, "conv", "summary-test");
+
+        save_message(&database, "conv", 1, "user", "帮我整理这周的读书笔记");
+        save_message(&database, "conv", 2, "assistant", "好的，先列出书目");
+        let update = maintain_conversation(&database, "conv").await.unwrap();
+        assert_eq!(update.title.as_deref(), Some("整理读书笔记"));
+        assert!(!update.summarized);
+        assert_eq!(database.get_ai_conversation("conv").unwrap().unwrap().title, "整理读书笔记");
+
+        // 第二轮之后不再改标题；较早的消息超过阈值后生成摘要，最近的消息不参与
+        let long = "读".repeat(SUMMARY_TRIGGER_TOKENS);
+        save_message(&database, "conv", 3, "user", &long);
+        for index in 4..(4 + KEEP_RECENT_MESSAGES as i64) {
+            save_message(&database, "conv", index, if index % 2 == 0 { "assistant" } else { "user" }, "继续");
+        }
+        let update = maintain_conversation(&database, "conv").await.unwrap();
+        assert_eq!(update.title, None);
+        assert!(update.summarized);
+        let requests = provider.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].messages[0].content, TITLE_PROMPT);
+        assert_eq!(requests[1].model, "mock-model");
+        assert!(requests[1].messages[1].content.contains("帮我整理这周的读书笔记"));
+
+        let summary = database.get_ai_conversation_summary("conv").unwrap().unwrap();
+        assert_eq!(summary, AiConversationSummary { content: "用户在整理读书笔记".to_string(), until: 3 });
+        // 摘要覆盖的部分不会再次摘要
+        assert!(!maintain_conversation(&database, "conv").await.unwrap().summarized);
+
+        // 前端随后同步对话时保留摘要、消息和生成的标题
+        database.save_ai_conversation(&crate::database::AiConversation {
+            id: "conv".to_string(),
+            title: "帮我整理这周的读书笔记".to_string(),
+            provider: "summary-test".to_string(),
+            model: "other-model".to_string(),
+            created_at: String::new(),
+            updated_at: "2026-01-01T00:00:00Z".to_string(),
+        }).unwrap();
+        let conversation = database.get_ai_conversation("conv").unwrap().unwrap();
+        assert_eq!((conversation.title.as_str(), conversation.model.as_str()), ("整理读书笔记", "other-model"));
+        assert_eq!(database.get_ai_conversation_summary("conv").unwrap().unwrap(), summary);
+        assert_eq!(database.get_ai_messages("conv").unwrap().len(), 3 + KEEP_RECENT_MESSAGES);
+
+        // 用户手动改名后按新标题同步
+        database.update_ai_conversation_title("conv", "读书笔记").unwrap();
+        save_conversation(&database, "conv", "summary-test");
+        assert_eq!(database.get_ai_conversation("conv").unwrap().unwrap().title, "新对话");
+    }
+
+    #[test]
+    fn test_compact_history_replaces_summarized_turns() {
+        let database = Database::open_in_memory().unwrap();
+        save_conversation(&database, "conv", "ollama");
+        save_message(&database, "conv", 1, "user", "问题一");
+        save_message(&database, "conv", 2, "assistant", "回答一");
+        save_message(&database, "conv", 3, "user", "问题二");
+        save_message(&database, "conv", 4, "assistant", "回答二");
+        let request = vec![
+            message("system", "你是助手"),
+            message("user", "问题一"),
+            message("assistant", "回答一"),
+            message("user", "问题二"),
+            message("assistant", "回答二"),
+            message("user", "问题三"),
+        ];
+
+        // 没有摘要时原样发送
+        assert_eq!(compact_history(&database, "conv", request.clone()).len(), 6);
+
+        database.save_ai_conversation_summary("conv", &AiConversationSummary { content: "讨论了问题一".to_string(), until: 2 }).unwrap();
+        let compacted = compact_history(&database, "conv", request.clone());
+        let contents: Vec<&str> = compacted.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(contents, vec!["你是助手", "以下是本次对话较早内容的摘要：\n讨论了问题一", "问题二", "回答二", "问题三"]);
+
+        // 前端发送的历史中找不到已摘要的消息时不做处理
+        let truncated = vec![message("user", "问题二"), message("assistant", "回答二"), message("user", "问题三")];
+        assert_eq!(compact_history(&database, "conv", truncated).len(), 3);
+    }
+}
@@ -0,0 +1,715 @@
+use crate::ai_chat::{self, AiChatMessage};
+use crate::ai_policy::{PolicyRun, RequestMetadata, RetryPolicy};
+use crate::ai_providers::{ChatRequest, ToolCall, ToolSpec};
+use crate::ai_usage::TokenUsage;
+use crate::cardbox_commands;
+use crate::database::Database;
+use futures_util::future::BoxFuture;
+use lazy_static::lazy_static;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::oneshot;
+
+// 一次对话中最多进行的工具调用轮数，防止模型反复调用
+const MAX_TOOL_ROUNDS: usize = 8;
+
+// 等待用户确认写操作的时间，超时按拒绝处理
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    // 等待用户确认的写操作，按 confirmation_id 保存
+    static ref PENDING_CONFIRMATIONS: Mutex<HashMap<String, oneshot::Sender<bool>>> = Mutex::new(HashMap::new());
+
+    static ref TOOLS: Vec<BuiltinTool> = builtin_tools();
+}
+
+// 内置工具，run 在数据库上执行并返回给模型的 JSON 结果
+pub struct BuiltinTool {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+    pub writes: bool,  // 写操作执行前需要用户确认
+    summary: fn(&Value) -> String,
+    run: fn(&Database, &Value) -> Result<Value, String>,
+}
+
+impl BuiltinTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: self.name.to_string(),
+            description: self.description.to_string(),
+            parameters: self.parameters.clone(),
+        }
+    }
+}
+
+fn find_tool(name: &str) -> Option<&'static BuiltinTool> {
+    TOOLS.iter().find(|tool| tool.name == name)
+}
+
+fn builtin_tools() -> Vec<BuiltinTool> {
+    vec![
+        BuiltinTool {
+            name: "search_pages",
+            description: "按关键词搜索知识库页面的标题和内容，返回匹配的页面列表",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string", "description": "搜索关键词" },
+                    "limit": { "type": "integer", "description": "最多返回的页面数，默认 10" }
+                },
+                "required": ["query"]
+            }),
+            writes: false,
+            summary: |args| format!("搜索页面「{}」", arg_str(args, "query").unwrap_or_default()),
+            run: search_pages,
+        },
+        BuiltinTool {
+            name: "get_pending_tasks",
+            description: "获取未完成的任务（待办和进行中），按截止日期排序",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "limit": { "type": "integer", "description": "最多返回的任务数，默认 20" }
+                }
+            }),
+            writes: false,
+            summary: |_| "查看未完成的任务".to_string(),
+            run: get_pending_tasks,
+        },
+        BuiltinTool {
+            name: "create_task",
+            description: "创建一个待办任务",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string", "description": "任务标题" },
+                    "description": { "type": "string", "description": "任务描述" },
+                    "priority": { "type": "string", "enum": ["low", "medium", "high", "urgent"], "description": "优先级，默认 medium" },
+                    "due_date": { "type": "string", "description": "截止日期，格式 YYYY-MM-DD" }
+                },
+                "required": ["title"]
+            }),
+            writes: true,
+            summary: |args| format!("创建任务「{}」", arg_str(args, "title").unwrap_or_default()),
+            run: create_task,
+        },
+        BuiltinTool {
+            name: "record_habit_completion",
+            description: "记录某个习惯在某一天的完成情况",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "habit": { "type": "string", "description": "习惯名称或 ID" },
+                    "date": { "type": "string", "description": "日期，格式 YYYY-MM-DD，默认今天" },
+                    "count": { "type": "integer", "description": "完成次数，默认 1" },
+                    "notes": { "type": "string", "description": "备注" }
+                },
+                "required": ["habit"]
+            }),
+            writes: true,
+            summary: |args| format!(
+                "记录习惯「{}」{}完成",
+                arg_str(args, "habit").unwrap_or_default(),
+                arg_str(args, "date").map(|date| format!("在 {} ", date)).unwrap_or_default()
+            ),
+            run: record_habit_completion,
+        },
+        BuiltinTool {
+            name: "create_timeline_entry",
+            description: "在时光记中添加一条记录",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "content": { "type": "string", "description": "记录内容" },
+                    "date": { "type": "string", "description": "日期，格式 YYYY-MM-DD，默认今天" },
+                    "time": { "type": "string", "description": "时间，格式 HH:MM，默认当前时间" },
+                    "mood": { "type": "string", "description": "心情" },
+                    "weather": { "type": "string", "description": "天气" }
+                },
+                "required": ["content"]
+            }),
+            writes: true,
+            summary: |args| format!("添加时光记：{}", arg_str(args, "content").unwrap_or_default()),
+            run: create_timeline_entry,
+        },
+        BuiltinTool {
+            name: "create_card",
+            description: "在卡片盒中新建一张卡片",
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "title": { "type": "string", "description": "卡片标题" },
+                    "content": { "type": "string", "description": "卡片正文，纯文本，空行分段" },
+                    "box": { "type": "string", "description": "卡片盒名称或 ID，默认第一个卡片盒" }
+                },
+                "required": ["title", "content"]
+            }),
+            writes: true,
+            summary: |args| format!("新建卡片「{}」", arg_str(args, "title").unwrap_or_default()),
+            run: create_card,
+        },
+    ]
+}
+
+// ===== 参数解析 =====
+
+fn arg_str<'a>(args: &'a Value, key: &str) -> Option<&'a str> {
+    args[key].as_str().map(str::trim).filter(|value| !value.is_empty())
+}
+
+fn required_str<'a>(args: &'a Value, key: &str) -> Result<&'a str, String> {
+    arg_str(args, key).ok_or_else(|| format!("缺少参数 {}", key))
+}
+
+// 模型有时把数字写成字符串
+fn arg_i64(args: &Value, key: &str) -> Option<i64> {
+    args[key].as_i64().or_else(|| args[key].as_str().and_then(|value| value.trim().parse().ok()))
+}
+
+fn arg_date(args: &Value, key: &str) -> Result<String, String> {
+    match arg_str(args, key) {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .map_err(|_| format!("{} 的格式应为 YYYY-MM-DD", key)),
+        None => Ok(chrono::Local::now().format("%Y-%m-%d").to_string()),
+    }
+}
+
+// ===== 工具实现 =====
+
+fn search_pages(database: &Database, args: &Value) -> Result<Value, String> {
+    let query = required_str(args, "query")?;
+    let limit = arg_i64(args, "limit").unwrap_or(10).clamp(1, 50) as usize;
+
+    let mut results = Vec::new();
+    for kb in database.get_knowledge_bases().map_err(|e| e.to_string())? {
+        for page in database.search_pages(&kb.id, query).map_err(|e| e.to_string())? {
+            results.push(json!({
+                "id": page.id,
+                "title": page.title,
+                "knowledge_base": kb.name,
+                "updated_at": page.updated_at,
+            }));
+        }
+    }
+    results.truncate(limit);
+    Ok(json!({ "pages": results }))
+}
+
+fn get_pending_tasks(database: &Database, args: &Value) -> Result<Value, String> {
+    let limit = arg_i64(args, "limit").unwrap_or(20).clamp(1, 100) as usize;
+    let mut tasks: Vec<_> = database.get_all_tasks().map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|task| task.status == "todo" || task.status == "in_progress")
+        .collect();
+    // 有截止日期的排在前面
+    tasks.sort_by(|a, b| match (&a.due_date, &b.due_date) {
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    tasks.truncate(limit);
+
+    let tasks: Vec<Value> = tasks.iter()
+        .map(|task| json!({
+            "id": task.id,
+            "title": task.title,
+            "status": task.status,
+            "priority": task.priority,
+            "due_date": task.due_date,
+        }))
+        .collect();
+    Ok(json!({ "tasks": tasks }))
+}
+
+fn create_task(database: &Database, args: &Value) -> Result<Value, String> {
+    let title = required_str(args, "title")?;
+    let priority = arg_str(args, "priority").unwrap_or("medium");
+    if !["low", "medium", "high", "urgent"].contains(&priority) {
+        return Err("priority 只能是 low、medium、high 或 urgent".to_string());
+    }
+    let due_date = match arg_str(args, "due_date") {
+        Some(_) => Some(arg_date(args, "due_date")?),
+        None => None,
+    };
+
+    let id = database.create_task(title, arg_str(args, "description"), "todo", priority, due_date.as_deref(), None)
+        .map_err(|e| e.to_string())?;
+    Ok(json!({ "id": id, "title": title, "priority": priority, "due_date": due_date }))
+}
+
+fn record_habit_completion(database: &Database, args: &Value) -> Result<Value, String> {
+    let habit_name = required_str(args, "habit")?;
+    let habits = database.get_habits().map_err(|e| e.to_string())?;
+    let habit = habits.iter()
+        .find(|habit| habit.id.map(|id| id.to_string()).as_deref() == Some(habit_name))
+        .or_else(|| habits.iter().find(|habit| habit.name.eq_ignore_ascii_case(habit_name)))
+        .ok_or_else(|| {
+            let names: Vec<&str> = habits.iter().map(|habit| habit.name.as_str()).collect();
+            format!("找不到习惯「{}」，已有的习惯：{}", habit_name, names.join("、"))
+        })?;
+    let habit_id = habit.id.ok_or_else(|| "习惯缺少 ID".to_string())?;
+    let date = arg_date(args, "date")?;
+    let count = arg_i64(args, "count").unwrap_or(1).max(1);
+
+    database.record_habit_completion(habit_id, &date, count, arg_str(args, "notes"))
+        .map_err(|e| e.to_string())?;
+    Ok(json!({ "habit": habit.name, "date": date, "completed_count": count }))
+}
+
+fn create_timeline_entry(database: &Database, args: &Value) -> Result<Value, String> {
+    let content = required_str(args, "content")?;
+    let date = arg_date(args, "date")?;
+    let time = match arg_str(args, "time") {
+        Some(time) => chrono::NaiveTime::parse_from_str(time, "%H:%M")
+            .map(|time| time.format("%H:%M").to_string())
+            .map_err(|_| "time 的格式应为 HH:MM".to_string())?,
+        None => chrono::Local::now().format("%H:%M").to_string(),
+    };
+
+    let id = database.create_timeline_entry(&date, &time, content, arg_str(args, "weather"), arg_str(args, "mood"), None)
+        .map_err(|e| e.to_string())?;
+    Ok(json!({ "id": id, "date": date, "time": time }))
+}
+
+// 卡片正文保存为 HTML
+fn text_to_html(text: &str) -> String {
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let escaped = paragraph.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+            format!("<p>{}</p>", escaped.replace('\n', "<br>"))
+        })
+        .collect()
+}
+
+fn create_card(database: &Database, args: &Value) -> Result<Value, String> {
+    let title = required_str(args, "title")?.to_string();
+    let content = text_to_html(required_str(args, "content")?);
+    let box_name = arg_str(args, "box");
+
+    database.with_connection(|conn| {
+        let card_box: Option<(String, String)> = match box_name {
+            Some(name) => conn.query_row(
+                "SELECT id, name FROM card_boxes WHERE id = ?1 OR name = ?1 LIMIT 1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).optional()?,
+            None => conn.query_row(
+                "SELECT id, name FROM card_boxes ORDER BY sort_order, created_at LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).optional()?,
+        };
+        let Some((box_id, box_name)) = card_box else {
+            return Ok(Err(match box_name {
+                Some(name) => format!("找不到卡片盒「{}」", name),
+                None => "还没有卡片盒，请先创建卡片盒".to_string(),
+            }));
+        };
+
+        let card = cardbox_commands::insert_card(conn, box_id, title, content)?;
+        Ok(Ok(json!({ "id": card.id, "title": card.title, "box": box_name })))
+    })?
+}
+
+// ===== 工具调用循环 =====
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCallStatus {
+    Ok,
+    Error,
+    Rejected,    // 用户拒绝了写操作
+    NotAllowed,  // 不在智能体的工具白名单中
+}
+
+// 一次工具调用的结果，随回答一起返回给前端展示
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub id: String,
+    pub name: String,
+    pub summary: String,
+    pub arguments: Value,
+    pub result: Value,
+    pub status: ToolCallStatus,
+}
+
+// 写操作的确认请求，通过 ai-tool-confirm 事件发给前端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolConfirmation {
+    pub confirmation_id: String,
+    pub request_id: String,
+    pub tool: String,
+    pub summary: String,
+    pub arguments: Value,
+}
+
+// 工具调用循环与界面的交互：确认写操作、报告调用结果
+pub trait ToolHost: Send + Sync {
+    fn confirm<'a>(&'a self, tool: &'a BuiltinTool, call: &'a ToolCall) -> BoxFuture<'a, bool>;
+
+    fn on_tool_result(&self, _record: &ToolCallRecord) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentReply {
+    pub content: String,
+    pub usage: Option<TokenUsage>,  // 各轮请求的用量之和
+    pub tool_calls: Vec<ToolCallRecord>,
+}
+
+async fn execute_call(database: &Database, call: &ToolCall, allowed: &[String], host: &dyn ToolHost) -> ToolCallRecord {
+    let record = |summary: String, result: Value, status: ToolCallStatus| ToolCallRecord {
+        id: call.id.clone(),
+        name: call.name.clone(),
+        summary,
+        arguments: call.arguments.clone(),
+        result,
+        status,
+    };
+
+    let tool = match find_tool(&call.name) {
+        Some(tool) if allowed.iter().any(|name| name == tool.name) => tool,
+        _ => return record(
+            call.name.clone(),
+            json!({ "error": format!("当前智能体不能使用工具 {}", call.name) }),
+            ToolCallStatus::NotAllowed,
+        ),
+    };
+
+    let summary = (tool.summary)(&call.arguments);
+    if tool.writes && !host.confirm(tool, call).await {
+        return record(summary, json!({ "error": "用户拒绝了该操作" }), ToolCallStatus::Rejected);
+    }
+    match (tool.run)(database, &call.arguments) {
+        Ok(result) => record(summary, result, ToolCallStatus::Ok),
+        Err(e) => record(summary, json!({ "error": e }), ToolCallStatus::Error),
+    }
+}
+
+// 反复请求模型并执行其调用的工具，直到模型给出不含工具调用的回答。
+// 第一轮按 ai_policy 重试和回退；之后的轮次固定使用第一轮实际响应的提供商和模型，只在其上重试
+pub async fn run_tool_loop(
+    database: &Database,
+    provider: &str,
+    mut request: ChatRequest,
+    allowed: &[String],
+    host: &dyn ToolHost
+) -> (Result<AgentReply, String>, RequestMetadata) {
+    request.tools = TOOLS.iter()
+        .filter(|tool| allowed.iter().any(|name| name == tool.name))
+        .map(BuiltinTool::spec)
+        .collect();
+
+    let mut run = PolicyRun::new(database, provider, request, RetryPolicy::default());
+    let mut usage: Option<TokenUsage> = None;
+    let mut records = Vec::new();
+    for _ in 0..MAX_TOOL_ROUNDS {
+        let Some(reply) = run.chat().await else {
+            let metadata = run.metadata.clone();
+            return (Err(run.into_error()), metadata);
+        };
+        if let Some(reply_usage) = &reply.usage {
+            usage.get_or_insert_with(TokenUsage::default).add(reply_usage);
+        }
+        if reply.tool_calls.is_empty() {
+            return (Ok(AgentReply { content: reply.content, usage, tool_calls: records }), run.metadata);
+        }
+
+        let mut messages = vec![AiChatMessage {
+            role: "assistant".to_string(),
+            content: reply.content,
+            tool_calls: Some(reply.tool_calls.clone()),
+            ..Default::default()
+        }];
+        for call in &reply.tool_calls {
+            let record = execute_call(database, call, allowed, host).await;
+            host.on_tool_result(&record);
+            messages.push(AiChatMessage {
+                role: "tool".to_string(),
+                content: record.result.to_string(),
+                tool_call_id: Some(call.id.clone()),
+                ..Default::default()
+            });
+            records.push(record);
+        }
+        if let Some(request) = run.pin() {
+            request.messages.extend(messages);
+        }
+    }
+    (Err(format!("工具调用超过 {} 轮，已停止", MAX_TOOL_ROUNDS)), run.metadata)
+}
+
+// ===== Tauri 命令 =====
+
+// 通过事件请求前端确认，前端调用 confirm_ai_tool_call 回复
+pub struct EventToolHost {
+    app: AppHandle,
+    request_id: String,
+}
+
+impl EventToolHost {
+    pub fn new(app: AppHandle, request_id: String) -> Self {
+        Self { app, request_id }
+    }
+}
+
+// 确认结束（包括超时和请求被中止）时移除等待项
+struct PendingConfirmation {
+    confirmation_id: String,
+}
+
+impl Drop for PendingConfirmation {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = PENDING_CONFIRMATIONS.lock() {
+            pending.remove(&self.confirmation_id);
+        }
+    }
+}
+
+impl ToolHost for EventToolHost {
+    fn confirm<'a>(&'a self, tool: &'a BuiltinTool, call: &'a ToolCall) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            let confirmation = ToolConfirmation {
+                confirmation_id: uuid::Uuid::new_v4().to_string(),
+                request_id: self.request_id.clone(),
+                tool: tool.name.to_string(),
+                summary: (tool.summary)(&call.arguments),
+                arguments: call.arguments.clone(),
+            };
+            let (sender, receiver) = oneshot::channel();
+            match PENDING_CONFIRMATIONS.lock() {
+                Ok(mut pending) => pending.insert(confirmation.confirmation_id.clone(), sender),
+                Err(_) => return false,
+            };
+            let _pending = PendingConfirmation { confirmation_id: confirmation.confirmation_id.clone() };
+
+            if self.app.emit("ai-tool-confirm", &confirmation).is_err() {
+                return false;
+            }
+            matches!(tokio::time::timeout(CONFIRM_TIMEOUT, receiver).await, Ok(Ok(true)))
+        })
+    }
+
+    fn on_tool_result(&self, record: &ToolCallRecord) {
+        let _ = self.app.emit("ai-tool-result", json!({ "request_id": self.request_id, "record": record }));
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiToolInfo {
+    pub name: String,
+    pub description: String,
+    pub writes: bool,
+}
+
+// 提供商、模型和采样参数都取自智能体配置，请求只带消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentToolRequest {
+    pub agent_id: String,
+    // 确认事件和 cancel_ai_stream 使用的请求 ID
+    pub request_id: String,
+    pub messages: Vec<AiChatMessage>,
+    // 指定时保存本轮的用户消息和回复
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentToolResponse {
+    pub success: bool,
+    pub content: Option<String>,
+    pub message: Option<String>,
+    pub usage: Option<TokenUsage>,
+    pub tool_calls: Vec<ToolCallRecord>,
+}
+
+// 列出内置工具，供智能体设置中勾选
+#[tauri::command]
+pub async fn get_ai_tools() -> Result<Vec<AiToolInfo>, String> {
+    Ok(TOOLS.iter()
+        .map(|tool| AiToolInfo {
+            name: tool.name.to_string(),
+            description: tool.description.to_string(),
+            writes: tool.writes,
+        })
+        .collect())
+}
+
+// 回复写操作确认，确认请求已结束时返回 false
+#[tauri::command]
+pub async fn confirm_ai_tool_call(confirmation_id: String, approved: bool) -> Result<bool, String> {
+    let sender = PENDING_CONFIRMATIONS.lock()
+        .map_err(|_| "确认状态不可用".to_string())?
+        .remove(&confirmation_id);
+    Ok(sender.is_some_and(|sender| sender.send(approved).is_ok()))
+}
+
+// 带工具调用的智能体对话，只能使用该智能体白名单中的工具
+#[tauri::command]
+pub async fn run_ai_agent(
+    app: AppHandle,
+    database: State<'_, Arc<Database>>,
+    request: AgentToolRequest
+) -> Result<AgentToolResponse, String> {
+    let registration = ai_chat::register_stream(&request.request_id)?;
+    let host = EventToolHost::new(app, request.request_id.clone());
+
+    let result = ai_chat::run_cancellable(
+        registration,
+        ai_chat::run_agent_chat(&database, &request.agent_id, request.conversation_id.as_deref(), request.messages, &host),
+    ).await;
+    Ok(match result {
+        Ok(Ok(response)) => AgentToolResponse {
+            success: response.success,
+            content: response.content,
+            message: response.message.filter(|_| !response.success),
+            usage: response.usage,
+            tool_calls: response.tool_calls,
+        },
+        Ok(Err(e)) => AgentToolResponse { success: false, content: None, message: Some(e), usage: None, tool_calls: Vec::new() },
+        Err(_) => AgentToolResponse {
+            success: false,
+            content: None,
+            message: Some("已停止".to_string()),
+            usage: None,
+            tool_calls: Vec::new(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_providers::{self, ChatReply, ScriptedProvider};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct AutoConfirm {
+        approve: bool,
+        asked: AtomicUsize,
+    }
+
+    impl ToolHost for AutoConfirm {
+        fn confirm<'a>(&'a self, _tool: &'a BuiltinTool, _call: &'a ToolCall) -> BoxFuture<'a, bool> {
+            self.asked.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { self.approve })
+        }
+    }
+
+    fn tool_reply(calls: Vec<(&str, Value)>) -> ChatReply {
+        ChatReply {
+            content: String::new(),
+            usage: Some(TokenUsage { input_tokens: 10, output_tokens: 5, ..Default::default() }),
+            tool_calls: calls.into_iter().enumerate()
+                .map(|(index, (name, arguments))| ToolCall { id: format!("call_{}", index), name: name.to_string(), arguments })
+                .collect(),
+        }
+    }
+
+    fn chat_request() -> ChatRequest {
+        ChatRequest {
+            base_url: String::new(),
+            api_key: String::new(),
+            model: "test".to_string(),
+            messages: vec![AiChatMessage { role: "user".to_string(), content: "帮我记一下".to_string(), ..Default::default() }],
+            temperature: 0.7,
+            max_tokens: 100,
+            tools: Vec::new(),
+        }
+    }
+
+    fn allowed(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_loop_executes_tools_and_returns_answer() {
+        let database = Database::open_in_memory().unwrap();
+        let provider = ScriptedProvider::new(vec![
+            Ok(tool_reply(vec![
+                ("create_task", json!({ "title": "写周报", "priority": "high", "due_date": "2026-10-20" })),
+                ("get_pending_tasks", json!({})),
+            ])),
+            ScriptedProvider::text("已创建任务「写周报」"),
+        ]);
+        ai_providers::register("tools-loop-test", provider.clone());
+        let host = AutoConfirm { approve: true, asked: AtomicUsize::new(0) };
+
+        let reply = run_tool_loop(&database, "tools-loop-test", chat_request(), &allowed(&["create_task", "get_pending_tasks"]), &host)
+            .await.0.unwrap();
+        assert_eq!(reply.content, "已创建任务「写周报」");
+        assert_eq!(reply.usage.map(|usage| usage.input_tokens), Some(10));
+        assert_eq!(reply.tool_calls.len(), 2);
+        assert!(reply.tool_calls.iter().all(|record| record.status == ToolCallStatus::Ok));
+        assert_eq!(reply.tool_calls[1].result["tasks"][0]["title"], "写周报");
+        // 只有写操作需要确认
+        assert_eq!(host.asked.load(Ordering::SeqCst), 1);
+
+        // 第二轮请求带上了工具定义、工具调用和工具结果
+        let requests = provider.requests();
+        assert_eq!(requests[0].tools.len(), 2);
+        let messages = &requests[1].messages;
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].tool_calls.as_ref().unwrap().len(), 2);
+        assert_eq!(messages[2].role, "tool");
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call_0"));
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_and_rejection() {
+        let database = Database::open_in_memory().unwrap();
+        let provider = ScriptedProvider::new(vec![
+            Ok(tool_reply(vec![
+                ("create_timeline_entry", json!({ "content": "散步" })),
+                ("create_task", json!({ "title": "不允许" })),
+            ])),
+            ScriptedProvider::text("好的"),
+        ]);
+        ai_providers::register("tools-allowlist-test", provider.clone());
+        let host = AutoConfirm { approve: false, asked: AtomicUsize::new(0) };
+
+        let reply = run_tool_loop(&database, "tools-allowlist-test", chat_request(), &allowed(&["create_timeline_entry"]), &host)
+            .await.0.unwrap();
+        let statuses: Vec<&ToolCallStatus> = reply.tool_calls.iter().map(|record| &record.status).collect();
+        assert_eq!(statuses, vec![&ToolCallStatus::Rejected, &ToolCallStatus::NotAllowed]);
+        assert_eq!(provider.requests()[0].tools.len(), 1);
+
+        // 被拒绝和不允许的调用都没有写入数据
+        let count = database.with_connection(|conn| {
+            conn.query_row("SELECT (SELECT COUNT(*) FROM tasks) + (SELECT COUNT(*) FROM timeline_entries)", [], |row| row.get::<_, i64>(0))
+        }).unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_write_tools() {
+        let database = Database::open_in_memory().unwrap();
+        database.with_connection(|conn| {
+            conn.execute_batch(
+                "INSERT INTO habits (name) VALUES ('Reading');
+                 INSERT INTO card_boxes (id, name, sort_order) VALUES ('box', '灵感', 1);"
+            )
+        }).unwrap();
+
+        let recorded = record_habit_completion(&database, &json!({ "habit": "reading", "date": "2026-10-01", "count": "2" })).unwrap();
+        assert_eq!(recorded, json!({ "habit": "Reading", "date": "2026-10-01", "completed_count": 2 }));
+        assert!(record_habit_completion(&database, &json!({ "habit": "跑步" })).unwrap_err().contains("Reading"));
+        assert!(create_task(&database, &json!({ "title": "x", "due_date": "下周" })).is_err());
+
+        let card = create_card(&database, &json!({ "title": "想法", "content": "a < b\n\n第二段" })).unwrap();
+        assert_eq!(card["box"], "灵感");
+        let content: String = database.with_connection(|conn| {
+            conn.query_row("SELECT content FROM cards WHERE title = '想法'", [], |row| row.get(0))
+        }).unwrap();
+        assert_eq!(content, "<p>a &lt; b</p><p>第二段</p>");
+    }
+}
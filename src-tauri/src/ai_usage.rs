@@ -0,0 +1,460 @@
+use crate::database::Database;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tauri::State;
+
+// 一次请求的 token 用量。input_tokens 不含命中缓存的部分，便于分别计价
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    #[serde(default)]
+    pub cache_write_tokens: u64,
+}
+
+impl TokenUsage {
+    // OpenAI 兼容接口的 usage：prompt_tokens 包含缓存命中，
+    // DeepSeek 用 prompt_cache_hit_tokens，OpenAI 用 prompt_tokens_details.cached_tokens
+    pub fn from_openai(usage: &Value) -> Option<Self> {
+        let prompt = usage.get("prompt_tokens")?.as_u64()?;
+        let cached = usage["prompt_cache_hit_tokens"].as_u64()
+            .or_else(|| usage["prompt_tokens_details"]["cached_tokens"].as_u64())
+            .unwrap_or(0);
+        Some(Self {
+            input_tokens: prompt.saturating_sub(cached),
+            output_tokens: usage["completion_tokens"].as_u64().unwrap_or(0),
+            cache_read_tokens: cached,
+            cache_write_tokens: 0,
+        })
+    }
+
+    // Anthropic 的 usage；流式的 message_delta 只带部分字段，按已有值合并
+    pub fn merge_anthropic(&mut self, usage: &Value) {
+        if let Some(tokens) = usage["input_tokens"].as_u64() {
+            self.input_tokens = tokens;
+        }
+        if let Some(tokens) = usage["output_tokens"].as_u64() {
+            self.output_tokens = tokens;
+        }
+        if let Some(tokens) = usage["cache_read_input_tokens"].as_u64() {
+            self.cache_read_tokens = tokens;
+        }
+        if let Some(tokens) = usage["cache_creation_input_tokens"].as_u64() {
+            self.cache_write_tokens = tokens;
+        }
+    }
+
+    // 累加多次请求的用量，例如工具调用的多轮请求
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+    }
+
+    // Ollama 在最后一个分块中返回 prompt_eval_count 和 eval_count
+    pub fn from_ollama(chunk: &Value) -> Option<Self> {
+        let input = chunk["prompt_eval_count"].as_u64();
+        let output = chunk["eval_count"].as_u64();
+        if input.is_none() && output.is_none() {
+            return None;
+        }
+        Some(Self {
+            input_tokens: input.unwrap_or(0),
+            output_tokens: output.unwrap_or(0),
+            ..Self::default()
+        })
+    }
+}
+
+// 模型单价（美元 / 百万 token）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub provider: String,
+    pub model: String,  // 按前缀匹配，例如 claude-3-5-sonnet 匹配 claude-3-5-sonnet-20241022
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+    pub cache_read_per_mtok: f64,
+    pub cache_write_per_mtok: f64,
+    #[serde(default)]
+    pub custom: bool,  // 用户修改过的价格
+}
+
+// 内置参考价格，官方调价后可在设置中覆盖
+const BUILTIN_PRICES: &[(&str, &str, f64, f64, f64, f64)] = &[
+    ("deepseek", "deepseek-chat", 0.27, 1.10, 0.07, 0.0),
+    ("deepseek", "deepseek-reasoner", 0.55, 2.19, 0.14, 0.0),
+    ("claude", "claude-3-haiku", 0.25, 1.25, 0.03, 0.30),
+    ("claude", "claude-3-5-haiku", 0.80, 4.0, 0.08, 1.0),
+    ("claude", "claude-3-5-sonnet", 3.0, 15.0, 0.30, 3.75),
+    ("claude", "claude-3-7-sonnet", 3.0, 15.0, 0.30, 3.75),
+    ("claude", "claude-sonnet-4", 3.0, 15.0, 0.30, 3.75),
+    ("claude", "claude-3-opus", 15.0, 75.0, 1.50, 18.75),
+    ("claude", "claude-opus-4", 15.0, 75.0, 1.50, 18.75),
+    ("openai", "gpt-4o", 2.50, 10.0, 1.25, 0.0),
+    ("openai", "gpt-4o-mini", 0.15, 0.60, 0.075, 0.0),
+];
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_mtok
+            + usage.output_tokens as f64 * self.output_per_mtok
+            + usage.cache_read_tokens as f64 * self.cache_read_per_mtok
+            + usage.cache_write_tokens as f64 * self.cache_write_per_mtok) / 1_000_000.0
+    }
+}
+
+fn builtin_prices() -> Vec<ModelPrice> {
+    BUILTIN_PRICES.iter()
+        .map(|&(provider, model, input, output, cache_read, cache_write)| ModelPrice {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_per_mtok: input,
+            output_per_mtok: output,
+            cache_read_per_mtok: cache_read,
+            cache_write_per_mtok: cache_write,
+            custom: false,
+        })
+        .collect()
+}
+
+// 内置价格与用户覆盖的价格合并，同一模型以用户设置为准
+fn load_prices(database: &Database) -> Result<Vec<ModelPrice>, String> {
+    let custom: Vec<ModelPrice> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT provider, model, input_per_mtok, output_per_mtok, cache_read_per_mtok, cache_write_per_mtok
+             FROM ai_model_prices ORDER BY provider, model"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ModelPrice {
+                provider: row.get(0)?,
+                model: row.get(1)?,
+                input_per_mtok: row.get(2)?,
+                output_per_mtok: row.get(3)?,
+                cache_read_per_mtok: row.get(4)?,
+                cache_write_per_mtok: row.get(5)?,
+                custom: true,
+            })
+        })?;
+        rows.collect()
+    })?;
+
+    let mut prices: Vec<ModelPrice> = builtin_prices().into_iter()
+        .filter(|builtin| !custom.iter().any(|price| price.provider == builtin.provider && price.model == builtin.model))
+        .collect();
+    prices.extend(custom);
+    Ok(prices)
+}
+
+// "anthropic" 与 "claude" 在注册表中是同一个后端，价格统一按 claude 查找
+fn price_provider(provider: &str) -> &str {
+    match provider {
+        "anthropic" => "claude",
+        provider => provider,
+    }
+}
+
+// 按提供商和最长模型名前缀查找价格
+fn find_price<'a>(prices: &'a [ModelPrice], provider: &str, model: &str) -> Option<&'a ModelPrice> {
+    let provider = price_provider(provider);
+    prices.iter()
+        .filter(|price| price_provider(&price.provider) == provider && model.starts_with(&price.model))
+        .max_by_key(|price| price.model.len())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UsageBucket {
+    pub key: String,
+    pub label: String,
+    pub message_count: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_write_tokens: u64,
+    pub estimated_cost_usd: f64,
+    pub unpriced_messages: u64,  // 没有价格的模型（如本地模型）不计入费用
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AiUsageReport {
+    pub total: UsageBucket,
+    pub by_day: Vec<UsageBucket>,
+    pub by_provider: Vec<UsageBucket>,
+    pub by_model: Vec<UsageBucket>,
+    pub by_agent: Vec<UsageBucket>,
+}
+
+impl UsageBucket {
+    fn add(&mut self, count: u64, usage: &TokenUsage, cost: Option<f64>) {
+        self.message_count += count;
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+        self.cache_read_tokens += usage.cache_read_tokens;
+        self.cache_write_tokens += usage.cache_write_tokens;
+        match cost {
+            Some(cost) => self.estimated_cost_usd += cost,
+            None => self.unpriced_messages += count,
+        }
+    }
+}
+
+// 按 (日期, 提供商, 模型, 智能体) 汇总后的一组用量
+struct UsageGroup {
+    day: String,
+    provider: String,
+    model: String,
+    agent_id: Option<String>,
+    agent_name: Option<String>,
+    count: u64,
+    usage: TokenUsage,
+}
+
+fn build_report(groups: &[UsageGroup], prices: &[ModelPrice]) -> AiUsageReport {
+    let mut total = UsageBucket { key: "total".to_string(), label: "合计".to_string(), ..UsageBucket::default() };
+    let mut by_day: BTreeMap<String, UsageBucket> = BTreeMap::new();
+    let mut by_provider: BTreeMap<String, UsageBucket> = BTreeMap::new();
+    let mut by_model: BTreeMap<String, UsageBucket> = BTreeMap::new();
+    let mut by_agent: BTreeMap<String, UsageBucket> = BTreeMap::new();
+
+    for group in groups {
+        let cost = find_price(prices, &group.provider, &group.model).map(|price| price.cost(&group.usage));
+        let agent_key = group.agent_id.clone().unwrap_or_default();
+        let agent_label = group.agent_name.clone()
+            .unwrap_or_else(|| if agent_key.is_empty() { "未指定智能体".to_string() } else { agent_key.clone() });
+
+        for (buckets, key, label) in [
+            (&mut by_day, group.day.clone(), group.day.clone()),
+            (&mut by_provider, group.provider.clone(), group.provider.clone()),
+            (&mut by_model, format!("{}/{}", group.provider, group.model), group.model.clone()),
+            (&mut by_agent, agent_key, agent_label),
+        ] {
+            buckets.entry(key.clone())
+                .or_insert_with(|| UsageBucket { key, label, ..UsageBucket::default() })
+                .add(group.count, &group.usage, cost);
+        }
+        total.add(group.count, &group.usage, cost);
+    }
+
+    // 按天按时间顺序，其余按费用从高到低
+    let by_cost = |buckets: BTreeMap<String, UsageBucket>| {
+        let mut buckets: Vec<UsageBucket> = buckets.into_values().collect();
+        buckets.sort_by(|a, b| b.estimated_cost_usd.total_cmp(&a.estimated_cost_usd)
+            .then_with(|| (b.input_tokens + b.output_tokens).cmp(&(a.input_tokens + a.output_tokens))));
+        buckets
+    };
+    AiUsageReport {
+        total,
+        by_day: by_day.into_values().collect(),
+        by_provider: by_cost(by_provider),
+        by_model: by_cost(by_model),
+        by_agent: by_cost(by_agent),
+    }
+}
+
+// 统计最近 days 天（默认 30 天，0 表示全部）有用量记录的助手消息
+#[tauri::command]
+pub async fn get_ai_usage_report(
+    database: State<'_, Arc<Database>>,
+    days: Option<i64>
+) -> Result<AiUsageReport, String> {
+    let days = days.unwrap_or(30);
+    let since_ms = if days > 0 {
+        chrono::Utc::now().timestamp_millis() - days * 24 * 60 * 60 * 1000
+    } else {
+        0
+    };
+
+    let groups = load_usage_groups(&database, since_ms)?;
+    Ok(build_report(&groups, &load_prices(&database)?))
+}
+
+fn load_usage_groups(database: &Database, since_ms: i64) -> Result<Vec<UsageGroup>, String> {
+    database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT DATE(m.timestamp / 1000, 'unixepoch', 'localtime') AS day,
+                    COALESCE(m.provider, c.provider), COALESCE(m.model, c.model),
+                    m.agent_id, a.name, COUNT(*),
+                    SUM(COALESCE(m.input_tokens, 0)), SUM(COALESCE(m.output_tokens, 0)),
+                    SUM(COALESCE(m.cache_read_tokens, 0)), SUM(COALESCE(m.cache_write_tokens, 0))
+             FROM ai_messages m
+             JOIN ai_conversations c ON c.id = m.conversation_id
+             LEFT JOIN ai_agents a ON a.agent_id = m.agent_id
+             WHERE m.role = 'assistant' AND m.timestamp >= ?1
+               AND (m.input_tokens IS NOT NULL OR m.output_tokens IS NOT NULL)
+             GROUP BY day, 2, 3, m.agent_id
+             ORDER BY day"
+        )?;
+        let rows = stmt.query_map(params![since_ms], |row| {
+            Ok(UsageGroup {
+                day: row.get(0)?,
+                provider: row.get(1)?,
+                model: row.get(2)?,
+                agent_id: row.get(3)?,
+                agent_name: row.get(4)?,
+                count: row.get::<_, i64>(5)? as u64,
+                usage: TokenUsage {
+                    input_tokens: row.get::<_, i64>(6)? as u64,
+                    output_tokens: row.get::<_, i64>(7)? as u64,
+                    cache_read_tokens: row.get::<_, i64>(8)? as u64,
+                    cache_write_tokens: row.get::<_, i64>(9)? as u64,
+                },
+            })
+        })?;
+        rows.collect()
+    })
+}
+
+#[tauri::command]
+pub async fn get_ai_model_prices(database: State<'_, Arc<Database>>) -> Result<Vec<ModelPrice>, String> {
+    load_prices(&database)
+}
+
+// 保存自定义价格，覆盖同名的内置价格
+#[tauri::command]
+pub async fn save_ai_model_price(
+    database: State<'_, Arc<Database>>,
+    price: ModelPrice
+) -> Result<(), String> {
+    if price.provider.trim().is_empty() || price.model.trim().is_empty() {
+        return Err("提供商和模型不能为空".to_string());
+    }
+    let rates = [price.input_per_mtok, price.output_per_mtok, price.cache_read_per_mtok, price.cache_write_per_mtok];
+    if rates.iter().any(|rate| !rate.is_finite() || *rate < 0.0) {
+        return Err("价格不能为负数".to_string());
+    }
+
+    database.with_connection(|conn| {
+        conn.execute(
+            "INSERT INTO ai_model_prices (provider, model, input_per_mtok, output_per_mtok, cache_read_per_mtok, cache_write_per_mtok, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, DATETIME('now'))
+             ON CONFLICT(provider, model) DO UPDATE SET
+                 input_per_mtok = excluded.input_per_mtok,
+                 output_per_mtok = excluded.output_per_mtok,
+                 cache_read_per_mtok = excluded.cache_read_per_mtok,
+                 cache_write_per_mtok = excluded.cache_write_per_mtok,
+                 updated_at = excluded.updated_at",
+            params![price.provider.trim(), price.model.trim(), price.input_per_mtok, price.output_per_mtok,
+                    price.cache_read_per_mtok, price.cache_write_per_mtok],
+        )?;
+        Ok(())
+    })
+}
+
+// 删除自定义价格，内置模型恢复为内置价格
+#[tauri::command]
+pub async fn delete_ai_model_price(
+    database: State<'_, Arc<Database>>,
+    provider: String,
+    model: String
+) -> Result<(), String> {
+    database.with_connection(|conn| {
+        conn.execute("DELETE FROM ai_model_prices WHERE provider = ?1 AND model = ?2", params![provider, model])?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_usage_blocks() {
+        // DeepSeek
+        let usage = TokenUsage::from_openai(&json!({
+            "prompt_tokens": 120, "completion_tokens": 30, "prompt_cache_hit_tokens": 100, "prompt_cache_miss_tokens": 20
+        })).unwrap();
+        assert_eq!(usage, TokenUsage { input_tokens: 20, output_tokens: 30, cache_read_tokens: 100, cache_write_tokens: 0 });
+        assert!(TokenUsage::from_openai(&Value::Null).is_none());
+
+        // Anthropic 流式：message_start 带输入用量，message_delta 更新输出用量
+        let mut usage = TokenUsage::default();
+        usage.merge_anthropic(&json!({ "input_tokens": 50, "output_tokens": 1, "cache_read_input_tokens": 2000, "cache_creation_input_tokens": 10 }));
+        usage.merge_anthropic(&json!({ "output_tokens": 400 }));
+        assert_eq!(usage, TokenUsage { input_tokens: 50, output_tokens: 400, cache_read_tokens: 2000, cache_write_tokens: 10 });
+
+        let usage = TokenUsage::from_ollama(&json!({ "done": true, "prompt_eval_count": 12, "eval_count": 34 })).unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (12, 34));
+    }
+
+    #[test]
+    fn test_load_usage_groups() {
+        use crate::database::{AiConversation, AiMessage};
+
+        let database = Database::open_in_memory().unwrap();
+        database.save_ai_conversation(&AiConversation {
+            id: "c1".to_string(),
+            title: "t".to_string(),
+            provider: "deepseek".to_string(),
+            model: "deepseek-chat".to_string(),
+            created_at: String::new(),
+            updated_at: String::new(),
+        }).unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        for (id, role, usage) in [
+            ("m1", "user", None),
+            ("m2", "assistant", Some(TokenUsage { input_tokens: 10, output_tokens: 5, ..TokenUsage::default() })),
+            ("m3", "assistant", Some(TokenUsage { input_tokens: 20, output_tokens: 7, cache_read_tokens: 3, ..TokenUsage::default() })),
+            ("m4", "assistant", None),
+        ] {
+            database.save_ai_message(&AiMessage {
+                id: id.to_string(),
+                conversation_id: "c1".to_string(),
+                role: role.to_string(),
+                content: String::new(),
+                provider: None,
+                model: None,
+                error: false,
+                timestamp: now,
+                created_at: String::new(),
+                usage,
+                agent_id: None,
+                attachments: Vec::new(),
+            }).unwrap();
+        }
+
+        // 消息没有记录提供商时使用对话的提供商；没有用量的消息不统计
+        let groups = load_usage_groups(&database, 0).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!((groups[0].provider.as_str(), groups[0].model.as_str(), groups[0].count), ("deepseek", "deepseek-chat", 2));
+        assert_eq!(groups[0].usage, TokenUsage { input_tokens: 30, output_tokens: 12, cache_read_tokens: 3, cache_write_tokens: 0 });
+        assert!(load_usage_groups(&database, now + 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_price_lookup_and_report() {
+        let prices = builtin_prices();
+        assert_eq!(find_price(&prices, "openai", "gpt-4o-mini-2024-07-18").unwrap().model, "gpt-4o-mini");
+        assert_eq!(find_price(&prices, "claude", "claude-3-5-sonnet-20241022").unwrap().input_per_mtok, 3.0);
+        assert_eq!(find_price(&prices, "anthropic", "claude-3-5-sonnet-20241022").unwrap().input_per_mtok, 3.0);
+        assert!(find_price(&prices, "ollama", "qwen2.5").is_none());
+
+        let usage = |input, output| TokenUsage { input_tokens: input, output_tokens: output, ..TokenUsage::default() };
+        let group = |day: &str, provider: &str, model: &str, agent: Option<&str>, usage| UsageGroup {
+            day: day.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            agent_id: agent.map(str::to_string),
+            agent_name: None,
+            count: 1,
+            usage,
+        };
+        let report = build_report(&[
+            group("2026-01-01", "deepseek", "deepseek-chat", None, usage(1_000_000, 0)),
+            group("2026-01-02", "claude", "claude-3-5-sonnet-20241022", Some("writer"), usage(0, 1_000_000)),
+            group("2026-01-02", "ollama", "qwen2.5", Some("writer"), usage(500, 500)),
+        ], &prices);
+
+        assert_eq!(report.by_day.len(), 2);
+        assert_eq!(report.by_provider[0].key, "claude");
+        assert!((report.total.estimated_cost_usd - 15.27).abs() < 1e-9);
+        assert_eq!(report.total.unpriced_messages, 1);
+        let writer = report.by_agent.iter().find(|bucket| bucket.key == "writer").unwrap();
+        assert_eq!((writer.message_count, writer.output_tokens), (2, 1_000_500));
+    }
+}
@@ -1,4 +1,4 @@
-use rusqlite::{params, Result};
+use rusqlite::{params, Connection, Result};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tauri::State;
@@ -293,33 +293,37 @@ pub async fn create_card(
     title: String,
     content: String,
 ) -> Result<Card, String> {
+    database.with_connection(|conn| insert_card(conn, box_id, title, content))
+        .map_err(|e| format!("Insert failed: {}", e))
+}
+
+// 新建卡片，供 AI 工具等其他模块复用
+pub fn insert_card(conn: &Connection, box_id: String, title: String, content: String) -> Result<Card> {
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().timestamp_millis();
     let preview = generate_preview_from_html(&content);
     let sort_order = now as f64;
     
-    database.with_connection(|conn| {
-        conn.execute(
-            "INSERT INTO cards (id, box_id, title, content, preview, color, tags, is_pinned, is_archived, sort_order, created_at, updated_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-            params![id, box_id, title, content, preview, None::<String>, None::<String>, 0, 0, sort_order, now, now],
-        )?;
-        
-        Ok(Card {
-            id,
-            box_id: box_id,
-            title,
-            content: Some(content),
-            preview: Some(preview),
-            color: None,
-            tags: None,
-            is_pinned: false,
-            is_archived: false,
-            sort_order,
-            created_at: now,
-            updated_at: now,
-        })
-    }).map_err(|e| format!("Insert failed: {}", e))
+    conn.execute(
+        "INSERT INTO cards (id, box_id, title, content, preview, color, tags, is_pinned, is_archived, sort_order, created_at, updated_at) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![id, box_id, title, content, preview, None::<String>, None::<String>, 0, 0, sort_order, now, now],
+    )?;
+    
+    Ok(Card {
+        id,
+        box_id,
+        title,
+        content: Some(content),
+        preview: Some(preview),
+        color: None,
+        tags: None,
+        is_pinned: false,
+        is_archived: false,
+        sort_order,
+        created_at: now,
+        updated_at: now,
+    })
 }
 
 #[tauri::command]
@@ -1,3 +1,4 @@
+use crate::ai_keys;
 use crate::database::{Database, TimelineEntry, Task, TaskProject, KnowledgeBase, Page, Block, Habit, HabitRecord, AiProvider, AiAgent};
 use serde_json::Value;
 use std::sync::Arc;
@@ -725,12 +726,17 @@ pub async fn save_ai_provider(
     db: State<'_, Arc<Database>>,
     provider: AiProvider,
 ) -> Result<(), String> {
+    // 密钥加密后保存，留空时沿用已保存的密钥
+    let mut provider = provider;
+    ai_keys::prepare_for_save(&db, &mut provider)?;
     db.save_ai_provider(&provider).map_err(|e| e.to_string())
 }
 
+// 返回的配置中不包含密钥，密钥只在后端发起请求时解密
 #[tauri::command]
 pub async fn get_ai_providers(db: State<'_, Arc<Database>>) -> Result<Vec<AiProvider>, String> {
-    db.get_ai_providers().map_err(|e| e.to_string())
+    let providers = db.get_ai_providers().map_err(|e| e.to_string())?;
+    Ok(providers.into_iter().map(ai_keys::redact).collect())
 }
 
 #[tauri::command]
@@ -738,7 +744,8 @@ pub async fn get_ai_provider(
     db: State<'_, Arc<Database>>,
     provider_name: String,
 ) -> Result<Option<AiProvider>, String> {
-    db.get_ai_provider(&provider_name).map_err(|e| e.to_string())
+    let provider = db.get_ai_provider(&provider_name).map_err(|e| e.to_string())?;
+    Ok(provider.map(ai_keys::redact))
 }
 
 #[tauri::command]
@@ -2,8 +2,10 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Key, Nonce
 };
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::{Engine as _, engine::general_purpose};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use std::collections::HashMap;
 use std::sync::Mutex;
@@ -13,12 +15,87 @@ lazy_static::lazy_static! {
     static ref KEY_CACHE: Mutex<HashMap<String, Vec<u8>>> = Mutex::new(HashMap::new());
 }
 
+// 密文头部：魔数 + 格式版本。版本1（旧格式）没有头部，直接以 nonce 开头
+const CIPHERTEXT_MAGIC: &[u8] = b"ANV";
+const CIPHERTEXT_VERSION: u8 = 2;
+const NONCE_LEN: usize = 12;
+
+// 密文格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiphertextFormat {
+    Legacy,  // nonce + 密文，密钥由 SHA-256 派生
+    V2,      // 头部 + nonce + 密文，密钥由 Argon2id 派生
+}
+
+// Argon2id 密钥派生参数
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    // 下限取 OWASP 对 Argon2id 的最低建议：19 MiB 内存、2 次迭代
+    pub const MIN_MEMORY_KIB: u32 = 19 * 1024;
+    pub const MIN_ITERATIONS: u32 = 2;
+    // 上限为默认值的 4 倍，避免来自备份文件等外部的参数耗尽内存或长时间占用 CPU
+    pub const MAX_MEMORY_KIB: u32 = 4 * 64 * 1024;
+    pub const MAX_ITERATIONS: u32 = 4 * 3;
+    pub const MAX_PARALLELISM: u32 = 8;
+
+    // 设置主密码、修改主密码和导入备份前校验参数范围
+    pub fn validate(&self) -> Result<(), String> {
+        if !(Self::MIN_MEMORY_KIB..=Self::MAX_MEMORY_KIB).contains(&self.memory_kib) {
+            return Err(format!(
+                "Argon2id 内存参数需在 {} 到 {} KiB 之间，当前为 {}",
+                Self::MIN_MEMORY_KIB, Self::MAX_MEMORY_KIB, self.memory_kib
+            ));
+        }
+        if !(Self::MIN_ITERATIONS..=Self::MAX_ITERATIONS).contains(&self.iterations) {
+            return Err(format!(
+                "Argon2id 迭代次数需在 {} 到 {} 之间，当前为 {}",
+                Self::MIN_ITERATIONS, Self::MAX_ITERATIONS, self.iterations
+            ));
+        }
+        if !(1..=Self::MAX_PARALLELISM).contains(&self.parallelism) {
+            return Err(format!(
+                "Argon2id 并行度需在 1 到 {} 之间，当前为 {}",
+                Self::MAX_PARALLELISM, self.parallelism
+            ));
+        }
+        Ok(())
+    }
+}
+
 pub struct CryptoService;
 
 #[allow(dead_code)]
 impl CryptoService {
-    // 从主密码派生加密密钥
-    pub fn derive_key_from_password(master_password: &str, salt: &[u8]) -> Vec<u8> {
+    // 从主密码派生加密密钥（Argon2id）
+    pub fn derive_key_from_password(master_password: &str, salt: &[u8], params: &KdfParams) -> Result<Vec<u8>, String> {
+        let argon2_params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(32))
+            .map_err(|e| format!("Invalid KDF parameters: {}", e))?;
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params);
+        
+        let mut key = vec![0u8; 32];
+        argon2.hash_password_into(master_password.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+    
+    // 旧版本的密钥派生（单次 SHA-256），仅用于解锁和升级旧数据
+    pub fn derive_legacy_key(master_password: &str, salt: &[u8]) -> Vec<u8> {
         let mut hasher = Sha256::new();
         hasher.update(master_password.as_bytes());
         hasher.update(salt);
@@ -26,7 +103,6 @@ impl CryptoService {
     }
     
     // 生成随机盐值
-    #[allow(dead_code)]
     pub fn generate_salt() -> Vec<u8> {
         let mut salt = [0u8; 16];
         OsRng.fill(&mut salt);
@@ -43,8 +119,8 @@ impl CryptoService {
     
     // 验证主密码（通过尝试解密一个测试字符串）
     #[allow(dead_code)]
-    pub fn verify_master_password(master_password: &str, salt: &[u8], test_encrypted: &str) -> Result<bool, String> {
-        let key = Self::derive_key_from_password(master_password, salt);
+    pub fn verify_master_password(master_password: &str, salt: &[u8], params: &KdfParams, test_encrypted: &str) -> Result<bool, String> {
+        let key = Self::derive_key_from_password(master_password, salt, params)?;
         let test_result = Self::decrypt_with_key(&key, test_encrypted);
         Ok(test_result.is_ok())
     }
@@ -64,8 +140,10 @@ impl CryptoService {
         let ciphertext = cipher.encrypt(nonce, plaintext.as_bytes())
             .map_err(|e| format!("Encryption failed: {}", e))?;
         
-        // 将nonce和密文拼接在一起，然后base64编码
+        // 将头部、nonce和密文拼接在一起，然后base64编码
         let mut result = Vec::new();
+        result.extend_from_slice(CIPHERTEXT_MAGIC);
+        result.push(CIPHERTEXT_VERSION);
         result.extend_from_slice(&nonce_bytes);
         result.extend_from_slice(&ciphertext);
         
@@ -81,11 +159,47 @@ impl CryptoService {
         let combined_data = general_purpose::STANDARD.decode(ciphertext_base64)
             .map_err(|e| format!("Base64 decode failed: {}", e))?;
         
-        if combined_data.len() < 12 {
+        if combined_data.len() < NONCE_LEN {
             return Err("Invalid ciphertext: too short".to_string());
         }
         
-        let (nonce_bytes, ciphertext) = combined_data.split_at(12);
+        // 旧格式的 nonce 是随机的，极少数情况下会恰好以魔数开头，解密失败时按旧格式重试
+        if let Some(body) = Self::strip_header(&combined_data) {
+            if let Ok(plaintext) = Self::decrypt_body(key, body) {
+                return Ok(plaintext);
+            }
+        }
+        
+        Self::decrypt_body(key, &combined_data)
+    }
+    
+    // 判断密文格式，用于选择解密密钥
+    pub fn ciphertext_format(ciphertext_base64: &str) -> Result<CiphertextFormat, String> {
+        let combined_data = general_purpose::STANDARD.decode(ciphertext_base64)
+            .map_err(|e| format!("Base64 decode failed: {}", e))?;
+        
+        if Self::strip_header(&combined_data).is_some() {
+            Ok(CiphertextFormat::V2)
+        } else {
+            Ok(CiphertextFormat::Legacy)
+        }
+    }
+    
+    // 去掉版本头部，返回 nonce + 密文
+    fn strip_header(data: &[u8]) -> Option<&[u8]> {
+        let header_len = CIPHERTEXT_MAGIC.len() + 1;
+        if data.len() >= header_len + NONCE_LEN
+            && data.starts_with(CIPHERTEXT_MAGIC)
+            && data[CIPHERTEXT_MAGIC.len()] == CIPHERTEXT_VERSION
+        {
+            Some(&data[header_len..])
+        } else {
+            None
+        }
+    }
+    
+    fn decrypt_body(key: &[u8], data: &[u8]) -> Result<String, String> {
+        let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
         let key = Key::<Aes256Gcm>::from_slice(key);
         let cipher = Aes256Gcm::new(key);
         let nonce = Nonce::from_slice(nonce_bytes);
@@ -98,20 +212,25 @@ impl CryptoService {
     }
     
     // 使用主密码加密
-    pub fn encrypt_with_master_password(master_password: &str, salt: &[u8], plaintext: &str) -> Result<String, String> {
-        let key = Self::derive_key_from_password(master_password, salt);
+    pub fn encrypt_with_master_password(master_password: &str, salt: &[u8], params: &KdfParams, plaintext: &str) -> Result<String, String> {
+        let key = Self::derive_key_from_password(master_password, salt, params)?;
         Self::encrypt_with_key(&key, plaintext)
     }
     
     // 使用主密码解密
-    pub fn decrypt_with_master_password(master_password: &str, salt: &[u8], ciphertext: &str) -> Result<String, String> {
-        let key = Self::derive_key_from_password(master_password, salt);
+    pub fn decrypt_with_master_password(master_password: &str, salt: &[u8], params: &KdfParams, ciphertext: &str) -> Result<String, String> {
+        let key = Self::derive_key_from_password(master_password, salt, params)?;
         Self::decrypt_with_key(&key, ciphertext)
     }
     
     // 缓存主密钥（会话期间有效）
-    pub fn cache_master_key(session_id: &str, master_password: &str, salt: &[u8]) -> Result<(), String> {
-        let key = Self::derive_key_from_password(master_password, salt);
+    pub fn cache_master_key(session_id: &str, master_password: &str, salt: &[u8], params: &KdfParams) -> Result<(), String> {
+        let key = Self::derive_key_from_password(master_password, salt, params)?;
+        Self::cache_key(session_id, key)
+    }
+
+    // 缓存已派生的密钥
+    pub fn cache_key(session_id: &str, key: Vec<u8>) -> Result<(), String> {
         let mut cache = KEY_CACHE.lock().map_err(|e| format!("Lock error: {}", e))?;
         cache.insert(session_id.to_string(), key);
         Ok(())
@@ -184,42 +303,9 @@ impl CryptoService {
             .collect()
     }
     
-    // 检查密码强度（返回0-100的分数）
+    // 检查密码强度（返回0-100的分数），按估算的猜测次数评分
     pub fn check_password_strength(password: &str) -> u8 {
-        let mut score = 0u8;
-        let length = password.len();
-        
-        // 长度评分
-        if length >= 8 {
-            score += 25;
-        }
-        if length >= 12 {
-            score += 10;
-        }
-        if length >= 16 {
-            score += 5;
-        }
-        
-        // 字符类型评分
-        let has_lowercase = password.chars().any(|c| c.is_ascii_lowercase());
-        let has_uppercase = password.chars().any(|c| c.is_ascii_uppercase());
-        let has_numbers = password.chars().any(|c| c.is_ascii_digit());
-        let has_symbols = password.chars().any(|c| !c.is_ascii_alphanumeric());
-        
-        if has_lowercase {
-            score += 15;
-        }
-        if has_uppercase {
-            score += 15;
-        }
-        if has_numbers {
-            score += 15;
-        }
-        if has_symbols {
-            score += 15;
-        }
-        
-        score.min(100)
+        crate::password_strength::estimate(password).score
     }
 }
 
@@ -227,16 +313,71 @@ impl CryptoService {
 mod tests {
     use super::*;
 
+    // 测试使用较小的参数，避免拖慢测试
+    fn test_kdf_params() -> KdfParams {
+        KdfParams {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
     #[test]
     fn test_encryption_decryption() {
         let master_password = "test_password_123";
         let salt = CryptoService::generate_salt();
+        let params = test_kdf_params();
         let plaintext = "Hello, World!";
         
-        let encrypted = CryptoService::encrypt_with_master_password(master_password, &salt, plaintext).unwrap();
-        let decrypted = CryptoService::decrypt_with_master_password(master_password, &salt, &encrypted).unwrap();
+        let encrypted = CryptoService::encrypt_with_master_password(master_password, &salt, &params, plaintext).unwrap();
+        let decrypted = CryptoService::decrypt_with_master_password(master_password, &salt, &params, &encrypted).unwrap();
         
         assert_eq!(plaintext, decrypted);
+        assert_eq!(CryptoService::ciphertext_format(&encrypted).unwrap(), CiphertextFormat::V2);
+    }
+    
+    #[test]
+    fn test_key_derivation_depends_on_params() {
+        let salt = CryptoService::generate_salt();
+        let params = test_kdf_params();
+        let key = CryptoService::derive_key_from_password("password", &salt, &params).unwrap();
+        
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, CryptoService::derive_key_from_password("password", &salt, &params).unwrap());
+        
+        let stronger = KdfParams { iterations: 2, ..params };
+        assert_ne!(key, CryptoService::derive_key_from_password("password", &salt, &stronger).unwrap());
+    }
+    
+    #[test]
+    fn test_kdf_params_bounds() {
+        assert!(KdfParams::default().validate().is_ok());
+        assert!(KdfParams { memory_kib: KdfParams::MIN_MEMORY_KIB, iterations: KdfParams::MIN_ITERATIONS, parallelism: 1 }.validate().is_ok());
+
+        // 低于 OWASP 下限或超过上限的参数都被拒绝
+        assert!(test_kdf_params().validate().is_err());
+        assert!(KdfParams { iterations: 1, ..KdfParams::default() }.validate().is_err());
+        assert!(KdfParams { memory_kib: KdfParams::MAX_MEMORY_KIB + 1, ..KdfParams::default() }.validate().is_err());
+        assert!(KdfParams { iterations: u32::MAX, ..KdfParams::default() }.validate().is_err());
+        assert!(KdfParams { parallelism: 0, ..KdfParams::default() }.validate().is_err());
+        assert!(KdfParams { parallelism: 64, ..KdfParams::default() }.validate().is_err());
+    }
+    
+    #[test]
+    fn test_legacy_ciphertext_still_decrypts() {
+        let salt = CryptoService::generate_salt();
+        let key = CryptoService::derive_legacy_key("password", &salt);
+        
+        // 构造旧格式密文：nonce + 密文，没有头部
+        let nonce_bytes = CryptoService::generate_nonce();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce_bytes), b"legacy secret".as_ref()).unwrap();
+        let mut combined = nonce_bytes.clone();
+        combined.extend_from_slice(&ciphertext);
+        let legacy = general_purpose::STANDARD.encode(combined);
+        
+        assert_eq!(CryptoService::ciphertext_format(&legacy).unwrap(), CiphertextFormat::Legacy);
+        assert_eq!(CryptoService::decrypt_with_key(&key, &legacy).unwrap(), "legacy secret");
     }
     
     #[test]
@@ -248,6 +389,8 @@ mod tests {
     #[test]
     fn test_password_strength() {
         assert!(CryptoService::check_password_strength("weak") < 50);
-        assert!(CryptoService::check_password_strength("StrongPassword123!") > 80);
+        // 常见单词加数字后缀不再因为字符类型齐全而得高分
+        assert!(CryptoService::check_password_strength("Password123!") < 50);
+        assert!(CryptoService::check_password_strength("rT7#kq9!Lm2@xV") > 80);
     }
 }
\ No newline at end of file
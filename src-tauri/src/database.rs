@@ -3,6 +3,8 @@ use std::path::PathBuf;
 use std::sync::Mutex;
 use tauri::{AppHandle, Manager};
 use serde::{Deserialize, Serialize};
+use crate::ai_attachments::Attachment as AiAttachment;
+use crate::ai_usage::TokenUsage;
 use uuid::Uuid;
 
 // 新的数据模型定义
@@ -142,6 +144,13 @@ pub struct AiConversation {
     pub updated_at: String,
 }
 
+// 对话较早部分的摘要，until 为已摘要的最后一条消息的时间戳
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AiConversationSummary {
+    pub content: String,
+    pub until: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AiMessage {
     pub id: String,
@@ -153,6 +162,13 @@ pub struct AiMessage {
     pub error: bool,
     pub timestamp: i64,
     pub created_at: String,
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+    #[serde(default)]
+    pub agent_id: Option<String>,
+    // 用户消息的附件，以 JSON 保存在 attachments 列
+    #[serde(default)]
+    pub attachments: Vec<AiAttachment>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -246,7 +262,13 @@ pub struct HabitRecord {
 pub struct AiProvider {
     pub id: Option<i64>,
     pub provider: String,    // 'deepseek' | 'claude' | etc
-    pub api_key: String,     // 加密存储
+    pub api_key: String,     // 加密存储，返回给前端时清空
+    #[serde(skip)]
+    pub api_key_scheme: String,
+    #[serde(default)]
+    pub has_api_key: bool,   // 是否已保存密钥
+    #[serde(default)]
+    pub vault_protected: bool,  // 密钥改用密码库主密钥加密，密码库锁定时无法使用
     pub base_url: Option<String>,
     pub model: String,
     pub temperature: f64,
@@ -273,6 +295,8 @@ pub struct AiAgent {
     pub model: Option<String>,     // 可选绑定的模型
     pub is_builtin: i32,     // 0 or 1, 是否为内置智能体
     pub is_current: i32,     // 0 or 1, 是否为当前选中的智能体
+    #[serde(default)]
+    pub tools: Vec<String>,  // 允许调用的工具名，为空时不启用工具调用
     pub created_at: String,
     pub updated_at: String,
 }
@@ -336,6 +360,18 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         
         Ok(db)
     }
+
+    // 测试用内存数据库
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let db = Database {
+            conn: Mutex::new(conn),
+        };
+        db.init_tables()?;
+        Ok(db)
+    }
     
     fn init_tables(&self) -> Result<()> {
         let conn = self.lock_conn();
@@ -354,6 +390,9 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
 
         // 创建其他表（保持不变）
         self.create_other_tables(&conn)?;
+
+        // 创建 AI 检索索引（依赖上面的页面、卡片、读书笔记和时光记表）
+        self.create_rag_tables(&conn)?;
         
         Ok(())
     }
@@ -930,14 +969,66 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
             "CREATE TABLE IF NOT EXISTS ai_conversations (
                 id TEXT PRIMARY KEY,
                 title TEXT NOT NULL,
-                provider TEXT NOT NULL CHECK(provider IN ('deepseek', 'claude')),
+                provider TEXT NOT NULL,
                 model TEXT NOT NULL,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )",
             [],
         )?;
-        
+
+        // 旧版本限制 provider 只能是 deepseek/claude，SQLite 不能删除约束，需要重建表
+        let has_provider_check: bool = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'ai_conversations' AND sql LIKE '%CHECK(provider IN%'",
+            [],
+            |row| row.get::<_, i64>(0),
+        )? > 0;
+        if has_provider_check {
+            // 重建期间关闭外键，避免删除旧表时级联删除消息
+            conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+            let rebuilt = conn.execute_batch(
+                "BEGIN;
+                 CREATE TABLE ai_conversations_rebuild (
+                     id TEXT PRIMARY KEY,
+                     title TEXT NOT NULL,
+                     provider TEXT NOT NULL,
+                     model TEXT NOT NULL,
+                     created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                     updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                 );
+                 INSERT INTO ai_conversations_rebuild (id, title, provider, model, created_at, updated_at)
+                     SELECT id, title, provider, model, created_at, updated_at FROM ai_conversations;
+                 -- 引用旧表的触发器会导致重命名失败，稍后统一重建
+                 DROP TRIGGER IF EXISTS update_conversation_on_new_message;
+                 DROP TABLE ai_conversations;
+                 ALTER TABLE ai_conversations_rebuild RENAME TO ai_conversations;
+                 COMMIT;",
+            );
+            if rebuilt.is_err() {
+                let _ = conn.execute_batch("ROLLBACK;");
+            }
+            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+            rebuilt?;
+        }
+        
+        // 较早消息的滚动摘要，summary_until 为摘要覆盖到的最后一条消息的时间戳；
+        // title_generated 标记标题由模型生成，前端同步时不再用本地标题覆盖
+        for (column, definition) in [
+            ("summary", "TEXT"),
+            ("summary_until", "BIGINT"),
+            ("title_generated", "INTEGER DEFAULT 0"),
+        ] {
+            let exists = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('ai_conversations') WHERE name = ?1",
+                params![column],
+                |row| row.get::<_, i32>(0)
+            ).unwrap_or(0) > 0;
+            if !exists {
+                conn.execute(&format!("ALTER TABLE ai_conversations ADD COLUMN {} {}", column, definition), [])?;
+                println!("✅ 已为 ai_conversations 表添加 {} 列", column);
+            }
+        }
+
         // 创建AI消息表
         conn.execute(
             "CREATE TABLE IF NOT EXISTS ai_messages (
@@ -950,10 +1041,52 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 error INTEGER DEFAULT 0,
                 timestamp BIGINT NOT NULL,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                -- 助手消息的 token 用量，未知时为 NULL
+                input_tokens INTEGER,
+                output_tokens INTEGER,
+                cache_read_tokens INTEGER,
+                cache_write_tokens INTEGER,
+                agent_id TEXT,
+                attachments TEXT,
                 FOREIGN KEY (conversation_id) REFERENCES ai_conversations(id) ON DELETE CASCADE
             )",
             [],
         )?;
+
+        // 为旧版 ai_messages 表添加用量统计列
+        for (column, definition) in [
+            ("input_tokens", "INTEGER"),
+            ("output_tokens", "INTEGER"),
+            ("cache_read_tokens", "INTEGER"),
+            ("cache_write_tokens", "INTEGER"),
+            ("agent_id", "TEXT"),
+            ("attachments", "TEXT"),
+        ] {
+            let exists = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('ai_messages') WHERE name = ?1",
+                params![column],
+                |row| row.get::<_, i32>(0)
+            ).unwrap_or(0) > 0;
+            if !exists {
+                conn.execute(&format!("ALTER TABLE ai_messages ADD COLUMN {} {}", column, definition), [])?;
+                println!("✅ 已为 ai_messages 表添加 {} 列", column);
+            }
+        }
+
+        // 用户自定义的模型单价（美元 / 百万 token），覆盖内置价格
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ai_model_prices (
+                provider TEXT NOT NULL,
+                model TEXT NOT NULL,
+                input_per_mtok REAL NOT NULL DEFAULT 0,
+                output_per_mtok REAL NOT NULL DEFAULT 0,
+                cache_read_per_mtok REAL NOT NULL DEFAULT 0,
+                cache_write_per_mtok REAL NOT NULL DEFAULT 0,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (provider, model)
+            )",
+            [],
+        )?;
         
         // 创建密码设置表（存储盐值等安全配置）
         conn.execute(
@@ -961,6 +1094,17 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 id INTEGER PRIMARY KEY CHECK (id = 1),
                 master_password_salt TEXT NOT NULL,
                 test_encrypted_data TEXT,
+                -- 密钥派生算法：argon2id，旧版本为 sha256
+                kdf_algorithm TEXT NOT NULL DEFAULT 'sha256',
+                kdf_params TEXT,
+                -- 升级前的旧版盐值，旧密文全部重新加密后清空
+                legacy_password_salt TEXT,
+                -- 空闲自动锁定时间（分钟），0 表示不自动锁定
+                auto_lock_minutes INTEGER NOT NULL DEFAULT 15,
+                -- 每个条目保留的历史密码版本数，0 表示不保留
+                history_retention INTEGER NOT NULL DEFAULT 10,
+                -- 查看记录保留天数，0 表示永久保留
+                audit_retention_days INTEGER NOT NULL DEFAULT 90,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
             )",
@@ -1000,6 +1144,26 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
             )?;
         }
         
+        // 密钥派生和自动锁定相关列（旧版本固定使用 SHA-256）
+        for (column_name, column_def) in [
+            ("kdf_algorithm", "kdf_algorithm TEXT NOT NULL DEFAULT 'sha256'"),
+            ("kdf_params", "kdf_params TEXT"),
+            ("legacy_password_salt", "legacy_password_salt TEXT"),
+            ("auto_lock_minutes", "auto_lock_minutes INTEGER NOT NULL DEFAULT 15"),
+            ("history_retention", "history_retention INTEGER NOT NULL DEFAULT 10"),
+            ("audit_retention_days", "audit_retention_days INTEGER NOT NULL DEFAULT 90"),
+        ] {
+            let has_column = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('password_settings') WHERE name = ?1",
+                params![column_name],
+                |row| row.get::<_, i32>(0),
+            )? > 0;
+            
+            if !has_column {
+                conn.execute(&format!("ALTER TABLE password_settings ADD COLUMN {}", column_def), [])?;
+            }
+        }
+        
         // 创建密码分类表
         conn.execute(
             "CREATE TABLE IF NOT EXISTS password_categories (
@@ -1032,6 +1196,17 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 category_id INTEGER,
                 tags TEXT,
                 is_favorite INTEGER DEFAULT 0,
+                -- 条目类型（login / secure_note / ssh_key / bank_card / api_token）及加密后的类型字段 JSON
+                item_type TEXT NOT NULL DEFAULT 'login',
+                fields_encrypted TEXT,
+                -- password_encrypted 是否已用主密钥加密（旧数据为明文）
+                is_encrypted INTEGER NOT NULL DEFAULT 0,
+                totp_encrypted TEXT,
+                -- 最近一次修改密码的时间，为空时以 created_at 为准
+                password_changed_at DATETIME,
+                -- 本地泄露数据检查结果：出现次数，为空表示尚未检查
+                breach_count INTEGER,
+                breach_checked_at DATETIME,
                 last_used_at DATETIME,
                 created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
                 updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
@@ -1073,6 +1248,39 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         add_column_if_not_exists("db_ip", "db_ip TEXT")?;
         add_column_if_not_exists("db_username", "db_username TEXT")?;
         add_column_if_not_exists("app_name", "app_name TEXT")?;
+        add_column_if_not_exists("is_encrypted", "is_encrypted INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_not_exists("totp_encrypted", "totp_encrypted TEXT")?;
+        add_column_if_not_exists("password_changed_at", "password_changed_at DATETIME")?;
+        add_column_if_not_exists("breach_count", "breach_count INTEGER")?;
+        add_column_if_not_exists("breach_checked_at", "breach_checked_at DATETIME")?;
+        add_column_if_not_exists("item_type", "item_type TEXT NOT NULL DEFAULT 'login'")?;
+        add_column_if_not_exists("fields_encrypted", "fields_encrypted TEXT")?;
+        
+        // 密码历史表：条目修改前的旧密码（用当前主密钥加密）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS password_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry_id INTEGER NOT NULL,
+                password_encrypted TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (entry_id) REFERENCES password_entries(id) ON DELETE CASCADE
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_password_history_entry ON password_history(entry_id, id DESC)", [])?;
+        
+        // 密码查看记录表：条目删除后保留记录，因此不设外键
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS password_audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry_id INTEGER NOT NULL,
+                entry_title TEXT,
+                action TEXT NOT NULL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            )",
+            [],
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_password_audit_entry ON password_audit_log(entry_id, created_at DESC)", [])?;
         
         // 密码分类迁移：精简为4个核心分类
         // 首先检查是否需要迁移（如果存在旧分类则进行清理）
@@ -1178,6 +1386,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 provider TEXT NOT NULL UNIQUE,
                 api_key TEXT NOT NULL,
+                -- api_key 的加密方式：app（应用密钥）、vault（密码库主密钥）、plain（旧版明文，启动时迁移）
+                api_key_scheme TEXT NOT NULL DEFAULT 'plain',
                 base_url TEXT,
                 model TEXT NOT NULL,
                 temperature REAL NOT NULL DEFAULT 0.7,
@@ -1192,6 +1402,17 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
             )",
             [],
         )?;
+        
+        let has_api_key_scheme = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('ai_providers') WHERE name = 'api_key_scheme'",
+            [],
+            |row| row.get::<_, i32>(0)
+        ).unwrap_or(0) > 0;
+        
+        if !has_api_key_scheme {
+            conn.execute("ALTER TABLE ai_providers ADD COLUMN api_key_scheme TEXT NOT NULL DEFAULT 'plain'", [])?;
+            println!("✅ 已为 ai_providers 表添加 api_key_scheme 列");
+        }
 
         // 创建AI智能体配置表
         conn.execute(
@@ -1216,6 +1437,17 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
             [],
         )?;
 
+        let has_agent_tools = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('ai_agents') WHERE name = 'tools'",
+            [],
+            |row| row.get::<_, i32>(0)
+        ).unwrap_or(0) > 0;
+
+        if !has_agent_tools {
+            conn.execute("ALTER TABLE ai_agents ADD COLUMN tools TEXT NOT NULL DEFAULT '[]'", [])?;
+            println!("✅ 已为 ai_agents 表添加 tools 列");
+        }
+
         // 创建索引
         conn.execute_batch(
             "CREATE INDEX IF NOT EXISTS idx_timeline_date ON timeline_entries(date DESC);
@@ -1361,6 +1593,161 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         Ok(())
     }
     
+    fn create_rag_tables(&self, conn: &Connection) -> Result<()> {
+        let index_exists = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'rag_chunks'",
+            [],
+            |row| row.get::<_, i32>(0)
+        ).unwrap_or(0) > 0;
+
+        // 检索用的文本分块，原文保存在 rag_chunks，分词后的文本保存在 rag_chunks_fts（rowid 相同）
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rag_chunks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                source_type TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                chunk_index INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                token_count INTEGER NOT NULL,
+                content_hash TEXT NOT NULL DEFAULT ''
+            )",
+            [],
+        )?;
+
+        // 早期的分块没有内容哈希，清空后重新分块，向量按哈希匹配不必重新计算
+        let has_content_hash = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('rag_chunks') WHERE name = 'content_hash'",
+            [],
+            |row| row.get::<_, i32>(0)
+        ).unwrap_or(0) > 0;
+        if !has_content_hash {
+            conn.execute("ALTER TABLE rag_chunks ADD COLUMN content_hash TEXT NOT NULL DEFAULT ''", [])?;
+            conn.execute("DELETE FROM rag_chunks", [])?;
+            println!("✅ 已为 rag_chunks 表添加 content_hash 列");
+        }
+        conn.execute("CREATE INDEX IF NOT EXISTS idx_rag_chunks_source ON rag_chunks(source_type, source_id)", [])?;
+        conn.execute(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS rag_chunks_fts USING fts5(
+                title,
+                body,
+                tokenize='unicode61'
+            )",
+            [],
+        )?;
+        if !has_content_hash {
+            conn.execute("DELETE FROM rag_chunks_fts", [])?;
+        }
+
+        // 内容变化后待重新分块的来源，检索前统一处理
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rag_pending (
+                source_type TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                PRIMARY KEY (source_type, source_id)
+            )",
+            [],
+        )?;
+
+        for (table, source_type) in [
+            ("pages", "page"),
+            ("cards", "card"),
+            ("reading_notes", "reading_note"),
+            ("timeline_entries", "timeline"),
+        ] {
+            for (event, row) in [("INSERT", "NEW"), ("UPDATE", "NEW"), ("DELETE", "OLD")] {
+                conn.execute(
+                    &format!(
+                        "CREATE TRIGGER IF NOT EXISTS rag_pending_{table}_{event_lower}
+                         AFTER {event} ON {table}
+                         FOR EACH ROW
+                         BEGIN
+                           INSERT OR IGNORE INTO rag_pending (source_type, source_id) VALUES ('{source_type}', CAST({row}.id AS TEXT));
+                         END",
+                        table = table,
+                        event = event,
+                        event_lower = event.to_lowercase(),
+                        row = row,
+                        source_type = source_type,
+                    ),
+                    [],
+                )?;
+            }
+
+            // 首次创建索引或需要重新分块时把已有内容加入待处理队列
+            if !index_exists || !has_content_hash {
+                conn.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT '{}', CAST(id AS TEXT) FROM {}",
+                        source_type, table
+                    ),
+                    [],
+                )?;
+            }
+        }
+
+        // 块属于所在页面，块变化时重新处理整个页面
+        let block_triggers_exist = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'trigger' AND name = 'rag_pending_blocks_insert'",
+            [],
+            |row| row.get::<_, i32>(0)
+        ).unwrap_or(0) > 0;
+        for (event, row) in [("INSERT", "NEW"), ("UPDATE", "NEW"), ("DELETE", "OLD")] {
+            conn.execute(
+                &format!(
+                    "CREATE TRIGGER IF NOT EXISTS rag_pending_blocks_{event_lower}
+                     AFTER {event} ON blocks
+                     FOR EACH ROW
+                     BEGIN
+                       INSERT OR IGNORE INTO rag_pending (source_type, source_id) VALUES ('page', {row}.page_id);
+                     END",
+                    event = event,
+                    event_lower = event.to_lowercase(),
+                    row = row,
+                ),
+                [],
+            )?;
+        }
+        if !block_triggers_exist {
+            conn.execute(
+                "INSERT OR IGNORE INTO rag_pending (source_type, source_id) SELECT DISTINCT 'page', page_id FROM blocks",
+                [],
+            )?;
+        }
+
+        // 分块的向量嵌入，content_hash 与分块内容不一致或模型变化时重新计算
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rag_embeddings (
+                source_type TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                chunk_no INTEGER NOT NULL,
+                model TEXT NOT NULL,
+                dim INTEGER NOT NULL,
+                vector BLOB NOT NULL,
+                content_hash TEXT NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (source_type, source_id, chunk_no)
+            )",
+            [],
+        )?;
+
+        // 向量嵌入接口配置（单行），密钥使用 ai_providers 中该提供商保存的密钥
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS ai_embedding_settings (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                provider TEXT NOT NULL,
+                base_url TEXT,
+                model TEXT NOT NULL,
+                enabled INTEGER NOT NULL DEFAULT 1,
+                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                CHECK (enabled IN (0, 1))
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+    
     // 旧的迁移函数和笔记系统已删除，使用新的知识库系统
     
     // ====== 新的知识库系统 ======
@@ -1471,10 +1858,34 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     }
     
     // AI对话相关操作
+    // 已存在时只更新标题、模型和时间，保留摘要和消息（INSERT OR REPLACE 会级联删除消息）
     pub fn save_ai_conversation(&self, conversation: &AiConversation) -> Result<()> {
         let conn = self.lock_conn();
         conn.execute(
-            "INSERT OR REPLACE INTO ai_conversations (id, title, provider, model, created_at, updated_at) 
+            "INSERT INTO ai_conversations (id, title, provider, model, created_at, updated_at) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT(id) DO UPDATE SET
+                 title = CASE WHEN title_generated = 1 THEN title ELSE excluded.title END,
+                 provider = excluded.provider,
+                 model = excluded.model,
+                 updated_at = excluded.updated_at",
+            params![
+                conversation.id,
+                conversation.title,
+                conversation.provider,
+                conversation.model,
+                conversation.created_at,
+                conversation.updated_at
+            ],
+        )?;
+        Ok(())
+    }
+    
+    // 对话不存在时创建，已存在时保持原样（INSERT OR REPLACE 会级联删除已有消息）
+    pub fn ensure_ai_conversation(&self, conversation: &AiConversation) -> Result<()> {
+        let conn = self.lock_conn();
+        conn.execute(
+            "INSERT OR IGNORE INTO ai_conversations (id, title, provider, model, created_at, updated_at) 
              VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
             params![
                 conversation.id,
@@ -1488,11 +1899,27 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         Ok(())
     }
     
+    // 前端同步时可能不带用量、智能体和附件，已保存的值不被空值覆盖
     pub fn save_ai_message(&self, message: &AiMessage) -> Result<()> {
         let conn = self.lock_conn();
         conn.execute(
-            "INSERT OR REPLACE INTO ai_messages (id, conversation_id, role, content, provider, model, error, timestamp, created_at) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            "INSERT INTO ai_messages (id, conversation_id, role, content, provider, model, error, timestamp, created_at,
+                 input_tokens, output_tokens, cache_read_tokens, cache_write_tokens, agent_id, attachments) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)
+             ON CONFLICT(id) DO UPDATE SET
+                 conversation_id = excluded.conversation_id,
+                 role = excluded.role,
+                 content = excluded.content,
+                 provider = COALESCE(excluded.provider, provider),
+                 model = COALESCE(excluded.model, model),
+                 error = excluded.error,
+                 timestamp = excluded.timestamp,
+                 input_tokens = COALESCE(excluded.input_tokens, input_tokens),
+                 output_tokens = COALESCE(excluded.output_tokens, output_tokens),
+                 cache_read_tokens = COALESCE(excluded.cache_read_tokens, cache_read_tokens),
+                 cache_write_tokens = COALESCE(excluded.cache_write_tokens, cache_write_tokens),
+                 agent_id = COALESCE(excluded.agent_id, agent_id),
+                 attachments = COALESCE(excluded.attachments, attachments)",
             params![
                 message.id,
                 message.conversation_id,
@@ -1502,7 +1929,17 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 message.model,
                 if message.error { 1 } else { 0 },
                 message.timestamp,
-                message.created_at
+                message.created_at,
+                message.usage.map(|usage| usage.input_tokens as i64),
+                message.usage.map(|usage| usage.output_tokens as i64),
+                message.usage.map(|usage| usage.cache_read_tokens as i64),
+                message.usage.map(|usage| usage.cache_write_tokens as i64),
+                message.agent_id,
+                if message.attachments.is_empty() {
+                    None
+                } else {
+                    serde_json::to_string(&message.attachments).ok()
+                }
             ],
         )?;
         Ok(())
@@ -1546,7 +1983,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     pub fn get_ai_messages(&self, conversation_id: &str) -> Result<Vec<AiMessage>> {
         let conn = self.lock_conn();
         let mut stmt = conn.prepare(
-            "SELECT id, conversation_id, role, content, provider, model, error, timestamp, created_at 
+            "SELECT id, conversation_id, role, content, provider, model, error, timestamp, created_at,
+                    input_tokens, output_tokens, cache_read_tokens, cache_write_tokens, agent_id, attachments 
              FROM ai_messages 
              WHERE conversation_id = ?1 
              ORDER BY timestamp ASC"
@@ -1563,6 +2001,19 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 error: row.get::<_, i32>(6)? != 0,
                 timestamp: row.get(7)?,
                 created_at: row.get(8)?,
+                usage: match (row.get::<_, Option<i64>>(9)?, row.get::<_, Option<i64>>(10)?) {
+                    (None, None) => None,
+                    (input, output) => Some(TokenUsage {
+                        input_tokens: input.unwrap_or(0) as u64,
+                        output_tokens: output.unwrap_or(0) as u64,
+                        cache_read_tokens: row.get::<_, Option<i64>>(11)?.unwrap_or(0) as u64,
+                        cache_write_tokens: row.get::<_, Option<i64>>(12)?.unwrap_or(0) as u64,
+                    }),
+                },
+                agent_id: row.get(13)?,
+                attachments: row.get::<_, Option<String>>(14)?
+                    .and_then(|json| serde_json::from_str(&json).ok())
+                    .unwrap_or_default(),
             })
         })?;
         
@@ -1582,10 +2033,67 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         Ok(())
     }
     
+    pub fn get_ai_conversation(&self, conversation_id: &str) -> Result<Option<AiConversation>> {
+        let conn = self.lock_conn();
+        let result = conn.query_row(
+            "SELECT id, title, provider, model, created_at, updated_at FROM ai_conversations WHERE id = ?1",
+            params![conversation_id],
+            |row| Ok(AiConversation {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                provider: row.get(2)?,
+                model: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+            }),
+        );
+
+        match result {
+            Ok(conversation) => Ok(Some(conversation)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn get_ai_conversation_summary(&self, conversation_id: &str) -> Result<Option<AiConversationSummary>> {
+        let conn = self.lock_conn();
+        let result = conn.query_row(
+            "SELECT summary, summary_until FROM ai_conversations WHERE id = ?1",
+            params![conversation_id],
+            |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<i64>>(1)?)),
+        );
+
+        match result {
+            Ok((Some(content), Some(until))) => Ok(Some(AiConversationSummary { content, until })),
+            Ok(_) | Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // 不更新 updated_at，后台整理不应改变对话列表的排序
+    pub fn save_ai_conversation_summary(&self, conversation_id: &str, summary: &AiConversationSummary) -> Result<()> {
+        let conn = self.lock_conn();
+        conn.execute(
+            "UPDATE ai_conversations SET summary = ?1, summary_until = ?2 WHERE id = ?3",
+            params![summary.content, summary.until, conversation_id],
+        )?;
+        Ok(())
+    }
+
+    // 用户手动改名后，之后的同步按前端标题保存
     pub fn update_ai_conversation_title(&self, conversation_id: &str, title: &str) -> Result<()> {
         let conn = self.lock_conn();
         conn.execute(
-            "UPDATE ai_conversations SET title = ?1, updated_at = DATETIME('now') WHERE id = ?2",
+            "UPDATE ai_conversations SET title = ?1, title_generated = 0, updated_at = DATETIME('now') WHERE id = ?2",
+            params![title, conversation_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_generated_conversation_title(&self, conversation_id: &str, title: &str) -> Result<()> {
+        let conn = self.lock_conn();
+        conn.execute(
+            "UPDATE ai_conversations SET title = ?1, title_generated = 1, updated_at = DATETIME('now') WHERE id = ?2",
             params![title, conversation_id],
         )?;
         Ok(())
@@ -1657,8 +2165,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         
         conn.execute(
             "INSERT OR REPLACE INTO ai_providers 
-             (id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+             (id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current, api_key_scheme) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
             params![
                 provider.id,
                 provider.provider,
@@ -1669,7 +2177,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 provider.max_tokens,
                 provider.system_prompt,
                 provider.enabled,
-                provider.is_current
+                provider.is_current,
+                provider.api_key_scheme
             ],
         )?;
         
@@ -1679,7 +2188,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     pub fn get_ai_providers(&self) -> Result<Vec<AiProvider>> {
         let conn = self.lock_conn();
         let mut stmt = conn.prepare(
-            "SELECT id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current, created_at, updated_at 
+            "SELECT id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current, created_at, updated_at, api_key_scheme 
              FROM ai_providers 
              ORDER BY provider"
         )?;
@@ -1689,6 +2198,9 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 id: row.get(0)?,
                 provider: row.get(1)?,
                 api_key: row.get(2)?,
+                api_key_scheme: row.get(12)?,
+                has_api_key: false,
+                vault_protected: false,
                 base_url: row.get(3)?,
                 model: row.get(4)?,
                 temperature: row.get(5)?,
@@ -1712,7 +2224,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     pub fn get_ai_provider(&self, provider_name: &str) -> Result<Option<AiProvider>> {
         let conn = self.lock_conn();
         let mut stmt = conn.prepare(
-            "SELECT id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current, created_at, updated_at 
+            "SELECT id, provider, api_key, base_url, model, temperature, max_tokens, system_prompt, enabled, is_current, created_at, updated_at, api_key_scheme 
              FROM ai_providers 
              WHERE provider = ?1"
         )?;
@@ -1722,6 +2234,9 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 id: row.get(0)?,
                 provider: row.get(1)?,
                 api_key: row.get(2)?,
+                api_key_scheme: row.get(12)?,
+                has_api_key: false,
+                vault_protected: false,
                 base_url: row.get(3)?,
                 model: row.get(4)?,
                 temperature: row.get(5)?,
@@ -1779,8 +2294,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
         
         conn.execute(
             "INSERT OR REPLACE INTO ai_agents 
-             (id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+             (id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current, tools) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
             params![
                 agent.id,
                 agent.agent_id,
@@ -1793,7 +2308,8 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 agent.provider,
                 agent.model,
                 agent.is_builtin,
-                agent.is_current
+                agent.is_current,
+                serde_json::to_string(&agent.tools).unwrap_or_else(|_| "[]".to_string())
             ],
         )?;
         
@@ -1803,7 +2319,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     pub fn get_ai_agents(&self) -> Result<Vec<AiAgent>> {
         let conn = self.lock_conn();
         let mut stmt = conn.prepare(
-            "SELECT id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current, created_at, updated_at 
+            "SELECT id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current, created_at, updated_at, tools 
              FROM ai_agents 
              ORDER BY is_builtin DESC, created_at ASC"
         )?;
@@ -1822,6 +2338,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 model: row.get(9)?,
                 is_builtin: row.get(10)?,
                 is_current: row.get(11)?,
+                tools: serde_json::from_str(&row.get::<_, String>(14)?).unwrap_or_default(),
                 created_at: row.get(12)?,
                 updated_at: row.get(13)?,
             })
@@ -1838,7 +2355,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
     pub fn get_ai_agent(&self, agent_id: &str) -> Result<Option<AiAgent>> {
         let conn = self.lock_conn();
         let mut stmt = conn.prepare(
-            "SELECT id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current, created_at, updated_at 
+            "SELECT id, agent_id, name, description, icon, system_prompt, temperature, max_tokens, provider, model, is_builtin, is_current, created_at, updated_at, tools 
              FROM ai_agents 
              WHERE agent_id = ?1"
         )?;
@@ -1857,6 +2374,7 @@ impl Database {    fn lock_conn(&self) -> std::sync::MutexGuard<'_, Connection>
                 model: row.get(9)?,
                 is_builtin: row.get(10)?,
                 is_current: row.get(11)?,
+                tools: serde_json::from_str(&row.get::<_, String>(14)?).unwrap_or_default(),
                 created_at: row.get(12)?,
                 updated_at: row.get(13)?,
             })
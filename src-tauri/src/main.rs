@@ -7,8 +7,28 @@ mod commands;
 mod ai_test;
 mod ai_chat;
 mod ai_commands;
+mod ai_keys;
+mod ai_providers;
+mod ai_usage;
+mod sse;
+mod ai_retrieval;
+mod ai_embeddings;
+mod ai_tools;
+mod ai_summary;
+mod ai_policy;
+mod ai_attachments;
 mod crypto;
 mod password_commands;
+mod vault_session;
+mod totp;
+mod password_import;
+mod password_backup;
+mod password_history;
+mod password_health;
+mod password_strength;
+mod password_generator;
+mod password_breach;
+mod vault_items;
 mod knowledge;
 mod cardbox_commands;
 
@@ -33,6 +53,23 @@ fn main() {
             match database::Database::new(&app.handle()) {
                 Ok(db) => {
                     let db = std::sync::Arc::new(db);
+                    
+                    // 密码库空闲自动锁定
+                    vault_session::set_auto_lock_minutes(password_commands::load_auto_lock_minutes(&db));
+                    vault_session::start_watcher(app.handle().clone());
+                    
+                    // AI 提供商密钥加密：加载应用密钥并迁移旧版明文密钥
+                    let ai_keys_ready = app.path().app_data_dir()
+                        .map_err(|e| e.to_string())
+                        .and_then(|app_dir| ai_keys::init_app_key(&app_dir))
+                        .and_then(|_| ai_keys::migrate_plaintext_keys(&db));
+                    if let Err(e) = ai_keys_ready {
+                        eprintln!("AI 密钥初始化失败: {}", e);
+                    }
+                    
+                    // 补齐上次未完成的向量索引更新
+                    ai_embeddings::spawn_index_update(db.clone());
+                    
                     app.manage(db);
                 }
                 Err(e) => {
@@ -73,6 +110,8 @@ fn main() {
                             if let Some(window) = app.get_webview_window("main") {
                                 let _ = window.hide();
                             }
+                            // 隐藏到托盘时锁定密码库
+                            let _ = vault_session::lock(app, "hide");
                         }
                         "show" => {
                             if let Some(window) = app.get_webview_window("main") {
@@ -91,6 +130,8 @@ fn main() {
             if let WindowEvent::CloseRequested { api, .. } = event {
                 let _ = window.hide();
                 api.prevent_close();
+                // 关闭窗口同样隐藏到托盘，与托盘菜单的“隐藏”一致锁定密码库
+                let _ = vault_session::lock(window.app_handle(), "hide");
             }
         })
         .invoke_handler(tauri::generate_handler![
@@ -138,6 +179,22 @@ fn main() {
             // AI 聊天命令
             ai_chat::send_ai_chat,
             ai_chat::send_ai_chat_stream,
+            ai_chat::send_agent_chat,
+            ai_retrieval::search_rag_context,
+            ai_retrieval::rebuild_rag_index,
+            ai_retrieval::send_rag_chat,
+            ai_embeddings::get_embedding_settings,
+            ai_embeddings::save_embedding_settings,
+            ai_embeddings::update_embedding_index,
+            ai_embeddings::semantic_search,
+            ai_tools::get_ai_tools,
+            ai_tools::confirm_ai_tool_call,
+            ai_tools::run_ai_agent,
+            ai_chat::cancel_ai_stream,
+            ai_usage::get_ai_usage_report,
+            ai_usage::get_ai_model_prices,
+            ai_usage::save_ai_model_price,
+            ai_usage::delete_ai_model_price,
             // AI 对话管理命令
             ai_commands::save_ai_conversation,
             ai_commands::save_ai_message,
@@ -149,6 +206,13 @@ fn main() {
             ai_commands::cleanup_old_ai_conversations,
             ai_commands::sync_ai_conversation_with_messages,
             // 密码管理命令
+            password_commands::get_vault_status,
+            password_commands::setup_master_password,
+            password_commands::unlock_vault,
+            password_commands::lock_vault,
+            password_commands::change_master_password,
+            password_commands::get_vault_auto_lock,
+            password_commands::set_vault_auto_lock,
             password_commands::get_password_categories,
             password_commands::create_password_category,
             password_commands::update_password_category,
@@ -159,9 +223,25 @@ fn main() {
             password_commands::update_password_entry,
             password_commands::delete_password_entry,
             password_commands::get_decrypted_password,
+            password_commands::get_totp_code,
+            password_import::import_password_entries,
+            password_backup::export_password_vault,
+            password_backup::import_password_vault,
+            password_history::get_password_history,
+            password_history::get_history_password,
+            password_history::restore_password_version,
+            password_history::get_password_audit_log,
+            password_history::get_password_retention,
+            password_history::set_password_retention,
+            password_health::get_vault_health_report,
+            password_breach::check_breached_passwords,
+            password_commands::get_password_entries_by_type,
+            vault_items::get_vault_item_types,
+            vault_items::get_vault_item_fields,
             password_commands::search_password_entries,
             password_commands::generate_password,
             password_commands::check_password_strength,
+            password_commands::estimate_password_strength,
             password_commands::get_favorite_password_entries,
             password_commands::test_password_connection,
             // 卡片盒命令
@@ -0,0 +1,568 @@
+use crate::crypto::{CryptoService, KdfParams};
+use crate::database::Database;
+use crate::password_commands::{decrypt_entry_password, encrypt_totp_secret, run_key_derivation, vault_key};
+use crate::password_history;
+use crate::password_import::duplicate_key;
+use crate::vault_items::{self, ItemType};
+use base64::{Engine as _, engine::general_purpose};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tauri::State;
+
+// 密码库备份文件格式（版本 1）
+//
+// 备份文件是一个 JSON 对象：
+// {
+//   "format": "anning-vault-backup",
+//   "version": 1,
+//   "kdf": { "algorithm": "argon2id", "salt": "<base64>", "params": { "memory_kib", "iterations", "parallelism" } },
+//   "payload": "<CryptoService::encrypt_with_key 输出的 base64 密文>"
+// }
+//
+// 加密密钥由导出口令经 Argon2id 派生，与主密码无关，因此可以在另一台设备上导入。
+// payload 解密后是 BackupPayload 的 JSON：分类按名称关联，条目中的密码、TOTP 密钥和类型字段为明文。
+// item_type 和 fields 是后来增加的可选字段，缺省时按登录类型导入。
+// 格式发生不兼容变化时递增 version，导入时拒绝无法识别的版本。
+const BACKUP_FORMAT: &str = "anning-vault-backup";
+const BACKUP_VERSION: u32 = 1;
+const BACKUP_KDF: &str = "argon2id";
+
+// 导出口令最短长度
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupKdf {
+    algorithm: String,
+    salt: String,
+    params: KdfParams,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupFile {
+    format: String,
+    version: u32,
+    kdf: BackupKdf,
+    payload: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BackupCategory {
+    name: String,
+    icon: String,
+    color: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BackupEntry {
+    title: String,
+    username: Option<String>,
+    password: String,
+    url: Option<String>,
+    notes: Option<String>,
+    ip: Option<String>,
+    db_type: Option<String>,
+    db_ip: Option<String>,
+    db_username: Option<String>,
+    app_name: Option<String>,
+    category: Option<String>,  // 分类名称
+    tags: Option<String>,      // 与 password_entries.tags 相同的 JSON 字符串
+    is_favorite: bool,
+    totp: Option<String>,
+    #[serde(default)]
+    item_type: ItemType,
+    #[serde(default)]
+    fields: Option<BTreeMap<String, String>>,
+    last_used_at: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BackupPayload {
+    exported_at: String,
+    categories: Vec<BackupCategory>,
+    entries: Vec<BackupEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupImportReport {
+    pub mode: String,
+    pub categories_created: usize,
+    pub added: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+// 合并时的重复检测键：优先按网址+用户名，两者都为空（如安全笔记）时按类型+标题
+fn entry_key(url: Option<&str>, username: Option<&str>, title: &str, item_type: ItemType) -> Option<String> {
+    duplicate_key(url, username).or_else(|| {
+        let title = title.trim().to_lowercase();
+        // 以 \0 开头，不会与网址+用户名的键冲突
+        (!title.is_empty()).then(|| format!("\0{}\n{}", item_type.as_str(), title))
+    })
+}
+
+// 用导出口令加密备份内容
+fn seal_backup(payload: &BackupPayload, passphrase: &str, kdf_params: KdfParams) -> Result<String, String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("导出口令至少需要 {} 个字符", MIN_PASSPHRASE_LEN));
+    }
+
+    let salt = CryptoService::generate_salt();
+    let key = CryptoService::derive_key_from_password(passphrase, &salt, &kdf_params)?;
+    let plaintext = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+
+    let file = BackupFile {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        kdf: BackupKdf {
+            algorithm: BACKUP_KDF.to_string(),
+            salt: general_purpose::STANDARD.encode(&salt),
+            params: kdf_params,
+        },
+        payload: CryptoService::encrypt_with_key(&key, &plaintext)?,
+    };
+    serde_json::to_string_pretty(&file).map_err(|e| e.to_string())
+}
+
+// 校验备份文件头并用导出口令解密
+fn open_backup(content: &str, passphrase: &str) -> Result<BackupPayload, String> {
+    let file: BackupFile = serde_json::from_str(content)
+        .map_err(|e| format!("无法解析备份文件: {}", e))?;
+
+    if file.format != BACKUP_FORMAT {
+        return Err("不是安宁密码库备份文件".to_string());
+    }
+    if file.version != BACKUP_VERSION {
+        return Err(format!("不支持的备份文件版本: {}", file.version));
+    }
+    if file.kdf.algorithm != BACKUP_KDF {
+        return Err(format!("不支持的密钥派生算法: {}", file.kdf.algorithm));
+    }
+    // 参数来自不受信任的文件，派生密钥前先限制范围
+    file.kdf.params.validate().map_err(|e| format!("备份文件密钥派生参数无效: {}", e))?;
+
+    let salt = general_purpose::STANDARD.decode(&file.kdf.salt)
+        .map_err(|e| format!("备份文件盐值无效: {}", e))?;
+    let key = CryptoService::derive_key_from_password(passphrase, &salt, &file.kdf.params)?;
+    let plaintext = CryptoService::decrypt_with_key(&key, &file.payload)
+        .map_err(|_| "导出口令错误或备份文件已损坏".to_string())?;
+
+    serde_json::from_str(&plaintext).map_err(|e| format!("备份内容无效: {}", e))
+}
+
+// 导出整个密码库，返回加密后的备份文件内容
+#[tauri::command]
+pub async fn export_password_vault(
+    database: State<'_, Arc<Database>>,
+    passphrase: String
+) -> Result<String, String> {
+    vault_key()?;
+
+    let (categories, rows) = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT name, icon, color FROM password_categories ORDER BY id")?;
+        let categories = stmt.query_map([], |row| {
+            Ok(BackupCategory {
+                name: row.get(0)?,
+                icon: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                color: row.get(2)?,
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT e.title, e.username, e.password_encrypted, e.url, e.notes, e.ip, e.db_type, e.db_ip,
+                    e.db_username, e.app_name, c.name, e.tags, e.is_favorite, e.totp_encrypted,
+                    e.last_used_at, e.created_at, e.updated_at, e.is_encrypted, e.item_type, e.fields_encrypted
+             FROM password_entries e
+             LEFT JOIN password_categories c ON c.id = e.category_id
+             ORDER BY e.id"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let entry = BackupEntry {
+                title: row.get(0)?,
+                username: row.get(1)?,
+                password: row.get(2)?,
+                url: row.get(3)?,
+                notes: row.get(4)?,
+                ip: row.get(5)?,
+                db_type: row.get(6)?,
+                db_ip: row.get(7)?,
+                db_username: row.get(8)?,
+                app_name: row.get(9)?,
+                category: row.get(10)?,
+                tags: row.get(11)?,
+                is_favorite: row.get::<_, i32>(12)? == 1,
+                totp: row.get(13)?,
+                item_type: ItemType::parse(&row.get::<_, String>(18)?).unwrap_or_default(),
+                fields: None,
+                last_used_at: row.get(14)?,
+                created_at: row.get(15)?,
+                updated_at: row.get(16)?,
+            };
+            Ok((entry, row.get::<_, i32>(17)? == 1, row.get::<_, Option<String>>(19)?))
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok((categories, rows))
+    })?;
+
+    // 备份内只保存明文，由导出口令整体加密
+    let mut entries = Vec::with_capacity(rows.len());
+    for (mut entry, is_encrypted, fields_ciphertext) in rows {
+        if is_encrypted {
+            entry.password = decrypt_entry_password(&entry.password)?;
+        }
+        if let Some(totp) = &entry.totp {
+            entry.totp = Some(decrypt_entry_password(totp)?);
+        }
+        if let Some(fields_ciphertext) = &fields_ciphertext {
+            entry.fields = Some(vault_items::decrypt_fields(fields_ciphertext)?);
+        }
+        entries.push(entry);
+    }
+
+    let payload = BackupPayload {
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        categories,
+        entries,
+    };
+    let (category_count, entry_count) = (payload.categories.len(), payload.entries.len());
+    let backup = run_key_derivation(move || seal_backup(&payload, &passphrase, KdfParams::default())).await?;
+
+    println!("✅ 已导出 {} 个分类、{} 条密码条目", category_count, entry_count);
+    Ok(backup)
+}
+
+// 把备份内容写入密码库，key 为当前主密钥
+fn import_backup(database: &Database, key: &[u8], payload: &BackupPayload, replace: bool) -> Result<BackupImportReport, String> {
+    // 合并模式下的已有条目：重复键 -> (id, updated_at, 密码密文, 是否已加密)
+    let mut existing: HashMap<String, (i64, String, String, bool)> = HashMap::new();
+    if !replace {
+        let rows = database.with_connection(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, url, username, updated_at, title, item_type, password_encrypted, is_encrypted
+                 FROM password_entries ORDER BY id"
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let url: Option<String> = row.get(1)?;
+                let username: Option<String> = row.get(2)?;
+                let title: String = row.get(4)?;
+                let item_type = ItemType::parse(&row.get::<_, String>(5)?).unwrap_or_default();
+                Ok((
+                    entry_key(url.as_deref(), username.as_deref(), &title, item_type),
+                    (row.get(0)?, row.get(3)?, row.get(6)?, row.get::<_, i32>(7)? == 1),
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })?;
+        for (key, row) in rows {
+            if let Some(key) = key {
+                existing.entry(key).or_insert(row);
+            }
+        }
+    }
+
+    // 先在事务外完成全部加密，失败时不改动数据库
+    let mut skipped = 0;
+    let mut encrypted_entries = Vec::with_capacity(payload.entries.len());
+    for entry in &payload.entries {
+        let matched = entry_key(entry.url.as_deref(), entry.username.as_deref(), &entry.title, entry.item_type)
+            .and_then(|key| existing.get(&key));
+        let (target_id, previous_password) = match matched {
+            Some((id, updated_at, password, is_encrypted)) => {
+                // SQLite 的 DATETIME 文本可直接按字典序比较
+                if entry.updated_at <= *updated_at {
+                    skipped += 1;
+                    continue;
+                }
+                // 与 update_entry 一样，密码有变化时用当前主密钥重新加密旧密码写入历史
+                let old_password = if *is_encrypted {
+                    decrypt_entry_password(password)?
+                } else {
+                    password.clone()
+                };
+                let previous_password = if old_password != entry.password {
+                    Some(CryptoService::encrypt_with_key(key, &old_password)?)
+                } else {
+                    None
+                };
+                (Some(*id), previous_password)
+            }
+            None => (None, None),
+        };
+        encrypted_entries.push((
+            entry,
+            target_id,
+            previous_password,
+            CryptoService::encrypt_with_key(key, &entry.password)?,
+            encrypt_totp_secret(key, entry.totp.as_deref())?,
+            vault_items::encrypt_fields(key, entry.item_type, entry.fields.as_ref())?,
+        ));
+    }
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        let mut report = BackupImportReport {
+            mode: if replace { "replace" } else { "merge" }.to_string(),
+            categories_created: 0,
+            added: 0,
+            updated: 0,
+            skipped,
+        };
+
+        if replace {
+            tx.execute("DELETE FROM password_entries", [])?;
+            tx.execute("DELETE FROM password_categories", [])?;
+        }
+
+        let mut category_ids: HashMap<String, i64> = HashMap::new();
+        {
+            let mut stmt = tx.prepare("SELECT id, name FROM password_categories")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(1)?, row.get(0)?)))?;
+            for row in rows {
+                let (name, id) = row?;
+                category_ids.insert(name, id);
+            }
+        }
+        for category in &payload.categories {
+            if category_ids.contains_key(&category.name) {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO password_categories (name, icon, color) VALUES (?1, ?2, ?3)",
+                params![category.name, category.icon, category.color],
+            )?;
+            category_ids.insert(category.name.clone(), tx.last_insert_rowid());
+            report.categories_created += 1;
+        }
+
+        for (entry, target_id, previous_password, stored_password, stored_totp, stored_fields) in &encrypted_entries {
+            let category_id = entry.category.as_ref().and_then(|name| category_ids.get(name).copied());
+
+            if let Some(id) = target_id {
+                tx.execute(
+                    "UPDATE password_entries
+                     SET title = ?1, username = ?2, password_encrypted = ?3, url = ?4, notes = ?5, ip = ?6,
+                         db_type = ?7, db_ip = ?8, db_username = ?9, app_name = ?10, category_id = ?11,
+                         tags = ?12, is_favorite = ?13, totp_encrypted = ?14, last_used_at = ?15,
+                         updated_at = ?16, item_type = ?17, fields_encrypted = ?18, is_encrypted = 1,
+                         breach_count = NULL, breach_checked_at = NULL
+                     WHERE id = ?19",
+                    params![
+                        entry.title, entry.username, stored_password, entry.url, entry.notes, entry.ip,
+                        entry.db_type, entry.db_ip, entry.db_username, entry.app_name, category_id,
+                        entry.tags, if entry.is_favorite { 1 } else { 0 }, stored_totp, entry.last_used_at,
+                        entry.updated_at, entry.item_type.as_str(), stored_fields, id
+                    ],
+                )?;
+                if let Some(previous_password) = previous_password {
+                    password_history::record_history(&tx, *id, previous_password)?;
+                }
+                report.updated += 1;
+            } else {
+                tx.execute(
+                    "INSERT INTO password_entries
+                     (title, username, password_encrypted, url, notes, ip, db_type, db_ip, db_username, app_name,
+                      category_id, tags, is_favorite, totp_encrypted, last_used_at, created_at, updated_at,
+                      item_type, fields_encrypted, is_encrypted)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, 1)",
+                    params![
+                        entry.title, entry.username, stored_password, entry.url, entry.notes, entry.ip,
+                        entry.db_type, entry.db_ip, entry.db_username, entry.app_name, category_id,
+                        entry.tags, if entry.is_favorite { 1 } else { 0 }, stored_totp, entry.last_used_at,
+                        entry.created_at, entry.updated_at, entry.item_type.as_str(), stored_fields
+                    ],
+                )?;
+                report.added += 1;
+            }
+        }
+
+        tx.commit()?;
+        Ok(report)
+    })
+}
+
+// 导入备份：merge 按网址+用户名（没有时按类型+标题）合并（备份中更新的条目覆盖本地，被覆盖的旧密码写入历史），
+// replace 清空后整体替换；备份不含密码历史，replace 会连同全部条目的历史版本一起删除，前端需在导入前向用户确认
+#[tauri::command]
+pub async fn import_password_vault(
+    database: State<'_, Arc<Database>>,
+    content: String,
+    passphrase: String,
+    mode: String
+) -> Result<BackupImportReport, String> {
+    let replace = match mode.as_str() {
+        "merge" => false,
+        "replace" => true,
+        _ => return Err(format!("不支持的导入模式: {}", mode)),
+    };
+
+    let key = vault_key()?;
+    let payload = run_key_derivation(move || open_backup(&content, &passphrase)).await?;
+
+    let report = import_backup(&database, &key, &payload, replace)?;
+
+    println!(
+        "✅ 密码库备份导入完成（{}）：新增 {}，更新 {}，跳过 {}",
+        report.mode, report.added, report.updated, report.skipped
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 导入时会校验参数范围，测试使用允许的最小参数
+    fn test_kdf_params() -> KdfParams {
+        KdfParams { memory_kib: KdfParams::MIN_MEMORY_KIB, iterations: KdfParams::MIN_ITERATIONS, parallelism: 1 }
+    }
+
+    fn sample_payload() -> BackupPayload {
+        BackupPayload {
+            exported_at: "2024-01-01T00:00:00+00:00".to_string(),
+            categories: vec![BackupCategory {
+                name: "网站".to_string(),
+                icon: "🌐".to_string(),
+                color: None,
+            }],
+            entries: vec![BackupEntry {
+                title: "Example".to_string(),
+                username: Some("alice".to_string()),
+                password: "correct horse battery staple".to_string(),
+                url: Some("https://example.com".to_string()),
+                notes: None,
+                ip: None,
+                db_type: None,
+                db_ip: None,
+                db_username: None,
+                app_name: None,
+                category: Some("网站".to_string()),
+                tags: Some("[\"工作\"]".to_string()),
+                is_favorite: true,
+                totp: Some("JBSWY3DPEHPK3PXP".to_string()),
+                item_type: ItemType::Login,
+                fields: None,
+                last_used_at: None,
+                created_at: "2024-01-01 00:00:00".to_string(),
+                updated_at: "2024-01-01 00:00:00".to_string(),
+            }, BackupEntry {
+                title: "Recovery codes".to_string(),
+                username: None,
+                password: String::new(),
+                url: None,
+                notes: None,
+                ip: None,
+                db_type: None,
+                db_ip: None,
+                db_username: None,
+                app_name: None,
+                category: None,
+                tags: None,
+                is_favorite: false,
+                totp: None,
+                item_type: ItemType::SecureNote,
+                fields: Some(BTreeMap::from([("content".to_string(), "1234-5678".to_string())])),
+                last_used_at: None,
+                created_at: "2024-01-01 00:00:00".to_string(),
+                updated_at: "2024-01-01 00:00:00".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_backup_round_trip() {
+        let payload = sample_payload();
+        let backup = seal_backup(&payload, "export-passphrase", test_kdf_params()).unwrap();
+
+        // 备份文件中不应出现明文密码
+        assert!(!backup.contains("correct horse"));
+        assert_eq!(open_backup(&backup, "export-passphrase").unwrap(), payload);
+    }
+
+    #[test]
+    fn test_backup_entry_without_item_type() {
+        // 增加条目类型之前导出的备份按登录类型导入
+        let mut entry = serde_json::to_value(&sample_payload().entries[0]).unwrap();
+        entry.as_object_mut().unwrap().remove("item_type");
+        entry.as_object_mut().unwrap().remove("fields");
+
+        let entry: BackupEntry = serde_json::from_value(entry).unwrap();
+        assert_eq!(entry.item_type, ItemType::Login);
+        assert_eq!(entry.fields, None);
+    }
+
+    #[test]
+    fn test_backup_rejects_wrong_passphrase() {
+        let backup = seal_backup(&sample_payload(), "export-passphrase", test_kdf_params()).unwrap();
+        assert!(open_backup(&backup, "wrong-passphrase").is_err());
+        assert!(seal_backup(&sample_payload(), "short", test_kdf_params()).is_err());
+    }
+
+    #[test]
+    fn test_backup_rejects_unknown_version() {
+        let backup = seal_backup(&sample_payload(), "export-passphrase", test_kdf_params()).unwrap();
+        let mut file: serde_json::Value = serde_json::from_str(&backup).unwrap();
+        file["version"] = serde_json::json!(BACKUP_VERSION + 1);
+
+        let error = open_backup(&file.to_string(), "export-passphrase").unwrap_err();
+        assert!(error.contains("版本"));
+    }
+
+    #[test]
+    fn test_merge_import_keeps_password_history() {
+        let database = Database::open_in_memory().unwrap();
+        let key = CryptoService::derive_legacy_key("password", &CryptoService::generate_salt());
+        let id = database.with_connection(|conn| {
+            conn.execute(
+                "INSERT INTO password_entries (title, username, url, password_encrypted, is_encrypted, updated_at)
+                 VALUES ('Example', 'alice', 'https://example.com', 'old password', 0, '2023-01-01 00:00:00')",
+                [],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }).unwrap();
+
+        let report = import_backup(&database, &key, &sample_payload(), false).unwrap();
+        assert_eq!((report.added, report.updated, report.skipped), (1, 1, 0));
+
+        let history: Vec<String> = database.with_connection(|conn| {
+            let mut stmt = conn.prepare("SELECT password_encrypted FROM password_history WHERE entry_id = ?1")?;
+            let rows = stmt.query_map(params![id], |row| row.get(0))?;
+            rows.collect()
+        }).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(CryptoService::decrypt_with_key(&key, &history[0]).unwrap(), "old password");
+
+        // 再次导入同一备份不会覆盖，也不会再写历史
+        let report = import_backup(&database, &key, &sample_payload(), false).unwrap();
+        assert_eq!((report.added, report.updated, report.skipped), (0, 0, 2));
+    }
+
+    #[test]
+    fn test_entry_key_falls_back_to_title() {
+        let login = entry_key(Some("https://example.com/"), Some("Alice"), "Example", ItemType::Login);
+        assert_eq!(login, duplicate_key(Some("https://example.com"), Some("alice")));
+
+        // 没有网址和用户名的条目按类型+标题匹配
+        let note = entry_key(None, None, " Recovery Codes ", ItemType::SecureNote);
+        assert!(note.is_some());
+        assert_eq!(note, entry_key(Some(""), None, "recovery codes", ItemType::SecureNote));
+        assert_ne!(note, entry_key(None, None, "Recovery codes", ItemType::ApiToken));
+        assert_eq!(entry_key(None, None, "  ", ItemType::SecureNote), None);
+    }
+
+    #[test]
+    fn test_backup_rejects_out_of_range_kdf_params() {
+        let backup = seal_backup(&sample_payload(), "export-passphrase", test_kdf_params()).unwrap();
+        let mut file: serde_json::Value = serde_json::from_str(&backup).unwrap();
+
+        // 过大的内存参数在派生密钥前就被拒绝
+        file["kdf"]["params"]["memory_kib"] = serde_json::json!(u32::MAX);
+        let error = open_backup(&file.to_string(), "export-passphrase").unwrap_err();
+        assert!(error.contains("密钥派生参数"));
+
+        file["kdf"]["params"]["memory_kib"] = serde_json::json!(1024);
+        assert!(open_backup(&file.to_string(), "export-passphrase").is_err());
+    }
+}
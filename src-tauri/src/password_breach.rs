@@ -0,0 +1,255 @@
+use crate::database::Database;
+use crate::password_commands::{decrypt_entry_password, vault_key};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::State;
+
+// 前缀长度与 HIBP range 接口一致：只按 SHA-1 前 5 位查找，完整哈希只在本地比较
+const PREFIX_LEN: usize = 5;
+
+// 本地 HIBP 数据源
+enum BreachSource {
+    // 按哈希排序的完整文件，每行 "SHA1:次数"
+    SortedFile(PathBuf),
+    // range 文件目录，文件名为 5 位前缀（可带 .txt），每行 "后 35 位:次数"
+    RangeDirectory(PathBuf),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BreachedEntry {
+    pub entry_id: i64,
+    pub title: String,
+    pub breach_count: u64,  // 该密码在泄露数据中出现的次数
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BreachCheckReport {
+    pub checked: usize,
+    pub breached: Vec<BreachedEntry>,
+}
+
+impl BreachSource {
+    fn open(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| format!("无法读取泄露数据 {}: {}", path.display(), e))?;
+        if metadata.is_dir() {
+            Ok(BreachSource::RangeDirectory(path))
+        } else {
+            Ok(BreachSource::SortedFile(path))
+        }
+    }
+
+    // 返回该前缀下所有哈希的后缀（大写）及出现次数
+    fn lookup_range(&self, prefix: &str) -> Result<Vec<(String, u64)>, String> {
+        match self {
+            BreachSource::SortedFile(path) => {
+                let file = File::open(path).map_err(|e| format!("无法打开泄露数据文件: {}", e))?;
+                let len = file.metadata().map_err(|e| e.to_string())?.len();
+                lookup_sorted(&mut BufReader::new(file), len, prefix).map_err(|e| format!("读取泄露数据文件失败: {}", e))
+            }
+            BreachSource::RangeDirectory(dir) => {
+                let Some(range_file) = range_file_path(dir, prefix) else {
+                    return Ok(Vec::new());
+                };
+                let content = std::fs::read_to_string(&range_file)
+                    .map_err(|e| format!("读取 {} 失败: {}", range_file.display(), e))?;
+                Ok(content.lines().filter_map(parse_hash_line).collect())
+            }
+        }
+    }
+}
+
+fn range_file_path(dir: &Path, prefix: &str) -> Option<PathBuf> {
+    [prefix.to_string(), format!("{}.txt", prefix), prefix.to_lowercase(), format!("{}.txt", prefix.to_lowercase())]
+        .into_iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn sha1_hex(password: &str) -> String {
+    Sha1::digest(password.as_bytes()).iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+// 解析 "哈希:次数"，没有次数时按出现一次计算
+fn parse_hash_line(line: &str) -> Option<(String, u64)> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let (hash, count) = match line.split_once(':') {
+        Some((hash, count)) => (hash, count.trim().parse().unwrap_or(1)),
+        None => (line, 1),
+    };
+    Some((hash.trim().to_uppercase(), count))
+}
+
+// 读取从 pos 开始的第一整行（pos 位于行中间时跳过该行剩余部分）
+fn line_starting_at<R: BufRead + Seek>(reader: &mut R, pos: u64) -> std::io::Result<Option<String>> {
+    if pos > 0 {
+        reader.seek(SeekFrom::Start(pos - 1))?;
+        reader.read_until(b'\n', &mut Vec::new())?;
+    } else {
+        reader.seek(SeekFrom::Start(0))?;
+    }
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+// 在排序文件中二分查找第一条不小于前缀的行，再顺序读取同前缀的所有行
+fn lookup_sorted<R: BufRead + Seek>(reader: &mut R, len: u64, prefix: &str) -> std::io::Result<Vec<(String, u64)>> {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let reached = match line_starting_at(reader, mid)? {
+            Some(line) => line.trim().to_uppercase().as_str() >= prefix,
+            None => true,
+        };
+        if reached {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    let mut matches = Vec::new();
+    let Some(mut line) = line_starting_at(reader, low)? else {
+        return Ok(matches);
+    };
+    loop {
+        match parse_hash_line(&line) {
+            Some((hash, count)) if hash.starts_with(prefix) => matches.push((hash[prefix.len()..].to_string(), count)),
+            Some(_) => break,
+            None => {}
+        }
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+    }
+    Ok(matches)
+}
+
+// 使用本地 HIBP 数据检查所有条目，并把结果写回条目
+#[tauri::command]
+pub async fn check_breached_passwords(
+    database: State<'_, Arc<Database>>,
+    source_path: String
+) -> Result<BreachCheckReport, String> {
+    vault_key()?;
+
+    let rows: Vec<(i64, String, String, bool)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, title, password_encrypted, is_encrypted FROM password_entries")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, i32>(3)? == 1))
+        })?;
+        rows.collect()
+    })?;
+
+    // 明文只用于计算哈希，按前缀分组以减少查找次数
+    let mut by_prefix: HashMap<String, Vec<(i64, String, String)>> = HashMap::new();
+    for (entry_id, title, password, is_encrypted) in rows {
+        let password = if is_encrypted { decrypt_entry_password(&password)? } else { password };
+        if password.is_empty() {
+            continue;
+        }
+        let hash = sha1_hex(&password);
+        by_prefix.entry(hash[..PREFIX_LEN].to_string())
+            .or_default()
+            .push((entry_id, title, hash[PREFIX_LEN..].to_string()));
+    }
+
+    // 泄露数据文件可能有数十 GB，查找放到阻塞线程中，不占用异步运行时
+    let (mut report, results) = tauri::async_runtime::spawn_blocking(move || {
+        let source = BreachSource::open(&source_path)?;
+        let mut report = BreachCheckReport { checked: 0, breached: Vec::new() };
+        let mut results: Vec<(i64, u64)> = Vec::new();
+        for (prefix, entries) in by_prefix {
+            let range: HashMap<String, u64> = source.lookup_range(&prefix)?.into_iter().collect();
+            for (entry_id, title, suffix) in entries {
+                let breach_count = range.get(&suffix).copied().unwrap_or(0);
+                if breach_count > 0 {
+                    report.breached.push(BreachedEntry { entry_id, title, breach_count });
+                }
+                results.push((entry_id, breach_count));
+                report.checked += 1;
+            }
+        }
+        Ok::<_, String>((report, results))
+    }).await.map_err(|e| format!("泄露检查任务失败: {}", e))??;
+    report.breached.sort_by_key(|entry| std::cmp::Reverse(entry.breach_count));
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        for (entry_id, breach_count) in &results {
+            tx.execute(
+                "UPDATE password_entries SET breach_count = ?1, breach_checked_at = DATETIME('now') WHERE id = ?2",
+                params![*breach_count as i64, entry_id],
+            )?;
+        }
+        tx.commit()
+    })?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sorted_file(hashes: &[(&str, u64)]) -> Cursor<Vec<u8>> {
+        let mut lines: Vec<String> = hashes.iter().map(|(hash, count)| format!("{}:{}\r\n", hash, count)).collect();
+        lines.sort();
+        Cursor::new(lines.concat().into_bytes())
+    }
+
+    #[test]
+    fn test_sha1_hex() {
+        assert_eq!(sha1_hex("password"), "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8");
+    }
+
+    #[test]
+    fn test_lookup_sorted_file() {
+        let mut file = sorted_file(&[
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8", 9545824),
+            ("5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD9", 3),
+            ("5BAA5FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", 1),
+            ("5BAA700000000000000000000000000000000000", 2),
+            ("00000000000000000000000000000000000000AA", 7),
+            ("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", 1),
+        ]);
+        let len = file.get_ref().len() as u64;
+
+        let range = lookup_sorted(&mut file, len, "5BAA6").unwrap();
+        assert_eq!(range, vec![
+            ("1E4C9B93F3F0682250B6CF8331B7EE68FD8".to_string(), 9545824),
+            ("1E4C9B93F3F0682250B6CF8331B7EE68FD9".to_string(), 3),
+        ]);
+
+        // 首行、末行和不存在的前缀
+        assert_eq!(lookup_sorted(&mut file, len, "00000").unwrap().len(), 1);
+        assert_eq!(lookup_sorted(&mut file, len, "FFFFF").unwrap().len(), 1);
+        assert!(lookup_sorted(&mut file, len, "12345").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_range_line() {
+        assert_eq!(
+            parse_hash_line("1e4c9b93f3f0682250b6cf8331b7ee68fd8:42\r"),
+            Some(("1E4C9B93F3F0682250B6CF8331B7EE68FD8".to_string(), 42))
+        );
+        assert_eq!(parse_hash_line("ABCDEF"), Some(("ABCDEF".to_string(), 1)));
+        assert_eq!(parse_hash_line("   "), None);
+    }
+}
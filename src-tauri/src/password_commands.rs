@@ -1,9 +1,18 @@
+use crate::ai_keys;
 use crate::database::{Database, PasswordCategory};
-use crate::crypto::CryptoService;
+use crate::crypto::{CiphertextFormat, CryptoService, KdfParams};
+use crate::password_generator::{self, GeneratedPassword, GeneratorMode, WordCapitalization};
+use crate::password_history;
+use crate::password_strength::{self, StrengthEstimate};
+use crate::totp::TotpConfig;
+use crate::vault_items::{self, ItemType};
+use crate::vault_session;
+use base64::{Engine as _, engine::general_purpose};
 use rusqlite::{params, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, State};
 
 // 密码管理相关的数据传输对象
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,11 +42,21 @@ pub struct PasswordEntryDto {
     pub category_id: Option<i64>,
     pub tags: Option<Vec<String>>,
     pub is_favorite: bool,
+    // 两步验证密钥：base32 密钥或 otpauth:// URI；更新时不传表示保持不变，传空字符串表示停用
+    #[serde(default)]
+    pub totp: Option<String>,
+    // 新建时缺省为登录类型，更新时不传表示保持不变
+    #[serde(default)]
+    pub item_type: Option<ItemType>,
+    // 类型专属字段，按 vault_items 中的定义校验后加密保存；更新时不传表示保持不变
+    #[serde(default)]
+    pub fields: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PasswordEntryResponse {
     pub id: Option<i64>,
+    pub item_type: String,
     pub title: String,
     pub username: Option<String>,
     pub url: Option<String>,
@@ -53,17 +72,34 @@ pub struct PasswordEntryResponse {
     pub category_id: Option<i64>,
     pub tags: Option<Vec<String>>,
     pub is_favorite: bool,
+    pub has_totp: bool,
+    // 泄露检查：出现次数，None 表示尚未检查或密码修改后未重新检查
+    pub breach_count: Option<i64>,
+    pub breach_checked_at: Option<String>,
     pub last_used_at: Option<String>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpCodeResponse {
+    pub code: String,
+    pub seconds_remaining: u64,
+    pub period: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MasterPasswordRequest {
     pub password: String,
     pub salt: Option<String>,  // base64编码的盐值
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultStatus {
+    pub initialized: bool,  // 是否已设置主密码
+    pub unlocked: bool,     // 当前会话是否已解锁
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PasswordGeneratorOptions {
     pub length: usize,
@@ -71,6 +107,558 @@ pub struct PasswordGeneratorOptions {
     pub include_lowercase: bool,
     pub include_numbers: bool,
     pub include_symbols: bool,
+    #[serde(default)]
+    pub mode: GeneratorMode,
+    #[serde(default)]
+    pub require_each_class: bool,   // 每类已选字符至少出现一次
+    #[serde(default)]
+    pub exclude_ambiguous: bool,    // 排除 I、l、1、O、0 等易混淆字符
+    #[serde(default)]
+    pub custom_charset: Option<String>,  // 非空时替代上面的字符类型
+    // 口令短语模式
+    #[serde(default = "default_word_count")]
+    pub word_count: usize,
+    #[serde(default = "default_word_separator")]
+    pub separator: String,
+    #[serde(default)]
+    pub capitalization: WordCapitalization,
+}
+
+fn default_word_count() -> usize {
+    6
+}
+
+fn default_word_separator() -> String {
+    "-".to_string()
+}
+
+// 密码库主密钥在 KEY_CACHE 中的会话ID
+pub const VAULT_SESSION_ID: &str = "password_vault";
+
+// 旧版 SHA-256 密钥的会话ID，仅在后台升级旧密文期间存在
+pub const VAULT_LEGACY_SESSION_ID: &str = "password_vault_legacy";
+
+// 用于校验主密码的已知明文
+const VAULT_CHECK_PLAINTEXT: &str = "anning-password-vault";
+
+// 密钥派生算法
+const KDF_ARGON2ID: &str = "argon2id";
+const KDF_LEGACY_SHA256: &str = "sha256";
+
+// 后台升级时每批处理的条目数
+const UPGRADE_BATCH_SIZE: usize = 50;
+
+// 条目列表查询的列，与 entry_from_row 的下标对应
+const ENTRY_COLUMNS: &str = "id, title, username, url, notes, ip, db_type, db_ip, db_username, app_name, \
+     category_id, tags, is_favorite, last_used_at, created_at, updated_at, totp_encrypted IS NOT NULL, \
+     breach_count, breach_checked_at, item_type";
+
+// 密码库设置（password_settings 表中 id = 1 的行）
+struct VaultSettings {
+    salt: Vec<u8>,
+    test_encrypted: String,
+    kdf_algorithm: String,
+    kdf_params: KdfParams,
+    legacy_salt: Option<Vec<u8>>,  // 旧版盐值，旧密文全部升级后清空
+}
+
+// 获取已解锁的主密钥，未解锁时返回错误；每次使用都会重置空闲计时
+pub fn vault_key() -> Result<Vec<u8>, String> {
+    let key = CryptoService::get_cached_key(VAULT_SESSION_ID)
+        .ok_or_else(|| "密码库已锁定，请先解锁".to_string())?;
+    vault_session::record_activity();
+    Ok(key)
+}
+
+// 按密文格式选择密钥解密：旧格式使用旧版密钥，没有旧版密钥时说明整个密码库仍是旧格式
+// 旧格式的随机 nonce 可能恰好以版本头部开头，按新格式解密失败时再用旧版密钥重试
+fn decrypt_with_vault_keys(key: &[u8], legacy_key: Option<&[u8]>, ciphertext: &str) -> Result<String, String> {
+    match CryptoService::ciphertext_format(ciphertext)? {
+        CiphertextFormat::Legacy => CryptoService::decrypt_with_key(legacy_key.unwrap_or(key), ciphertext),
+        CiphertextFormat::V2 => CryptoService::decrypt_with_key(key, ciphertext).or_else(|e| match legacy_key {
+            Some(legacy_key) => CryptoService::decrypt_with_key(legacy_key, ciphertext),
+            None => Err(e),
+        }),
+    }
+}
+
+// Argon2id 派生会占用数百毫秒，放到阻塞线程执行，避免卡住异步运行时
+pub async fn run_key_derivation<T, F>(task: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(task).await
+        .map_err(|e| format!("密钥派生任务失败: {}", e))?
+}
+
+// 使用当前会话的密钥解密条目密码
+pub fn decrypt_entry_password(ciphertext: &str) -> Result<String, String> {
+    let key = vault_key()?;
+    let legacy_key = CryptoService::get_cached_key(VAULT_LEGACY_SESSION_ID);
+    decrypt_with_vault_keys(&key, legacy_key.as_deref(), ciphertext)
+}
+
+// 校验并加密 TOTP 密钥，空值表示不启用
+pub fn encrypt_totp_secret(key: &[u8], totp: Option<&str>) -> Result<Option<String>, String> {
+    match totp.map(str::trim).filter(|totp| !totp.is_empty()) {
+        Some(totp) => {
+            TotpConfig::parse(totp)?;
+            Ok(Some(CryptoService::encrypt_with_key(key, totp)?))
+        }
+        None => Ok(None),
+    }
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<PasswordEntryResponse> {
+    let tags_json: Option<String> = row.get(11)?;
+    let tags = tags_json.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok());
+
+    Ok(PasswordEntryResponse {
+        id: Some(row.get(0)?),
+        item_type: row.get(19)?,
+        title: row.get(1)?,
+        username: row.get(2)?,
+        url: row.get(3)?,
+        notes: row.get(4)?,
+        ip: row.get(5)?,
+        db_type: row.get(6)?,
+        db_ip: row.get(7)?,
+        db_username: row.get(8)?,
+        app_name: row.get(9)?,
+        category_id: row.get(10)?,
+        tags,
+        is_favorite: row.get::<_, i32>(12)? == 1,
+        has_totp: row.get(16)?,
+        breach_count: row.get(17)?,
+        breach_checked_at: row.get(18)?,
+        last_used_at: row.get(13)?,
+        created_at: row.get(14)?,
+        updated_at: row.get(15)?,
+    })
+}
+
+// 读取密码库设置，未设置主密码时返回 None
+fn load_vault_settings(database: &Database) -> Result<Option<VaultSettings>, String> {
+    let settings = database.with_connection(|conn| {
+        let result = conn.query_row(
+            "SELECT master_password_salt, test_encrypted_data, kdf_algorithm, kdf_params, legacy_password_salt
+             FROM password_settings WHERE id = 1",
+            [],
+            |row| Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            )),
+        );
+
+        match result {
+            Ok(settings) => Ok(Some(settings)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    })?;
+
+    let (salt_base64, test_encrypted, kdf_algorithm, kdf_params_json, legacy_salt_base64) = match settings {
+        Some((salt, Some(test_encrypted), algorithm, params, legacy_salt)) => (salt, test_encrypted, algorithm, params, legacy_salt),
+        _ => return Ok(None),
+    };
+
+    let decode_salt = |value: String| general_purpose::STANDARD.decode(value)
+        .map_err(|e| format!("主密码盐值损坏: {}", e));
+    let kdf_params = match kdf_params_json {
+        Some(json) => serde_json::from_str(&json).map_err(|e| format!("密钥派生参数损坏: {}", e))?,
+        None => KdfParams::default(),
+    };
+
+    Ok(Some(VaultSettings {
+        salt: decode_salt(salt_base64)?,
+        test_encrypted,
+        kdf_algorithm,
+        kdf_params,
+        legacy_salt: legacy_salt_base64.map(decode_salt).transpose()?,
+    }))
+}
+
+// 保存密码库设置
+fn save_vault_settings(conn: &rusqlite::Connection, settings: &VaultSettings) -> rusqlite::Result<()> {
+    let kdf_params_json = serde_json::to_string(&settings.kdf_params).unwrap_or_default();
+
+    conn.execute(
+        "INSERT INTO password_settings
+            (id, master_password_salt, test_encrypted_data, kdf_algorithm, kdf_params, legacy_password_salt)
+         VALUES (1, ?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(id) DO UPDATE SET
+            master_password_salt = excluded.master_password_salt,
+            test_encrypted_data = excluded.test_encrypted_data,
+            kdf_algorithm = excluded.kdf_algorithm,
+            kdf_params = excluded.kdf_params,
+            legacy_password_salt = excluded.legacy_password_salt,
+            updated_at = DATETIME('now')",
+        params![
+            general_purpose::STANDARD.encode(&settings.salt),
+            settings.test_encrypted,
+            settings.kdf_algorithm,
+            kdf_params_json,
+            settings.legacy_salt.as_ref().map(|salt| general_purpose::STANDARD.encode(salt)),
+        ],
+    )?;
+    Ok(())
+}
+
+// 用新盐值和 Argon2id 参数生成密码库设置，返回设置和派生出的密钥
+fn new_vault_settings(password: &str, kdf_params: KdfParams) -> Result<(VaultSettings, Vec<u8>), String> {
+    if password.chars().count() < 8 {
+        return Err("主密码至少需要8个字符".to_string());
+    }
+    kdf_params.validate()?;
+
+    let salt = CryptoService::generate_salt();
+    let key = CryptoService::derive_key_from_password(password, &salt, &kdf_params)?;
+    let test_encrypted = CryptoService::encrypt_with_key(&key, VAULT_CHECK_PLAINTEXT)?;
+
+    let settings = VaultSettings {
+        salt,
+        test_encrypted,
+        kdf_algorithm: KDF_ARGON2ID.to_string(),
+        kdf_params,
+        legacy_salt: None,
+    };
+    Ok((settings, key))
+}
+
+// 校验主密码，成功时返回按设置中的算法派生出的密钥
+fn verify_master_password(settings: &VaultSettings, password: &str) -> Result<Vec<u8>, String> {
+    let key = if settings.kdf_algorithm == KDF_LEGACY_SHA256 {
+        CryptoService::derive_legacy_key(password, &settings.salt)
+    } else {
+        CryptoService::derive_key_from_password(password, &settings.salt, &settings.kdf_params)?
+    };
+
+    match CryptoService::decrypt_with_key(&key, &settings.test_encrypted) {
+        Ok(plaintext) if plaintext == VAULT_CHECK_PLAINTEXT => Ok(key),
+        _ => Err("主密码错误".to_string()),
+    }
+}
+
+// 一次性迁移：用主密钥加密仍以明文存储的条目
+fn encrypt_plaintext_entries(database: &Database, key: &[u8]) -> Result<usize, String> {
+    let plaintext_rows: Vec<(i64, String)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, password_encrypted FROM password_entries WHERE is_encrypted = 0"
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    })?;
+
+    if plaintext_rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut encrypted_rows = Vec::with_capacity(plaintext_rows.len());
+    for (id, plaintext) in plaintext_rows {
+        encrypted_rows.push((id, CryptoService::encrypt_with_key(key, &plaintext)?));
+    }
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        for (id, ciphertext) in &encrypted_rows {
+            tx.execute(
+                "UPDATE password_entries SET password_encrypted = ?1, is_encrypted = 1 WHERE id = ?2",
+                params![ciphertext, id],
+            )?;
+        }
+        tx.commit()?;
+
+        println!("✅ 已加密 {} 条明文密码条目", encrypted_rows.len());
+        Ok(encrypted_rows.len())
+    })
+}
+
+// 后台重新加密：把旧格式密文逐批升级为当前格式，全部完成后丢弃旧版盐值和密钥
+fn upgrade_legacy_entries(database: &Database) -> Result<usize, String> {
+    let legacy_key = match CryptoService::get_cached_key(VAULT_LEGACY_SESSION_ID) {
+        Some(key) => key,
+        None => return Ok(0),
+    };
+
+    let encrypted_rows: Vec<(i64, String)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, password_encrypted FROM password_entries WHERE is_encrypted = 1"
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    })?;
+
+    // 看起来是新格式但当前密钥解不开的，是 nonce 恰好以版本头部开头的旧密文
+    let key = CryptoService::get_cached_key(VAULT_SESSION_ID)
+        .ok_or_else(|| "密码库已锁定".to_string())?;
+    let legacy_rows: Vec<(i64, String)> = encrypted_rows
+        .into_iter()
+        .filter(|(_, ciphertext)| match CryptoService::ciphertext_format(ciphertext) {
+            Ok(CiphertextFormat::Legacy) => true,
+            Ok(CiphertextFormat::V2) => CryptoService::decrypt_with_key(&key, ciphertext).is_err(),
+            Err(_) => false,
+        })
+        .collect();
+
+    let mut upgraded = 0;
+    for batch in legacy_rows.chunks(UPGRADE_BATCH_SIZE) {
+        // 每批重新获取密钥，期间密码库被锁定则停止，下次解锁时继续（后台任务不算用户活动）
+        let key = CryptoService::get_cached_key(VAULT_SESSION_ID)
+            .ok_or_else(|| "密码库已锁定".to_string())?;
+
+        let mut reencrypted = Vec::with_capacity(batch.len());
+        for (id, ciphertext) in batch {
+            let plaintext = CryptoService::decrypt_with_key(&legacy_key, ciphertext)?;
+            reencrypted.push((*id, ciphertext, CryptoService::encrypt_with_key(&key, &plaintext)?));
+        }
+
+        upgraded += database.with_connection(|conn| {
+            let tx = conn.unchecked_transaction()?;
+            let mut count = 0;
+            for (id, old_ciphertext, new_ciphertext) in &reencrypted {
+                // 条目在此期间被修改过则跳过，避免覆盖新值
+                count += tx.execute(
+                    "UPDATE password_entries SET password_encrypted = ?1 WHERE id = ?2 AND password_encrypted = ?3",
+                    params![new_ciphertext, id, old_ciphertext],
+                )?;
+            }
+            tx.commit()?;
+            Ok(count)
+        })?;
+    }
+
+    database.with_connection(|conn| {
+        conn.execute("UPDATE password_settings SET legacy_password_salt = NULL WHERE id = 1", [])
+    })?;
+    CryptoService::clear_cached_key(VAULT_LEGACY_SESSION_ID)?;
+
+    println!("✅ 已将 {} 条旧格式密文升级为 Argon2id 密钥加密", upgraded);
+    Ok(upgraded)
+}
+
+// 密码库相关命令
+#[tauri::command]
+pub async fn get_vault_status(database: State<'_, Arc<Database>>) -> Result<VaultStatus, String> {
+    let initialized = load_vault_settings(&database)?.is_some();
+
+    Ok(VaultStatus {
+        initialized,
+        unlocked: initialized && CryptoService::get_cached_key(VAULT_SESSION_ID).is_some(),
+    })
+}
+
+#[tauri::command]
+pub async fn setup_master_password(
+    database: State<'_, Arc<Database>>,
+    password: String,
+    kdf_params: Option<KdfParams>
+) -> Result<(), String> {
+    if load_vault_settings(&database)?.is_some() {
+        return Err("主密码已设置，请使用修改主密码".to_string());
+    }
+
+    let (settings, key) = run_key_derivation(move || new_vault_settings(&password, kdf_params.unwrap_or_default())).await?;
+    database.with_connection(|conn| save_vault_settings(conn, &settings))?;
+
+    encrypt_plaintext_entries(&database, &key)?;
+    CryptoService::cache_key(VAULT_SESSION_ID, key)?;
+    vault_session::record_activity();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unlock_vault(
+    database: State<'_, Arc<Database>>,
+    password: String
+) -> Result<(), String> {
+    let settings = load_vault_settings(&database)?
+        .ok_or_else(|| "尚未设置主密码".to_string())?;
+
+    let (key, legacy_key, upgraded) = run_key_derivation(move || {
+        let key = verify_master_password(&settings, &password)?;
+        if settings.kdf_algorithm == KDF_LEGACY_SHA256 {
+            // 旧版 SHA-256 密码库：改用 Argon2id 派生新密钥，旧密钥只保留到后台升级完成
+            let (mut upgraded, new_key) = new_vault_settings(&password, KdfParams::default())?;
+            upgraded.legacy_salt = Some(settings.salt);
+            Ok((new_key, Some(key), Some(upgraded)))
+        } else {
+            let legacy_key = settings.legacy_salt
+                .map(|legacy_salt| CryptoService::derive_legacy_key(&password, &legacy_salt));
+            Ok((key, legacy_key, None))
+        }
+    }).await?;
+    if let Some(upgraded) = &upgraded {
+        database.with_connection(|conn| save_vault_settings(conn, upgraded))?;
+    }
+
+    // 旧版本遗留的明文条目在首次解锁时加密
+    encrypt_plaintext_entries(&database, &key)?;
+    CryptoService::cache_key(VAULT_SESSION_ID, key)?;
+    vault_session::record_activity();
+
+    if let Some(legacy_key) = legacy_key {
+        CryptoService::cache_key(VAULT_LEGACY_SESSION_ID, legacy_key)?;
+
+        let database = database.inner().clone();
+        tauri::async_runtime::spawn_blocking(move || {
+            if let Err(e) = upgrade_legacy_entries(&database) {
+                eprintln!("旧格式密文升级中断: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn lock_vault(app_handle: AppHandle) -> Result<(), String> {
+    vault_session::lock(&app_handle, "manual")
+}
+
+// 读取空闲自动锁定时间（分钟），未设置主密码时返回默认值
+pub fn load_auto_lock_minutes(database: &Database) -> u32 {
+    database.with_connection(|conn| {
+        conn.query_row(
+            "SELECT auto_lock_minutes FROM password_settings WHERE id = 1",
+            [],
+            |row| row.get::<_, u32>(0),
+        )
+    }).unwrap_or(vault_session::DEFAULT_AUTO_LOCK_MINUTES)
+}
+
+#[tauri::command]
+pub async fn get_vault_auto_lock() -> Result<u32, String> {
+    Ok(vault_session::auto_lock_minutes())
+}
+
+#[tauri::command]
+pub async fn set_vault_auto_lock(
+    database: State<'_, Arc<Database>>,
+    minutes: u32
+) -> Result<(), String> {
+    let updated = database.with_connection(|conn| {
+        conn.execute(
+            "UPDATE password_settings SET auto_lock_minutes = ?1, updated_at = DATETIME('now') WHERE id = 1",
+            params![minutes],
+        )
+    })?;
+    if updated == 0 {
+        return Err("尚未设置主密码".to_string());
+    }
+
+    vault_session::set_auto_lock_minutes(minutes);
+    Ok(())
+}
+
+// 用新主密码派生密钥并重新加密全部密文，返回新密钥；包含 Argon2id 派生，需在阻塞线程调用
+fn reencrypt_vault(
+    database: &Database,
+    old_password: &str,
+    new_password: &str,
+    kdf_params: Option<KdfParams>
+) -> Result<Vec<u8>, String> {
+    let settings = load_vault_settings(database)?
+        .ok_or_else(|| "尚未设置主密码".to_string())?;
+    let old_key = verify_master_password(&settings, old_password)?;
+    let legacy_key = settings.legacy_salt.as_ref()
+        .map(|legacy_salt| CryptoService::derive_legacy_key(old_password, legacy_salt));
+    encrypt_plaintext_entries(database, &old_key)?;
+
+    // 不指定参数时沿用当前参数，可借此调整 Argon2id 参数
+    let kdf_params = kdf_params.unwrap_or(settings.kdf_params);
+    let (new_settings, new_key) = new_vault_settings(new_password, kdf_params)?;
+
+    // 用新密钥重新加密全部条目
+    let encrypted_rows: Vec<(i64, String, Option<String>, Option<String>)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, password_encrypted, totp_encrypted, fields_encrypted FROM password_entries")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+        rows.collect()
+    })?;
+
+    // TOTP 密钥和类型字段总是用新格式加密，直接用旧主密钥解密
+    let reencrypt_optional = |ciphertext: Option<String>| -> Result<Option<String>, String> {
+        match ciphertext {
+            Some(ciphertext) => {
+                let plaintext = CryptoService::decrypt_with_key(&old_key, &ciphertext)?;
+                Ok(Some(CryptoService::encrypt_with_key(&new_key, &plaintext)?))
+            }
+            None => Ok(None),
+        }
+    };
+
+    let mut reencrypted_rows = Vec::with_capacity(encrypted_rows.len());
+    for (id, ciphertext, totp_ciphertext, fields_ciphertext) in encrypted_rows {
+        let plaintext = decrypt_with_vault_keys(&old_key, legacy_key.as_deref(), &ciphertext)?;
+        reencrypted_rows.push((
+            id,
+            CryptoService::encrypt_with_key(&new_key, &plaintext)?,
+            reencrypt_optional(totp_ciphertext)?,
+            reencrypt_optional(fields_ciphertext)?,
+        ));
+    }
+
+    let history_rows: Vec<(i64, String)> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, password_encrypted FROM password_history")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    })?;
+
+    let mut reencrypted_history = Vec::with_capacity(history_rows.len());
+    for (id, ciphertext) in history_rows {
+        let plaintext = decrypt_with_vault_keys(&old_key, legacy_key.as_deref(), &ciphertext)?;
+        reencrypted_history.push((id, CryptoService::encrypt_with_key(&new_key, &plaintext)?));
+    }
+
+    // 由密码库保护的 AI 提供商密钥
+    let reencrypted_ai_keys = ai_keys::reencrypt_vault_keys(database, &old_key, &new_key)?;
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        for (id, ciphertext, totp_ciphertext, fields_ciphertext) in &reencrypted_rows {
+            tx.execute(
+                "UPDATE password_entries SET password_encrypted = ?1, totp_encrypted = ?2, fields_encrypted = ?3, is_encrypted = 1 WHERE id = ?4",
+                params![ciphertext, totp_ciphertext, fields_ciphertext, id],
+            )?;
+        }
+        for (id, ciphertext) in &reencrypted_history {
+            tx.execute(
+                "UPDATE password_history SET password_encrypted = ?1 WHERE id = ?2",
+                params![ciphertext, id],
+            )?;
+        }
+        for (id, ciphertext) in &reencrypted_ai_keys {
+            tx.execute(
+                "UPDATE ai_providers SET api_key = ?1 WHERE id = ?2",
+                params![ciphertext, id],
+            )?;
+        }
+        save_vault_settings(&tx, &new_settings)?;
+        tx.commit()
+    })?;
+
+    Ok(new_key)
+}
+
+#[tauri::command]
+pub async fn change_master_password(
+    database: State<'_, Arc<Database>>,
+    old_password: String,
+    new_password: String,
+    kdf_params: Option<KdfParams>
+) -> Result<(), String> {
+    let database = database.inner().clone();
+    let new_key = run_key_derivation(move || {
+        reencrypt_vault(&database, &old_password, &new_password, kdf_params)
+    }).await?;
+
+    CryptoService::clear_cached_key(VAULT_LEGACY_SESSION_ID)?;
+    CryptoService::cache_key(VAULT_SESSION_ID, new_key)?;
+    vault_session::record_activity();
+    Ok(())
 }
 
 // 密码分类相关命令
@@ -150,34 +738,10 @@ pub async fn delete_password_category(database: State<'_, Arc<Database>>, id: i6
 pub async fn get_password_entries(database: State<'_, Arc<Database>>) -> Result<Vec<PasswordEntryResponse>, String> {
     database.with_connection(|conn| {
         let mut stmt = conn.prepare(
-            "SELECT id, title, username, url, notes, ip, db_type, db_ip, db_username, app_name, 
-             category_id, tags, is_favorite, last_used_at, created_at, updated_at 
-             FROM password_entries ORDER BY created_at DESC"
+            &format!("SELECT {} FROM password_entries ORDER BY created_at DESC", ENTRY_COLUMNS)
         )?;
             
-        let entry_iter = stmt.query_map([], |row| {
-            let tags_json: Option<String> = row.get(11)?;
-            let tags = tags_json.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok());
-            
-            Ok(PasswordEntryResponse {
-                id: Some(row.get(0)?),
-                title: row.get(1)?,
-                username: row.get(2)?,
-                url: row.get(3)?,
-                notes: row.get(4)?,
-                ip: row.get(5)?,
-                db_type: row.get(6)?,
-                db_ip: row.get(7)?,
-                db_username: row.get(8)?,
-                app_name: row.get(9)?,
-                category_id: row.get(10)?,
-                tags,
-                is_favorite: row.get::<_, i32>(12)? == 1,
-                last_used_at: row.get(13)?,
-                created_at: row.get(14)?,
-                updated_at: row.get(15)?,
-            })
-        })?;
+        let entry_iter = stmt.query_map([], entry_from_row)?;
         
         let mut entries = Vec::new();
         for entry in entry_iter {
@@ -195,34 +759,10 @@ pub async fn get_password_entries_by_category(
 ) -> Result<Vec<PasswordEntryResponse>, String> {
     database.with_connection(|conn| {
         let mut stmt = conn.prepare(
-            "SELECT id, title, username, url, notes, ip, db_type, db_ip, db_username, app_name, 
-             category_id, tags, is_favorite, last_used_at, created_at, updated_at 
-             FROM password_entries WHERE category_id = ?1 ORDER BY created_at DESC"
+            &format!("SELECT {} FROM password_entries WHERE category_id = ?1 ORDER BY created_at DESC", ENTRY_COLUMNS)
         )?;
             
-        let entry_iter = stmt.query_map([category_id], |row| {
-            let tags_json: Option<String> = row.get(11)?;
-            let tags = tags_json.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok());
-            
-            Ok(PasswordEntryResponse {
-                id: Some(row.get(0)?),
-                title: row.get(1)?,
-                username: row.get(2)?,
-                url: row.get(3)?,
-                notes: row.get(4)?,
-                ip: row.get(5)?,
-                db_type: row.get(6)?,
-                db_ip: row.get(7)?,
-                db_username: row.get(8)?,
-                app_name: row.get(9)?,
-                category_id: row.get(10)?,
-                tags,
-                is_favorite: row.get::<_, i32>(12)? == 1,
-                last_used_at: row.get(13)?,
-                created_at: row.get(14)?,
-                updated_at: row.get(15)?,
-            })
-        })?;
+        let entry_iter = stmt.query_map([category_id], entry_from_row)?;
         
         let mut entries = Vec::new();
         for entry in entry_iter {
@@ -233,21 +773,41 @@ pub async fn get_password_entries_by_category(
     })
 }
 
+#[tauri::command]
+pub async fn get_password_entries_by_type(
+    database: State<'_, Arc<Database>>,
+    item_type: ItemType
+) -> Result<Vec<PasswordEntryResponse>, String> {
+    database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            &format!("SELECT {} FROM password_entries WHERE item_type = ?1 ORDER BY created_at DESC", ENTRY_COLUMNS)
+        )?;
+
+        let entry_iter = stmt.query_map([item_type.as_str()], entry_from_row)?;
+        entry_iter.collect()
+    })
+}
+
 #[tauri::command]
 pub async fn create_password_entry(
     database: State<'_, Arc<Database>>,
     entry: PasswordEntryDto
 ) -> Result<i64, String> {
-    // 直接存储明文密码（不加密）
-    let stored_password = entry.password;
+    // 使用已解锁的主密钥加密密码和 TOTP 密钥
+    let key = vault_key()?;
+    let stored_password = CryptoService::encrypt_with_key(&key, &entry.password)?;
+    let stored_totp = encrypt_totp_secret(&key, entry.totp.as_deref())?;
+    let item_type = entry.item_type.unwrap_or_default();
+    let stored_fields = vault_items::encrypt_fields(&key, item_type, entry.fields.as_ref())?;
     
     let tags_json = entry.tags.map(|tags| serde_json::to_string(&tags).unwrap_or_default());
     
     database.with_connection(|conn| {
         conn.execute(
             "INSERT INTO password_entries 
-             (title, username, password_encrypted, url, notes, ip, db_type, db_ip, db_username, app_name, category_id, tags, is_favorite) 
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+             (title, username, password_encrypted, url, notes, ip, db_type, db_ip, db_username, app_name, category_id, tags, is_favorite, totp_encrypted,
+              item_type, fields_encrypted, is_encrypted) 
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, 1)",
             params![
                 entry.title,
                 entry.username,
@@ -261,7 +821,10 @@ pub async fn create_password_entry(
                 entry.app_name,
                 entry.category_id,
                 tags_json,
-                if entry.is_favorite { 1 } else { 0 }
+                if entry.is_favorite { 1 } else { 0 },
+                stored_totp,
+                item_type.as_str(),
+                stored_fields
             ],
         )?;
         
@@ -275,18 +838,46 @@ pub async fn update_password_entry(
     id: i64,
     entry: PasswordEntryDto
 ) -> Result<(), String> {
-    // 直接存储明文密码（不加密）
-    let stored_password = entry.password;
+    // 使用已解锁的主密钥加密密码和 TOTP 密钥
+    let key = vault_key()?;
+    update_entry(&database, &key, id, entry)
+}
+
+fn update_entry(database: &Database, key: &[u8], id: i64, entry: PasswordEntryDto) -> Result<(), String> {
+    let stored_password = CryptoService::encrypt_with_key(key, &entry.password)?;
+    // 没有传入 TOTP 时保留已保存的密钥
+    let update_totp = entry.totp.is_some();
+    let stored_totp = encrypt_totp_secret(key, entry.totp.as_deref())?;
+    // 没有传入类型时沿用已保存的类型；没有传入字段时保留已保存的字段
+    // 类型改变时旧类型的字段不再适用，没有传入字段则按新类型清空（新类型有必填字段时报错）
+    let stored_type: String = database.with_connection(|conn| {
+        conn.query_row("SELECT item_type FROM password_entries WHERE id = ?1", params![id], |row| row.get(0))
+    })?;
+    let stored_type = ItemType::parse(&stored_type)?;
+    let item_type = entry.item_type.unwrap_or(stored_type);
+    let update_fields = entry.fields.is_some() || item_type != stored_type;
+    let stored_fields = if update_fields {
+        vault_items::encrypt_fields(key, item_type, entry.fields.as_ref())?
+    } else {
+        None
+    };
+    // 密码有变化时保留旧密码
+    let previous_password = password_history::previous_password_ciphertext(database, id, &entry.password, key)?;
     
     let tags_json = entry.tags.map(|tags| serde_json::to_string(&tags).unwrap_or_default());
     
     database.with_connection(|conn| {
-        conn.execute(
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
             "UPDATE password_entries 
              SET title = ?1, username = ?2, password_encrypted = ?3, url = ?4, 
                  notes = ?5, ip = ?6, db_type = ?7, db_ip = ?8, db_username = ?9, app_name = ?10,
-                 category_id = ?11, tags = ?12, is_favorite = ?13
-             WHERE id = ?14",
+                 category_id = ?11, tags = ?12, is_favorite = ?13,
+                 totp_encrypted = CASE WHEN ?18 THEN ?14 ELSE totp_encrypted END,
+                 item_type = ?15,
+                 fields_encrypted = CASE WHEN ?19 THEN ?16 ELSE fields_encrypted END,
+                 is_encrypted = 1
+             WHERE id = ?17",
             params![
                 entry.title,
                 entry.username,
@@ -301,11 +892,19 @@ pub async fn update_password_entry(
                 entry.category_id,
                 tags_json,
                 if entry.is_favorite { 1 } else { 0 },
-                id
+                stored_totp,
+                item_type.as_str(),
+                stored_fields,
+                id,
+                update_totp,
+                update_fields
             ],
         )?;
+        if let Some(previous_password) = &previous_password {
+            password_history::record_history(&tx, id, previous_password)?;
+        }
         
-        Ok(())
+        tx.commit()
     })
 }
 
@@ -326,7 +925,10 @@ pub async fn get_decrypted_password(
     database: State<'_, Arc<Database>>,
     entry_id: i64
 ) -> Result<String, String> {
-    let plaintext_password = database.with_connection(|conn| {
+    // 未解锁时不读取密文，也不更新使用时间
+    vault_key()?;
+    
+    let encrypted_password = database.with_connection(|conn| {
         let password: String = conn.query_row(
             "SELECT password_encrypted FROM password_entries WHERE id = ?1",
             params![entry_id],
@@ -338,11 +940,46 @@ pub async fn get_decrypted_password(
             "UPDATE password_entries SET last_used_at = DATETIME('now') WHERE id = ?1",
             params![entry_id],
         );
+        password_history::record_audit(conn, entry_id, password_history::AUDIT_REVEAL)?;
         
         Ok(password)
     })?;
     
-    Ok(plaintext_password)
+    decrypt_entry_password(&encrypted_password)
+}
+
+#[tauri::command]
+pub async fn get_totp_code(
+    database: State<'_, Arc<Database>>,
+    entry_id: i64
+) -> Result<TotpCodeResponse, String> {
+    vault_key()?;
+    
+    let encrypted_totp: Option<String> = database.with_connection(|conn| {
+        let totp: Option<String> = conn.query_row(
+            "SELECT totp_encrypted FROM password_entries WHERE id = ?1",
+            params![entry_id],
+            |row| row.get(0),
+        )?;
+        if totp.is_some() {
+            password_history::record_audit(conn, entry_id, password_history::AUDIT_TOTP)?;
+        }
+        Ok(totp)
+    })?;
+    
+    let encrypted_totp = encrypted_totp.ok_or_else(|| "该条目未设置两步验证密钥".to_string())?;
+    let config = TotpConfig::parse(&decrypt_entry_password(&encrypted_totp)?)?;
+    
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs();
+    
+    Ok(TotpCodeResponse {
+        code: config.generate(now),
+        seconds_remaining: config.seconds_remaining(now),
+        period: config.period,
+    })
 }
 
 #[tauri::command]
@@ -354,36 +991,12 @@ pub async fn search_password_entries(
         let search_pattern = format!("%{}%", query);
         
         let mut stmt = conn.prepare(
-            "SELECT id, title, username, url, notes, ip, db_type, db_ip, db_username, app_name, 
-             category_id, tags, is_favorite, last_used_at, created_at, updated_at 
-             FROM password_entries 
+            &format!("SELECT {} FROM password_entries
              WHERE title LIKE ?1 OR username LIKE ?1 OR url LIKE ?1 OR notes LIKE ?1 OR ip LIKE ?1 OR db_type LIKE ?1 OR db_ip LIKE ?1 OR db_username LIKE ?1 OR app_name LIKE ?1
-             ORDER BY title"
+             ORDER BY title", ENTRY_COLUMNS)
         )?;
             
-        let entry_iter = stmt.query_map([search_pattern], |row| {
-            let tags_json: Option<String> = row.get(11)?;
-            let tags = tags_json.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok());
-            
-            Ok(PasswordEntryResponse {
-                id: Some(row.get(0)?),
-                title: row.get(1)?,
-                username: row.get(2)?,
-                url: row.get(3)?,
-                notes: row.get(4)?,
-                ip: row.get(5)?,
-                db_type: row.get(6)?,
-                db_ip: row.get(7)?,
-                db_username: row.get(8)?,
-                app_name: row.get(9)?,
-                category_id: row.get(10)?,
-                tags,
-                is_favorite: row.get::<_, i32>(12)? == 1,
-                last_used_at: row.get(13)?,
-                created_at: row.get(14)?,
-                updated_at: row.get(15)?,
-            })
-        })?;
+        let entry_iter = stmt.query_map([search_pattern], entry_from_row)?;
         
         let mut entries = Vec::new();
         for entry in entry_iter {
@@ -404,14 +1017,8 @@ pub async fn test_password_connection() -> Result<String, String> {
 
 
 #[tauri::command]
-pub async fn generate_password(options: PasswordGeneratorOptions) -> Result<String, String> {
-    Ok(CryptoService::generate_password(
-        options.length,
-        options.include_uppercase,
-        options.include_lowercase,
-        options.include_numbers,
-        options.include_symbols,
-    ))
+pub async fn generate_password(options: PasswordGeneratorOptions) -> Result<GeneratedPassword, String> {
+    password_generator::generate(&options)
 }
 
 #[tauri::command]
@@ -419,40 +1026,22 @@ pub async fn check_password_strength(password: String) -> Result<u8, String> {
     Ok(CryptoService::check_password_strength(&password))
 }
 
+// 详细的强度估算：猜测次数、破解时间和改进建议
+#[tauri::command]
+pub async fn estimate_password_strength(password: String) -> Result<StrengthEstimate, String> {
+    Ok(password_strength::estimate(&password))
+}
+
 #[tauri::command]
 pub async fn get_favorite_password_entries(
     database: State<'_, Arc<Database>>
 ) -> Result<Vec<PasswordEntryResponse>, String> {
     database.with_connection(|conn| {
         let mut stmt = conn.prepare(
-            "SELECT id, title, username, url, notes, ip, db_type, db_ip, db_username, app_name, 
-             category_id, tags, is_favorite, last_used_at, created_at, updated_at 
-             FROM password_entries WHERE is_favorite = 1 ORDER BY last_used_at DESC"
+            &format!("SELECT {} FROM password_entries WHERE is_favorite = 1 ORDER BY last_used_at DESC", ENTRY_COLUMNS)
         )?;
             
-        let entry_iter = stmt.query_map([], |row| {
-            let tags_json: Option<String> = row.get(11)?;
-            let tags = tags_json.and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok());
-            
-            Ok(PasswordEntryResponse {
-                id: Some(row.get(0)?),
-                title: row.get(1)?,
-                username: row.get(2)?,
-                url: row.get(3)?,
-                notes: row.get(4)?,
-                ip: row.get(5)?,
-                db_type: row.get(6)?,
-                db_ip: row.get(7)?,
-                db_username: row.get(8)?,
-                app_name: row.get(9)?,
-                category_id: row.get(10)?,
-                tags,
-                is_favorite: row.get::<_, i32>(12)? == 1,
-                last_used_at: row.get(13)?,
-                created_at: row.get(14)?,
-                updated_at: row.get(15)?,
-            })
-        })?;
+        let entry_iter = stmt.query_map([], entry_from_row)?;
         
         let mut entries = Vec::new();
         for entry in entry_iter {
@@ -461,4 +1050,155 @@ pub async fn get_favorite_password_entries(
         
         Ok(entries)
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Key, Nonce};
+
+    // 直接插入一条未加密的条目，更新时不需要解锁密码库即可读取旧密码
+    fn insert_entry(database: &Database, password: &str, totp: &str) -> i64 {
+        database.with_connection(|conn| {
+            conn.execute(
+                "INSERT INTO password_entries (title, password_encrypted, totp_encrypted, is_encrypted) VALUES ('Example', ?1, ?2, 0)",
+                params![password, totp],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }).unwrap()
+    }
+
+    fn entry_dto(password: &str, totp: Option<&str>) -> PasswordEntryDto {
+        PasswordEntryDto {
+            id: None,
+            title: "Example (edited)".to_string(),
+            username: Some("alice".to_string()),
+            password: password.to_string(),
+            url: None,
+            notes: None,
+            ip: None,
+            db_type: None,
+            db_ip: None,
+            db_username: None,
+            app_name: None,
+            category_id: None,
+            tags: None,
+            is_favorite: false,
+            totp: totp.map(str::to_string),
+            item_type: None,
+            fields: None,
+        }
+    }
+
+    fn stored_totp(database: &Database, id: i64) -> Option<String> {
+        database.with_connection(|conn| {
+            conn.query_row("SELECT totp_encrypted FROM password_entries WHERE id = ?1", params![id], |row| row.get(0))
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_ciphertext_with_header_like_nonce() {
+        let legacy_key = CryptoService::derive_legacy_key("password", &CryptoService::generate_salt());
+        let key = CryptoService::derive_legacy_key("other", &CryptoService::generate_salt());
+
+        // 旧格式密文的 nonce 恰好以 "ANV" + 版本号开头
+        let mut nonce_bytes = CryptoService::generate_nonce();
+        nonce_bytes[..4].copy_from_slice(b"ANV\x02");
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&legacy_key));
+        let mut combined = nonce_bytes.clone();
+        combined.extend(cipher.encrypt(Nonce::from_slice(&nonce_bytes), b"legacy secret".as_ref()).unwrap());
+        let ciphertext = general_purpose::STANDARD.encode(combined);
+
+        assert_eq!(CryptoService::ciphertext_format(&ciphertext).unwrap(), CiphertextFormat::V2);
+        assert_eq!(decrypt_with_vault_keys(&key, Some(&legacy_key), &ciphertext).unwrap(), "legacy secret");
+        assert!(decrypt_with_vault_keys(&key, None, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_update_keeps_totp_when_not_provided() {
+        let database = Database::open_in_memory().unwrap();
+        let key = vec![7u8; 32];
+
+        // 编辑时没有传 totp，已保存的密钥保持不变
+        let id = insert_entry(&database, "secret", "totp-ciphertext");
+        update_entry(&database, &key, id, entry_dto("secret", None)).unwrap();
+        assert_eq!(stored_totp(&database, id).as_deref(), Some("totp-ciphertext"));
+
+        // 传空字符串表示停用两步验证
+        let id = insert_entry(&database, "secret", "totp-ciphertext");
+        update_entry(&database, &key, id, entry_dto("secret", Some(""))).unwrap();
+        assert_eq!(stored_totp(&database, id), None);
+
+        // 传入新密钥时替换
+        let id = insert_entry(&database, "secret", "totp-ciphertext");
+        update_entry(&database, &key, id, entry_dto("secret", Some("JBSWY3DPEHPK3PXP"))).unwrap();
+        let totp = stored_totp(&database, id).unwrap();
+        assert_eq!(CryptoService::decrypt_with_key(&key, &totp).unwrap(), "JBSWY3DPEHPK3PXP");
+    }
+
+    #[test]
+    fn test_update_keeps_item_type_and_fields_when_not_provided() {
+        let database = Database::open_in_memory().unwrap();
+        let key = vec![7u8; 32];
+        let stored = |id: i64| -> (String, Option<String>) {
+            database.with_connection(|conn| {
+                conn.query_row(
+                    "SELECT item_type, fields_encrypted FROM password_entries WHERE id = ?1",
+                    params![id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+            }).unwrap()
+        };
+
+        let typed_entry = || {
+            let id = insert_entry(&database, "secret", "totp-ciphertext");
+            database.with_connection(|conn| {
+                conn.execute(
+                    "UPDATE password_entries SET item_type = 'api_token', fields_encrypted = 'fields-ciphertext' WHERE id = ?1",
+                    params![id],
+                )
+            }).unwrap();
+            id
+        };
+
+        // 只编辑通用字段时类型和类型字段都不变
+        let id = typed_entry();
+        update_entry(&database, &key, id, entry_dto("secret", None)).unwrap();
+        assert_eq!(stored(id), ("api_token".to_string(), Some("fields-ciphertext".to_string())));
+
+        // 只传字段时按已保存的类型校验
+        let id = typed_entry();
+        let mut entry = entry_dto("secret", None);
+        entry.fields = Some(BTreeMap::from([("unknown".to_string(), "value".to_string())]));
+        assert!(update_entry(&database, &key, id, entry).is_err());
+
+        // 显式改为登录类型并清空字段
+        let mut entry = entry_dto("secret", None);
+        entry.item_type = Some(ItemType::Login);
+        entry.fields = Some(BTreeMap::new());
+        update_entry(&database, &key, id, entry).unwrap();
+        assert_eq!(stored(id), ("login".to_string(), None));
+
+        // 只改类型不传字段时清空旧类型的字段，新类型有必填字段则拒绝
+        let id = typed_entry();
+        let mut entry = entry_dto("secret", None);
+        entry.item_type = Some(ItemType::Login);
+        update_entry(&database, &key, id, entry).unwrap();
+        assert_eq!(stored(id), ("login".to_string(), None));
+
+        let id = typed_entry();
+        let mut entry = entry_dto("secret", None);
+        entry.item_type = Some(ItemType::SecureNote);
+        assert!(update_entry(&database, &key, id, entry).is_err());
+        assert_eq!(stored(id), ("api_token".to_string(), Some("fields-ciphertext".to_string())));
+
+        // 已保存的类型无法识别时报错，而不是按登录类型校验
+        let id = typed_entry();
+        database.with_connection(|conn| {
+            conn.execute("UPDATE password_entries SET item_type = 'unknown' WHERE id = ?1", params![id])
+        }).unwrap();
+        let mut entry = entry_dto("secret", None);
+        entry.fields = Some(BTreeMap::new());
+        assert!(update_entry(&database, &key, id, entry).is_err());
+    }
+}
@@ -0,0 +1,366 @@
+use crate::password_commands::PasswordGeneratorOptions;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const NUMBERS: &str = "0123456789";
+const SYMBOLS: &str = "!@#$%^&*()_+-=[]{}|;:,.<>?";
+
+// 容易混淆的字符：I/l/1/|、O/0/o 以及不易分辨的标点
+const AMBIGUOUS: &str = "Il1|O0o;:,.";
+
+// 易读密码的辅音和元音
+const CONSONANTS: &str = "bcdfghjklmnprstvwz";
+const VOWELS: &str = "aeiou";
+
+// 要求每类字符都出现时，随机生成后检查，超过次数仍不满足则报错
+const MAX_REQUIRE_ATTEMPTS: usize = 10_000;
+
+const MAX_PASSWORD_LENGTH: usize = 256;
+const MAX_PASSPHRASE_WORDS: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref DICEWARE_WORDS: Vec<&'static str> = include_str!("wordlists/diceware.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+}
+
+// 生成模式：随机字符、口令短语、易读密码
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratorMode {
+    #[default]
+    Random,
+    Passphrase,
+    Pronounceable,
+}
+
+// 口令短语中单词的大小写
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WordCapitalization {
+    #[default]
+    Lowercase,
+    Capitalize,
+    Uppercase,
+    Random,  // 每个单词随机首字母大写，每词多 1 bit 熵
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeneratedPassword {
+    pub password: String,
+    pub entropy_bits: f64,
+}
+
+fn without_ambiguous(chars: &str, exclude_ambiguous: bool) -> Vec<char> {
+    let mut result: Vec<char> = Vec::new();
+    for c in chars.chars() {
+        if (!exclude_ambiguous || !AMBIGUOUS.contains(c)) && !result.contains(&c) {
+            result.push(c);
+        }
+    }
+    result
+}
+
+// 按选项得到各类字符集，自定义字符集视为单独一类
+fn character_classes(options: &PasswordGeneratorOptions) -> Vec<Vec<char>> {
+    if let Some(custom) = options.custom_charset.as_deref().filter(|custom| !custom.is_empty()) {
+        return vec![without_ambiguous(custom, options.exclude_ambiguous)];
+    }
+
+    let mut classes = Vec::new();
+    for (enabled, chars) in [
+        (options.include_lowercase, LOWERCASE),
+        (options.include_uppercase, UPPERCASE),
+        (options.include_numbers, NUMBERS),
+        (options.include_symbols, SYMBOLS),
+    ] {
+        if enabled {
+            classes.push(without_ambiguous(chars, options.exclude_ambiguous));
+        }
+    }
+
+    // 与原有行为一致：未选择任何类型时使用字母和数字
+    if classes.is_empty() {
+        classes = [LOWERCASE, UPPERCASE, NUMBERS]
+            .iter()
+            .map(|chars| without_ambiguous(chars, options.exclude_ambiguous))
+            .collect();
+    }
+    classes.retain(|class| !class.is_empty());
+    classes
+}
+
+// 长度为 length、每类至少出现一次的字符串个数取以 2 为底的对数（容斥原理）
+fn require_each_class_entropy(class_sizes: &[usize], length: usize) -> f64 {
+    let total: usize = class_sizes.iter().sum();
+    let mut fraction = 0.0;
+    for subset in 0u32..(1 << class_sizes.len()) {
+        let excluded: usize = class_sizes.iter()
+            .enumerate()
+            .filter(|(index, _)| subset & (1 << index) != 0)
+            .map(|(_, size)| size)
+            .sum();
+        let sign = if subset.count_ones() % 2 == 0 { 1.0 } else { -1.0 };
+        fraction += sign * ((total - excluded) as f64 / total as f64).powi(length as i32);
+    }
+    length as f64 * (total as f64).log2() + fraction.log2()
+}
+
+fn generate_random(options: &PasswordGeneratorOptions) -> Result<GeneratedPassword, String> {
+    if options.length == 0 || options.length > MAX_PASSWORD_LENGTH {
+        return Err(format!("密码长度必须在 1 到 {} 之间", MAX_PASSWORD_LENGTH));
+    }
+
+    let classes = character_classes(options);
+    let charset: Vec<char> = classes.concat();
+    if charset.is_empty() {
+        return Err("可用字符集为空".to_string());
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut sample = || -> String {
+        (0..options.length).map(|_| charset[rng.gen_range(0..charset.len())]).collect()
+    };
+
+    if !options.require_each_class || classes.len() == 1 {
+        return Ok(GeneratedPassword {
+            password: sample(),
+            entropy_bits: options.length as f64 * (charset.len() as f64).log2(),
+        });
+    }
+
+    if options.length < classes.len() {
+        return Err(format!("密码长度不能少于所选字符类型数（{}）", classes.len()));
+    }
+
+    // 不满足时整体重新生成，保证结果在所有合格密码中均匀分布
+    for _ in 0..MAX_REQUIRE_ATTEMPTS {
+        let password = sample();
+        if classes.iter().all(|class| password.chars().any(|c| class.contains(&c))) {
+            let class_sizes: Vec<usize> = classes.iter().map(Vec::len).collect();
+            return Ok(GeneratedPassword {
+                password,
+                entropy_bits: require_each_class_entropy(&class_sizes, options.length),
+            });
+        }
+    }
+    Err("无法生成满足条件的密码，请增加长度".to_string())
+}
+
+fn generate_passphrase(options: &PasswordGeneratorOptions) -> Result<GeneratedPassword, String> {
+    if options.word_count == 0 || options.word_count > MAX_PASSPHRASE_WORDS {
+        return Err(format!("单词数必须在 1 到 {} 之间", MAX_PASSPHRASE_WORDS));
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut words = Vec::with_capacity(options.word_count);
+    for _ in 0..options.word_count {
+        let word = *DICEWARE_WORDS.choose(&mut rng).ok_or_else(|| "词表为空".to_string())?;
+        let capitalize = match options.capitalization {
+            WordCapitalization::Capitalize => true,
+            WordCapitalization::Random => rng.gen_bool(0.5),
+            _ => false,
+        };
+        let word = match options.capitalization {
+            WordCapitalization::Uppercase => word.to_uppercase(),
+            _ if capitalize => {
+                let mut chars = word.chars();
+                chars.next()
+                    .map(|first| first.to_uppercase().chain(chars).collect())
+                    .unwrap_or_default()
+            }
+            _ => word.to_string(),
+        };
+        words.push(word);
+    }
+
+    let mut entropy_bits = options.word_count as f64 * (DICEWARE_WORDS.len() as f64).log2();
+    if options.capitalization == WordCapitalization::Random {
+        entropy_bits += options.word_count as f64;
+    }
+
+    Ok(GeneratedPassword {
+        password: words.join(&options.separator),
+        entropy_bits,
+    })
+}
+
+// 辅音、元音交替组成的易读密码，可选首字母大写和末尾数字、符号
+fn generate_pronounceable(options: &PasswordGeneratorOptions) -> Result<GeneratedPassword, String> {
+    if options.length == 0 || options.length > MAX_PASSWORD_LENGTH {
+        return Err(format!("密码长度必须在 1 到 {} 之间", MAX_PASSWORD_LENGTH));
+    }
+
+    let consonants = without_ambiguous(CONSONANTS, options.exclude_ambiguous);
+    let vowels = without_ambiguous(VOWELS, options.exclude_ambiguous);
+    let numbers = without_ambiguous(NUMBERS, options.exclude_ambiguous);
+    let symbols = without_ambiguous(SYMBOLS, options.exclude_ambiguous);
+
+    let mut suffix_len = 0;
+    if options.include_numbers {
+        suffix_len += 2;
+    }
+    if options.include_symbols {
+        suffix_len += 1;
+    }
+    // 至少保留一个字母，数字和符号也计入长度
+    if options.length <= suffix_len {
+        return Err(format!("易读密码长度至少为 {}", suffix_len + 1));
+    }
+    let letters = options.length - suffix_len;
+
+    let mut rng = rand::thread_rng();
+    let mut password = String::with_capacity(options.length);
+    let mut entropy_bits = 0.0;
+    for index in 0..letters {
+        let pool = if index % 2 == 0 { &consonants } else { &vowels };
+        password.push(pool[rng.gen_range(0..pool.len())]);
+        entropy_bits += (pool.len() as f64).log2();
+    }
+
+    if options.include_uppercase {
+        // 随机把一个字母改为大写，排除易混淆字符时跳过 i（大写为 I）
+        let positions: Vec<usize> = password.chars()
+            .enumerate()
+            .filter(|(_, c)| !options.exclude_ambiguous || !AMBIGUOUS.contains(c.to_ascii_uppercase()))
+            .map(|(index, _)| index)
+            .collect();
+        if let Some(&position) = positions.choose(&mut rng) {
+            password = password.chars()
+                .enumerate()
+                .map(|(index, c)| if index == position { c.to_ascii_uppercase() } else { c })
+                .collect();
+            entropy_bits += (positions.len() as f64).log2();
+        }
+    }
+    if options.include_numbers {
+        for _ in 0..2 {
+            password.push(numbers[rng.gen_range(0..numbers.len())]);
+        }
+        entropy_bits += 2.0 * (numbers.len() as f64).log2();
+    }
+    if options.include_symbols {
+        password.push(symbols[rng.gen_range(0..symbols.len())]);
+        entropy_bits += (symbols.len() as f64).log2();
+    }
+
+    Ok(GeneratedPassword { password, entropy_bits })
+}
+
+pub fn generate(options: &PasswordGeneratorOptions) -> Result<GeneratedPassword, String> {
+    match options.mode {
+        GeneratorMode::Random => generate_random(options),
+        GeneratorMode::Passphrase => generate_passphrase(options),
+        GeneratorMode::Pronounceable => generate_pronounceable(options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(length: usize) -> PasswordGeneratorOptions {
+        serde_json::from_value(serde_json::json!({
+            "length": length,
+            "include_uppercase": true,
+            "include_lowercase": true,
+            "include_numbers": true,
+            "include_symbols": false,
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_random_entropy() {
+        let generated = generate(&options(16)).unwrap();
+        assert_eq!(generated.password.chars().count(), 16);
+        assert!((generated.entropy_bits - 16.0 * 62f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_require_each_class() {
+        let mut options = options(3);
+        options.require_each_class = true;
+        for _ in 0..50 {
+            let password = generate(&options).unwrap().password;
+            assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+            assert!(password.chars().any(|c| c.is_ascii_digit()));
+        }
+
+        // 3 位且三类各一个：3! * 26 * 26 * 10 种
+        let expected = (6.0 * 26.0 * 26.0 * 10.0f64).log2();
+        assert!((generate(&options).unwrap().entropy_bits - expected).abs() < 1e-6);
+
+        options.length = 2;
+        assert!(generate(&options).is_err());
+    }
+
+    #[test]
+    fn test_exclude_ambiguous_and_custom_charset() {
+        let mut options = options(200);
+        options.exclude_ambiguous = true;
+        let password = generate(&options).unwrap().password;
+        assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+
+        options.custom_charset = Some("ab".to_string());
+        let generated = generate(&options).unwrap();
+        assert!(generated.password.chars().all(|c| c == 'a' || c == 'b'));
+        assert!((generated.entropy_bits - 200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_passphrase() {
+        assert_eq!(DICEWARE_WORDS.len(), 1296);
+
+        let mut options = options(0);
+        options.mode = GeneratorMode::Passphrase;
+        options.word_count = 5;
+        options.separator = "-".to_string();
+        options.capitalization = WordCapitalization::Capitalize;
+
+        let generated = generate(&options).unwrap();
+        let words: Vec<&str> = generated.password.split('-').collect();
+        assert_eq!(words.len(), 5);
+        assert!(words.iter().all(|word| word.chars().next().is_some_and(|c| c.is_ascii_uppercase())));
+        assert!((generated.entropy_bits - 5.0 * 1296f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pronounceable() {
+        let mut options = options(12);
+        options.mode = GeneratorMode::Pronounceable;
+        let generated = generate(&options).unwrap();
+        assert_eq!(generated.password.chars().count(), 12);
+        assert!(generated.password.chars().rev().take(2).all(|c| c.is_ascii_digit()));
+        assert!(generated.entropy_bits > 0.0);
+    }
+
+    #[test]
+    fn test_pronounceable_length_and_ambiguous() {
+        let mut options = options(3);
+        options.mode = GeneratorMode::Pronounceable;
+        options.include_symbols = true;
+        options.exclude_ambiguous = true;
+
+        // 数字和符号占 3 位，长度必须能再容纳至少一个字母
+        assert!(generate(&options).is_err());
+        for length in 4..=40 {
+            options.length = length;
+            for _ in 0..20 {
+                let password = generate(&options).unwrap().password;
+                assert_eq!(password.chars().count(), length);
+                assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)), "{}", password);
+            }
+        }
+
+        options.include_numbers = false;
+        options.include_symbols = false;
+        options.length = 1;
+        assert_eq!(generate(&options).unwrap().password.chars().count(), 1);
+    }
+}
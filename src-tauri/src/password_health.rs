@@ -0,0 +1,220 @@
+use crate::crypto::CryptoService;
+use crate::database::Database;
+use crate::password_commands::{decrypt_entry_password, vault_key};
+use crate::password_import::duplicate_key;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tauri::State;
+
+// 强度评分低于该值视为弱密码
+const WEAK_PASSWORD_SCORE: u8 = 60;
+
+// 默认超过 180 天未修改视为过旧
+const DEFAULT_MAX_AGE_DAYS: u32 = 180;
+
+// 参与检查的条目，密码只在内存中以明文存在
+struct HealthInput {
+    entry_id: i64,
+    title: String,
+    username: Option<String>,
+    url: Option<String>,
+    category_id: Option<i64>,
+    category_name: Option<String>,
+    password: String,
+    password_age_days: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EntryHealthIssue {
+    pub entry_id: i64,
+    pub title: String,
+    pub username: Option<String>,
+    pub url: Option<String>,
+    pub reused_with: Vec<i64>,   // 使用相同密码的其他条目
+    pub weak: bool,
+    pub strength: u8,
+    pub old: bool,
+    pub password_age_days: i64,
+    pub duplicate_of: Vec<i64>,  // 网址和用户名相同的其他条目
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CategoryHealth {
+    pub category_id: Option<i64>,
+    pub category_name: Option<String>,
+    pub issues: Vec<EntryHealthIssue>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VaultHealthReport {
+    pub total_entries: usize,
+    pub max_age_days: u32,
+    pub reused_count: usize,
+    pub weak_count: usize,
+    pub old_count: usize,
+    pub duplicate_count: usize,
+    pub categories: Vec<CategoryHealth>,
+}
+
+// 把同一键下的条目两两关联起来：返回 条目ID -> 其他条目ID
+fn group_links<K: std::hash::Hash + Eq>(keys: impl Iterator<Item = (K, i64)>) -> HashMap<i64, Vec<i64>> {
+    let mut groups: HashMap<K, Vec<i64>> = HashMap::new();
+    for (key, entry_id) in keys {
+        groups.entry(key).or_default().push(entry_id);
+    }
+
+    let mut links = HashMap::new();
+    for ids in groups.into_values().filter(|ids| ids.len() > 1) {
+        for &id in &ids {
+            links.insert(id, ids.iter().copied().filter(|other| *other != id).collect());
+        }
+    }
+    links
+}
+
+fn analyze(entries: Vec<HealthInput>, max_age_days: u32) -> VaultHealthReport {
+    let reused = group_links(entries.iter()
+        .filter(|entry| !entry.password.is_empty())
+        .map(|entry| (entry.password.as_str(), entry.entry_id)));
+    let duplicates = group_links(entries.iter()
+        .filter_map(|entry| Some((duplicate_key(entry.url.as_deref(), entry.username.as_deref())?, entry.entry_id))));
+
+    let mut report = VaultHealthReport {
+        total_entries: entries.len(),
+        max_age_days,
+        reused_count: 0,
+        weak_count: 0,
+        old_count: 0,
+        duplicate_count: 0,
+        categories: Vec::new(),
+    };
+
+    // 按分类分组，未分类排在最后
+    let mut categories: BTreeMap<(bool, Option<String>, Option<i64>), Vec<EntryHealthIssue>> = BTreeMap::new();
+    for entry in entries {
+        let strength = CryptoService::check_password_strength(&entry.password);
+        let issue = EntryHealthIssue {
+            entry_id: entry.entry_id,
+            title: entry.title,
+            username: entry.username,
+            url: entry.url,
+            reused_with: reused.get(&entry.entry_id).cloned().unwrap_or_default(),
+            weak: strength < WEAK_PASSWORD_SCORE,
+            strength,
+            old: max_age_days > 0 && entry.password_age_days >= i64::from(max_age_days),
+            password_age_days: entry.password_age_days,
+            duplicate_of: duplicates.get(&entry.entry_id).cloned().unwrap_or_default(),
+        };
+
+        let has_issue = !issue.reused_with.is_empty() || issue.weak || issue.old || !issue.duplicate_of.is_empty();
+        if !has_issue {
+            continue;
+        }
+
+        report.reused_count += usize::from(!issue.reused_with.is_empty());
+        report.weak_count += usize::from(issue.weak);
+        report.old_count += usize::from(issue.old);
+        report.duplicate_count += usize::from(!issue.duplicate_of.is_empty());
+
+        categories
+            .entry((entry.category_id.is_none(), entry.category_name, entry.category_id))
+            .or_default()
+            .push(issue);
+    }
+
+    report.categories = categories.into_iter()
+        .map(|((_, category_name, category_id), issues)| CategoryHealth { category_id, category_name, issues })
+        .collect();
+    report
+}
+
+// 密码库健康检查：重复使用、弱密码、长期未修改、网址+用户名重复
+#[tauri::command]
+pub async fn get_vault_health_report(
+    database: State<'_, Arc<Database>>,
+    max_age_days: Option<u32>
+) -> Result<VaultHealthReport, String> {
+    vault_key()?;
+
+    let rows = database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT e.id, e.title, e.username, e.url, e.category_id, c.name, e.password_encrypted, e.is_encrypted,
+                    CAST(julianday('now') - julianday(COALESCE(e.password_changed_at, e.created_at)) AS INTEGER)
+             FROM password_entries e
+             LEFT JOIN password_categories c ON c.id = e.category_id
+             WHERE e.item_type = 'login'
+             ORDER BY e.title"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                HealthInput {
+                    entry_id: row.get(0)?,
+                    title: row.get(1)?,
+                    username: row.get(2)?,
+                    url: row.get(3)?,
+                    category_id: row.get(4)?,
+                    category_name: row.get(5)?,
+                    password: row.get(6)?,
+                    password_age_days: row.get::<_, Option<i64>>(8)?.unwrap_or(0),
+                },
+                row.get::<_, i32>(7)? == 1,
+            ))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+    })?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    for (mut entry, is_encrypted) in rows {
+        if is_encrypted {
+            entry.password = decrypt_entry_password(&entry.password)?;
+        }
+        entries.push(entry);
+    }
+
+    Ok(analyze(entries, max_age_days.unwrap_or(DEFAULT_MAX_AGE_DAYS)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(entry_id: i64, category_id: Option<i64>, url: &str, username: &str, password: &str, age: i64) -> HealthInput {
+        HealthInput {
+            entry_id,
+            title: format!("entry {}", entry_id),
+            username: Some(username.to_string()),
+            url: Some(url.to_string()),
+            category_id,
+            category_name: category_id.map(|id| format!("category {}", id)),
+            password: password.to_string(),
+            password_age_days: age,
+        }
+    }
+
+    #[test]
+    fn test_health_report() {
+        let report = analyze(vec![
+            input(1, Some(1), "https://a.com", "alice", "Str0ng!Passw0rd#1", 10),
+            input(2, Some(1), "https://b.com", "alice", "Str0ng!Passw0rd#1", 10),
+            input(3, None, "https://c.com", "bob", "abc", 10),
+            input(4, Some(2), "https://d.com", "carol", "An0ther!Str0ng#Pw", 400),
+            input(5, Some(2), "https://d.com/", "Carol", "Th1rd!Str0ng#Pass", 10),
+            input(6, Some(2), "https://e.com", "dave", "F0urth!Str0ng#Pw", 10),
+        ], 180);
+
+        assert_eq!(report.total_entries, 6);
+        assert_eq!(report.reused_count, 2);
+        assert_eq!(report.weak_count, 1);
+        assert_eq!(report.old_count, 1);
+        assert_eq!(report.duplicate_count, 2);
+
+        // 分类 1、分类 2，未分类排在最后；没有问题的条目 6 不出现
+        let groups: Vec<Vec<i64>> = report.categories.iter()
+            .map(|category| category.issues.iter().map(|issue| issue.entry_id).collect())
+            .collect();
+        assert_eq!(groups, vec![vec![1, 2], vec![4, 5], vec![3]]);
+        assert_eq!(report.categories[0].issues[0].reused_with, vec![2]);
+        assert_eq!(report.categories[1].issues[1].duplicate_of, vec![4]);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::crypto::CryptoService;
+use crate::database::Database;
+use crate::password_commands::{decrypt_entry_password, vault_key};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tauri::State;
+
+// 默认保留最近 10 个历史版本和 90 天的查看记录
+pub const DEFAULT_HISTORY_RETENTION: u32 = 10;
+pub const DEFAULT_AUDIT_RETENTION_DAYS: u32 = 90;
+
+// 查看记录的操作类型
+pub const AUDIT_REVEAL: &str = "reveal";
+pub const AUDIT_REVEAL_HISTORY: &str = "reveal_history";
+pub const AUDIT_TOTP: &str = "totp";
+pub const AUDIT_RESTORE: &str = "restore";
+pub const AUDIT_REVEAL_FIELDS: &str = "reveal_fields";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordRetention {
+    pub history_versions: u32,  // 每个条目保留的历史版本数，0 表示不保留
+    pub audit_days: u32,        // 查看记录保留天数，0 表示永久保留
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordHistoryItem {
+    pub id: i64,
+    pub entry_id: i64,
+    pub created_at: String,  // 该版本被替换的时间
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordAuditEntry {
+    pub id: i64,
+    pub entry_id: i64,
+    pub entry_title: Option<String>,
+    pub action: String,
+    pub created_at: String,
+}
+
+// 读取保留设置，未设置主密码时返回默认值
+pub fn load_retention(conn: &Connection) -> PasswordRetention {
+    conn.query_row(
+        "SELECT history_retention, audit_retention_days FROM password_settings WHERE id = 1",
+        [],
+        |row| Ok(PasswordRetention {
+            history_versions: row.get(0)?,
+            audit_days: row.get(1)?,
+        }),
+    ).unwrap_or(PasswordRetention {
+        history_versions: DEFAULT_HISTORY_RETENTION,
+        audit_days: DEFAULT_AUDIT_RETENTION_DAYS,
+    })
+}
+
+// 读取条目当前密码，与新密码不同时用当前主密钥重新加密后返回，供写入历史
+pub fn previous_password_ciphertext(
+    database: &Database,
+    entry_id: i64,
+    new_password: &str,
+    key: &[u8]
+) -> Result<Option<String>, String> {
+    let (ciphertext, is_encrypted): (String, bool) = database.with_connection(|conn| {
+        conn.query_row(
+            "SELECT password_encrypted, is_encrypted FROM password_entries WHERE id = ?1",
+            params![entry_id],
+            |row| Ok((row.get(0)?, row.get::<_, i32>(1)? == 1)),
+        )
+    })?;
+
+    // 旧版密钥会在后台升级后丢弃，因此历史中不直接保存原密文
+    let old_password = if is_encrypted {
+        decrypt_entry_password(&ciphertext)?
+    } else {
+        ciphertext
+    };
+
+    if old_password == new_password {
+        return Ok(None);
+    }
+    CryptoService::encrypt_with_key(key, &old_password).map(Some)
+}
+
+// 密码已修改：记录修改时间、清除泄露检查结果，写入一条历史版本并清理超出保留数量的旧版本
+pub fn record_history(conn: &Connection, entry_id: i64, ciphertext: &str) -> rusqlite::Result<()> {
+    let retention = load_retention(conn);
+    conn.execute(
+        "UPDATE password_entries
+         SET password_changed_at = DATETIME('now'), breach_count = NULL, breach_checked_at = NULL
+         WHERE id = ?1",
+        params![entry_id],
+    )?;
+    if retention.history_versions > 0 {
+        conn.execute(
+            "INSERT INTO password_history (entry_id, password_encrypted) VALUES (?1, ?2)",
+            params![entry_id, ciphertext],
+        )?;
+    }
+
+    conn.execute(
+        "DELETE FROM password_history WHERE entry_id = ?1 AND id NOT IN (
+             SELECT id FROM password_history WHERE entry_id = ?1 ORDER BY id DESC LIMIT ?2
+         )",
+        params![entry_id, retention.history_versions],
+    )?;
+    Ok(())
+}
+
+// 记录一次查看/恢复操作，并清理过期记录
+pub fn record_audit(conn: &Connection, entry_id: i64, action: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO password_audit_log (entry_id, entry_title, action)
+         SELECT id, title, ?2 FROM password_entries WHERE id = ?1",
+        params![entry_id, action],
+    )?;
+    prune_audit_log(conn, load_retention(conn).audit_days)
+}
+
+fn prune_audit_log(conn: &Connection, audit_days: u32) -> rusqlite::Result<()> {
+    if audit_days > 0 {
+        conn.execute(
+            "DELETE FROM password_audit_log WHERE created_at < DATETIME('now', ?1)",
+            params![format!("-{} days", audit_days)],
+        )?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_password_history(
+    database: State<'_, Arc<Database>>,
+    entry_id: i64
+) -> Result<Vec<PasswordHistoryItem>, String> {
+    database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, entry_id, created_at FROM password_history WHERE entry_id = ?1 ORDER BY id DESC"
+        )?;
+        let rows = stmt.query_map(params![entry_id], |row| {
+            Ok(PasswordHistoryItem {
+                id: row.get(0)?,
+                entry_id: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    })
+}
+
+// 查看历史版本的明文密码
+#[tauri::command]
+pub async fn get_history_password(
+    database: State<'_, Arc<Database>>,
+    history_id: i64
+) -> Result<String, String> {
+    vault_key()?;
+
+    let ciphertext: String = database.with_connection(|conn| {
+        let (entry_id, ciphertext): (i64, String) = conn.query_row(
+            "SELECT entry_id, password_encrypted FROM password_history WHERE id = ?1",
+            params![history_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        record_audit(conn, entry_id, AUDIT_REVEAL_HISTORY)?;
+        Ok(ciphertext)
+    })?;
+
+    decrypt_entry_password(&ciphertext)
+}
+
+// 恢复历史版本：当前密码写入历史，历史版本成为当前密码
+#[tauri::command]
+pub async fn restore_password_version(
+    database: State<'_, Arc<Database>>,
+    history_id: i64
+) -> Result<(), String> {
+    let key = vault_key()?;
+
+    let (entry_id, ciphertext): (i64, String) = database.with_connection(|conn| {
+        conn.query_row(
+            "SELECT entry_id, password_encrypted FROM password_history WHERE id = ?1",
+            params![history_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    })?;
+    let restored_password = decrypt_entry_password(&ciphertext)?;
+    let current_ciphertext = previous_password_ciphertext(&database, entry_id, &restored_password, &key)?;
+    let stored_password = CryptoService::encrypt_with_key(&key, &restored_password)?;
+
+    database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE password_entries SET password_encrypted = ?1, is_encrypted = 1, updated_at = DATETIME('now') WHERE id = ?2",
+            params![stored_password, entry_id],
+        )?;
+        if let Some(current_ciphertext) = &current_ciphertext {
+            record_history(&tx, entry_id, current_ciphertext)?;
+        }
+        record_audit(&tx, entry_id, AUDIT_RESTORE)?;
+        tx.commit()
+    })
+}
+
+// 查看记录，不指定条目时返回全部条目的记录
+#[tauri::command]
+pub async fn get_password_audit_log(
+    database: State<'_, Arc<Database>>,
+    entry_id: Option<i64>,
+    limit: Option<u32>
+) -> Result<Vec<PasswordAuditEntry>, String> {
+    database.with_connection(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, entry_id, entry_title, action, created_at FROM password_audit_log
+             WHERE ?1 IS NULL OR entry_id = ?1
+             ORDER BY id DESC LIMIT ?2"
+        )?;
+        let rows = stmt.query_map(params![entry_id, limit.unwrap_or(100)], |row| {
+            Ok(PasswordAuditEntry {
+                id: row.get(0)?,
+                entry_id: row.get(1)?,
+                entry_title: row.get(2)?,
+                action: row.get(3)?,
+                created_at: row.get(4)?,
+            })
+        })?;
+        rows.collect()
+    })
+}
+
+#[tauri::command]
+pub async fn get_password_retention(database: State<'_, Arc<Database>>) -> Result<PasswordRetention, String> {
+    database.with_connection(|conn| Ok(load_retention(conn)))
+}
+
+// 修改保留设置，并立即按新设置清理
+#[tauri::command]
+pub async fn set_password_retention(
+    database: State<'_, Arc<Database>>,
+    retention: PasswordRetention
+) -> Result<(), String> {
+    let updated = database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE password_settings SET history_retention = ?1, audit_retention_days = ?2, updated_at = DATETIME('now') WHERE id = 1",
+            params![retention.history_versions, retention.audit_days],
+        )?;
+        if updated == 0 {
+            return Ok(false);
+        }
+
+        tx.execute(
+            "DELETE FROM password_history WHERE id IN (
+                 SELECT id FROM (
+                     SELECT id, ROW_NUMBER() OVER (PARTITION BY entry_id ORDER BY id DESC) AS version_no
+                     FROM password_history
+                 ) WHERE version_no > ?1
+             )",
+            params![retention.history_versions],
+        )?;
+        prune_audit_log(&tx, retention.audit_days)?;
+        tx.commit()?;
+        Ok(true)
+    })?;
+    if !updated {
+        return Err("尚未设置主密码".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,501 @@
+use crate::crypto::CryptoService;
+use crate::database::Database;
+use crate::password_commands::{encrypt_totp_secret, vault_key};
+use crate::totp::TotpConfig;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tauri::State;
+
+// 从导出文件中解析出的条目
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ImportedEntry {
+    title: String,
+    username: Option<String>,
+    password: String,
+    url: Option<String>,
+    notes: Option<String>,
+    folder: Option<String>,  // Bitwarden 文件夹 / KeePass 分组
+    tags: Vec<String>,
+    totp: Option<String>,
+    is_favorite: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportPreviewEntry {
+    pub title: String,
+    pub username: Option<String>,
+    pub url: Option<String>,
+    pub category_id: Option<i64>,
+    pub tags: Vec<String>,
+    pub has_totp: bool,
+    pub duplicate_of: Option<i64>,  // 与已有条目重复时为已有条目ID
+    pub duplicate_in_file: bool,    // 与导入文件中前面的条目重复
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub format: String,
+    pub dry_run: bool,
+    pub total: usize,
+    pub new_count: usize,
+    pub duplicate_count: usize,
+    pub imported: usize,
+    pub entries: Vec<ImportPreviewEntry>,
+    pub warnings: Vec<String>,
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|value| !value.is_empty()).map(str::to_string)
+}
+
+// 没有标题时依次用网址主机名、网址、用户名代替
+fn fallback_title(title: Option<String>, url: Option<&str>, username: Option<&str>) -> String {
+    title
+        .or_else(|| url.and_then(|url| url::Url::parse(url).ok()?.host_str().map(str::to_string)))
+        .or_else(|| non_empty(url))
+        .or_else(|| non_empty(username))
+        .unwrap_or_else(|| "未命名".to_string())
+}
+
+// 重复检测键：网址（忽略大小写和末尾斜杠）+ 用户名，两者都为空时不参与检测
+pub fn duplicate_key(url: Option<&str>, username: Option<&str>) -> Option<String> {
+    let url = url.unwrap_or("").trim().trim_end_matches('/').to_lowercase();
+    let username = username.unwrap_or("").trim().to_lowercase();
+    if url.is_empty() && username.is_empty() {
+        return None;
+    }
+    Some(format!("{}\n{}", url, username))
+}
+
+// Bitwarden 未加密 JSON 导出，只导入登录类型（type = 1）
+fn parse_bitwarden_json(content: &str) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let root: serde_json::Value = serde_json::from_str(content)
+        .map_err(|e| format!("无法解析 Bitwarden JSON: {}", e))?;
+
+    if root["encrypted"].as_bool() == Some(true) {
+        return Err("不支持加密的 Bitwarden 导出，请选择未加密的 JSON 格式".to_string());
+    }
+
+    let folders: HashMap<&str, &str> = root["folders"].as_array()
+        .map(|folders| folders.iter()
+            .filter_map(|folder| Some((folder["id"].as_str()?, folder["name"].as_str()?)))
+            .collect())
+        .unwrap_or_default();
+
+    let items = root["items"].as_array()
+        .ok_or_else(|| "Bitwarden JSON 缺少 items 字段".to_string())?;
+
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    for item in items {
+        let name = non_empty(item["name"].as_str());
+        if item["type"].as_i64() != Some(1) {
+            warnings.push(format!("跳过非登录类型的条目: {}", name.unwrap_or_default()));
+            continue;
+        }
+
+        let login = &item["login"];
+        let username = non_empty(login["username"].as_str());
+        let url = login["uris"].as_array()
+            .and_then(|uris| uris.iter().find_map(|uri| non_empty(uri["uri"].as_str())));
+
+        entries.push(ImportedEntry {
+            title: fallback_title(name, url.as_deref(), username.as_deref()),
+            username,
+            password: login["password"].as_str().unwrap_or("").to_string(),
+            url,
+            notes: non_empty(item["notes"].as_str()),
+            folder: item["folderId"].as_str()
+                .and_then(|id| folders.get(id))
+                .map(|name| name.to_string()),
+            tags: Vec::new(),
+            totp: non_empty(login["totp"].as_str()),
+            is_favorite: item["favorite"].as_bool().unwrap_or(false),
+        });
+    }
+
+    Ok((entries, warnings))
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children().find(|child| child.has_tag_name(name)).and_then(|child| child.text())
+}
+
+fn collect_keepass_group(
+    group: roxmltree::Node,
+    folder: Option<String>,
+    recycle_bin: Option<&str>,
+    entries: &mut Vec<ImportedEntry>,
+) {
+    if recycle_bin.is_some() && child_text(group, "UUID") == recycle_bin {
+        return;
+    }
+
+    // 只取分组的直接子条目，Entry 下的 History 是旧版本
+    for entry in group.children().filter(|node| node.has_tag_name("Entry")) {
+        let mut fields: HashMap<&str, &str> = HashMap::new();
+        for string in entry.children().filter(|node| node.has_tag_name("String")) {
+            if let Some(key) = child_text(string, "Key") {
+                fields.insert(key, child_text(string, "Value").unwrap_or(""));
+            }
+        }
+
+        let username = non_empty(fields.get("UserName").copied());
+        let url = non_empty(fields.get("URL").copied());
+        let tags = child_text(entry, "Tags")
+            .map(|tags| tags.split([';', ',']).filter_map(|tag| non_empty(Some(tag))).collect())
+            .unwrap_or_default();
+
+        entries.push(ImportedEntry {
+            title: fallback_title(non_empty(fields.get("Title").copied()), url.as_deref(), username.as_deref()),
+            username,
+            password: fields.get("Password").copied().unwrap_or("").to_string(),
+            url,
+            notes: non_empty(fields.get("Notes").copied()),
+            folder: folder.clone(),
+            tags,
+            totp: non_empty(fields.get("otp").copied()),
+            is_favorite: false,
+        });
+    }
+
+    for child in group.children().filter(|node| node.has_tag_name("Group")) {
+        let name = non_empty(child_text(child, "Name"));
+        collect_keepass_group(child, name, recycle_bin, entries);
+    }
+}
+
+// KeePass 2 XML 导出，跳过回收站
+fn parse_keepass_xml(content: &str) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let document = roxmltree::Document::parse(content)
+        .map_err(|e| format!("无法解析 KeePass XML: {}", e))?;
+    let root = document.root_element();
+    if !root.has_tag_name("KeePassFile") {
+        return Err("不是 KeePass 2 XML 导出文件".to_string());
+    }
+
+    let recycle_bin = root.children()
+        .find(|node| node.has_tag_name("Meta"))
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"));
+    let root_group = root.children()
+        .find(|node| node.has_tag_name("Root"))
+        .ok_or_else(|| "KeePass XML 缺少 Root 节点".to_string())?;
+
+    // 顶层分组是数据库本身，其中的条目不归入任何文件夹
+    let mut entries = Vec::new();
+    for group in root_group.children().filter(|node| node.has_tag_name("Group")) {
+        collect_keepass_group(group, None, recycle_bin, &mut entries);
+    }
+
+    Ok((entries, Vec::new()))
+}
+
+// Chrome（name,url,username,password,note）与 Firefox（url,username,password,...）导出的 CSV
+fn parse_browser_csv(content: &str) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(content.trim_start_matches('\u{feff}').as_bytes());
+
+    let headers: Vec<String> = reader.headers()
+        .map_err(|e| format!("无法读取 CSV 表头: {}", e))?
+        .iter()
+        .map(|header| header.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| headers.iter().position(|header| names.contains(&header.as_str()));
+
+    let password_column = column(&["password"])
+        .ok_or_else(|| "CSV 缺少 password 列".to_string())?;
+    let title_column = column(&["name", "title"]);
+    let url_column = column(&["url", "origin"]);
+    let username_column = column(&["username", "login"]);
+    let notes_column = column(&["note", "notes"]);
+
+    let mut entries = Vec::new();
+    let mut warnings = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                warnings.push(format!("跳过第 {} 行: {}", index + 2, e));
+                continue;
+            }
+        };
+        let get = |column: Option<usize>| non_empty(column.and_then(|column| record.get(column)));
+
+        let url = get(url_column);
+        let username = get(username_column);
+        entries.push(ImportedEntry {
+            title: fallback_title(get(title_column), url.as_deref(), username.as_deref()),
+            username,
+            password: record.get(password_column).unwrap_or("").to_string(),
+            url,
+            notes: get(notes_column),
+            ..Default::default()
+        });
+    }
+
+    Ok((entries, warnings))
+}
+
+fn parse_import(format: &str, content: &str) -> Result<(Vec<ImportedEntry>, Vec<String>), String> {
+    match format {
+        "bitwarden" => parse_bitwarden_json(content),
+        "keepass" => parse_keepass_xml(content),
+        "csv" => parse_browser_csv(content),
+        _ => Err(format!("不支持的导入格式: {}", format)),
+    }
+}
+
+// 导入密码条目；dry_run 为 true 时只返回预览报告，不写入数据库
+#[tauri::command]
+pub async fn import_password_entries(
+    database: State<'_, Arc<Database>>,
+    format: String,
+    content: String,
+    category_id: Option<i64>,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    let (mut entries, mut warnings) = parse_import(&format, &content)?;
+
+    // 文件夹名与已有分类同名时归入该分类，否则作为标签保留
+    let categories: HashMap<String, i64> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, name FROM password_categories")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(1)?.to_lowercase(), row.get(0)?)))?;
+        rows.collect()
+    })?;
+    let existing: HashMap<String, i64> = database.with_connection(|conn| {
+        let mut stmt = conn.prepare("SELECT id, url, username FROM password_entries ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            let url: Option<String> = row.get(1)?;
+            let username: Option<String> = row.get(2)?;
+            Ok((duplicate_key(url.as_deref(), username.as_deref()), row.get(0)?))
+        })?;
+        let mut existing = HashMap::new();
+        for row in rows {
+            let (key, id) = row?;
+            if let Some(key) = key {
+                existing.entry(key).or_insert(id);
+            }
+        }
+        Ok(existing)
+    })?;
+
+    let mut seen = HashSet::new();
+    let mut previews = Vec::with_capacity(entries.len());
+    for entry in entries.iter_mut() {
+        if let Some(totp) = &entry.totp {
+            if let Err(e) = TotpConfig::parse(totp) {
+                warnings.push(format!("{}: 忽略无效的两步验证密钥（{}）", entry.title, e));
+                entry.totp = None;
+            }
+        }
+
+        let entry_category = match entry.folder.as_deref() {
+            Some(folder) => match categories.get(&folder.to_lowercase()) {
+                Some(id) => Some(*id),
+                None => {
+                    if !entry.tags.iter().any(|tag| tag == folder) {
+                        entry.tags.push(folder.to_string());
+                    }
+                    category_id
+                }
+            },
+            None => category_id,
+        };
+
+        let key = duplicate_key(entry.url.as_deref(), entry.username.as_deref());
+        let duplicate_of = key.as_ref().and_then(|key| existing.get(key).copied());
+        let duplicate_in_file = duplicate_of.is_none() && key.map(|key| !seen.insert(key)).unwrap_or(false);
+
+        previews.push(ImportPreviewEntry {
+            title: entry.title.clone(),
+            username: entry.username.clone(),
+            url: entry.url.clone(),
+            category_id: entry_category,
+            tags: entry.tags.clone(),
+            has_totp: entry.totp.is_some(),
+            duplicate_of,
+            duplicate_in_file,
+        });
+    }
+
+    let duplicate_count = previews.iter()
+        .filter(|preview| preview.duplicate_of.is_some() || preview.duplicate_in_file)
+        .count();
+    let mut report = ImportReport {
+        format,
+        dry_run,
+        total: previews.len(),
+        new_count: previews.len() - duplicate_count,
+        duplicate_count,
+        imported: 0,
+        entries: previews,
+        warnings,
+    };
+
+    if dry_run {
+        return Ok(report);
+    }
+
+    // 重复条目不导入，其余条目用主密钥加密后在一个事务中写入
+    let key = vault_key()?;
+    let mut rows = Vec::with_capacity(report.new_count);
+    for (entry, preview) in entries.iter().zip(&report.entries) {
+        if preview.duplicate_of.is_some() || preview.duplicate_in_file {
+            continue;
+        }
+        let tags_json = if entry.tags.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&entry.tags).unwrap_or_default())
+        };
+        rows.push((
+            entry,
+            preview.category_id,
+            tags_json,
+            CryptoService::encrypt_with_key(&key, &entry.password)?,
+            encrypt_totp_secret(&key, entry.totp.as_deref())?,
+        ));
+    }
+
+    report.imported = database.with_connection(|conn| {
+        let tx = conn.unchecked_transaction()?;
+        for (entry, entry_category, tags_json, stored_password, stored_totp) in &rows {
+            tx.execute(
+                "INSERT INTO password_entries
+                 (title, username, password_encrypted, url, notes, category_id, tags, is_favorite, totp_encrypted, is_encrypted)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, 1)",
+                params![
+                    entry.title,
+                    entry.username,
+                    stored_password,
+                    entry.url,
+                    entry.notes,
+                    entry_category,
+                    tags_json,
+                    if entry.is_favorite { 1 } else { 0 },
+                    stored_totp
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(rows.len())
+    })?;
+
+    println!("✅ 已导入 {} 条密码条目，跳过 {} 条重复", report.imported, report.duplicate_count);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bitwarden_json() {
+        let content = r#"{
+            "encrypted": false,
+            "folders": [{ "id": "f1", "name": "工作" }],
+            "items": [
+                {
+                    "type": 1, "name": "GitHub", "notes": "备注", "favorite": true, "folderId": "f1",
+                    "login": {
+                        "username": "alice", "password": "secret", "totp": "JBSWY3DPEHPK3PXP",
+                        "uris": [{ "match": null, "uri": "https://github.com/login" }]
+                    }
+                },
+                { "type": 2, "name": "Secure note", "notes": "text" }
+            ]
+        }"#;
+
+        let (entries, warnings) = parse_bitwarden_json(content).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(warnings.len(), 1);
+
+        let entry = &entries[0];
+        assert_eq!(entry.title, "GitHub");
+        assert_eq!(entry.username.as_deref(), Some("alice"));
+        assert_eq!(entry.password, "secret");
+        assert_eq!(entry.url.as_deref(), Some("https://github.com/login"));
+        assert_eq!(entry.folder.as_deref(), Some("工作"));
+        assert_eq!(entry.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert!(entry.is_favorite);
+
+        assert!(parse_bitwarden_json(r#"{ "encrypted": true, "items": [] }"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_keepass_xml() {
+        let content = r#"<?xml version="1.0" encoding="utf-8"?>
+            <KeePassFile>
+                <Meta><RecycleBinUUID>BIN</RecycleBinUUID></Meta>
+                <Root>
+                    <Group>
+                        <UUID>ROOT</UUID><Name>Database</Name>
+                        <Entry>
+                            <String><Key>Title</Key><Value>Router</Value></String>
+                            <String><Key>UserName</Key><Value>admin</Value></String>
+                            <String><Key>Password</Key><Value ProtectInMemory="True">pw1</Value></String>
+                            <Tags>home;network</Tags>
+                            <History>
+                                <Entry><String><Key>Password</Key><Value>old</Value></String></Entry>
+                            </History>
+                        </Entry>
+                        <Group>
+                            <UUID>EMAIL</UUID><Name>Email</Name>
+                            <Entry>
+                                <String><Key>URL</Key><Value>https://mail.example.com</Value></String>
+                                <String><Key>Password</Key><Value>pw2</Value></String>
+                            </Entry>
+                        </Group>
+                        <Group>
+                            <UUID>BIN</UUID><Name>Recycle Bin</Name>
+                            <Entry><String><Key>Title</Key><Value>Deleted</Value></String></Entry>
+                        </Group>
+                    </Group>
+                </Root>
+            </KeePassFile>"#;
+
+        let (entries, _) = parse_keepass_xml(content).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].title, "Router");
+        assert_eq!(entries[0].password, "pw1");
+        assert_eq!(entries[0].folder, None);
+        assert_eq!(entries[0].tags, vec!["home", "network"]);
+
+        assert_eq!(entries[1].title, "mail.example.com");
+        assert_eq!(entries[1].folder.as_deref(), Some("Email"));
+    }
+
+    #[test]
+    fn test_parse_browser_csv() {
+        let chrome = "name,url,username,password,note\nExample,https://example.com/,bob,pw,\"多行\n备注\"\n";
+        let (entries, _) = parse_browser_csv(chrome).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Example");
+        assert_eq!(entries[0].notes.as_deref(), Some("多行\n备注"));
+
+        let firefox = "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\"\n\
+                       \"https://example.org\",\"carol\",\"pw\",,\"https://example.org\",\"{guid}\"\n";
+        let (entries, _) = parse_browser_csv(firefox).unwrap();
+        assert_eq!(entries[0].title, "example.org");
+        assert_eq!(entries[0].username.as_deref(), Some("carol"));
+
+        assert!(parse_browser_csv("url,username\nhttps://a.com,dan\n").is_err());
+    }
+
+    #[test]
+    fn test_duplicate_key() {
+        assert_eq!(
+            duplicate_key(Some("https://Example.com/"), Some("Bob")),
+            duplicate_key(Some("https://example.com"), Some("bob"))
+        );
+        assert_ne!(
+            duplicate_key(Some("https://example.com"), Some("bob")),
+            duplicate_key(Some("https://example.com"), Some("alice"))
+        );
+        assert_eq!(duplicate_key(None, Some("  ")), None);
+    }
+}
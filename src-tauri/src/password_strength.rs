@@ -0,0 +1,1026 @@
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// 参考 zxcvbn 的密码强度估算：先找出字典词、键盘路径、重复、序列和日期等模式，
+// 再用动态规划求出猜测次数最少的模式组合，以此估算破解所需的猜测次数。
+
+// 超出部分按暴力破解计算，避免超长输入拖慢匹配
+const MAX_ANALYZED_CHARS: usize = 100;
+
+// 暴力破解每个字符的基数
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_SUBMATCH_GUESSES_SINGLE_CHAR: f64 = 10.0;
+const MIN_SUBMATCH_GUESSES_MULTI_CHAR: f64 = 50.0;
+
+// 每多拼接一个模式的额外惩罚，避免把密码切得过碎
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 10000.0;
+
+// 日期与年份
+const MIN_YEAR_SPACE: i32 = 20;
+const DATE_MIN_YEAR: i32 = 1000;
+const DATE_MAX_YEAR: i32 = 2050;
+
+// 序列中相邻字符的最大差值
+const MAX_SEQUENCE_DELTA: i32 = 5;
+
+// 字典词最短匹配长度
+const MIN_WORD_LEN: usize = 3;
+
+// l33t 替换最多尝试的组合数
+const MAX_L33T_VARIANTS: usize = 32;
+
+const L33T_TABLE: &[(char, &[char])] = &[
+    ('a', &['4', '@']),
+    ('b', &['8']),
+    ('c', &['(', '{', '[', '<']),
+    ('e', &['3']),
+    ('g', &['6', '9']),
+    ('i', &['1', '!', '|']),
+    ('l', &['1', '|', '7']),
+    ('o', &['0']),
+    ('s', &['$', '5']),
+    ('t', &['+', '7']),
+    ('x', &['%']),
+    ('z', &['2']),
+];
+
+// QWERTY 键盘布局，每个键写作“非Shift字符+Shift字符”，空串表示 Tab/Caps/Shift 等占位键。
+// 下一行比上一行右移半个键，因此 (r, c) 的上方相邻键是 (r-1, c) 和 (r-1, c+1)
+const QWERTY_ROWS: &[&[&str]] = &[
+    &["`~", "1!", "2@", "3#", "4$", "5%", "6^", "7&", "8*", "9(", "0)", "-_", "=+"],
+    &["", "qQ", "wW", "eE", "rR", "tT", "yY", "uU", "iI", "oO", "pP", "[{", "]}", "\\|"],
+    &["", "aA", "sS", "dD", "fF", "gG", "hH", "jJ", "kK", "lL", ";:", "'\""],
+    &["", "zZ", "xX", "cC", "vV", "bB", "nN", "mM", ",<", ".>", "/?"],
+];
+
+struct KeyboardGraph {
+    // 字符 -> 各方向的相邻键（按 左、左上、右上、右、右下、左下 排列）
+    adjacency: HashMap<char, Vec<Option<&'static str>>>,
+    shifted: HashSet<char>,
+    starting_positions: f64,
+    average_degree: f64,
+}
+
+fn build_keyboard_graph() -> KeyboardGraph {
+    let key_at = |row: isize, col: isize| -> Option<&'static str> {
+        if row < 0 || col < 0 {
+            return None;
+        }
+        QWERTY_ROWS.get(row as usize)
+            .and_then(|keys| keys.get(col as usize))
+            .copied()
+            .filter(|key| !key.is_empty())
+    };
+
+    let mut adjacency = HashMap::new();
+    let mut shifted = HashSet::new();
+    let mut keys = 0;
+    let mut degrees = 0;
+    for (row, row_keys) in QWERTY_ROWS.iter().enumerate() {
+        for (col, key) in row_keys.iter().enumerate() {
+            if key.is_empty() {
+                continue;
+            }
+            let (r, c) = (row as isize, col as isize);
+            let neighbors = vec![
+                key_at(r, c - 1),
+                key_at(r - 1, c),
+                key_at(r - 1, c + 1),
+                key_at(r, c + 1),
+                key_at(r + 1, c),
+                key_at(r + 1, c - 1),
+            ];
+            keys += 1;
+            degrees += neighbors.iter().filter(|neighbor| neighbor.is_some()).count();
+            for ch in key.chars() {
+                adjacency.insert(ch, neighbors.clone());
+            }
+            shifted.extend(key.chars().nth(1));
+        }
+    }
+
+    KeyboardGraph {
+        adjacency,
+        shifted,
+        starting_positions: keys as f64,
+        average_degree: degrees as f64 / keys as f64,
+    }
+}
+
+// 读取内置词表：忽略空行和 # 注释，行序即排名（从 1 开始）
+fn load_ranked_words(content: &'static str) -> HashMap<&'static str, usize> {
+    let mut words = HashMap::new();
+    for (index, word) in content.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .enumerate()
+    {
+        words.entry(word).or_insert(index + 1);
+    }
+    words
+}
+
+lazy_static::lazy_static! {
+    static ref DICTIONARIES: Vec<(&'static str, HashMap<&'static str, usize>)> = vec![
+        ("passwords", load_ranked_words(include_str!("wordlists/passwords.txt"))),
+        ("english", load_ranked_words(include_str!("wordlists/english.txt"))),
+        ("names", load_ranked_words(include_str!("wordlists/names.txt"))),
+        ("pinyin", load_ranked_words(include_str!("wordlists/pinyin.txt"))),
+    ];
+    static ref MAX_WORD_LEN: usize = DICTIONARIES.iter()
+        .flat_map(|(_, words)| words.keys().map(|word| word.chars().count()))
+        .max()
+        .unwrap_or(MIN_WORD_LEN);
+    static ref KEYBOARD: KeyboardGraph = build_keyboard_graph();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    Dictionary {
+        dictionary: &'static str,
+        rank: usize,
+        reversed: bool,
+        l33t_subs: Vec<(char, char)>,  // (替换字符, 原字母)
+    },
+    Spatial { turns: usize, shifted: usize },
+    Repeat { base_guesses: f64, repeat_count: usize },
+    Sequence { ascending: bool },
+    Date { year: i32, separator: bool },
+    Year { year: i32 },
+    Bruteforce,
+}
+
+#[derive(Debug, Clone)]
+struct Match {
+    pattern: Pattern,
+    i: usize,
+    j: usize,
+    token: Vec<char>,
+    guesses: f64,
+}
+
+impl Match {
+    fn new(pattern: Pattern, chars: &[char], i: usize, j: usize) -> Self {
+        Match { pattern, i, j, token: chars[i..=j].to_vec(), guesses: 0.0 }
+    }
+
+    fn pattern_name(&self) -> &'static str {
+        match self.pattern {
+            Pattern::Dictionary { .. } => "dictionary",
+            Pattern::Spatial { .. } => "spatial",
+            Pattern::Repeat { .. } => "repeat",
+            Pattern::Sequence { .. } => "sequence",
+            Pattern::Date { .. } => "date",
+            Pattern::Year { .. } => "year",
+            Pattern::Bruteforce => "bruteforce",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrackTimes {
+    pub online_throttling: f64,     // 在线攻击，限速每小时 100 次
+    pub online_no_throttling: f64,  // 在线攻击，不限速每秒 10 次
+    pub offline_slow_hashing: f64,  // 离线攻击，慢哈希每秒 1 万次
+    pub offline_fast_hashing: f64,  // 离线攻击，快哈希每秒 100 亿次
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrackTimesDisplay {
+    pub online_throttling: String,
+    pub online_no_throttling: String,
+    pub offline_slow_hashing: String,
+    pub offline_fast_hashing: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrengthFeedback {
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchSummary {
+    pub pattern: String,
+    pub token: String,
+    pub guesses_log10: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrengthEstimate {
+    pub score: u8,  // 0-100，与 check_password_strength 一致
+    pub guesses: f64,
+    pub guesses_log10: f64,
+    pub crack_times_seconds: CrackTimes,
+    pub crack_times_display: CrackTimesDisplay,
+    pub feedback: StrengthFeedback,
+    pub sequence: Vec<MatchSummary>,
+}
+
+fn factorial(n: usize) -> f64 {
+    (2..=n).fold(1.0, |acc, i| acc * i as f64)
+}
+
+fn n_choose_k(n: usize, k: usize) -> f64 {
+    if k > n {
+        return 0.0;
+    }
+    (1..=k).fold(1.0, |acc, i| acc * (n - k + i) as f64 / i as f64)
+}
+
+// 大小写变化带来的额外猜测次数
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+
+    // 首字母大写、末字母大写、全部大写都很常见
+    let first_upper = token.first().is_some_and(|c| c.is_uppercase()) && upper == 1;
+    let last_upper = token.last().is_some_and(|c| c.is_uppercase()) && upper == 1;
+    if lower == 0 || first_upper || last_upper {
+        return 2.0;
+    }
+
+    (1..=upper.min(lower)).map(|i| n_choose_k(upper + lower, i)).sum()
+}
+
+// l33t 替换带来的额外猜测次数
+fn l33t_variations(token: &[char], subs: &[(char, char)]) -> f64 {
+    let lower: Vec<char> = token.iter().map(|c| to_lower(*c)).collect();
+    let mut variations = 1.0;
+    for (sub, letter) in subs {
+        let substituted = lower.iter().filter(|c| *c == sub).count();
+        let unsubstituted = lower.iter().filter(|c| *c == letter).count();
+        if substituted == 0 || unsubstituted == 0 {
+            variations *= 2.0;
+        } else {
+            let possibilities: f64 = (1..=substituted.min(unsubstituted))
+                .map(|i| n_choose_k(substituted + unsubstituted, i))
+                .sum();
+            variations *= possibilities;
+        }
+    }
+    variations
+}
+
+fn to_lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn dictionary_matches(chars: &[char], reversed: bool) -> Vec<Match> {
+    let n = chars.len();
+    let lower: Vec<char> = chars.iter().map(|c| to_lower(*c)).collect();
+    let mut matches = Vec::new();
+
+    for i in 0..n {
+        for j in (i + MIN_WORD_LEN - 1)..n.min(i + *MAX_WORD_LEN) {
+            let word: String = lower[i..=j].iter().collect();
+            for (dictionary, words) in DICTIONARIES.iter() {
+                if let Some(rank) = words.get(word.as_str()) {
+                    matches.push(Match::new(
+                        Pattern::Dictionary { dictionary, rank: *rank, reversed, l33t_subs: Vec::new() },
+                        chars, i, j,
+                    ));
+                }
+            }
+        }
+    }
+    matches
+}
+
+fn reverse_dictionary_matches(chars: &[char]) -> Vec<Match> {
+    let n = chars.len();
+    let reversed: Vec<char> = chars.iter().rev().copied().collect();
+
+    dictionary_matches(&reversed, true)
+        .into_iter()
+        .map(|m| Match::new(m.pattern, chars, n - 1 - m.j, n - 1 - m.i))
+        // 回文词正反相同，不重复计入
+        .filter(|m| m.token.iter().rev().ne(m.token.iter()))
+        .collect()
+}
+
+// 把 l33t 字符还原成字母的所有组合（数量有上限）
+fn l33t_variants(token: &[char]) -> Vec<(String, Vec<(char, char)>)> {
+    let mut variants: Vec<(String, Vec<(char, char)>)> = vec![(String::new(), Vec::new())];
+    for &c in token {
+        let letters: Vec<char> = L33T_TABLE.iter()
+            .filter(|(_, subs)| subs.contains(&c))
+            .map(|(letter, _)| *letter)
+            .collect();
+
+        if letters.is_empty() {
+            for (word, _) in variants.iter_mut() {
+                word.push(c);
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for (word, subs) in &variants {
+            for &letter in &letters {
+                let mut word = word.clone();
+                word.push(letter);
+                let mut subs = subs.clone();
+                if !subs.contains(&(c, letter)) {
+                    subs.push((c, letter));
+                }
+                next.push((word, subs));
+                if next.len() >= MAX_L33T_VARIANTS {
+                    break;
+                }
+            }
+        }
+        variants = next;
+    }
+    variants.retain(|(_, subs)| !subs.is_empty());
+    variants
+}
+
+fn l33t_matches(chars: &[char]) -> Vec<Match> {
+    let n = chars.len();
+    let lower: Vec<char> = chars.iter().map(|c| to_lower(*c)).collect();
+    let is_l33t = |c: &char| L33T_TABLE.iter().any(|(_, subs)| subs.contains(c));
+    let mut matches = Vec::new();
+
+    for i in 0..n {
+        for j in (i + MIN_WORD_LEN - 1)..n.min(i + *MAX_WORD_LEN) {
+            let token = &lower[i..=j];
+            if !token.iter().any(is_l33t) {
+                continue;
+            }
+            for (word, subs) in l33t_variants(token) {
+                for (dictionary, words) in DICTIONARIES.iter() {
+                    if let Some(rank) = words.get(word.as_str()) {
+                        matches.push(Match::new(
+                            Pattern::Dictionary { dictionary, rank: *rank, reversed: false, l33t_subs: subs.clone() },
+                            chars, i, j,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    matches
+}
+
+// 键盘路径，如 qwerty、asdf、1qaz
+fn spatial_matches(chars: &[char]) -> Vec<Match> {
+    let n = chars.len();
+    let graph = &*KEYBOARD;
+    let mut matches = Vec::new();
+
+    let mut i = 0;
+    while i + 1 < n {
+        let mut j = i + 1;
+        let mut last_direction = None;
+        let mut turns = 0;
+        let mut shifted = usize::from(graph.shifted.contains(&chars[i]));
+
+        loop {
+            let mut found = false;
+            if j < n {
+                if let Some(neighbors) = graph.adjacency.get(&chars[j - 1]) {
+                    for (direction, neighbor) in neighbors.iter().enumerate() {
+                        let Some(key) = neighbor else { continue };
+                        if let Some(position) = key.chars().position(|c| c == chars[j]) {
+                            found = true;
+                            if position == 1 {
+                                shifted += 1;
+                            }
+                            if last_direction != Some(direction) {
+                                turns += 1;
+                                last_direction = Some(direction);
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if found {
+                j += 1;
+            } else {
+                if j - i > 2 {
+                    matches.push(Match::new(Pattern::Spatial { turns, shifted }, chars, i, j - 1));
+                }
+                i = j;
+                break;
+            }
+        }
+    }
+    matches
+}
+
+// 重复字符或重复片段，如 aaa、abcabc
+fn repeat_matches(chars: &[char]) -> Vec<Match> {
+    let n = chars.len();
+    let mut matches = Vec::new();
+
+    let mut i = 0;
+    while i < n {
+        // 选覆盖范围最长的重复，范围相同时选最短的重复单元
+        let mut best: Option<(usize, usize)> = None;
+        for unit in 1..=(n - i) / 2 {
+            let mut count = 1;
+            while i + (count + 1) * unit <= n
+                && chars[i + count * unit..i + (count + 1) * unit] == chars[i..i + unit]
+            {
+                count += 1;
+            }
+            if count >= 2 && best.is_none_or(|(best_unit, best_count)| unit * count > best_unit * best_count) {
+                best = Some((unit, count));
+            }
+        }
+
+        match best {
+            Some((unit, count)) => {
+                let base_guesses = estimate_chars(&chars[i..i + unit]).0;
+                let j = i + unit * count - 1;
+                matches.push(Match::new(Pattern::Repeat { base_guesses, repeat_count: count }, chars, i, j));
+                i = j + 1;
+            }
+            None => i += 1,
+        }
+    }
+    matches
+}
+
+// 等差序列，如 abcd、13579、9876
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let n = chars.len();
+    let mut matches = Vec::new();
+    if n < 2 {
+        return matches;
+    }
+
+    let mut push = |i: usize, j: usize, delta: i32| {
+        if (j - i > 1 || delta.abs() == 1) && delta != 0 && delta.abs() <= MAX_SEQUENCE_DELTA {
+            matches.push(Match::new(Pattern::Sequence { ascending: delta > 0 }, chars, i, j));
+        }
+    };
+
+    let mut i = 0;
+    let mut last_delta: Option<i32> = None;
+    for k in 1..n {
+        let delta = chars[k] as i32 - chars[k - 1] as i32;
+        let previous = *last_delta.get_or_insert(delta);
+        if delta == previous {
+            continue;
+        }
+        push(i, k - 1, previous);
+        i = k - 1;
+        last_delta = Some(delta);
+    }
+    push(i, n - 1, last_delta.unwrap_or(0));
+    matches
+}
+
+fn two_to_four_digit_year(year: i32) -> i32 {
+    if year > 99 {
+        year
+    } else if year > 50 {
+        1900 + year
+    } else {
+        2000 + year
+    }
+}
+
+fn map_ints_to_day_month(first: i32, second: i32) -> bool {
+    [(first, second), (second, first)]
+        .iter()
+        .any(|&(day, month)| (1..=31).contains(&day) && (1..=12).contains(&month))
+}
+
+// 把三个整数解释为 日/月/年 的某种排列，返回年份
+fn map_ints_to_year(ints: [i32; 3]) -> Option<i32> {
+    if ints[1] > 31 || ints[1] <= 0 {
+        return None;
+    }
+
+    let mut over_12 = 0;
+    let mut over_31 = 0;
+    let mut under_1 = 0;
+    for &int in &ints {
+        if (99 < int && int < DATE_MIN_YEAR) || int > DATE_MAX_YEAR {
+            return None;
+        }
+        over_31 += usize::from(int > 31);
+        over_12 += usize::from(int > 12);
+        under_1 += usize::from(int <= 0);
+    }
+    if over_31 >= 2 || over_12 == 3 || under_1 >= 2 {
+        return None;
+    }
+
+    let splits = [(ints[2], ints[0], ints[1]), (ints[0], ints[1], ints[2])];
+    for &(year, first, second) in &splits {
+        if (DATE_MIN_YEAR..=DATE_MAX_YEAR).contains(&year) && map_ints_to_day_month(first, second) {
+            return Some(year);
+        }
+    }
+    for &(year, first, second) in &splits {
+        if map_ints_to_day_month(first, second) {
+            return Some(two_to_four_digit_year(year));
+        }
+    }
+    None
+}
+
+fn reference_year() -> i32 {
+    chrono::Local::now().year()
+}
+
+// 日期（19870612、12/06/1987、87-6-12）和近年份
+fn date_matches(chars: &[char]) -> Vec<Match> {
+    lazy_static::lazy_static! {
+        static ref SEPARATED_DATE: regex::Regex =
+            regex::Regex::new(r"^(\d{1,4})([\s/\\_.-])(\d{1,2})([\s/\\_.-])(\d{1,4})$").unwrap();
+    }
+
+    let n = chars.len();
+    let reference = reference_year();
+    let closest = |candidates: Vec<i32>| candidates.into_iter().min_by_key(|year| (year - reference).abs());
+    let mut matches = Vec::new();
+
+    for i in 0..n {
+        // 近年份
+        if i + 4 <= n && chars[i..i + 4].iter().all(|c| c.is_ascii_digit()) {
+            let year: i32 = chars[i..i + 4].iter().collect::<String>().parse().unwrap_or(0);
+            if (1900..=2039).contains(&year) {
+                matches.push(Match::new(Pattern::Year { year }, chars, i, i + 3));
+            }
+        }
+
+        // 不带分隔符的日期
+        for j in (i + 3)..n.min(i + 8) {
+            let token = &chars[i..=j];
+            if !token.iter().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let splits: &[(usize, usize)] = match token.len() {
+                4 => &[(1, 2), (2, 3)],
+                5 => &[(1, 3), (2, 3)],
+                6 => &[(1, 2), (2, 4), (4, 5)],
+                7 => &[(1, 3), (2, 3), (4, 5), (4, 6)],
+                8 => &[(2, 4), (4, 6)],
+                _ => &[],
+            };
+            let parse = |part: &[char]| part.iter().collect::<String>().parse::<i32>().unwrap_or(0);
+            let years: Vec<i32> = splits.iter()
+                .filter_map(|&(k, l)| map_ints_to_year([parse(&token[..k]), parse(&token[k..l]), parse(&token[l..])]))
+                .collect();
+            if let Some(year) = closest(years) {
+                matches.push(Match::new(Pattern::Date { year, separator: false }, chars, i, j));
+            }
+        }
+
+        // 带分隔符的日期
+        for j in (i + 5)..n.min(i + 10) {
+            let token: String = chars[i..=j].iter().collect();
+            let Some(captures) = SEPARATED_DATE.captures(&token) else { continue };
+            if captures[2] != captures[4] {
+                continue;
+            }
+            let ints = [1, 3, 5].map(|group| captures[group].parse::<i32>().unwrap_or(0));
+            if let Some(year) = map_ints_to_year(ints) {
+                matches.push(Match::new(Pattern::Date { year, separator: true }, chars, i, j));
+            }
+        }
+    }
+
+    // 去掉被其他日期完全包含的日期
+    let dates: Vec<(usize, usize)> = matches.iter()
+        .filter(|m| matches!(m.pattern, Pattern::Date { .. }))
+        .map(|m| (m.i, m.j))
+        .collect();
+    matches.retain(|m| {
+        !matches!(m.pattern, Pattern::Date { .. })
+            || !dates.iter().any(|&(i, j)| (i, j) != (m.i, m.j) && i <= m.i && j >= m.j)
+    });
+    matches
+}
+
+fn spatial_guesses(length: usize, turns: usize, shifted: usize) -> f64 {
+    let graph = &*KEYBOARD;
+    let mut guesses = 0.0;
+    for i in 2..=length {
+        for j in 1..=turns.min(i - 1) {
+            guesses += n_choose_k(i - 1, j - 1) * graph.starting_positions * graph.average_degree.powi(j as i32);
+        }
+    }
+
+    if shifted > 0 {
+        let unshifted = length - shifted;
+        if unshifted == 0 {
+            guesses *= 2.0;
+        } else {
+            guesses *= (1..=shifted.min(unshifted)).map(|i| n_choose_k(shifted + unshifted, i)).sum::<f64>();
+        }
+    }
+    guesses
+}
+
+// 单个模式的猜测次数
+fn estimate_match_guesses(m: &Match, password_len: usize) -> f64 {
+    let length = m.token.len();
+    let min_guesses = if length < password_len {
+        if length == 1 { MIN_SUBMATCH_GUESSES_SINGLE_CHAR } else { MIN_SUBMATCH_GUESSES_MULTI_CHAR }
+    } else {
+        1.0
+    };
+
+    let guesses = match &m.pattern {
+        Pattern::Dictionary { rank, reversed, l33t_subs, .. } => {
+            let mut guesses = *rank as f64 * uppercase_variations(&m.token);
+            if !l33t_subs.is_empty() {
+                guesses *= l33t_variations(&m.token, l33t_subs);
+            }
+            if *reversed {
+                guesses *= 2.0;
+            }
+            guesses
+        }
+        Pattern::Spatial { turns, shifted } => spatial_guesses(length, *turns, *shifted),
+        Pattern::Repeat { base_guesses, repeat_count } => base_guesses * *repeat_count as f64,
+        Pattern::Sequence { ascending } => {
+            let first = m.token[0];
+            let base = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let base = if *ascending { base } else { base * 2.0 };
+            base * length as f64
+        }
+        Pattern::Date { year, separator } => {
+            let year_space = (year - reference_year()).abs().max(MIN_YEAR_SPACE) as f64;
+            let guesses = year_space * 365.0;
+            if *separator { guesses * 4.0 } else { guesses }
+        }
+        Pattern::Year { year } => (year - reference_year()).abs().max(MIN_YEAR_SPACE) as f64,
+        Pattern::Bruteforce => {
+            let guesses = BRUTEFORCE_CARDINALITY.powi(length as i32);
+            let min = if length == 1 {
+                MIN_SUBMATCH_GUESSES_SINGLE_CHAR + 1.0
+            } else {
+                MIN_SUBMATCH_GUESSES_MULTI_CHAR + 1.0
+            };
+            if guesses.is_finite() { guesses.max(min) } else { f64::MAX }
+        }
+    };
+    guesses.max(min_guesses)
+}
+
+fn omnimatch(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    matches.extend(dictionary_matches(chars, false));
+    matches.extend(reverse_dictionary_matches(chars));
+    matches.extend(l33t_matches(chars));
+    matches.extend(spatial_matches(chars));
+    matches.extend(repeat_matches(chars));
+    matches.extend(sequence_matches(chars));
+    matches.extend(date_matches(chars));
+    matches.sort_by_key(|m| (m.i, m.j));
+    matches
+}
+
+struct SequenceState {
+    guesses: f64,
+    product: f64,
+    m: Match,
+}
+
+// 动态规划：找出猜测次数最少的模式组合，未被模式覆盖的部分按暴力破解计算
+fn most_guessable_sequence(chars: &[char], mut matches: Vec<Match>) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    if n == 0 {
+        return (1.0, Vec::new());
+    }
+
+    for m in matches.iter_mut() {
+        m.guesses = estimate_match_guesses(m, n);
+    }
+    let mut by_end: Vec<Vec<Match>> = vec![Vec::new(); n];
+    for m in matches {
+        let j = m.j;
+        by_end[j].push(m);
+    }
+
+    // optimal[k][l]：以第 k 个字符结尾、由 l 个模式组成的最优组合
+    let mut optimal: Vec<BTreeMap<usize, SequenceState>> = (0..n).map(|_| BTreeMap::new()).collect();
+
+    fn update(optimal: &mut [BTreeMap<usize, SequenceState>], m: Match, l: usize) {
+        let k = m.j;
+        let mut product = m.guesses;
+        if l > 1 {
+            product *= optimal[m.i - 1][&(l - 1)].product;
+        }
+        let guesses = factorial(l) * product + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(l as i32 - 1);
+
+        // 已有更短且不更差的组合时跳过
+        if optimal[k].iter().any(|(&other_l, state)| other_l <= l && state.guesses <= guesses) {
+            return;
+        }
+        optimal[k].insert(l, SequenceState { guesses, product, m });
+    }
+
+    let bruteforce = |i: usize, j: usize| {
+        let mut m = Match::new(Pattern::Bruteforce, chars, i, j);
+        m.guesses = estimate_match_guesses(&m, n);
+        m
+    };
+
+    for (k, ending) in by_end.into_iter().enumerate() {
+        for m in ending {
+            if m.i > 0 {
+                let lengths: Vec<usize> = optimal[m.i - 1].keys().copied().collect();
+                for l in lengths {
+                    update(&mut optimal, m.clone(), l + 1);
+                }
+            } else {
+                update(&mut optimal, m, 1);
+            }
+        }
+
+        update(&mut optimal, bruteforce(0, k), 1);
+        for i in 1..=k {
+            // 相邻的两段暴力破解应合并为一段
+            let lengths: Vec<usize> = optimal[i - 1].iter()
+                .filter(|(_, state)| state.m.pattern != Pattern::Bruteforce)
+                .map(|(&l, _)| l)
+                .collect();
+            for l in lengths {
+                update(&mut optimal, bruteforce(i, k), l + 1);
+            }
+        }
+    }
+
+    let (mut l, guesses) = optimal[n - 1].iter()
+        .map(|(&l, state)| (l, state.guesses))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((1, 1.0));
+
+    let mut sequence = Vec::new();
+    let mut k = n - 1;
+    loop {
+        let m = optimal[k][&l].m.clone();
+        let start = m.i;
+        sequence.push(m);
+        if start == 0 {
+            break;
+        }
+        k = start - 1;
+        l -= 1;
+    }
+    sequence.reverse();
+    (guesses, sequence)
+}
+
+fn estimate_chars(chars: &[char]) -> (f64, Vec<Match>) {
+    let analyzed = &chars[..chars.len().min(MAX_ANALYZED_CHARS)];
+    let (mut guesses, sequence) = most_guessable_sequence(analyzed, omnimatch(analyzed));
+
+    let remaining = chars.len() - analyzed.len();
+    if remaining > 0 {
+        guesses *= BRUTEFORCE_CARDINALITY.powi(remaining as i32);
+    }
+    (guesses.min(f64::MAX), sequence)
+}
+
+// 把猜测次数映射到 0-100：前端以 30/60/80 划分弱/一般/良好/强，
+// 对应约 10^6、10^8、10^10 次猜测，10^14 次及以上为满分
+fn score_from_guesses(guesses: f64) -> u8 {
+    let log = guesses.max(1.0).log10();
+    let score = if log < 6.0 {
+        log * 5.0
+    } else if log < 8.0 {
+        30.0 + (log - 6.0) * 15.0
+    } else if log < 10.0 {
+        60.0 + (log - 8.0) * 10.0
+    } else {
+        80.0 + (log - 10.0) * 5.0
+    };
+    score.clamp(0.0, 100.0) as u8
+}
+
+fn display_time(seconds: f64) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = MINUTE * 60.0;
+    const DAY: f64 = HOUR * 24.0;
+    const MONTH: f64 = DAY * 31.0;
+    const YEAR: f64 = MONTH * 12.0;
+    const CENTURY: f64 = YEAR * 100.0;
+
+    if seconds < 1.0 {
+        "不到 1 秒".to_string()
+    } else if seconds < MINUTE {
+        format!("{} 秒", seconds.round())
+    } else if seconds < HOUR {
+        format!("{} 分钟", (seconds / MINUTE).round())
+    } else if seconds < DAY {
+        format!("{} 小时", (seconds / HOUR).round())
+    } else if seconds < MONTH {
+        format!("{} 天", (seconds / DAY).round())
+    } else if seconds < YEAR {
+        format!("{} 个月", (seconds / MONTH).round())
+    } else if seconds < CENTURY {
+        format!("{} 年", (seconds / YEAR).round())
+    } else {
+        "数百年".to_string()
+    }
+}
+
+fn match_feedback(m: &Match, is_sole_match: bool) -> (Option<String>, Vec<String>) {
+    match &m.pattern {
+        Pattern::Dictionary { dictionary, rank, reversed, l33t_subs } => {
+            let warning = match *dictionary {
+                "passwords" if is_sole_match && l33t_subs.is_empty() && !reversed => {
+                    if *rank <= 10 {
+                        Some("这是最常用的密码之一")
+                    } else {
+                        Some("这是非常常见的密码")
+                    }
+                }
+                "passwords" => Some("这与常见密码非常相似"),
+                "english" if is_sole_match => Some("单个单词很容易被猜到"),
+                "names" => Some("姓名很容易被猜到"),
+                "pinyin" => Some("常见拼音很容易被猜到"),
+                _ => None,
+            };
+
+            let mut suggestions = Vec::new();
+            let upper = m.token.iter().filter(|c| c.is_uppercase()).count();
+            if m.token.first().is_some_and(|c| c.is_uppercase()) && upper == 1 {
+                suggestions.push("首字母大写帮助不大".to_string());
+            } else if upper > 0 && upper == m.token.iter().filter(|c| c.is_alphabetic()).count() {
+                suggestions.push("全部大写和全部小写一样容易猜到".to_string());
+            }
+            if *reversed && m.token.len() >= 4 {
+                suggestions.push("倒着拼写单词并不难猜".to_string());
+            }
+            if !l33t_subs.is_empty() {
+                suggestions.push("用 @ 代替 a 这类替换并不难猜".to_string());
+            }
+            (warning.map(str::to_string), suggestions)
+        }
+        Pattern::Spatial { turns, .. } => {
+            let warning = if *turns == 1 { "键盘上连成一排的按键很容易被猜到" } else { "较短的键盘路径很容易被猜到" };
+            (Some(warning.to_string()), vec!["使用更长且转折更多的键盘路径".to_string()])
+        }
+        Pattern::Repeat { .. } => {
+            let warning = if m.token.iter().all(|c| *c == m.token[0]) {
+                "像 aaa 这样的重复字符很容易被猜到"
+            } else {
+                "像 abcabc 这样的重复只比 abc 稍难猜一点"
+            };
+            (Some(warning.to_string()), vec!["避免重复的单词和字符".to_string()])
+        }
+        Pattern::Sequence { .. } => (
+            Some("像 abc、6543 这样的序列很容易被猜到".to_string()),
+            vec!["避免使用序列".to_string()],
+        ),
+        Pattern::Date { .. } => (
+            Some("日期通常很容易被猜到".to_string()),
+            vec!["避免使用与自己相关的日期和年份".to_string()],
+        ),
+        Pattern::Year { .. } => (
+            Some("近些年的年份很容易被猜到".to_string()),
+            vec!["避免使用近些年的年份".to_string(), "避免使用与自己相关的年份".to_string()],
+        ),
+        Pattern::Bruteforce => (None, Vec::new()),
+    }
+}
+
+fn feedback(guesses: f64, sequence: &[Match]) -> StrengthFeedback {
+    if sequence.is_empty() {
+        return StrengthFeedback {
+            warning: None,
+            suggestions: vec![
+                "使用几个单词组成的短语，避免常见短语".to_string(),
+                "不一定需要符号、数字或大写字母".to_string(),
+            ],
+        };
+    }
+
+    // 约 10^8 次以上的猜测不再给出建议
+    if guesses >= 1e8 {
+        return StrengthFeedback { warning: None, suggestions: Vec::new() };
+    }
+
+    let longest = sequence.iter().max_by_key(|m| m.token.len()).unwrap_or(&sequence[0]);
+    let (warning, mut suggestions) = match_feedback(longest, sequence.len() == 1);
+    suggestions.insert(0, "再加一两个单词，不常见的单词更好".to_string());
+    StrengthFeedback { warning, suggestions }
+}
+
+// 估算密码强度
+pub fn estimate(password: &str) -> StrengthEstimate {
+    let chars: Vec<char> = password.chars().collect();
+    let (guesses, sequence) = estimate_chars(&chars);
+
+    let crack_times_seconds = CrackTimes {
+        online_throttling: guesses / (100.0 / 3600.0),
+        online_no_throttling: guesses / 10.0,
+        offline_slow_hashing: guesses / 1e4,
+        offline_fast_hashing: guesses / 1e10,
+    };
+    let crack_times_display = CrackTimesDisplay {
+        online_throttling: display_time(crack_times_seconds.online_throttling),
+        online_no_throttling: display_time(crack_times_seconds.online_no_throttling),
+        offline_slow_hashing: display_time(crack_times_seconds.offline_slow_hashing),
+        offline_fast_hashing: display_time(crack_times_seconds.offline_fast_hashing),
+    };
+
+    StrengthEstimate {
+        score: score_from_guesses(guesses),
+        guesses,
+        guesses_log10: guesses.max(1.0).log10(),
+        crack_times_seconds,
+        crack_times_display,
+        feedback: feedback(guesses, &sequence),
+        sequence: sequence.iter()
+            .map(|m| MatchSummary {
+                pattern: m.pattern_name().to_string(),
+                token: m.token.iter().collect(),
+                guesses_log10: m.guesses.max(1.0).log10(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(password: &str) -> Vec<String> {
+        estimate(password).sequence.into_iter().map(|m| m.pattern).collect()
+    }
+
+    #[test]
+    fn test_dictionary_and_l33t() {
+        assert_eq!(patterns("password"), vec!["dictionary"]);
+        assert_eq!(patterns("p@ssw0rd"), vec!["dictionary"]);
+        assert!(estimate("p@ssw0rd").score < 30);
+        assert!(estimate("drowssap").guesses > estimate("password").guesses);
+    }
+
+    #[test]
+    fn test_keyboard_walk() {
+        assert_eq!(patterns("cvbnm,./"), vec!["spatial"]);
+        let estimate = estimate("cvbnm,./");
+        assert!(estimate.score < 30);
+        assert!(estimate.feedback.warning.is_some());
+    }
+
+    #[test]
+    fn test_repeats_and_sequences() {
+        assert_eq!(patterns("zzzzzzzz"), vec!["repeat"]);
+        assert_eq!(patterns("xyzxyzxyz"), vec!["repeat"]);
+        assert_eq!(patterns("13579"), vec!["sequence"]);
+        assert_eq!(patterns("jihgfe"), vec!["sequence"]);
+        assert!(estimate("zzzzzzzz").score < 30);
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(patterns("19870612"), vec!["date"]);
+        assert_eq!(patterns("12/06/1987"), vec!["date"]);
+        assert!(estimate("1987").guesses < 100.0);
+    }
+
+    #[test]
+    fn test_common_patterns_score_low() {
+        let estimate = estimate("Password123!");
+        assert!(estimate.score < 30, "score {}", estimate.score);
+        assert!(!estimate.feedback.suggestions.is_empty());
+        assert!(estimate.crack_times_seconds.offline_fast_hashing < 1.0);
+    }
+
+    #[test]
+    fn test_random_password_scores_high() {
+        let estimate = estimate("rT7#kq9!Lm2@xV");
+        assert!(estimate.score > 80, "score {}", estimate.score);
+        assert!(estimate.feedback.warning.is_none());
+    }
+
+    #[test]
+    fn test_counts_characters_not_bytes() {
+        // 4 个汉字按 4 个字符计算，而不是 12 个字节
+        let estimate = estimate("密码密码");
+        assert!(estimate.score < 30, "score {}", estimate.score);
+        assert!(estimate.guesses <= 1e4);
+    }
+
+    #[test]
+    fn test_empty_password() {
+        let estimate = estimate("");
+        assert_eq!(estimate.score, 0);
+        assert!(!estimate.feedback.suggestions.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+// Server-Sent Events 解码器（按 WHATWG 规范）
+// 按字节缓冲并在完整行上解码 UTF-8，避免多字节字符被拆到两个网络包时出现乱码
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: String,       // 未指定 event 字段时为 "message"
+    pub data: String,        // 多行 data 以 "\n" 连接
+    pub id: Option<String>,  // 最近一次的事件 ID
+}
+
+impl SseEvent {
+    // OpenAI 兼容接口以 "data: [DONE]" 表示流结束
+    pub fn is_done(&self) -> bool {
+        self.data.trim() == "[DONE]"
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    // 上一块以 \r 结尾时，下一块开头的 \n 属于同一个换行
+    skip_lf: bool,
+    started: bool,
+    event: String,
+    data: String,
+    has_data: bool,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 服务端通过 retry 字段建议的重连间隔（毫秒）
+    #[allow(dead_code)]
+    pub fn retry(&self) -> Option<u64> {
+        self.retry
+    }
+
+    // 输入一块原始字节，返回其中已完整的事件。流结束时未以空行结束的事件按规范丢弃
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        let mut bytes = bytes;
+        if self.skip_lf && !bytes.is_empty() {
+            if bytes[0] == b'\n' {
+                bytes = &bytes[1..];
+            }
+            self.skip_lf = false;
+        }
+        self.buffer.extend_from_slice(bytes);
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut index = 0;
+        while index < self.buffer.len() {
+            match self.buffer[index] {
+                b'\n' => {
+                    let line = self.buffer[start..index].to_vec();
+                    self.process_line(&line, &mut events);
+                    index += 1;
+                    start = index;
+                }
+                b'\r' => {
+                    let line = self.buffer[start..index].to_vec();
+                    self.process_line(&line, &mut events);
+                    index += 1;
+                    if index == self.buffer.len() {
+                        self.skip_lf = true;
+                    } else if self.buffer[index] == b'\n' {
+                        index += 1;
+                    }
+                    start = index;
+                }
+                _ => index += 1,
+            }
+        }
+        self.buffer.drain(..start);
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8], events: &mut Vec<SseEvent>) {
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !self.started {
+            self.started = true;
+            if let Some(stripped) = line.strip_prefix('\u{FEFF}') {
+                line = stripped.to_string();
+            }
+        }
+
+        if line.is_empty() {
+            self.dispatch(events);
+            return;
+        }
+        if line.starts_with(':') {
+            return; // 注释行，常用于保活
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_str(), ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self, events: &mut Vec<SseEvent>) {
+        let event = std::mem::take(&mut self.event);
+        if !self.has_data {
+            return;
+        }
+        self.has_data = false;
+        events.push(SseEvent {
+            event: if event.is_empty() { "message".to_string() } else { event },
+            data: std::mem::take(&mut self.data),
+            id: self.last_event_id.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_codepoint_across_chunks() {
+        let bytes = "data: 你好世界\n\n".as_bytes();
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        // 每次只输入一个字节，汉字的 UTF-8 编码必然被拆开
+        for byte in bytes {
+            events.extend(decoder.feed(std::slice::from_ref(byte)));
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "你好世界");
+    }
+
+    #[test]
+    fn test_fields_comments_and_multiline_data() {
+        let mut decoder = SseDecoder::new();
+        let events = decoder.feed(b"\xEF\xBB\xBF: keep-alive\r\nevent: content_block_delta\r\nid: 7\r\ndata: line1\r\ndata:line2\r\n\r\ndata\n\n");
+        assert_eq!(events, vec![
+            SseEvent { event: "content_block_delta".to_string(), data: "line1\nline2".to_string(), id: Some("7".to_string()) },
+            SseEvent { event: "message".to_string(), data: String::new(), id: Some("7".to_string()) },
+        ]);
+
+        // 没有 data 的事件不分发，event 字段随之重置
+        assert!(decoder.feed(b"event: ping\n\n").is_empty());
+        assert_eq!(decoder.feed(b"data: x\n\n")[0].event, "message");
+    }
+
+    #[test]
+    fn test_retry_and_cr_split_across_chunks() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.feed(b"retry: 3000\r").is_empty());
+        assert!(decoder.feed(b"\nretry: abc\n").is_empty());
+        assert_eq!(decoder.retry(), Some(3000));
+
+        assert!(decoder.feed(b"data: a\r").is_empty());
+        let events = decoder.feed(b"\n\r\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "a");
+    }
+
+    #[test]
+    fn test_done_and_incomplete_event() {
+        let mut decoder = SseDecoder::new();
+        let events = decoder.feed(b"data: {\"x\":1}\n\ndata: [DONE]\n\ndata: partial");
+        assert_eq!(events.len(), 2);
+        assert!(!events[0].is_done());
+        assert!(events[1].is_done());
+        // 未以空行结束的事件不会分发
+        assert!(decoder.feed(b"").is_empty());
+    }
+}
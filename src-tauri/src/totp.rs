@@ -0,0 +1,207 @@
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+// TOTP 哈希算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(TotpAlgorithm::Sha1),
+            "SHA256" => Ok(TotpAlgorithm::Sha256),
+            "SHA512" => Ok(TotpAlgorithm::Sha512),
+            other => Err(format!("不支持的 TOTP 算法: {}", other)),
+        }
+    }
+}
+
+// TOTP 配置（RFC 6238）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpConfig {
+    pub secret: Vec<u8>,
+    pub algorithm: TotpAlgorithm,
+    pub digits: u32,
+    pub period: u64,
+}
+
+impl TotpConfig {
+    // 解析 otpauth:// URI 或 base32 编码的密钥
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        if input.to_ascii_lowercase().starts_with("otpauth://") {
+            Self::parse_uri(input)
+        } else {
+            Self::new(decode_base32(input)?, TotpAlgorithm::Sha1, 6, 30)
+        }
+    }
+
+    pub fn new(secret: Vec<u8>, algorithm: TotpAlgorithm, digits: u32, period: u64) -> Result<Self, String> {
+        if secret.is_empty() {
+            return Err("TOTP 密钥不能为空".to_string());
+        }
+        if digits != 6 && digits != 8 {
+            return Err("TOTP 位数只支持 6 或 8 位".to_string());
+        }
+        if period == 0 {
+            return Err("TOTP 周期必须大于 0".to_string());
+        }
+
+        Ok(TotpConfig { secret, algorithm, digits, period })
+    }
+
+    fn parse_uri(uri: &str) -> Result<Self, String> {
+        let parsed = url::Url::parse(uri).map_err(|e| format!("无效的 otpauth URI: {}", e))?;
+        if parsed.host_str().map(|host| host.to_ascii_lowercase()) != Some("totp".to_string()) {
+            return Err("只支持 otpauth://totp/ 类型".to_string());
+        }
+
+        let mut secret = None;
+        let mut algorithm = TotpAlgorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+
+        for (key, value) in parsed.query_pairs() {
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(decode_base32(&value)?),
+                "algorithm" => algorithm = TotpAlgorithm::from_name(&value)?,
+                "digits" => digits = value.parse().map_err(|_| format!("无效的 TOTP 位数: {}", value))?,
+                "period" => period = value.parse().map_err(|_| format!("无效的 TOTP 周期: {}", value))?,
+                _ => {}
+            }
+        }
+
+        let secret = secret.ok_or_else(|| "otpauth URI 缺少 secret 参数".to_string())?;
+        Self::new(secret, algorithm, digits, period)
+    }
+
+    // 生成指定 Unix 时间的验证码
+    pub fn generate(&self, unix_time: u64) -> String {
+        let counter = unix_time / self.period;
+        let digest = match self.algorithm {
+            TotpAlgorithm::Sha1 => hmac_digest::<Hmac<Sha1>>(&self.secret, counter),
+            TotpAlgorithm::Sha256 => hmac_digest::<Hmac<Sha256>>(&self.secret, counter),
+            TotpAlgorithm::Sha512 => hmac_digest::<Hmac<Sha512>>(&self.secret, counter),
+        };
+
+        // RFC 4226 动态截断
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = (u32::from(digest[offset] & 0x7f) << 24)
+            | (u32::from(digest[offset + 1]) << 16)
+            | (u32::from(digest[offset + 2]) << 8)
+            | u32::from(digest[offset + 3]);
+
+        let code = binary % 10u32.pow(self.digits);
+        format!("{:0width$}", code, width = self.digits as usize)
+    }
+
+    // 当前验证码剩余的有效秒数
+    pub fn seconds_remaining(&self, unix_time: u64) -> u64 {
+        self.period - unix_time % self.period
+    }
+}
+
+fn hmac_digest<M: Mac + hmac::digest::KeyInit>(secret: &[u8], counter: u64) -> Vec<u8> {
+    let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(secret)
+        .expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// RFC 4648 base32 解码，忽略大小写、空格和填充
+fn decode_base32(input: &str) -> Result<Vec<u8>, String> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut output = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.chars().filter(|c| !c.is_whitespace() && *c != '-' && *c != '=') {
+        let upper = c.to_ascii_uppercase() as u8;
+        let value = ALPHABET.iter().position(|&a| a == upper)
+            .ok_or_else(|| format!("无效的 base32 字符: {}", c))?;
+
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    // RFC 6238 附录 B 测试向量
+    const RFC_VECTORS: &[(u64, &str, &str, &str)] = &[
+        (59, "94287082", "46119246", "90693936"),
+        (1111111109, "07081804", "68084774", "25091201"),
+        (1111111111, "14050471", "67062674", "99943326"),
+        (1234567890, "89005924", "91819424", "93441116"),
+        (2000000000, "69279037", "90698825", "38618901"),
+        (20000000000, "65353130", "77737706", "47863826"),
+    ];
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let sha1 = TotpConfig::new(SEED_SHA1.to_vec(), TotpAlgorithm::Sha1, 8, 30).unwrap();
+        let sha256 = TotpConfig::new(SEED_SHA256.to_vec(), TotpAlgorithm::Sha256, 8, 30).unwrap();
+        let sha512 = TotpConfig::new(SEED_SHA512.to_vec(), TotpAlgorithm::Sha512, 8, 30).unwrap();
+
+        for (time, expected_sha1, expected_sha256, expected_sha512) in RFC_VECTORS {
+            assert_eq!(sha1.generate(*time), *expected_sha1, "SHA1 at {}", time);
+            assert_eq!(sha256.generate(*time), *expected_sha256, "SHA256 at {}", time);
+            assert_eq!(sha512.generate(*time), *expected_sha512, "SHA512 at {}", time);
+        }
+    }
+
+    #[test]
+    fn test_six_digit_codes_and_remaining_seconds() {
+        let config = TotpConfig::new(SEED_SHA1.to_vec(), TotpAlgorithm::Sha1, 6, 30).unwrap();
+        assert_eq!(config.generate(59), "287082");
+        assert_eq!(config.seconds_remaining(59), 1);
+        assert_eq!(config.seconds_remaining(60), 30);
+    }
+
+    #[test]
+    fn test_parse_otpauth_uri() {
+        let config = TotpConfig::parse(
+            "otpauth://totp/Example:alice@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example&algorithm=SHA256&digits=8&period=60"
+        ).unwrap();
+
+        assert_eq!(config.secret, SEED_SHA1);
+        assert_eq!(config.algorithm, TotpAlgorithm::Sha256);
+        assert_eq!(config.digits, 8);
+        assert_eq!(config.period, 60);
+    }
+
+    #[test]
+    fn test_parse_plain_secret() {
+        let config = TotpConfig::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(config.secret, SEED_SHA1);
+        assert_eq!(config.algorithm, TotpAlgorithm::Sha1);
+        assert_eq!(config.digits, 6);
+        assert_eq!(config.period, 30);
+    }
+
+    #[test]
+    fn test_rejects_invalid_input() {
+        assert!(TotpConfig::parse("not base32!").is_err());
+        assert!(TotpConfig::parse("otpauth://hotp/Example?secret=GEZDGNBV&counter=1").is_err());
+        assert!(TotpConfig::parse("otpauth://totp/Example?secret=GEZDGNBV&digits=7").is_err());
+    }
+}
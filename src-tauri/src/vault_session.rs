@@ -0,0 +1,231 @@
+use crate::crypto::CryptoService;
+use crate::password_commands::{VAULT_LEGACY_SESSION_ID, VAULT_SESSION_ID};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+// 默认空闲自动锁定时间（分钟），0 表示不自动锁定
+pub const DEFAULT_AUTO_LOCK_MINUTES: u32 = 15;
+
+// 空闲检查间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(15);
+
+// 两次检查之间计入休眠的时钟比不计休眠的时钟多走了这么久，视为系统经历过休眠
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(60);
+
+// 两个单调时钟的读数：awake 不计休眠时间，total 计入休眠时间
+// 两者都不受手动修改系统时间或 NTP 校时影响，差值的增长就是休眠时长
+#[derive(Debug, Clone, Copy)]
+struct ClockReading {
+    awake: Duration,
+    total: Duration,
+}
+
+// 密码库会话状态
+struct SessionState {
+    last_activity: Option<Instant>,
+    auto_lock_minutes: u32,
+}
+
+lazy_static::lazy_static! {
+    static ref SESSION: Mutex<SessionState> = Mutex::new(SessionState {
+        last_activity: None,
+        auto_lock_minutes: DEFAULT_AUTO_LOCK_MINUTES,
+    });
+}
+
+// 密码库锁定事件，reason: manual | idle | hide | suspend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultLockedEvent {
+    pub reason: String,
+}
+
+fn session() -> std::sync::MutexGuard<'static, SessionState> {
+    match SESSION.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// 记录一次密码库活动，重置空闲计时
+pub fn record_activity() {
+    session().last_activity = Some(Instant::now());
+}
+
+pub fn auto_lock_minutes() -> u32 {
+    session().auto_lock_minutes
+}
+
+pub fn set_auto_lock_minutes(minutes: u32) {
+    session().auto_lock_minutes = minutes;
+}
+
+// 清除会话密钥，密码库原本处于解锁状态时通知前端
+pub fn lock(app_handle: &AppHandle, reason: &str) -> Result<(), String> {
+    let was_unlocked = CryptoService::get_cached_key(VAULT_SESSION_ID).is_some();
+
+    CryptoService::clear_cached_key(VAULT_LEGACY_SESSION_ID)?;
+    CryptoService::clear_cached_key(VAULT_SESSION_ID)?;
+    session().last_activity = None;
+
+    if was_unlocked {
+        println!("🔒 密码库已锁定: {}", reason);
+        let _ = app_handle.emit("vault-locked", VaultLockedEvent {
+            reason: reason.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// 空闲时间是否已超过自动锁定阈值
+fn idle_expired() -> bool {
+    let state = session();
+    if state.auto_lock_minutes == 0 {
+        return false;
+    }
+
+    let timeout = Duration::from_secs(u64::from(state.auto_lock_minutes) * 60);
+    state.last_activity
+        .map(|last_activity| last_activity.elapsed() >= timeout)
+        .unwrap_or(false)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_clock(clock_id: libc::clockid_t) -> Option<Duration> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: time 是有效的可写 timespec
+    if unsafe { libc::clock_gettime(clock_id, &mut time) } != 0 {
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+// Linux 的 CLOCK_BOOTTIME、macOS 的 CLOCK_MONOTONIC 计入休眠时间
+#[cfg(target_os = "linux")]
+fn read_clocks() -> Option<ClockReading> {
+    Some(ClockReading {
+        awake: read_clock(libc::CLOCK_MONOTONIC)?,
+        total: read_clock(libc::CLOCK_BOOTTIME)?,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn read_clocks() -> Option<ClockReading> {
+    Some(ClockReading {
+        awake: read_clock(libc::CLOCK_UPTIME_RAW)?,
+        total: read_clock(libc::CLOCK_MONOTONIC)?,
+    })
+}
+
+// Windows 的中断时间计入休眠，去掉休眠部分的是 unbiased 中断时间（单位 100 纳秒）
+#[cfg(windows)]
+fn read_clocks() -> Option<ClockReading> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn QueryUnbiasedInterruptTime(unbiased_time: *mut u64) -> i32;
+        fn GetTickCount64() -> u64;
+    }
+
+    let mut unbiased = 0u64;
+    // SAFETY: 两个函数都没有前置条件，unbiased 是有效的可写地址
+    unsafe {
+        if QueryUnbiasedInterruptTime(&mut unbiased) == 0 {
+            return None;
+        }
+        Some(ClockReading {
+            awake: Duration::from_nanos(unbiased.saturating_mul(100)),
+            total: Duration::from_millis(GetTickCount64()),
+        })
+    }
+}
+
+// 其他平台无法区分休眠，只按空闲时间锁定
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn read_clocks() -> Option<ClockReading> {
+    None
+}
+
+// 根据两次检查之间的时钟读数决定是否锁定及原因
+fn lock_reason(previous: Option<ClockReading>, current: Option<ClockReading>, idle_expired: bool) -> Option<&'static str> {
+    if let (Some(previous), Some(current)) = (previous, current) {
+        let awake = current.awake.saturating_sub(previous.awake);
+        let total = current.total.saturating_sub(previous.total);
+        if total.saturating_sub(awake) > SUSPEND_THRESHOLD {
+            return Some("suspend");
+        }
+    }
+
+    if idle_expired {
+        Some("idle")
+    } else {
+        None
+    }
+}
+
+// 启动后台检查：空闲超时或检测到系统休眠时锁定密码库
+pub fn start_watcher(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_reading = read_clocks();
+
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let reading = read_clocks();
+            let previous = std::mem::replace(&mut last_reading, reading);
+
+            if CryptoService::get_cached_key(VAULT_SESSION_ID).is_none() {
+                continue;
+            }
+
+            let reason = match lock_reason(previous, reading, idle_expired()) {
+                Some(reason) => reason,
+                None => continue,
+            };
+
+            if let Err(e) = lock(&app_handle, reason) {
+                eprintln!("自动锁定密码库失败: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(awake_secs: u64, total_secs: u64) -> Option<ClockReading> {
+        Some(ClockReading {
+            awake: Duration::from_secs(awake_secs),
+            total: Duration::from_secs(total_secs),
+        })
+    }
+
+    #[test]
+    fn test_lock_reason() {
+        // 正常运行：两个时钟同步前进
+        assert_eq!(lock_reason(reading(100, 100), reading(115, 115), false), None);
+        assert_eq!(lock_reason(reading(100, 100), reading(115, 115), true), Some("idle"));
+
+        // 休眠一小时：只有计入休眠的时钟前进
+        assert_eq!(lock_reason(reading(100, 100), reading(115, 3715), false), Some("suspend"));
+        assert_eq!(lock_reason(reading(100, 100), reading(115, 3715), true), Some("suspend"));
+
+        // 修改系统时间或 NTP 校时不影响单调时钟，两次读数照常前进；短暂的差值不算休眠
+        assert_eq!(lock_reason(reading(100, 100), reading(115, 120), false), None);
+
+        // 读不到时钟时只按空闲判断
+        assert_eq!(lock_reason(None, reading(115, 3715), false), None);
+        assert_eq!(lock_reason(reading(100, 100), None, true), Some("idle"));
+    }
+
+    #[test]
+    fn test_read_clocks_is_monotonic() {
+        if let (Some(first), Some(second)) = (read_clocks(), read_clocks()) {
+            assert!(second.awake >= first.awake);
+            assert!(second.total >= first.total);
+            assert_eq!(lock_reason(Some(first), Some(second), false), None);
+        }
+    }
+}
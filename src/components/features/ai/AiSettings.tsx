@@ -1,6 +1,12 @@
 ﻿import { useState, useEffect } from 'react';
 import { useAppStore } from '@/stores';
-import { AI_PROVIDERS, AiProviderType, validateApiKey, type AiConfig } from '@/types/aiConfig';
+import {
+  AI_PROVIDERS,
+  AiProviderType,
+  hasUsableApiKey,
+  validateApiKey,
+  type AiConfig,
+} from '@/types/aiConfig';
 import { testAiConnection, getAiProviderStatus } from '@/utils/aiUtils';
 
 export const Ais: React.FC = () => {
@@ -36,9 +42,11 @@ export const Ais: React.FC = () => {
 
   // 连接测试
   const handleTestConnection = async () => {
+    // 未输入新密钥时使用后端已保存的密钥测试
     if (
-      !currentConfig.apiKey ||
-      !validateApiKey(selectedProvider, currentConfig.apiKey, currentConfig.baseURL)
+      !hasUsableApiKey(currentConfig) ||
+      (currentConfig.apiKey &&
+        !validateApiKey(selectedProvider, currentConfig.apiKey, currentConfig.baseURL))
     ) {
       setTestResult({
         success: false,
@@ -137,7 +145,7 @@ export const Ais: React.FC = () => {
                 type={showApiKey ? 'text' : 'password'}
                 value={currentConfig.apiKey}
                 onChange={(e) => handleConfigChange('apiKey', e.target.value)}
-                placeholder="API Key"
+                placeholder={currentConfig.hasApiKey ? '已保存，留空则继续使用' : 'API Key'}
                 className="flex-1 px-3 py-2 text-sm rounded-lg outline-none theme-text-primary feather-glass-deco border border-white/10"
               />
               <button
@@ -151,6 +159,15 @@ export const Ais: React.FC = () => {
               !validateApiKey(selectedProvider, currentConfig.apiKey, currentConfig.baseURL) && (
                 <p className="text-sm theme-text-error mt-2">⚠️ 格式不正确</p>
               )}
+            <label className="flex items-center gap-2 mt-2 cursor-pointer">
+              <input
+                type="checkbox"
+                checked={!!currentConfig.vaultProtected}
+                onChange={(e) => handleConfigChange('vaultProtected', e.target.checked)}
+                className="w-4 h-4 theme-text-accent bg-transparent border-2 theme-border rounded"
+              />
+              <span className="text-sm theme-text-secondary">由密码库保护（锁定时无法使用）</span>
+            </label>
           </div>
         </div>
 
@@ -231,9 +248,9 @@ export const Ais: React.FC = () => {
           <div className="flex gap-2">
             <button
               onClick={handleTestConnection}
-              disabled={!currentConfig.apiKey || isTestingConnection}
+              disabled={!hasUsableApiKey(currentConfig) || isTestingConnection}
               className={`px-4 py-2 text-sm rounded-lg theme-text-accent transition-colors ${
-                !currentConfig.apiKey || isTestingConnection
+                !hasUsableApiKey(currentConfig) || isTestingConnection
                   ? 'opacity-50 cursor-not-allowed'
                   : 'hover:theme-text-primary feather-glass-deco'
               }`}
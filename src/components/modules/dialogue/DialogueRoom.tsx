@@ -11,6 +11,7 @@ import {
   getUnifiedModel,
   getCurrentAgent,
   AiAgent,
+  AiTokenUsage,
 } from '@/types/aiConfig';
 import { AiDatabaseSync } from '@/utils/aiDatabaseSync';
 import { TypewriterMessage } from '@/components/common/TypewriterMessage';
@@ -306,7 +307,7 @@ export const DialogueRoom: React.FC = () => {
             setStreamingResponse((prev) => prev + filteredToken);
           }
         },
-        (fullResponse: string) => {
+        (fullResponse: string, usage?: AiTokenUsage) => {
           // 应用完整内容过滤
           const filteredResponse = filterAIResponse(fullResponse, currentAgent);
 
@@ -315,6 +316,8 @@ export const DialogueRoom: React.FC = () => {
             role: 'assistant',
             content: filteredResponse,
             timestamp: Date.now(),
+            usage,
+            agentId: currentAgent?.id,
           };
 
           // 先添加消息，然后清空流式响应
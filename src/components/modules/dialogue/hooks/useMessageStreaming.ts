@@ -1,6 +1,6 @@
 import { useState, useCallback } from 'react';
 import { sendAiMessageStream } from '@/utils/aiUtils';
-import { AiMessage } from '@/types/aiConfig';
+import { AiMessage, hasUsableApiKey } from '@/types/aiConfig';
 import { useAppStore } from '@/stores';
 
 export const useMessageStreaming = () => {
@@ -16,7 +16,7 @@ export const useMessageStreaming = () => {
       const currentProvider = aiConfig.currentProvider;
       const currentConfig = aiConfig[currentProvider];
 
-      if (!currentConfig.enabled || !currentConfig.apiKey) {
+      if (!currentConfig.enabled || !hasUsableApiKey(currentConfig)) {
         setAiChatError('AI服务未配置或未启用');
         return;
       }
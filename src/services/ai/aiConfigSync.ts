@@ -5,7 +5,9 @@ import { safeAiInvoke } from '@/utils/tauriWrapper';
 interface DbAiProvider {
   id?: number;
   provider: string;
-  api_key: string;
+  api_key: string; // 读取时为空，保存时为空表示沿用已保存的密钥
+  has_api_key?: boolean;
+  vault_protected?: boolean;
   base_url?: string;
   model: string;
   temperature: number;
@@ -31,6 +33,7 @@ interface DbAiAgent {
   model?: string;
   is_builtin: number; // 0 or 1
   is_current: number; // 0 or 1
+  tools?: string[]; // 允许调用的工具名
   created_at: string;
   updated_at: string;
 }
@@ -52,6 +55,8 @@ export class AiConfigSync {
     const dbProvider: DbAiProvider = {
       provider,
       api_key: config.apiKey || '',
+      has_api_key: config.hasApiKey ?? false,
+      vault_protected: config.vaultProtected ?? false,
       base_url: config.baseURL,
       model: config.model,
       temperature: config.temperature,
@@ -78,7 +83,9 @@ export class AiConfigSync {
       const providerType = dbProvider.provider as AiProviderType;
       result[providerType] = {
         provider: providerType,
-        apiKey: dbProvider.api_key,
+        apiKey: '',
+        hasApiKey: dbProvider.has_api_key ?? false,
+        vaultProtected: dbProvider.vault_protected ?? false,
         baseURL: dbProvider.base_url,
         model: dbProvider.model,
         temperature: dbProvider.temperature,
@@ -105,7 +112,9 @@ export class AiConfigSync {
 
     return {
       provider,
-      apiKey: dbProvider.api_key,
+      apiKey: '',
+      hasApiKey: dbProvider.has_api_key ?? false,
+      vaultProtected: dbProvider.vault_protected ?? false,
       baseURL: dbProvider.base_url,
       model: dbProvider.model,
       temperature: dbProvider.temperature,
@@ -157,6 +166,7 @@ export class AiConfigSync {
       model: agent.model,
       is_builtin: agent.isBuiltIn ? 1 : 0,
       is_current: isCurrent ? 1 : 0,
+      tools: agent.tools ?? [],
       created_at: new Date().toISOString(),
       updated_at: new Date().toISOString(),
     };
@@ -181,6 +191,7 @@ export class AiConfigSync {
       provider: dbAgent.provider as AiProviderType | undefined,
       model: dbAgent.model,
       isBuiltIn: dbAgent.is_builtin === 1,
+      tools: dbAgent.tools ?? [],
       createdAt: new Date(dbAgent.created_at).getTime(),
       updatedAt: new Date(dbAgent.updated_at).getTime(),
     }));
@@ -211,6 +222,7 @@ export class AiConfigSync {
       provider: dbAgent.provider as AiProviderType | undefined,
       model: dbAgent.model,
       isBuiltIn: dbAgent.is_builtin === 1,
+      tools: dbAgent.tools ?? [],
       createdAt: new Date(dbAgent.created_at).getTime(),
       updatedAt: new Date(dbAgent.updated_at).getTime(),
     };
@@ -15,6 +15,12 @@ import {
 import { AiDatabaseSync, AiAutoSync } from '@/utils/aiDatabaseSync';
 import { AiConfigSync } from '@/services/ai/aiConfigSync';
 
+const withoutApiKey = (config: AiConfig): AiConfig => ({
+  ...config,
+  apiKey: '',
+  hasApiKey: !!config.hasApiKey || !!config.apiKey,
+});
+
 // 导航历史
 export interface NavigationItem {
   moduleId: string;
@@ -1043,7 +1049,12 @@ export const useAppStore = create<AppStore>()(
           blendMode: state.blendMode,
           sidebarOpen: state.sidebarOpen,
           // currentModule: state.currentModule, // 不持久化当前模块，每次启动都显示主页
-          aiConfig: state.aiConfig,
+          // API 密钥只保存在后端（加密），不写入 localStorage
+          aiConfig: {
+            ...state.aiConfig,
+            deepseek: withoutApiKey(state.aiConfig.deepseek),
+            claude: withoutApiKey(state.aiConfig.claude),
+          },
           // rightPanelOpen: state.rightPanelOpen, // 不持久化右侧栏状态，每次启动都默认折叠
           // 知识库相关持久化
           lastUsedKnowledgeBaseId: state.lastUsedKnowledgeBaseId,
@@ -6,6 +6,7 @@ import {
   PasswordEntry,
   PasswordEntryDisplay,
   PasswordGeneratorOptions,
+  GeneratedPassword,
   PasswordSearchFilters,
   PasswordSortOptions,
   PasswordStrengthResult,
@@ -41,7 +42,7 @@ const api = {
 
   // 密码生成和安全性检查
   generatePassword: (options: PasswordGeneratorOptions) =>
-    invoke<string>('generate_password', { options }),
+    invoke<GeneratedPassword>('generate_password', { options }),
   checkPasswordStrength: (password: string) =>
     invoke<number>('check_password_strength', { password }),
 };
@@ -441,13 +442,13 @@ export const usePasswordStore = create<PasswordStore>()(
         generatePassword: async () => {
           const { generatorOptions, setError } = get();
 
-          const password = await withErrorHandling(
+          const generated = await withErrorHandling(
             () => api.generatePassword(generatorOptions),
             setError
           );
 
-          if (password) {
-            set({ generatedPassword: password });
+          if (generated) {
+            set({ generatedPassword: generated.password });
           }
         },
 
@@ -15,7 +15,9 @@ export interface AiProvider {
 // AI 配置
 export interface AiConfig {
   provider: AiProviderType;
-  apiKey: string;
+  apiKey: string; // 仅在用户输入新密钥时有值，已保存的密钥不会返回前端
+  hasApiKey?: boolean; // 后端是否已保存密钥
+  vaultProtected?: boolean; // 密钥由密码库主密钥加密，密码库锁定时无法使用
   baseURL?: string; // 可选的自定义 BaseURL
   model: string;
   temperature: number;
@@ -36,6 +38,7 @@ export interface AiAgent {
   provider?: AiProviderType; // 可选：绑定到特定提供商
   model?: string; // 可选：绑定到特定模型
   isBuiltIn: boolean; // 是否为内置智能体
+  tools?: string[]; // 允许调用的工具，如 search_pages、create_task
   createdAt: number;
   updatedAt: number;
 }
@@ -49,6 +52,11 @@ export interface AiConfigStore {
   currentAgentId?: string; // 当前选中的智能体ID
 }
 
+// 是否可以使用：输入了新密钥或后端已保存密钥
+export function hasUsableApiKey(config: AiConfig): boolean {
+  return !!config.apiKey || !!config.hasApiKey;
+}
+
 // 连接测试结果
 export interface AiConnectionTestResult {
   success: boolean;
@@ -59,6 +67,14 @@ export interface AiConnectionTestResult {
 // AI 消息角色
 export type MessageRole = 'user' | 'assistant' | 'system';
 
+// 消息附件：图片和 PDF 的 data 为 base64（可带 data URL 前缀），文本文件为原文
+export interface AiAttachment {
+  kind: 'image' | 'pdf' | 'text';
+  name?: string;
+  mime_type?: string;
+  data: string;
+}
+
 // AI 消息
 export interface AiMessage {
   id: string;
@@ -67,10 +83,13 @@ export interface AiMessage {
   images?: string[]; // 图片数组 (base64 或 URL) - 仅在内存中使用，不存储到localStorage
   imageCount?: number; // 图片数量 - 用于显示，存储到localStorage
   hasImages?: boolean; // 是否有图片 - 用于显示，存储到localStorage
+  attachments?: AiAttachment[]; // 随消息保存到数据库，重新加载后可以再次发送
   timestamp: number;
   provider?: AiProviderType;
   model?: string;
   error?: boolean;
+  usage?: AiTokenUsage; // 流式结束事件附带的 token 用量
+  agentId?: string; // 生成回复时使用的智能体
 }
 
 // AI 对话
@@ -101,6 +120,98 @@ export interface AiStreamResponse {
   content: string;
   finished: boolean;
   error?: string;
+  cancelled?: boolean; // 用户主动停止
+  usage?: AiTokenUsage; // 结束事件附带的 token 用量
+  metadata?: AiRequestMetadata; // 重试或换用提供商时的记录
+}
+
+// 一次失败的请求，retry_in_ms 为下次重试前的等待时间
+export interface AiFailedAttempt {
+  provider: string;
+  model: string;
+  error: string;
+  retry_in_ms?: number | null;
+}
+
+// 实际使用的提供商，以及重试和回退情况
+export interface AiRequestMetadata {
+  provider: string;
+  model: string;
+  retries: number;
+  fallback: boolean;
+  failures: AiFailedAttempt[];
+}
+
+// 单次请求的 token 用量
+export interface AiTokenUsage {
+  input_tokens: number;
+  output_tokens: number;
+  cache_read_tokens: number;
+  cache_write_tokens: number;
+}
+
+// 知识库检索的来源类型
+export type RagSourceType = 'page' | 'card' | 'reading_note' | 'timeline';
+
+// 检索结果的引用来源，index 对应回答中的 [编号]
+export interface RagCitation {
+  index: number;
+  source_type: RagSourceType;
+  source_id: string;
+  title: string;
+  snippet: string;
+}
+
+export interface RagContext {
+  context: string;
+  citations: RagCitation[];
+  token_count: number;
+}
+
+// 向量嵌入模型配置，密钥使用对应提供商已保存的密钥
+export interface EmbeddingSettings {
+  provider: string;
+  base_url?: string | null;
+  model: string;
+  enabled: boolean;
+}
+
+export interface EmbeddingIndexStats {
+  embedded: number;
+  removed: number;
+  total: number;
+}
+
+// 智能体可调用的内置工具，writes 为 true 的工具执行前需要用户确认
+export interface AiToolInfo {
+  name: string;
+  description: string;
+  writes: boolean;
+}
+
+// 写操作确认请求（ai-tool-confirm 事件）
+export interface AiToolConfirmation {
+  confirmation_id: string;
+  request_id: string;
+  tool: string;
+  summary: string;
+  arguments: Record<string, unknown>;
+}
+
+export interface AiToolCallRecord {
+  id: string;
+  name: string;
+  summary: string;
+  arguments: Record<string, unknown>;
+  result: unknown;
+  status: 'ok' | 'error' | 'rejected' | 'not_allowed';
+}
+
+// 后端在首轮对话后生成标题、或更新较早消息的摘要时发送
+export interface AiConversationUpdate {
+  conversation_id: string;
+  title?: string | null;
+  summarized: boolean;
 }
 
 // 预设 AI 服务提供商配置
@@ -65,6 +65,22 @@ export interface PasswordGeneratorOptions {
   include_lowercase: boolean;
   include_numbers: boolean;
   include_symbols: boolean;
+  mode?: PasswordGeneratorMode;
+  require_each_class?: boolean; // 每种选中的字符类型至少出现一次
+  exclude_ambiguous?: boolean; // 排除 Il1|O0o 等易混淆字符
+  custom_charset?: string; // 自定义字符集，设置后忽略字符类型选项
+  word_count?: number; // 口令短语的单词数
+  separator?: string;
+  capitalization?: WordCapitalization;
+}
+
+export type PasswordGeneratorMode = 'random' | 'passphrase' | 'pronounceable';
+export type WordCapitalization = 'lowercase' | 'capitalize' | 'uppercase' | 'random';
+
+// 生成结果，附带估算熵值
+export interface GeneratedPassword {
+  password: string;
+  entropy_bits: number;
 }
 
 // 密码强度等级
@@ -235,6 +251,12 @@ export const DEFAULT_PASSWORD_GENERATOR_OPTIONS: PasswordGeneratorOptions = {
   include_lowercase: true,
   include_numbers: true,
   include_symbols: true,
+  mode: 'random',
+  require_each_class: true,
+  exclude_ambiguous: false,
+  word_count: 6,
+  separator: '-',
+  capitalization: 'lowercase',
 };
 
 export const DEFAULT_SORT_OPTIONS: PasswordSortOptions = {
@@ -1,5 +1,5 @@
 ﻿import { invoke } from '@tauri-apps/api/core';
-import { AiConversation, AiMessage, AiProviderType } from '@/types/aiConfig';
+import { AiAttachment, AiConversation, AiMessage, AiProviderType, AiTokenUsage } from '@/types/aiConfig';
 import { invokeTauri } from '@/utils/tauriWrapper';
 // Avoid TS6133 on build by referencing imported symbol (will be cleaned later)
 void invoke;
@@ -23,6 +23,9 @@ interface SaveMessageRequest {
   error: boolean;
   timestamp: number;
   created_at: string;
+  attachments?: AiAttachment[];
+  usage?: AiTokenUsage;
+  agent_id?: string;
 }
 interface ConversationResponse {
   conversations: Array<{
@@ -54,6 +57,9 @@ interface ConversationDetailResponse {
     error: boolean;
     timestamp: number;
     created_at: string;
+    attachments?: AiAttachment[];
+    usage?: AiTokenUsage | null;
+    agent_id?: string | null;
   }>;
 }
 
@@ -79,6 +85,9 @@ function messageToRequest(message: AiMessage): SaveMessageRequest {
     error: message.error || false,
     timestamp: message.timestamp,
     created_at: new Date(message.timestamp).toISOString(),
+    attachments: message.attachments,
+    usage: message.usage,
+    agent_id: message.agentId,
   };
 }
 function dbConversationToType(dbConv: ConversationDetailResponse['conversation']): AiConversation {
@@ -101,6 +110,9 @@ function dbMessageToType(dbMsg: ConversationDetailResponse['messages'][0]): AiMe
     provider: dbMsg.provider as AiProviderType,
     model: dbMsg.model,
     error: dbMsg.error,
+    attachments: dbMsg.attachments?.length ? dbMsg.attachments : undefined,
+    usage: dbMsg.usage ?? undefined,
+    agentId: dbMsg.agent_id ?? undefined,
   };
 }
 
@@ -1,4 +1,20 @@
-import { AiProviderType, AiConfig, AiConnectionTestResult, AI_PROVIDERS } from '@/types/aiConfig';
+import {
+  AiProviderType,
+  AiAttachment,
+  AiConfig,
+  AiConversationUpdate,
+  AiRequestMetadata,
+  AiTokenUsage,
+  AiToolConfirmation,
+  AiToolInfo,
+  AiConnectionTestResult,
+  AI_PROVIDERS,
+  hasUsableApiKey,
+  EmbeddingIndexStats,
+  EmbeddingSettings,
+  RagContext,
+  RagSourceType,
+} from '@/types/aiConfig';
 import { invokeTauri } from '@/utils/tauriWrapper';
 import { listen, UnlistenFn } from '@tauri-apps/api/event';
 
@@ -38,7 +54,7 @@ export function getAiProviderStatus(config: AiConfig): 'ready' | 'partial' | 'di
     return 'disabled';
   }
 
-  if (config.apiKey && config.model) {
+  if (hasUsableApiKey(config) && config.model) {
     return 'ready';
   }
 
@@ -65,7 +81,7 @@ export function formatAiConfig(provider: AiProviderType, config: AiConfig): stri
 export function validateAiConfig(config: AiConfig): { valid: boolean; errors: string[] } {
   const errors: string[] = [];
 
-  if (!config.apiKey) {
+  if (!hasUsableApiKey(config)) {
     errors.push('API Key 不能为空');
   }
 
@@ -218,6 +234,7 @@ interface AiStreamMessage {
   role: string;
   content: string;
   images?: string[];
+  attachments?: AiAttachment[];
 }
 
 // AI 服务调用包装函数，兼容老的接口
@@ -240,6 +257,77 @@ export async function callAiService(
   }
 }
 
+// 从知识库、卡片、读书笔记和时光记中检索与问题相关的资料，可拼入提示词后再发起对话
+export async function searchRagContext(
+  query: string,
+  sources?: RagSourceType[],
+  tokenBudget?: number
+): Promise<RagContext> {
+  return invokeTauri<RagContext>('search_rag_context', { query, sources, tokenBudget });
+}
+
+export async function getEmbeddingSettings(): Promise<EmbeddingSettings | null> {
+  return invokeTauri<EmbeddingSettings | null>('get_embedding_settings');
+}
+
+export async function saveEmbeddingSettings(settings: EmbeddingSettings): Promise<void> {
+  await invokeTauri('save_embedding_settings', { settings });
+}
+
+// 为新增或修改过的内容计算向量，首次建立索引可能需要较长时间
+export async function updateEmbeddingIndex(): Promise<EmbeddingIndexStats> {
+  return invokeTauri<EmbeddingIndexStats>('update_embedding_index');
+}
+
+export async function getAiTools(): Promise<AiToolInfo[]> {
+  return invokeTauri<AiToolInfo[]>('get_ai_tools');
+}
+
+// 监听智能体发起的写操作确认，回调返回是否允许执行
+export async function listenAiToolConfirm(
+  onConfirm: (confirmation: AiToolConfirmation) => Promise<boolean> | boolean
+): Promise<UnlistenFn> {
+  return listen<AiToolConfirmation>('ai-tool-confirm', async (event) => {
+    const approved = await onConfirm(event.payload);
+    await invokeTauri('confirm_ai_tool_call', {
+      confirmationId: event.payload.confirmation_id,
+      approved,
+    });
+  });
+}
+
+// 按智能体绑定的提供商、模型和采样参数对话；指定 conversationId 时由后端保存本轮消息
+export async function sendAgentChat(
+  agentId: string,
+  messages: { role: string; content: string; images?: string[] }[],
+  conversationId?: string
+): Promise<string> {
+  const result = await invokeTauri<{ success: boolean; message?: string; content?: string }>(
+    'send_agent_chat',
+    { agentId, conversationId, messages }
+  );
+  if (result.success && result.content !== undefined) {
+    return result.content;
+  }
+  throw new Error(result.message || '发送消息失败');
+}
+
+// 监听后台整理对话的结果，用于刷新对话列表中的标题
+export async function listenAiConversationUpdated(
+  onUpdate: (update: AiConversationUpdate) => void
+): Promise<UnlistenFn> {
+  return listen<AiConversationUpdate>('ai-conversation-updated', (event) => onUpdate(event.payload));
+}
+
+export function createStreamRequestId(): string {
+  return `req_${Date.now()}_${Math.random().toString(36).substr(2, 9)}`;
+}
+
+// 停止流式请求，请求已结束时返回 false
+export async function cancelAiStream(requestId: string): Promise<boolean> {
+  return invokeTauri<boolean>('cancel_ai_stream', { requestId });
+}
+
 export async function sendAiMessageStream(
   provider: AiProviderType,
   config: AiConfig,
@@ -247,10 +335,14 @@ export async function sendAiMessageStream(
   images: string[] = [],
   previousMessages: AiStreamMessage[] = [],
   onChunk?: (chunk: string) => void,
-  onComplete?: (fullResponse: string) => void,
-  onError?: (error: string) => void
+  // 结束事件附带 token 用量时一并传出，随回复保存
+  onComplete?: (fullResponse: string, usage?: AiTokenUsage) => void,
+  onError?: (error: string) => void,
+  // 传入后可通过 cancelAiStream 停止本次请求
+  requestId: string = createStreamRequestId(),
+  // 后端重试或换用其他提供商时回调，结束时附带完整记录
+  onMetadata?: (metadata: AiRequestMetadata) => void
 ): Promise<void> {
-  const requestId = `req_${Date.now()}_${Math.random().toString(36).substr(2, 9)}`;
   let fullResponse = '';
   let unlisten: UnlistenFn | null = null;
 
@@ -261,6 +353,9 @@ export async function sendAiMessageStream(
       content: string;
       finished: boolean;
       error?: string;
+      cancelled?: boolean;
+      usage?: AiTokenUsage | null;
+      metadata?: AiRequestMetadata;
     }>('ai-stream-chunk', (event) => {
       const chunk = event.payload;
 
@@ -269,13 +364,18 @@ export async function sendAiMessageStream(
         return;
       }
 
+      if (chunk.metadata) {
+        onMetadata?.(chunk.metadata);
+      }
+
       if (chunk.error) {
         onError?.(chunk.error);
         return;
       }
 
+      // 用户停止时保留已收到的内容
       if (chunk.finished) {
-        onComplete?.(fullResponse);
+        onComplete?.(fullResponse, chunk.usage ?? undefined);
         unlisten?.(); // 清理事件监听
         return;
       }